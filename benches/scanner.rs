@@ -0,0 +1,91 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Scanner and layout benchmarks on large ASCII-only modules.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use mini_haskell::scanner::layout::{RawLexemeIterator, AugmentedLexemeIterator};
+
+const CHUNK: &str = r#"
+-- | Compute the length of a list, the hard way.
+myLength :: [a] -> Integer
+myLength xs = go 0 xs
+  where go acc [] = acc
+        go acc (_ : ys) = go (acc + 1) ys
+
+{- a block comment
+   spanning multiple lines -}
+main :: IO ()
+main = do
+    name <- getLine
+    let greeting = "Hello, " <> name <> "!\n"
+    putStrLn greeting
+    print (myLength [1, 2, 3 :: Integer], 0x2A, 3.14e-2, 'c', '\n')
+    if x >= 10 && y /= 0 then pure () else mapM_ print [x .. y]
+"#;
+
+fn large_module() -> String {
+    let mut src = String::from("module Main where\n");
+    for _ in 0..500 { src += CHUNK }
+    src
+}
+
+fn identifier_heavy_module() -> String {
+    let mut src = String::from("module Main where\n");
+    for k in 0..10_000 { src += &format!("x{} = Data.Map.lookup k{} m\n", k, k) }
+    src
+}
+
+fn special_heavy_module() -> String {
+    let mut src = String::from("module Main where\n");
+    for k in 0..10_000 { src += &format!("t{} = ([k], {{a; b}}, `f` [(x, y), ({})])\n", k, k) }
+    src
+}
+
+fn bench_scanner(c: &mut Criterion) {
+    let src = large_module();
+    let mut group = c.benchmark_group("scanner");
+    group.throughput(Throughput::Bytes(src.len() as u64));
+    group.bench_function("ascii module", |b| b.iter(|| {
+        RawLexemeIterator::new(src.as_bytes()).count()
+    }));
+    let src = identifier_heavy_module();
+    group.throughput(Throughput::Bytes(src.len() as u64));
+    group.bench_function("identifier-heavy module", |b| b.iter(|| {
+        RawLexemeIterator::new(src.as_bytes()).count()
+    }));
+    let src = special_heavy_module();
+    group.throughput(Throughput::Bytes(src.len() as u64));
+    group.bench_function("special-heavy module", |b| b.iter(|| {
+        RawLexemeIterator::new(src.as_bytes()).count()
+    }));
+    group.finish();
+}
+
+fn bench_layout(c: &mut Criterion) {
+    let src = large_module();
+    let mut group = c.benchmark_group("layout");
+    group.throughput(Throughput::Bytes(src.len() as u64));
+    group.bench_function("augmented iterator", |b| b.iter(|| {
+        AugmentedLexemeIterator::new(src.as_bytes()).count()
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, bench_scanner, bench_layout);
+criterion_main!(benches);
@@ -0,0 +1,203 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Lexing many files at once, in parallel across the files.
+//!
+//! Each file is lexed on a single thread, from start to end: only the results cross threads.
+//! There is no identifier interner yet, so nothing else is shared between the workers.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::error::Diagnostic;
+use crate::lexeme::Lexeme;
+use crate::location::Range;
+use crate::source::{FileId, SourceManager};
+use crate::scanner::{LexError, Scanner, ScannerConfig};
+use crate::scanner::layout::{RawLexemeIterator, FatLexemeIterator};
+
+/// Options for [`lex_files`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct LexOptions {
+    /// the scanner configuration, the same for all the files.
+    pub scanner: ScannerConfig,
+    /// the number of worker threads, or 0 for the available parallelism.
+    pub jobs: usize,
+}
+
+/// The result of lexing one file with [`lex_files`].
+#[derive(Debug)]
+pub struct FileLexResult {
+    /// the path of the file, as given.
+    pub path: PathBuf,
+    /// the id of the file, see [`lex_files_in`]; the diagnostics are stamped with it.
+    pub file: FileId,
+    /// the fat lexemes, up to the lexical error if any.
+    pub tokens: Vec<(Lexeme, Range)>,
+    /// all the diagnostics of this file, in source order.
+    pub diagnostics: Vec<Diagnostic>,
+    /// the error lexing stopped at, if any.
+    pub lex_error: Option<LexError>,
+    /// the error reading the file, if any: nothing is lexed then.
+    pub read_error: Option<std::io::Error>,
+    /// the time spent on this file, reading included.
+    pub elapsed: Duration,
+}
+
+impl FileLexResult {
+    /// Whether the file was read and lexed without any error.
+    pub fn is_ok(&self) -> bool {
+        self.read_error.is_none() && self.lex_error.is_none() &&
+            self.diagnostics.iter().all(|d| d.effective_severity() < crate::error::Severity::Error)
+    }
+}
+
+/// Lex the files into fat lexemes, in parallel. The results are in the order of `paths`.
+pub fn lex_files(paths: &[PathBuf], options: &LexOptions) -> Vec<FileLexResult> {
+    lex_files_in(&mut SourceManager::new(), paths, options)
+}
+
+/// Lex the files like [`lex_files`], registering them to `files` first: the results, and
+/// their diagnostics, carry the ids handed out, to render them with
+/// [`Diagnostic::render_in`].
+pub fn lex_files_in(files: &mut SourceManager, paths: &[PathBuf], options: &LexOptions)
+                    -> Vec<FileLexResult> {
+    let ids: Vec<_> = paths.iter().map(|path| files.add(path)).collect();
+    let jobs = match options.jobs {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }.min(paths.len()).max(1);
+    // the workers take the files one by one, so that large files do not hold up the rest.
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, FileLexResult)> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs).map(|_| scope.spawn(|| {
+            let mut results = Vec::new();
+            loop {
+                let k = next.fetch_add(1, Ordering::Relaxed);
+                let path = match paths.get(k) {
+                    Some(path) => path,
+                    None => break results,
+                };
+                results.push((k, lex_file(path, ids[k], options.scanner)));
+            }
+        })).collect();
+        workers.into_iter().flat_map(|w| w.join().unwrap()).collect()
+    });
+    results.sort_unstable_by_key(|(k, _)| *k);
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
+fn lex_file(path: &Path, file: FileId, config: ScannerConfig) -> FileLexResult {
+    let start = Instant::now();
+    let mut result = FileLexResult {
+        path: path.to_path_buf(),
+        file,
+        tokens: Vec::new(),
+        diagnostics: Vec::new(),
+        lex_error: None,
+        read_error: None,
+        elapsed: Duration::default(),
+    };
+    match std::fs::read(path) {
+        Ok(bytes) => {
+            let scanner = Scanner::new(bytes.as_slice()).with_config(config).with_file(file);
+            let mut it = FatLexemeIterator::from(RawLexemeIterator::from(scanner));
+            result.tokens = it.by_ref().collect();
+            let (lex_error, mut scanner) = it.into_scanner();
+            result.diagnostics = std::iter::from_fn(|| scanner.diagnostics_mut().pop()).collect();
+            result.lex_error = lex_error;
+        }
+        Err(err) => result.read_error = Some(err),
+    }
+    result.elapsed = start.elapsed();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lex_files, lex_files_in, LexOptions};
+    use crate::source::SourceManager;
+    use crate::term::Style;
+
+    #[test]
+    fn test_lex_files() {
+        let dir = std::env::temp_dir().join(format!("mini-haskell-batch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // file k has k + 1 definitions of 3 lexemes each, every third one with an invalid character.
+        let paths: Vec<_> = (0..12).map(|k| {
+            let path = dir.join(format!("M{}.hs", k));
+            let mut source: String = (0..=k).map(|n| format!("x{} = {}\n", n, n)).collect();
+            if k % 3 == 0 { source.push_str("y = \"\u{7}\"\n") }
+            std::fs::write(&path, source).unwrap();
+            path
+        }).collect();
+        let mut with_missing = paths.clone();
+        with_missing.push(dir.join("Missing.hs"));
+        let results = lex_files(&with_missing, &LexOptions { jobs: 4, ..LexOptions::default() });
+        assert_eq!(results.len(), 13);
+        for (k, result) in results[..12].iter().enumerate() {
+            assert_eq!(result.path, paths[k]);
+            let extra = if k % 3 == 0 { 3 } else { 0 };
+            assert_eq!(result.tokens.len(), 3 * (k + 1) + extra);
+            let errors: Vec<_> = result.diagnostics.iter().map(|d| d.to_string()).collect();
+            if k % 3 == 0 {
+                let line = k + 2;
+                assert_eq!(errors, [format!(
+                    r"{}:6-{}:7: error: control character '\x07' not allowed raw in a string literal, use an escape",
+                    line, line)]);
+            } else {
+                assert!(errors.is_empty(), "{:?}", errors);
+                assert!(result.is_ok());
+            }
+        }
+        assert!(results[12].read_error.is_some());
+        assert!(results[12].tokens.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_lex_files_in() {
+        let dir = std::env::temp_dir().join(format!("mini-haskell-files-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let sources = ["x = '\u{7}'\n", "y = 1\nz = \"\u{7}\"\n"];
+        let paths: Vec<_> = sources.iter().enumerate().map(|(k, source)| {
+            let path = dir.join(format!("M{}.hs", k));
+            std::fs::write(&path, source).unwrap();
+            path
+        }).collect();
+        let mut files = SourceManager::new();
+        let other = files.add(dir.join("Other.hs"));
+        let results = lex_files_in(&mut files, &paths, &LexOptions::default());
+        assert_eq!(files.len(), 3);
+        for (k, result) in results.iter().enumerate() {
+            assert_ne!(result.file, other);
+            assert_eq!(files.path(result.file), Some(paths[k].as_path()));
+            let rendered: Vec<_> = result.diagnostics.iter()
+                .map(|d| d.render_in(sources[k], &files, &Style::plain())).collect();
+            assert_eq!(rendered.len(), 1);
+            let line = rendered[0].lines().nth(1).unwrap();
+            assert_eq!(line, format!(" --> {}:{}:6", paths[k].display(), k + 1));
+        }
+        // ids of no file in the manager are rendered as usual.
+        let diagnostic = &results[1].diagnostics[0];
+        assert_eq!(diagnostic.render_in(sources[1], &SourceManager::new(), &Style::plain()),
+                   diagnostic.render(sources[1]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,153 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Comparing two versions of a source by their lexemes, e.g. to skip a rebuild when only the
+//! comments or the whitespaces changed.
+//!
+//! The lexemes are compared after the layout algorithm: indentation may change, as long as
+//! the implicit `{`, `;`, and `}` it gives stay the same.
+
+use crate::error::Severity;
+use crate::location::{Location, Range};
+use crate::scanner::layout::{AugmentedLexemeIterator, AugmentedLexeme::{self, *}};
+
+// the lexemes of a source one by one: two of them are read in lockstep, never all at once.
+struct Lexemes<'a> {
+    iterator: AugmentedLexemeIterator<&'a [u8]>,
+    // where the last real lexeme ends, for the phantom ones.
+    last: Location,
+}
+
+impl<'a> Lexemes<'a> {
+    fn new(source: &'a str) -> Self {
+        Lexemes { iterator: AugmentedLexemeIterator::new(source.as_bytes()), last: Location::new() }
+    }
+
+    fn next(&mut self) -> Option<AugmentedLexeme> {
+        let x = self.iterator.next()?;
+        if let Real(_, range) = &x { self.last = range.end }
+        Some(x)
+    }
+
+    // where `x` is: the phantom lexemes are right after the last real one.
+    fn range(&self, x: &Option<AugmentedLexeme>) -> Range {
+        match x {
+            Some(Real(_, range)) => *range,
+            Some(Eof(location)) => Range::point(*location),
+            _ => Range::point(self.last),
+        }
+    }
+
+    // no lexical errors at all, after reading everything.
+    fn lexed_cleanly(self) -> bool {
+        let (error, scanner) = self.iterator.into_scanner();
+        error.is_none() && scanner.diagnostics().iter().all(|d| d.severity() < Severity::Error)
+    }
+}
+
+fn same(x: &AugmentedLexeme, y: &AugmentedLexeme) -> bool {
+    match (x, y) {
+        (Real(x, _), Real(y, _)) => x == y,
+        (Eof(_), Eof(_)) => true,
+        _ => x == y,
+    }
+}
+
+fn divergence(old: &mut Lexemes<'_>, new: &mut Lexemes<'_>) -> Option<(Range, Range)> {
+    loop {
+        match (old.next(), new.next()) {
+            (None, None) => return None,
+            (Some(x), Some(y)) if same(&x, &y) => (),
+            (x, y) => return Some((old.range(&x), new.range(&y))),
+        }
+    }
+}
+
+/// Whether `old` and `new` differ only in trivia, i.e. whitespaces and comments.
+///
+/// Conservatively, a lexical error (or a layout error) in either of them is a change.
+pub fn only_trivia_changed(old: &str, new: &str) -> bool {
+    let (mut old, mut new) = (Lexemes::new(old), Lexemes::new(new));
+    divergence(&mut old, &mut new).is_none() && old.lexed_cleanly() && new.lexed_cleanly()
+}
+
+/// The ranges of the first lexemes differing between `old` and `new`, in each of them; `None`
+/// if the lexemes are the same, as far as they can be lexed.
+///
+/// A phantom lexeme from the layout algorithm is placed right after the real one before it.
+pub fn first_token_divergence(old: &str, new: &str) -> Option<(Range, Range)> {
+    divergence(&mut Lexemes::new(old), &mut Lexemes::new(new))
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use super::{only_trivia_changed, first_token_divergence};
+    use crate::location::{Location, Range};
+    use crate::range;
+
+    const SOURCE: &str = indoc! {"
+        module Main where
+        main = do
+          let x = 1+2
+          print x
+    "};
+
+    #[test]
+    fn test_reformatted() {
+        let reformatted = indoc! {"
+            -- | The entry point.
+            module Main where
+
+            main = do {- all of it -}
+                let x = 1 + 2
+                print   x
+        "};
+        assert!(only_trivia_changed(SOURCE, reformatted));
+        assert_eq!(first_token_divergence(SOURCE, reformatted), None);
+        assert!(only_trivia_changed(SOURCE, SOURCE));
+    }
+
+    #[test]
+    fn test_renamed() {
+        let renamed = SOURCE.replace("print x", "print y");
+        assert!(!only_trivia_changed(SOURCE, &renamed));
+        let at = Range { begin: Location::at(4, 9, 50), end: Location::at(4, 10, 51) };
+        assert_eq!(first_token_divergence(SOURCE, &renamed), Some((at, at)));
+        // `print x` continues the `let` binding now: the phantom `}` after `1+2` is gone.
+        let indented = SOURCE.replace("  print x", "        print x");
+        assert!(!only_trivia_changed(SOURCE, &indented));
+        let (old, new) = first_token_divergence(SOURCE, &indented).unwrap();
+        assert_eq!((old, new.begin.line), (Range::point(Location::at(3, 14, 41)), 4));
+        // a lexeme more at the end.
+        assert_eq!(first_token_divergence("x", "x y"), Some((range!(1:2-1:2), range!(1:3-1:4))));
+        assert!(!only_trivia_changed(SOURCE, &format!("{}  pure ()\n", SOURCE)));
+    }
+
+    #[test]
+    fn test_lex_error() {
+        let broken = SOURCE.replace("1+2", "1+2 {- unterminated");
+        assert!(!only_trivia_changed(SOURCE, &broken));
+        assert!(!only_trivia_changed(&broken, SOURCE));
+        // conservatively, even when the same.
+        assert!(!only_trivia_changed(&broken, &broken));
+        let invalid = SOURCE.replace("print", "pr\u{7F}int");
+        assert!(!only_trivia_changed(SOURCE, &invalid));
+        assert!(first_token_divergence(SOURCE, &invalid).is_some());
+    }
+}
@@ -0,0 +1,253 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The compiler driver: running the phases that exist so far on a source file.
+//!
+//! The exit codes of the command line tool, for scripts to rely on, are decided by
+//! [`exit_code`] here:
+//!
+//! - [`EXIT_CLEAN`] (0): no warnings nor errors, notes are fine;
+//! - [`EXIT_WARNINGS`] (1): warnings only, or [`EXIT_ERRORS`] with `--Werror`;
+//! - [`EXIT_ERRORS`] (2): errors in the source;
+//! - [`EXIT_FATAL`] (3): the input could not be processed, e.g. I/O failures, invalid UTF-8
+//!   under the strict policy, or files that cannot be opened;
+//! - [`EXIT_PANIC`] (101): a bug, for the panics (which should never happen).
+
+use crate::error::{Diagnostic, Severity};
+use crate::scanner::{LexError, Scanner, ScannerConfig};
+use crate::scanner::layout::{
+    RawLexemeIterator, FatLexemeIterator, EnrichedLexemeIterator, AugmentedLexemeIterator,
+    LayoutConfig, TabPolicy,
+};
+
+/// No warnings nor errors.
+pub const EXIT_CLEAN: i32 = 0;
+/// Warnings only.
+pub const EXIT_WARNINGS: i32 = 1;
+/// Errors in the source, or warnings with [`CheckOptions::warnings_as_errors`].
+pub const EXIT_ERRORS: i32 = 2;
+/// The input could not be processed at all.
+pub const EXIT_FATAL: i32 = 3;
+/// Reserved for panics, the same as the Rust runtime uses.
+pub const EXIT_PANIC: i32 = 101;
+
+/// Options for [`check`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct CheckOptions {
+    /// fail on warnings too, as on errors.
+    pub warnings_as_errors: bool,
+    /// keep only the diagnostics at least this severe in the report: the others still count
+    /// for the [`exit_code`].
+    pub min_severity_to_report: Severity,
+    /// the layout configuration: tabs in indentation are warned about by default.
+    pub layout: LayoutConfig,
+    /// the scanner configuration, e.g. for the error budget.
+    pub scanner: ScannerConfig,
+}
+
+impl Default for CheckOptions {
+    fn default() -> Self {
+        CheckOptions {
+            warnings_as_errors: false,
+            min_severity_to_report: Severity::Note,
+            layout: LayoutConfig { tabs_in_indentation: TabPolicy::Warn, ..LayoutConfig::default() },
+            scanner: ScannerConfig::default(),
+        }
+    }
+}
+
+/// The result of [`check`].
+#[derive(Debug)]
+pub struct CheckReport {
+    /// the diagnostics reported, in source order.
+    pub diagnostics: Vec<Diagnostic>,
+    /// the error lexing stopped at, if any.
+    pub lex_error: Option<LexError>,
+    warnings_as_errors: bool,
+    // the most severe of all the diagnostics, including those not reported.
+    worst: Option<Severity>,
+}
+
+impl CheckReport {
+    /// A report of the diagnostics and the lexical error from a run of the scanner, keeping
+    /// only the diagnostics [`CheckOptions::min_severity_to_report`] asks for.
+    pub fn new(diagnostics: impl IntoIterator<Item=Diagnostic>, lex_error: Option<LexError>,
+               options: &CheckOptions) -> Self {
+        let mut worst = None;
+        let diagnostics = diagnostics.into_iter()
+            .inspect(|d| worst = worst.max(Some(d.effective_severity())))
+            .filter(|d| d.severity() >= options.min_severity_to_report)
+            .collect();
+        // stopping at an invalid UTF-8 sequence: the input could not be processed.
+        let fatal = lex_error.as_ref().is_some_and(|e| e.invalid_utf8.is_some());
+        let stopped = lex_error.as_ref().map(|_| if fatal { Severity::Fatal } else { Severity::Error });
+        CheckReport {
+            diagnostics,
+            lex_error,
+            warnings_as_errors: options.warnings_as_errors,
+            worst: worst.max(stopped),
+        }
+    }
+
+    /// The most severe diagnostic found, reported or not, counting the lexical error as an
+    /// error (or a fatal one, at invalid UTF-8).
+    pub fn worst(&self) -> Option<Severity> { self.worst }
+
+    /// The number of diagnostics reported of the given severity.
+    pub fn count(&self, severity: Severity) -> usize {
+        self.diagnostics.iter().filter(|d| d.severity() == severity).count()
+    }
+
+    /// Whether the check passes: no lexical error, and no error diagnostics (nor warnings,
+    /// with [`CheckOptions::warnings_as_errors`]).
+    pub fn is_ok(&self) -> bool {
+        let worst = self.worst.unwrap_or(Severity::Note);
+        worst < Severity::Warning || worst == Severity::Warning && !self.warnings_as_errors
+    }
+}
+
+/// The exit code for a report, see the [module documentation](self).
+pub fn exit_code(report: &CheckReport, options: &CheckOptions) -> i32 {
+    match report.worst() {
+        None | Some(Severity::Note) => EXIT_CLEAN,
+        Some(Severity::Warning) if options.warnings_as_errors => EXIT_ERRORS,
+        Some(Severity::Warning) => EXIT_WARNINGS,
+        Some(Severity::Error) => EXIT_ERRORS,
+        Some(Severity::Fatal) => EXIT_FATAL,
+    }
+}
+
+/// Check the syntax of a source file, as far as it goes now: lexing and the layout algorithm.
+pub fn check(input: impl std::io::Read, options: CheckOptions) -> CheckReport {
+    let scanner = Scanner::new(input).with_config(options.scanner);
+    let it = EnrichedLexemeIterator::from(FatLexemeIterator::from(RawLexemeIterator::from(scanner)));
+    let mut it = AugmentedLexemeIterator::from(it).with_config(options.layout);
+    it.by_ref().for_each(drop);
+    let (lex_error, mut scanner) = it.into_scanner();
+    let diagnostics = std::iter::from_fn(|| scanner.diagnostics_mut().pop());
+    CheckReport::new(diagnostics, lex_error, &options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, exit_code, CheckOptions, CheckReport};
+    use super::{EXIT_CLEAN, EXIT_WARNINGS, EXIT_ERRORS, EXIT_FATAL};
+    use crate::error::{Diagnostic, DiagnosticMessage, Error, Warning, Note, Severity};
+    use crate::scanner::{LexError, Location, Range};
+    use crate::input::Utf8Error;
+
+    #[test]
+    fn test_check() {
+        let clean = check("main = do\n  pure ()\n".as_bytes(), CheckOptions::default());
+        assert!(clean.is_ok());
+        assert!(clean.diagnostics.is_empty());
+
+        let broken = check("x = \u{7F}\n".as_bytes(), CheckOptions::default());
+        assert!(!broken.is_ok());
+        assert_eq!(broken.lex_error.map(|e| e.location.column), Some(5));
+
+        let source = "main = do\n\tpure ()\n";
+        let warned = check(source.as_bytes(), CheckOptions::default());
+        assert_eq!((warned.count(Severity::Warning), warned.count(Severity::Error)), (1, 0));
+        assert!(warned.is_ok());
+        let options = CheckOptions { warnings_as_errors: true, ..CheckOptions::default() };
+        assert!(!check(source.as_bytes(), options).is_ok());
+    }
+
+    #[test]
+    fn test_exit_code() {
+        fn diagnostic(severity: Severity) -> Diagnostic {
+            let message = match severity {
+                Severity::Note => DiagnosticMessage::Note(Note::Latin1Fallback),
+                Severity::Warning => DiagnosticMessage::Warning(Warning::AdjacentStringLiterals),
+                Severity::Error => DiagnosticMessage::Error(Error::InvalidChar('\u{7F}')),
+                Severity::Fatal => DiagnosticMessage::Error(Error::InputFailure(
+                    std::sync::Arc::new(std::io::Error::other("disk on fire")))),
+            };
+            Diagnostic::new(Range::point(Location::new()), message)
+        }
+        fn lex_error(invalid_utf8: bool) -> LexError {
+            let invalid_utf8 = Some(Utf8Error { offset: 0, bytes: vec![0xFF] }).filter(|_| invalid_utf8);
+            LexError { expected: Vec::new(), unexpected: None, location: Location::new(), invalid_utf8 }
+        }
+        fn code(severities: &[Severity], lex: Option<bool>, werror: bool) -> i32 {
+            let options = CheckOptions { warnings_as_errors: werror, ..CheckOptions::default() };
+            let report = CheckReport::new(
+                severities.iter().copied().map(diagnostic), lex.map(lex_error), &options);
+            let code = exit_code(&report, &options);
+            assert_eq!(report.is_ok(), code == EXIT_CLEAN || code == EXIT_WARNINGS);
+            code
+        }
+        {
+            use Severity::*;
+            for werror in [false, true] {
+                let warnings = if werror { EXIT_ERRORS } else { EXIT_WARNINGS };
+                assert_eq!(code(&[], None, werror), EXIT_CLEAN);
+                assert_eq!(code(&[Note], None, werror), EXIT_CLEAN);
+                assert_eq!(code(&[Warning], None, werror), warnings);
+                assert_eq!(code(&[Note, Warning], None, werror), warnings);
+                assert_eq!(code(&[Error], None, werror), EXIT_ERRORS);
+                assert_eq!(code(&[Error, Warning], None, werror), EXIT_ERRORS);
+                assert_eq!(code(&[Fatal], None, werror), EXIT_FATAL);
+                assert_eq!(code(&[Fatal, Error, Warning, Note], None, werror), EXIT_FATAL);
+                // the lexical error counts as an error, or a fatal one at invalid UTF-8.
+                assert_eq!(code(&[], Some(false), werror), EXIT_ERRORS);
+                assert_eq!(code(&[Warning], Some(false), werror), EXIT_ERRORS);
+                assert_eq!(code(&[Fatal], Some(false), werror), EXIT_FATAL);
+                assert_eq!(code(&[], Some(true), werror), EXIT_FATAL);
+                assert_eq!(code(&[Note], Some(true), werror), EXIT_FATAL);
+            }
+        }
+    }
+
+    #[test]
+    fn test_min_severity_to_report() {
+        let source = "main = do\n\tpure ()\n{- oops";
+        let all = check(source.as_bytes(), CheckOptions::default());
+        let options = CheckOptions { min_severity_to_report: Severity::Error, ..CheckOptions::default() };
+        let errors = check(source.as_bytes(), options);
+        assert_eq!((all.count(Severity::Warning), all.count(Severity::Error)), (1, 1));
+        assert_eq!((errors.count(Severity::Warning), errors.count(Severity::Error)), (0, 1));
+        // the exit code is the same, whatever is reported.
+        assert_eq!(exit_code(&all, &options), EXIT_ERRORS);
+        assert_eq!(exit_code(&errors, &options), EXIT_ERRORS);
+        let options = CheckOptions { min_severity_to_report: Severity::Fatal, ..options };
+        let report = check("main = do\n\tpure ()\n".as_bytes(), options);
+        assert!(report.diagnostics.is_empty());
+        assert_eq!(report.worst(), Some(Severity::Warning));
+        assert_eq!(exit_code(&report, &options), EXIT_WARNINGS);
+    }
+
+    #[test]
+    fn test_no_error_budget() {
+        use crate::scanner::ScannerConfig;
+        // the first error is replaced by the note, and the scanner stops there.
+        let options = CheckOptions {
+            scanner: ScannerConfig { max_errors: 0, ..ScannerConfig::default() },
+            ..CheckOptions::default()
+        };
+        let report = check("x = '\u{7}'\ny = 1\n".as_bytes(), options);
+        assert_eq!((report.count(Severity::Error), report.count(Severity::Note)), (0, 1));
+        assert!(matches!(report.diagnostics[0].message(), DiagnosticMessage::Note(Note::TooManyErrors(0))));
+        assert_eq!(report.worst(), Some(Severity::Error));
+        assert!(!report.is_ok());
+        assert_eq!(exit_code(&report, &options), EXIT_ERRORS);
+        // nothing dropped, nothing noted.
+        assert_eq!(exit_code(&check("x = 1\n".as_bytes(), options), &options), EXIT_CLEAN);
+    }
+}
@@ -18,23 +18,32 @@
 
 //! error reporting for the mini-Haskell compiler.
 
+pub mod render;
+
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::fmt::{Display, Formatter};
 use num_bigint::BigInt;
 use crate::lexeme::LexemeType;
-use crate::scanner::{LexError, Location, Range};
+use crate::scanner::{LexError, Location, Range, Scanner};
+use crate::scanner::layout::LayoutError;
+use crate::source::FileId;
 
 /// An exhaustive list of compiler errors.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Error {
     /// An invalid UTF-8 sequence.
     InvalidUTF8(Vec<u8>),
-    /// A failure during the input process.
-    InputFailure(std::io::Error),
+    /// A failure during the input process, the I/O error shared by the clones.
+    InputFailure(Arc<std::io::Error>),
     /// A Unicode character not accepted by the Haskell language.
     InvalidChar(char),
     /// An error during the tokenization process.
     InvalidToken(LexError),
-    /// A lexeme ended prematurely, e.g. EOF in a block comment.
-    IncompleteLexeme(LexemeType),
+    /// A lexeme (or a part of it) ended prematurely, e.g. EOF in a block comment.
+    IncompleteLexeme(Construct),
     /// A float literal is too large (or small) to represent.
     ///
     /// **Note**:
@@ -47,44 +56,696 @@ pub enum Error {
     FloatOutOfBound(BigInt),
     /// A character/string literal contains a Unicode character out of bound.
     CharOutOfBound(BigInt),
+    /// A character/string literal contains a raw character not allowed there (e.g. a control
+    /// character), replaced by U+FFFD.
+    InvalidCharInString(char),
+    /// A tab in the indentation of a line, rejected in the strict layout mode.
+    TabInIndentation(TabInIndentation),
+    /// A lexeme longer than the scanner limit (in characters), its payload truncated.
+    TokenTooLong(LexemeType, usize),
+    /// A non-ASCII whitespace, e.g. U+00A0 NO-BREAK SPACE, counted as one column.
+    ConfusableWhitespace(char),
+    /// A mismatched explicit curly bracket in the layout algorithm.
+    Layout(LayoutError),
+    /// A C0 control character other than a whitespace, e.g. NUL, skipped; within a literal
+    /// (of the lexeme type given), replaced by U+FFFD.
+    ControlChar(char, Option<LexemeType>),
+    /// A repeated rule (e.g. in [`Scanner::many`](crate::scanner::Scanner::many)) succeeded
+    /// without consuming any input, and the repetition was stopped: a bug in the scanner.
+    NonProgressingRule,
+    /// An underscore in a numeric literal under `NumericUnderscores`, but not between digits,
+    /// e.g. right after `0x` or at the end.
+    MisplacedDigitSeparator,
+    /// A bidirectional control character, under [`BidiPolicy::Error`](crate::scanner::BidiPolicy),
+    /// kept as is: the same as [`Warning::BidiControl`] otherwise.
+    BidiControl(char),
+    /// A backslash in a character/string literal followed by no valid escape, e.g. `\q`: the
+    /// backslash and the character after it are replaced by U+FFFD, and the literal goes on.
+    UnknownEscape(char),
+    /// A numeric escape without its digits, `\x` or `\o` (the character given): replaced by
+    /// U+FFFD, and the literal goes on, e.g. with the `g` of `\xg`.
+    MissingEscapeDigits(char),
+}
+
+/// Lexical constructs for error messages, finer than lexeme types.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Construct {
+    /// A whole lexeme.
+    Lexeme(LexemeType),
+    /// A block comment: `{- ... -}`, inside whitespaces.
+    BlockComment,
+    /// A string gap: `\ ... \`, inside a string literal.
+    StringGap,
+    /// An escape sequence, i.e. a backslash followed by no graphic character: e.g. by a
+    /// control character, or by a space in a character literal.
+    Escape,
+}
+
+impl Construct {
+    /// A human-readable description, for error messages.
+    pub fn describe(self) -> &'static str {
+        match self {
+            Construct::Lexeme(t) => t.describe(),
+            Construct::BlockComment => "block comment",
+            Construct::StringGap => "string gap",
+            Construct::Escape => "escape sequence",
+        }
+    }
+}
+
+/// An exhaustive list of compiler warnings.
+#[derive(Clone, Debug)]
+pub enum Warning {
+    /// A tab in the indentation of a line.
+    TabInIndentation(TabInIndentation),
+    /// Two string literals next to each other, probably missing a `++` or `<>` in between.
+    AdjacentStringLiterals,
+    /// An identifier written with other code points than an earlier one of the same Unicode
+    /// normalization (NFC), so that the two look the same but are different names.
+    IdentifierNormalization(String),
+    /// A bidirectional control character, anywhere in the source: see
+    /// [`BidiPolicy`](crate::scanner::BidiPolicy).
+    BidiControl(char),
+}
+
+/// An exhaustive list of compiler notes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Note {
+    /// Invalid UTF-8 in the input decoded as Latin-1, reported once for the first occurrence.
+    Latin1Fallback,
+    /// More errors than the budget of the [`DiagnosticsEngine`], the further ones suppressed.
+    TooManyErrors(usize),
+    /// Most of the first lines begin with a `>` at column 1, as the bird tracks of literate
+    /// Haskell, which is lexed as plain Haskell here: reported once, at the first of them.
+    BirdTracks,
+}
+
+/// A tab in the whitespace before the first token of a line, making its column depend on
+/// the tab stop assumption.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TabInIndentation {
+    /// the column of the first token, with 8-column tab stops.
+    pub column: usize,
+    /// the column of the first token, if tabs were counted as one column.
+    pub naive_column: usize,
+}
+
+impl Error {
+    /// The stable code of this error, for looking it up in the documentation.
+    pub fn code(&self) -> &'static str {
+        use Error::*;
+        match self {
+            InvalidUTF8(_) => "L0001",
+            InvalidChar(_) => "L0002",
+            IncompleteLexeme(Construct::StringGap) => "L0010",
+            IncompleteLexeme(_) => "L0003",
+            FloatOutOfBound(_) => "L0004",
+            CharOutOfBound(_) => "L0005",
+            InputFailure(_) => "L0006",
+            InvalidToken(_) => "L0007",
+            TabInIndentation(_) => "L0008",
+            TokenTooLong(..) => "L0009",
+            InvalidCharInString(_) => "L0011",
+            ConfusableWhitespace(_) => "L0012",
+            Layout(_) => "L0013",
+            ControlChar(..) => "L0018",
+            NonProgressingRule => "L0019",
+            MisplacedDigitSeparator => "L0021",
+            BidiControl(_) => "L0022",
+            UnknownEscape(_) => "L0023",
+            MissingEscapeDigits(_) => "L0024",
+        }
+    }
+}
+
+impl Warning {
+    /// The stable code of this warning, shared with the error for the same problem.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Warning::TabInIndentation(_) => "L0008",
+            Warning::AdjacentStringLiterals => "L0015",
+            Warning::IdentifierNormalization(_) => "L0016",
+            Warning::BidiControl(_) => "L0022",
+        }
+    }
+}
+
+impl Note {
+    /// The stable code of this note.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Note::Latin1Fallback => "L0014",
+            Note::TooManyErrors(_) => "L0017",
+            Note::BirdTracks => "L0020",
+        }
+    }
+}
+
+/// Severity of a diagnostic, from the least severe.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub enum Severity {
+    /// Remarks on how the input is understood.
+    Note,
+    /// Warnings.
+    Warning,
+    /// Critical errors.
+    Error,
+    /// Errors about the input itself rather than the source, e.g. I/O failures: shown as errors.
+    Fatal,
 }
 
 /// A diagnostic message (body).
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum DiagnosticMessage {
     /// Critical errors.
     Error(Error),
+    /// Warnings.
+    Warning(Warning),
+    /// Notes.
+    Note(Note),
 }
 
-/// A diagnostic, with a source location, and an optional source range.
-#[derive(Debug)]
+impl DiagnosticMessage {
+    /// The stable code of this message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            DiagnosticMessage::Error(err) => err.code(),
+            DiagnosticMessage::Warning(warn) => warn.code(),
+            DiagnosticMessage::Note(note) => note.code(),
+        }
+    }
+
+    /// The severity of this message.
+    pub fn severity(&self) -> Severity {
+        match self {
+            DiagnosticMessage::Error(Error::InputFailure(_)) => Severity::Fatal,
+            DiagnosticMessage::Error(_) => Severity::Error,
+            DiagnosticMessage::Warning(_) => Severity::Warning,
+            DiagnosticMessage::Note(_) => Severity::Note,
+        }
+    }
+}
+
+/// A secondary source range of a diagnostic, with an explanation.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Label {
+    /// the source range this label points to.
+    pub range: Range,
+    /// what is there.
+    pub message: String,
+}
+
+/// A diagnostic, with a primary source range, and some labelled secondary ranges.
+#[derive(Clone, Debug)]
 pub struct Diagnostic {
-    location: Location,
-    range: Option<Range>,
+    range: Range,
     message: DiagnosticMessage,
+    labels: Vec<Label>,
+    file: FileId,
 }
 
-impl Diagnostic {
-    /// Create a new diagnostics.
-    pub fn new(location: Location, message: DiagnosticMessage) -> Diagnostic {
-        Diagnostic { location, message, range: None }
+/// Display an integer in user-facing text, with only its first and last digits if too long:
+/// literals may have thousands of them.
+///
+/// ```
+/// # use mini_haskell::error::BoundedInt;
+/// # use num_bigint::BigInt;
+/// let n: BigInt = "-1234567890123456789012345678901234567890123".parse().unwrap();
+/// assert_eq!(BoundedInt(&n).to_string(), "-1234567890…4567890123 (43 digits)");
+/// ```
+pub struct BoundedInt<'a>(pub &'a BigInt);
+
+impl BoundedInt<'_> {
+    /// Integers of at most so many digits are displayed in full.
+    pub const MAX_DIGITS: usize = 40;
+    /// The number of digits shown on each end of a longer integer.
+    pub const SHOWN_DIGITS: usize = 10;
+}
+
+impl Display for BoundedInt<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let s = self.0.to_string();
+        let digits = s.trim_start_matches('-');
+        if digits.len() <= Self::MAX_DIGITS { return f.write_str(&s); }
+        let sign = &s[..s.len() - digits.len()];
+        let (head, tail) = (&digits[..Self::SHOWN_DIGITS], &digits[digits.len() - Self::SHOWN_DIGITS..]);
+        write!(f, "{}{}…{} ({} digits)", sign, head, tail, digits.len())
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        use Error::*;
+        match self {
+            InvalidUTF8(bytes) => write!(f, "invalid UTF-8 sequence {:02X?}", bytes),
+            InputFailure(err) => write!(f, "input failure: {}", err),
+            InvalidChar(c) => write!(f, "invalid character {:?}", c),
+            InvalidToken(err) => write!(f, "invalid token: {}", err),
+            IncompleteLexeme(c) => write!(f, "unterminated {}", c.describe()),
+            FloatOutOfBound(exp) =>
+                write!(f, "float literal out of bound (exponent {})", BoundedInt(exp)),
+            CharOutOfBound(c) => write!(f, "character literal out of bound ({})", BoundedInt(c)),
+            InvalidCharInString(c) =>
+                write!(f, "character {:?} not allowed raw in a literal, use an escape", c),
+            TabInIndentation(tab) => write!(f, "{}", tab),
+            TokenTooLong(t, n) =>
+                write!(f, "{} too long ({} characters), truncated", t.describe(), n),
+            ConfusableWhitespace(c) =>
+                write!(f, "non-ASCII whitespace U+{:04X} counted as one column, use spaces", *c as u32),
+            Layout(err) => write!(f, "{}", err),
+            ControlChar(c, None) =>
+                write!(f, "control character '\\x{:02X}' not allowed in source", *c as u32),
+            ControlChar(c, Some(t)) => write!(
+                f, "control character '\\x{:02X}' not allowed raw in a {}, use an escape",
+                *c as u32, t.describe()),
+            NonProgressingRule =>
+                write!(f, "internal error: a repeated rule succeeded without consuming input"),
+            MisplacedDigitSeparator =>
+                write!(f, "misplaced digit separator '_', only allowed between digits"),
+            BidiControl(c) => write_bidi_control(f, *c),
+            UnknownEscape(c) => write!(f, "unknown escape sequence `\\{}`", c),
+            MissingEscapeDigits(c) => write!(f, "escape sequence `\\{}` without {} digits", c,
+                                             if *c == 'x' { "hexadecimal" } else { "octal" }),
+        }
+    }
+}
+
+// shared by the error and the warning.
+fn write_bidi_control(f: &mut Formatter<'_>, c: char) -> std::fmt::Result {
+    let name = crate::scanner::basic::bidi_control_name(c).unwrap_or("bidirectional control");
+    write!(f, "bidirectional control character U+{:04X} {}: the source may display differently \
+               from how it is read", c as u32, name)
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::TabInIndentation(tab) => write!(f, "{}", tab),
+            Warning::AdjacentStringLiterals =>
+                write!(f, "adjacent string literals, missing `++` or `<>`?"),
+            Warning::IdentifierNormalization(name) =>
+                write!(f, "identifier `{}` differs from an earlier one only in Unicode normalization", name),
+            Warning::BidiControl(c) => write_bidi_control(f, *c),
+        }
+    }
+}
+
+impl Display for Note {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Note::Latin1Fallback =>
+                write!(f, "invalid UTF-8 decoded as Latin-1, here and in the rest of the input"),
+            Note::TooManyErrors(max) =>
+                write!(f, "too many errors (more than {}); further errors suppressed", max),
+            Note::BirdTracks => write!(f, "this looks like literate Haskell (bird tracks), \
+                                           which is not supported: remove the `>` marks first"),
+        }
+    }
+}
+
+impl Display for TabInIndentation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "tab in indentation: the first token is at column {} with {}-column tab stops, \
+                   but at column {} if tabs count as one column",
+               self.column, Location::TAB_SIZE, self.naive_column)
+    }
+}
+
+impl Display for DiagnosticMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiagnosticMessage::Error(err) => write!(f, "error: {}", err),
+            DiagnosticMessage::Warning(warn) => write!(f, "warning: {}", warn),
+            DiagnosticMessage::Note(note) => write!(f, "note: {}", note),
+        }
     }
+}
 
-    /// Add a source range to the report.
-    pub fn within_range(self, range: Range) -> Self {
-        Self { range: Some(range), ..self }
+impl Diagnostic {
+    /// Create a new diagnostics for a source range; use [`Range::point`] for a location.
+    pub fn new(range: Range, message: DiagnosticMessage) -> Diagnostic {
+        Diagnostic { range, message, labels: Vec::new(), file: FileId::ANONYMOUS }
     }
 
-    /// Add a source range from a `[begin, end)` pair to the report.
-    pub fn within(self, begin: Location, end: Location) -> Self {
-        Self { range: Some(Range { begin, end }), ..self }
+    /// Stamp this diagnostic with the file it is about.
+    pub fn with_file(self, file: FileId) -> Diagnostic { Diagnostic { file, ..self } }
+
+    /// Attach a secondary range to this diagnostic.
+    pub fn with_label(mut self, range: Range, message: impl Into<String>) -> Diagnostic {
+        self.labels.push(Label { range, message: message.into() });
+        self
     }
 
     /// Report to the diagnostics engine.
     pub fn report(self, engine: &mut DiagnosticsEngine) {
         engine.push(self)
     }
+
+    /// The source location of this diagnostic, i.e. where its range begins.
+    pub fn location(&self) -> Location { self.range.begin }
+
+    /// The source range of this diagnostic.
+    pub fn range(&self) -> Range { self.range }
+
+    /// The message body of this diagnostic.
+    pub fn message(&self) -> &DiagnosticMessage { &self.message }
+
+    /// The secondary ranges of this diagnostic.
+    pub fn labels(&self) -> &[Label] { &self.labels }
+
+    /// The file of this diagnostic, [`FileId::ANONYMOUS`] unless stamped.
+    pub fn file(&self) -> FileId { self.file }
+
+    /// The stable code of this diagnostic.
+    pub fn code(&self) -> &'static str { self.message.code() }
+
+    /// The severity of this diagnostic.
+    pub fn severity(&self) -> Severity { self.message.severity() }
+
+    pub(crate) fn is_input_error(&self) -> bool {
+        matches!(self.message,
+                 DiagnosticMessage::Error(Error::InvalidUTF8(_) | Error::InputFailure(_))
+                 | DiagnosticMessage::Note(Note::Latin1Fallback))
+    }
+
+    fn is_error(&self) -> bool { self.severity() >= Severity::Error }
+
+    /// The severity of this diagnostic for deciding whether the input is fine: a
+    /// [`Note::TooManyErrors`] stands for the errors dropped past the budget, so it counts as
+    /// an error, even with a budget of 0 where no error is left at all.
+    pub fn effective_severity(&self) -> Severity {
+        match self.message {
+            DiagnosticMessage::Note(Note::TooManyErrors(_)) => Severity::Error,
+            _ => self.severity(),
+        }
+    }
+
+    // the diagnostics engine keeps the diagnostics in this order.
+    fn key(&self) -> (FileId, Location) { (self.file, self.location()) }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.range, self.message)
+    }
 }
 
 /// The diagnostics engine.
-pub type DiagnosticsEngine = Vec<Diagnostic>;
+///
+/// Diagnostics are kept in non-decreasing order of their source locations. Since the scanner
+/// rarely reports a diagnostic before one it has already reported, this is almost always a
+/// plain push to the back. What is reported in a failed attempt is discarded by
+/// [`revert`](DiagnosticsEngine::revert), wherever it was inserted.
+///
+/// Errors are recorded up to a budget: past it, a single [`Note::TooManyErrors`] is recorded
+/// instead, the further errors are dropped, and the scanner stops as if at the end of input.
+///
+/// An engine stamps what it is reported with its [`file`](DiagnosticsEngine::set_file), so
+/// that scanners of several files can report to one engine, one after another: the
+/// diagnostics are then in order of their files first. See [`SharedDiagnostics`] for that.
+#[derive(Clone, Debug)]
+pub struct DiagnosticsEngine {
+    // with the generation each was reported in, for reverting.
+    diagnostics: VecDeque<(u64, Diagnostic)>,
+    // the generation of the next diagnostic reported.
+    generation: u64,
+    // errors recorded so far, drained or not, but not those reverted.
+    errors: usize,
+    max_errors: usize,
+    exceeded: bool,
+    file: FileId,
+}
+
+/// A point in the history of a [`DiagnosticsEngine`], to [`revert`](DiagnosticsEngine::revert)
+/// to: see [`checkpoint`](DiagnosticsEngine::checkpoint).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Checkpoint(u64);
+
+impl Default for DiagnosticsEngine {
+    fn default() -> Self {
+        DiagnosticsEngine {
+            diagnostics: VecDeque::new(),
+            generation: 0,
+            errors: 0,
+            max_errors: Self::DEFAULT_MAX_ERRORS,
+            exceeded: false,
+            file: FileId::default(),
+        }
+    }
+}
+
+impl DiagnosticsEngine {
+    /// Default for [`set_max_errors`](DiagnosticsEngine::set_max_errors): 1000.
+    pub const DEFAULT_MAX_ERRORS: usize = 1000;
+
+    /// Create an empty diagnostics engine.
+    pub fn new() -> Self { Self::default() }
+
+    /// Stamp the diagnostics reported from now on with `file`, unless already stamped.
+    pub fn set_file(&mut self, file: FileId) { self.file = file }
+
+    /// The file the diagnostics reported are stamped with.
+    pub fn file(&self) -> FileId { self.file }
+
+    /// Record at most `max` errors.
+    pub fn set_max_errors(&mut self, max: usize) { self.max_errors = max }
+
+    /// Check whether more errors than the budget are reported, see [`Note::TooManyErrors`].
+    pub fn budget_exceeded(&self) -> bool { self.exceeded }
+
+    /// Add a diagnostic to the engine, keeping the diagnostics ordered.
+    pub fn push(&mut self, mut diagnostic: Diagnostic) {
+        if diagnostic.file.is_anonymous() { diagnostic.file = self.file }
+        let diagnostic = if !diagnostic.is_error() {
+            diagnostic
+        } else if self.errors < self.max_errors {
+            self.errors += 1;
+            diagnostic
+        } else if !self.exceeded {
+            self.exceeded = true;
+            Diagnostic::new(diagnostic.range, DiagnosticMessage::Note(Note::TooManyErrors(self.max_errors)))
+                .with_file(diagnostic.file)
+        } else {
+            return;
+        };
+        let key = diagnostic.key();
+        let n = self.diagnostics.partition_point(|(_, d)| d.key() <= key);
+        self.diagnostics.insert(n, (self.generation, diagnostic));
+        self.generation += 1
+    }
+
+    /// Number of diagnostics not yet drained.
+    pub fn len(&self) -> usize { self.diagnostics.len() }
+
+    /// Check whether there are no diagnostics pending.
+    pub fn is_empty(&self) -> bool { self.diagnostics.is_empty() }
+
+    /// Keep only the first `n` diagnostics.
+    pub fn truncate(&mut self, n: usize) {
+        // saturating: those of other engines are here too while lent, see `SharedDiagnostics`.
+        let dropped = self.diagnostics.iter().skip(n).filter(|(_, d)| d.is_error()).count();
+        self.errors = self.errors.saturating_sub(dropped);
+        self.diagnostics.truncate(n)
+    }
+
+    /// The current point in the history of this engine, for [`revert`].
+    ///
+    /// [`revert`]: DiagnosticsEngine::revert
+    pub fn checkpoint(&self) -> Checkpoint { Checkpoint(self.generation) }
+
+    /// Drop the diagnostics reported since `checkpoint`, used for reverting failed attempts.
+    /// Those reported before are kept in place, even if some reported since were inserted
+    /// before them, out of order.
+    ///
+    /// Diagnostics about the input itself (invalid UTF-8, input failures) are kept: the input
+    /// is decoded only once, so these would never be reported again.
+    /// So is the [`Note::TooManyErrors`]: the scanner stops anyway.
+    pub fn revert(&mut self, checkpoint: Checkpoint) {
+        let errors = &mut self.errors;
+        self.diagnostics.retain(|(generation, d)| {
+            if *generation < checkpoint.0 || d.is_input_error()
+                || matches!(d.message, DiagnosticMessage::Note(Note::TooManyErrors(_))) {
+                return true;
+            }
+            if d.is_error() { *errors -= 1 }
+            false
+        })
+    }
+
+    /// Iterate through all the pending diagnostics.
+    pub fn iter(&self) -> impl Iterator<Item=&Diagnostic> { self.diagnostics.iter().map(|(_, d)| d) }
+
+    /// Peek the first pending diagnostic.
+    pub fn peek(&self) -> Option<&Diagnostic> { self.diagnostics.front().map(|(_, d)| d) }
+
+    // whether all the diagnostics reported since `checkpoint` satisfy `f`.
+    pub(crate) fn all_since(&self, checkpoint: Checkpoint, mut f: impl FnMut(&Diagnostic) -> bool) -> bool {
+        self.diagnostics.iter().all(|(generation, d)| *generation < checkpoint.0 || f(d))
+    }
+
+    // exchange the diagnostics (and their history) with `other`, keeping the budget and the file.
+    fn swap_storage(&mut self, other: &mut DiagnosticsEngine) {
+        std::mem::swap(&mut self.diagnostics, &mut other.diagnostics);
+        std::mem::swap(&mut self.generation, &mut other.generation);
+    }
+
+    /// Take the first pending diagnostic.
+    pub fn pop(&mut self) -> Option<Diagnostic> { self.diagnostics.pop_front().map(|(_, d)| d) }
+
+    /// Take the first pending diagnostic, if it is located strictly before `location`
+    /// in the file of this engine.
+    pub fn pop_before(&mut self, location: Location) -> Option<Diagnostic> {
+        let before = self.diagnostics.front()?.1.key() < (self.file, location);
+        if before { self.pop() } else { None }
+    }
+
+    /// Take all pending diagnostics located strictly before `location` in the file of this
+    /// engine, in source order.
+    pub fn drain_before(&mut self, location: Location) -> impl Iterator<Item=Diagnostic> + '_ {
+        let n = self.diagnostics.partition_point(|(_, d)| d.key() < (self.file, location));
+        self.diagnostics.drain(..n).map(|(_, d)| d)
+    }
+}
+
+impl IntoIterator for DiagnosticsEngine {
+    type Item = Diagnostic;
+    type IntoIter = std::iter::Map<std::collections::vec_deque::IntoIter<(u64, Diagnostic)>,
+                                   fn((u64, Diagnostic)) -> Diagnostic>;
+    fn into_iter(self) -> Self::IntoIter { self.diagnostics.into_iter().map(|(_, d)| d) }
+}
+
+impl<'a> IntoIterator for &'a DiagnosticsEngine {
+    type Item = &'a Diagnostic;
+    type IntoIter = std::iter::Map<std::collections::vec_deque::Iter<'a, (u64, Diagnostic)>,
+                                   fn(&'a (u64, Diagnostic)) -> &'a Diagnostic>;
+    fn into_iter(self) -> Self::IntoIter { self.diagnostics.iter().map(|(_, d)| d) }
+}
+
+/// A [`DiagnosticsEngine`] with several handles, for scanners reporting to the same
+/// diagnostics, e.g. a scanner and its clone in a speculative attempt, or the scanners of
+/// several files: cloning it gives another handle.
+///
+/// A scanner has an engine of its own: [`lend`](SharedDiagnostics::lend) puts the diagnostics
+/// of the shared one in its place for a while, so that only one scanner reports to them at a
+/// time, and a [`revert`](DiagnosticsEngine::revert) never drops what another one reported.
+#[derive(Clone, Debug, Default)]
+pub struct SharedDiagnostics(Rc<RefCell<DiagnosticsEngine>>);
+
+impl SharedDiagnostics {
+    /// Share `engine`.
+    pub fn new(engine: DiagnosticsEngine) -> Self { SharedDiagnostics(Rc::new(RefCell::new(engine))) }
+
+    /// The shared engine.
+    ///
+    /// # Panics
+    /// If lent, see [`lend`](SharedDiagnostics::lend).
+    pub fn borrow(&self) -> Ref<'_, DiagnosticsEngine> { self.0.borrow() }
+
+    /// The shared engine, mutable.
+    ///
+    /// # Panics
+    /// If lent or borrowed, see [`lend`](SharedDiagnostics::lend).
+    pub fn borrow_mut(&self) -> RefMut<'_, DiagnosticsEngine> { self.0.borrow_mut() }
+
+    /// Check whether there is another handle on this engine.
+    pub fn is_shared(&self) -> bool { Rc::strong_count(&self.0) > 1 }
+
+    /// Run `f` on `scanner`, with the diagnostics of the shared engine in place of its own:
+    /// what `scanner` reports meanwhile is stamped with its file still, and counts against its
+    /// own error budget ([`ScannerConfig::max_errors`](crate::scanner::ScannerConfig::max_errors)),
+    /// never against the one of the shared engine. The diagnostics are put back even if `f`
+    /// panics.
+    ///
+    /// # Panics
+    /// If lent or borrowed already: the shared engine is in use elsewhere.
+    pub fn lend<I, R>(&self, scanner: &mut Scanner<I>, f: impl FnOnce(&mut Scanner<I>) -> R) -> R {
+        // puts the diagnostics back when dropped, on unwinding as well.
+        struct Lent<'a, 'b, I> {
+            shared: RefMut<'a, DiagnosticsEngine>,
+            scanner: &'b mut Scanner<I>,
+        }
+        impl<I> Drop for Lent<'_, '_, I> {
+            fn drop(&mut self) { self.shared.swap_storage(self.scanner.diagnostics_mut()) }
+        }
+        let mut lent = Lent { shared: self.0.borrow_mut(), scanner };
+        lent.shared.swap_storage(lent.scanner.diagnostics_mut());
+        f(lent.scanner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BoundedInt, Diagnostic, DiagnosticMessage, DiagnosticsEngine, SharedDiagnostics, Warning};
+    use crate::scanner::{Location, Range, Scanner};
+    use crate::utils::Result3::Success;
+    use crate::source::FileId;
+    use num_bigint::BigInt;
+
+    #[test]
+    fn test_bounded_int() {
+        let show = |s: &str| BoundedInt(&s.parse::<BigInt>().unwrap()).to_string();
+        let full = "9".repeat(BoundedInt::MAX_DIGITS);
+        assert_eq!(show(&full), full);
+        assert_eq!(show(&format!("-{}", full)), format!("-{}", full));
+        assert_eq!(show(&format!("1{}2", "0".repeat(BoundedInt::MAX_DIGITS))),
+                   "1000000000…0000000002 (42 digits)");
+        assert_eq!(show("-0"), "0");
+    }
+
+    #[test]
+    fn test_engine_files() {
+        let warning = |line| Diagnostic::new(Range::point(Location::at(line, 1, 0)),
+                                             DiagnosticMessage::Warning(Warning::AdjacentStringLiterals));
+        let mut engine = DiagnosticsEngine::new();
+        for (file, line) in [(1, 5), (2, 1), (1, 3)] {
+            engine.set_file(FileId(file));
+            warning(line).report(&mut engine);
+        }
+        // stamped already: kept as is.
+        engine.set_file(FileId(2));
+        warning(2).with_file(FileId(7)).report(&mut engine);
+        let all: Vec<_> = engine.iter().map(|d| (d.file().0, d.location().line)).collect();
+        assert_eq!(all, [(1, 3), (1, 5), (2, 1), (7, 2)]);
+        assert_eq!(engine.drain_before(Location::at(2, 1, 0)).count(), 3);
+        assert_eq!(engine.len(), 1);
+        // the scanners of several files, one after another.
+        let shared = SharedDiagnostics::default();
+        for (file, input) in [(3, "x = '\u{7}'"), (2, "'\u{7}'")] {
+            let mut scanner = Scanner::new(input.as_bytes()).with_file(FileId(file));
+            shared.lend(&mut scanner, |s| {
+                while let (Success(_), Success(_)) = (s.skip_trivia(), s.next_lexeme()) {}
+            });
+            assert!(scanner.diagnostics().is_empty());
+        }
+        let all: Vec<_> = shared.borrow().iter().map(|d| (d.file().0, d.location().column)).collect();
+        assert_eq!(all, [(2, 2), (3, 6)]);
+        assert_eq!(shared.borrow().file(), FileId::ANONYMOUS);
+    }
+
+    #[test]
+    fn test_lend() {
+        use crate::scanner::ScannerConfig;
+        let lex = |s: &mut Scanner<&[u8]>| {
+            while let (Success(_), Success(_)) = (s.skip_trivia(), s.next_lexeme()) {}
+        };
+        // the budget of the scanner applies, not the one of the shared engine.
+        let mut shared_engine = DiagnosticsEngine::new();
+        shared_engine.set_max_errors(0);
+        let shared = SharedDiagnostics::new(shared_engine);
+        let config = ScannerConfig { max_errors: 1, ..ScannerConfig::default() };
+        let mut scanner = Scanner::new("'\u{7}' '\u{7}'".as_bytes()).with_config(config);
+        shared.lend(&mut scanner, lex);
+        let reported: Vec<_> = shared.borrow().iter().map(|d| d.to_string()).collect();
+        assert_eq!(reported.len(), 2);
+        assert!(reported[1].contains("too many errors"), "{:?}", reported);
+        assert!(scanner.diagnostics().budget_exceeded() && !shared.borrow().budget_exceeded());
+        // the diagnostics are put back on panics.
+        let mut scanner = Scanner::new("'\u{7}'".as_bytes());
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| shared.lend(&mut scanner, |s| {
+            lex(s);
+            panic!("in the middle of lending")
+        })));
+        assert!(res.is_err());
+        assert_eq!((shared.borrow().len(), scanner.diagnostics().len()), (3, 0));
+    }
+}
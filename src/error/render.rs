@@ -0,0 +1,311 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Rendering diagnostics against the source, in the style of compiler error messages.
+
+use std::fmt::Write;
+use super::{Diagnostic, DiagnosticMessage, Severity};
+use crate::scanner::{Location, SourceMap};
+use crate::location::SourcePos;
+use crate::source::SourceManager;
+use crate::term::{Class, Style};
+#[cfg(feature = "annotate-snippets")]
+use crate::scanner::Range;
+
+// split the source into lines, at the same newlines as the scanner.
+fn lines(source: &str) -> Vec<&str> {
+    let source = source.strip_prefix('\u{FEFF}').unwrap_or(source);
+    let mut res = Vec::new();
+    let mut rest = source;
+    while let Some(k) = rest.find(['\r', '\n', '\u{C}']) {
+        res.push(&rest[..k]);
+        let skip = if rest[k..].starts_with("\r\n") { 2 } else { 1 };
+        rest = &rest[k + skip..];
+    }
+    res.push(rest);
+    res
+}
+
+// expand the tabs, so that the column of a character is its position plus 1.
+fn expand_tabs(line: &str) -> String {
+    let mut res = String::new();
+    let mut location = Location::new();
+    for c in line.chars() {
+        location.step();
+        if c == '\t' {
+            location.tablise();
+            while res.chars().count() + 1 < location.column { res.push(' ') }
+        } else {
+            res.push(c)
+        }
+    }
+    res
+}
+
+impl Severity {
+    fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error | Severity::Fatal => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+}
+
+impl Diagnostic {
+    fn title(&self) -> String {
+        match &self.message {
+            DiagnosticMessage::Error(err) => err.to_string(),
+            DiagnosticMessage::Warning(warn) => warn.to_string(),
+            DiagnosticMessage::Note(note) => note.to_string(),
+        }
+    }
+
+    /// Render this diagnostic against the source it is reported on: the primary range is
+    /// marked with `^`, and the secondary ranges with `-` and their labels.
+    ///
+    /// ```text
+    /// error[L0003]: unterminated block comment
+    ///  --> 1:15
+    ///   |
+    /// 1 |   {- {- -} abc
+    ///   |               ^
+    ///   |   -- comment opened here
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        self.render_styled(source, &Style::plain())
+    }
+
+    /// Render this diagnostic like [`Diagnostic::render`], in colours if `style` says so.
+    pub fn render_styled(&self, source: &str, style: &Style) -> String {
+        self.render_at(source, &self.range.begin, style)
+    }
+
+    /// Render this diagnostic like [`Diagnostic::render`], but point to its logical position
+    /// through the line directives in `map`; the source lines shown are still the physical ones.
+    pub fn render_mapped(&self, source: &str, map: &SourceMap) -> String {
+        self.render_at(source, &map.logical(self.range.begin), &Style::plain())
+    }
+
+    /// Render this diagnostic like [`Diagnostic::render_styled`], pointing to its file by the
+    /// path registered in `files`: diagnostics of no file there are rendered as usual.
+    pub fn render_in(&self, source: &str, files: &SourceManager, style: &Style) -> String {
+        let begin = self.range.begin;
+        let file = files.path(self.file).map(|path| path.display().to_string().into());
+        self.render_at(source, &SourcePos { file, line: begin.line, column: begin.column }, style)
+    }
+
+    fn render_at(&self, source: &str, position: &dyn std::fmt::Display, style: &Style) -> String {
+        let lines = lines(source);
+        let severity = Class::Severity(self.severity());
+        let mut marks = vec![(self.range, '^', severity, None)];
+        marks.extend(self.labels.iter().map(|l| (l.range, '-', Class::Label, Some(l.message.as_str()))));
+        // stable: the primary range comes first among those on the same line.
+        marks.sort_by_key(|(range, _, _, _)| range.begin.line);
+        let width = marks.last().map_or(1, |(range, _, _, _)| range.begin.line.to_string().len());
+        let pad = " ".repeat(width);
+        let gutter = style.paint(Class::Gutter, &format!("{} |", pad));
+        let mut res = String::new();
+        let header = format!("{}[{}]", self.severity().as_str(), self.code());
+        let _ = writeln!(res, "{}: {}", style.paint(severity, &header), self.title());
+        let _ = writeln!(res, "{}{} {}", pad, style.paint(Class::Gutter, "-->"), position);
+        let _ = writeln!(res, "{}", gutter);
+        let mut last_line = None;
+        for (range, mark, class, label) in marks {
+            let (begin, end) = (range.begin, range.end);
+            let line = expand_tabs(lines.get(begin.line - 1).copied().unwrap_or(""));
+            if last_line != Some(begin.line) {
+                let number = format!("{:>width$} |", begin.line, width = width);
+                let row = format!("{} {}", style.paint(Class::Gutter, &number), line);
+                let _ = writeln!(res, "{}", row.trim_end());
+                last_line = Some(begin.line);
+            }
+            let end_column = if end.line == begin.line {
+                end.column
+            } else {
+                line.chars().count() + 1
+            };
+            let marks = end_column.saturating_sub(begin.column).max(1);
+            let row = format!("{} {}{} {}", gutter, " ".repeat(begin.column - 1),
+                              style.paint(class, &mark.to_string().repeat(marks)),
+                              style.paint(class, label.unwrap_or("")));
+            let _ = writeln!(res, "{}", row.trim_end());
+        }
+        res
+    }
+}
+
+#[cfg(feature = "annotate-snippets")]
+impl Diagnostic {
+    /// Convert this diagnostic to an [`annotate_snippets::Message`] against its source, and pass
+    /// it to `f`, e.g. to render it with an [`annotate_snippets::Renderer`].
+    pub fn with_snippet<R>(&self, source: &str,
+                           f: impl FnOnce(annotate_snippets::Message<'_>) -> R) -> R {
+        use annotate_snippets::{Level, Snippet};
+        let level = match self.severity() {
+            Severity::Error | Severity::Fatal => Level::Error,
+            Severity::Warning => Level::Warning,
+            Severity::Note => Level::Note,
+        };
+        let title = self.title();
+        let snippet = Snippet::source(source).line_start(1).fold(true)
+            .annotation(level.span(byte_range(source, self.range)))
+            .annotations(self.labels.iter().map(|l|
+                Level::Info.span(byte_range(source, l.range)).label(&l.message)));
+        f(level.title(&title).id(self.code()).snippet(snippet))
+    }
+}
+
+// byte offsets in the source of a range, located the same way as the scanner does.
+#[cfg(feature = "annotate-snippets")]
+fn byte_range(source: &str, range: Range) -> std::ops::Range<usize> {
+    let offset = |target: Location| {
+        let start = if source.starts_with('\u{FEFF}') { '\u{FEFF}'.len_utf8() } else { 0 };
+        let mut location = Location::new();
+        let mut chars = source[start..].char_indices().peekable();
+        while let Some((k, c)) = chars.next() {
+            // in characters, whichever column mode the scanner used.
+            if (location.line, location.char_column) >= (target.line, target.char_column) {
+                return start + k;
+            }
+            location.step();
+            match c {
+                '\r' if chars.peek().map(|(_, c)| *c) == Some('\n') => (),
+                '\r' | '\n' | '\u{C}' => location.newline(),
+                '\t' => location.tablise(),
+                _ => (),
+            }
+        }
+        source.len()
+    };
+    offset(range.begin)..offset(range.end)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scanner::Scanner;
+    use crate::scanner::layout::RawLexemeIterator;
+
+    fn render_all(source: &str) -> String {
+        let mut it = RawLexemeIterator::new(source.as_bytes());
+        it.by_ref().for_each(drop);
+        let (_, scanner) = it.into_scanner();
+        scanner.diagnostics().iter().map(|d| d.render(source)).collect()
+    }
+
+    #[test]
+    fn test_render_block_comment() {
+        let mut scanner = Scanner::new("  {- {- -} abc".as_bytes());
+        let _ = scanner.whitespace();
+        let rendered: Vec<_> = scanner.diagnostics().iter()
+            .map(|d| d.render("  {- {- -} abc")).collect();
+        expect_test::expect![[r#"
+            [
+                "error[L0003]: unterminated block comment\n --> 1:15\n  |\n1 |   {- {- -} abc\n  |               ^\n  |   -- comment opened here\n",
+            ]
+        "#]].assert_debug_eq(&rendered);
+        expect_test::expect![[r#"
+            error[L0003]: unterminated block comment
+             --> 3:15
+              |
+            2 | {- comment
+              | -- comment opened here
+            3 |         - }  x
+              |               ^
+        "#]].assert_eq(&render_all("x = 1\n{- comment\n \t- }  x"));
+    }
+
+    #[test]
+    fn test_render_string_gap() {
+        expect_test::expect![[r#"
+            error[L0010]: unterminated string gap
+              --> 10:6
+               |
+             9 | s = "abc\
+               |         - string gap opened here
+            10 |      def"
+               |      ^
+        "#]].assert_eq(&render_all("\n\n\n\n\n\n\n\ns = \"abc\\\n     def\""));
+    }
+
+    #[test]
+    fn test_render_unterminated_string() {
+        expect_test::expect![[r#"
+            error[L0003]: unterminated string literal
+             --> 1:9
+              |
+            1 | s = "abc
+              |         ^
+              |     - string literal starts here
+        "#]].assert_eq(&render_all("s = \"abc\nt = 1"));
+    }
+
+    #[test]
+    fn test_render_tabs() {
+        use crate::scanner::layout::{LayoutConfig, TabPolicy};
+        let source = "main = do\n  \tprint 42\n";
+        let config = LayoutConfig { tabs_in_indentation: TabPolicy::Warn, ..LayoutConfig::default() };
+        let mut it = RawLexemeIterator::new(source.as_bytes()).with_config(config);
+        it.by_ref().for_each(drop);
+        let (_, scanner) = it.into_scanner();
+        let rendered: String = scanner.diagnostics().iter().map(|d| d.render(source)).collect();
+        expect_test::expect![[r#"
+            warning[L0008]: tab in indentation: the first token is at column 9 with 8-column tab stops, but at column 4 if tabs count as one column
+             --> 2:3
+              |
+            2 |         print 42
+              |   ^^^^^^
+        "#]].assert_eq(&rendered);
+    }
+
+    #[test]
+    fn test_render_styled() {
+        use crate::term::{ColorChoice, Palette, Style};
+        let source = "x = 1\n{- comment\n";
+        let mut it = RawLexemeIterator::new(source.as_bytes());
+        it.by_ref().for_each(drop);
+        let (_, scanner) = it.into_scanner();
+        let diagnostic = scanner.diagnostics().peek().unwrap();
+        let never = Style::decide(ColorChoice::Never, Palette::Default, false, true);
+        assert_eq!(diagnostic.render_styled(source, &never), diagnostic.render(source));
+        let always = Style::decide(ColorChoice::Always, Palette::Default, false, false);
+        expect_test::expect![[r#"
+            "\u{1b}[1;31merror[L0003]\u{1b}[0m: unterminated block comment\n \u{1b}[34m-->\u{1b}[0m 3:1\n\u{1b}[34m  |\u{1b}[0m\n\u{1b}[34m2 |\u{1b}[0m {- comment\n\u{1b}[34m  |\u{1b}[0m \u{1b}[1m--\u{1b}[0m \u{1b}[1mcomment opened here\u{1b}[0m\n\u{1b}[34m3 |\u{1b}[0m\n\u{1b}[34m  |\u{1b}[0m \u{1b}[1;31m^\u{1b}[0m\n"
+        "#]].assert_debug_eq(&diagnostic.render_styled(source, &always));
+    }
+
+    #[cfg(feature = "annotate-snippets")]
+    #[test]
+    fn test_annotate_snippets() {
+        let source = "x = 1\n{- comment\n";
+        let mut it = RawLexemeIterator::new(source.as_bytes());
+        it.by_ref().for_each(drop);
+        let (_, scanner) = it.into_scanner();
+        let diagnostic = scanner.diagnostics().peek().unwrap();
+        let rendered = diagnostic.with_snippet(source, |message|
+            annotate_snippets::Renderer::plain().render(message).to_string());
+        expect_test::expect![[r#"
+            error[L0003]: unterminated block comment
+              |
+            2 | {- comment
+              | --        ^
+              | |
+              | info: comment opened here
+              |"#]].assert_eq(&rendered);
+    }
+}
@@ -0,0 +1,172 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Position queries over a lexed file, e.g. "which token is at line 3, column 7", for tools.
+
+use crate::lexeme::Lexeme;
+use crate::location::{Location, Range};
+
+/// What the queries answer for a position between two tokens, i.e. in whitespaces or comments.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum GapPolicy {
+    /// no token there.
+    #[default]
+    Miss,
+    /// the token right before the gap, if any.
+    Previous,
+    /// the token right after the gap, if any.
+    Next,
+}
+
+/// An index of the tokens in a lexed file, sorted by position, for binary searches.
+///
+/// Tokens are referred to by their index in [`TokenIndex::tokens`]. Queries by a [`Location`]
+/// look at its line and column only; queries by an offset look at the offsets only.
+#[derive(Clone, Debug, Default)]
+pub struct TokenIndex {
+    tokens: Vec<(Lexeme, Range)>,
+    // the end offsets of the tokens, in the same order.
+    ends: Vec<usize>,
+    gaps: GapPolicy,
+}
+
+fn key(location: &Location) -> (usize, usize) { (location.line, location.column) }
+
+impl TokenIndex {
+    /// Build an index of `tokens`, e.g. from a [`FatLexemeIterator`]: they are sorted by their
+    /// beginnings here, and should not overlap.
+    ///
+    /// [`FatLexemeIterator`]: crate::scanner::layout::FatLexemeIterator
+    pub fn build(tokens: &[(Lexeme, Range)]) -> TokenIndex {
+        let mut tokens = tokens.to_vec();
+        tokens.sort_by_key(|(_, r)| r.begin.offset);
+        let ends = tokens.iter().map(|(_, r)| r.end.offset).collect();
+        TokenIndex { tokens, ends, gaps: GapPolicy::Miss }
+    }
+
+    /// Answer the queries for positions between tokens as `gaps` says.
+    pub fn with_gaps(self, gaps: GapPolicy) -> Self { TokenIndex { gaps, ..self } }
+
+    /// All the tokens, sorted.
+    pub fn tokens(&self) -> &[(Lexeme, Range)] { &self.tokens }
+
+    /// The token at index `n`.
+    pub fn get(&self, n: usize) -> Option<&(Lexeme, Range)> { self.tokens.get(n) }
+
+    /// The number of tokens.
+    pub fn len(&self) -> usize { self.tokens.len() }
+
+    /// Whether there is no token at all.
+    pub fn is_empty(&self) -> bool { self.tokens.is_empty() }
+
+    /// The token containing the character at `location`.
+    pub fn token_at(&self, location: Location) -> Option<usize> {
+        // the tokens beginning at or before `location`, the last of which may contain it.
+        let n = self.tokens.partition_point(|(_, r)| key(&r.begin) <= key(&location));
+        let inside = n > 0 && key(&location) < key(&self.tokens[n - 1].1.end);
+        self.choose(n, inside)
+    }
+
+    /// The token containing the character at `offset`.
+    pub fn token_at_offset(&self, offset: usize) -> Option<usize> {
+        let n = self.tokens.partition_point(|(_, r)| r.begin.offset <= offset);
+        let inside = n > 0 && offset < self.ends[n - 1];
+        self.choose(n, inside)
+    }
+
+    /// The tokens overlapping `range`: none for an empty range.
+    pub fn tokens_in_range(&self, range: Range) -> &[(Lexeme, Range)] {
+        if range.end.offset <= range.begin.offset { return &[]; }
+        let begin = self.ends.partition_point(|&end| end <= range.begin.offset);
+        let end = self.tokens.partition_point(|(_, r)| r.begin.offset < range.end.offset);
+        &self.tokens[begin..end]
+    }
+
+    /// The last token ending at or before `location`, regardless of the [`GapPolicy`].
+    pub fn nearest_token_before(&self, location: Location) -> Option<usize> {
+        let n = self.tokens.partition_point(|(_, r)| key(&r.end) <= key(&location));
+        n.checked_sub(1)
+    }
+
+    // `n` tokens begin at or before the position, the last one containing it if `inside`.
+    fn choose(&self, n: usize, inside: bool) -> Option<usize> {
+        match self.gaps {
+            _ if inside => Some(n - 1),
+            GapPolicy::Miss => None,
+            GapPolicy::Previous => n.checked_sub(1),
+            GapPolicy::Next => Some(n).filter(|&n| n < self.tokens.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TokenIndex, GapPolicy};
+    use crate::location::{Location, Range};
+    use crate::scanner::layout::FatLexemeIterator;
+    use crate::utils::LIPSUM;
+
+    const SOURCE: &str = "module Main where\n\n-- | the entry\nmain :: IO ()\nmain = do\n  \
+                          print (x + 1)   {- x -}\n  where x = [1, 2]\n";
+
+    #[test]
+    fn test_every_position() {
+        for source in [LIPSUM, SOURCE] {
+            let tokens: Vec<_> = FatLexemeIterator::new(source.as_bytes()).collect();
+            let index = TokenIndex::build(&tokens);
+            assert_eq!(index.len(), tokens.len());
+            let mut location = Location::new();
+            for (offset, c) in source.chars().enumerate() {
+                assert_eq!(location.offset, offset);
+                let containing = tokens.iter()
+                    .position(|(_, r)| r.begin.offset <= offset && offset < r.end.offset);
+                let before = tokens.iter().rposition(|(_, r)| r.end.offset <= offset);
+                let after = tokens.iter().position(|(_, r)| r.begin.offset > offset);
+                assert_eq!(index.token_at_offset(offset), containing);
+                assert_eq!(index.token_at(location), containing);
+                assert_eq!(index.nearest_token_before(location), before);
+                if let Some(n) = containing {
+                    assert_eq!(index.tokens_in_range(tokens[n].1), &tokens[n..=n]);
+                    let point = Range::point(location);
+                    assert!(index.tokens_in_range(point).is_empty());
+                } else {
+                    let previous = index.clone().with_gaps(GapPolicy::Previous);
+                    let next = index.clone().with_gaps(GapPolicy::Next);
+                    assert_eq!(previous.token_at(location), before);
+                    assert_eq!(previous.token_at_offset(offset), before);
+                    assert_eq!(next.token_at(location), after);
+                    assert_eq!(next.token_at_offset(offset), after);
+                }
+                location.step();
+                if c == '\n' { location.newline() }
+            }
+        }
+    }
+
+    #[test]
+    fn test_tokens_in_range() {
+        let tokens: Vec<_> = FatLexemeIterator::new(SOURCE.as_bytes()).collect();
+        let index = TokenIndex::build(&tokens);
+        // from the middle of `main` to the middle of `IO`.
+        let range = Range { begin: Location::at(4, 2, 35), end: Location::at(4, 10, 43) };
+        let names: Vec<_> = index.tokens_in_range(range).iter().map(|(x, _)| x.to_string()).collect();
+        assert_eq!(names, ["main", "::", "IO"]);
+        assert_eq!(index.tokens_in_range(Range { begin: range.end, end: range.begin }), []);
+        assert_eq!(TokenIndex::default().token_at_offset(0), None);
+    }
+}
@@ -18,13 +18,15 @@
 
 //! Persistent input from a [`std::io::Read`].
 
-use std::cell::UnsafeCell;
-use std::rc::Rc;
+use std::cell::{Cell, RefCell, UnsafeCell};
+use std::rc::{Rc, Weak};
 
 use crate::rc_view::RcView;
 
-const DEFAULT_BUF_SIZE: usize = 4 * 1024;
-const MAXIMUM_RETRY: isize = 5;
+/// The default size (in bytes) of the buffered segments, see [`Input::set_buf_size`].
+pub const DEFAULT_BUF_SIZE: usize = 4 * 1024;
+/// The default number of retries on interrupted reads, see [`Input::set_io_retries`].
+pub const DEFAULT_IO_RETRIES: u8 = 5;
 
 /// A "raw" input.
 /// - segmented, shared, and immutable back buffer
@@ -37,6 +39,91 @@ impl<I> Clone for RawInput<I> {
     fn clone(&self) -> Self { RawInput(self.0.clone()) }
 }
 
+impl<I> Drop for RawInput<I> {
+    fn drop(&mut self) {
+        // unlink the segments no longer shared one by one, instead of recursively.
+        let mut next = self.take_unshared_next();
+        while let Some(mut node) = next {
+            next = node.take_unshared_next();
+        }
+    }
+}
+
+/// Statistics on the buffered segments of an input, shared by all its segments.
+#[derive(Default, Debug)]
+pub struct InputStats {
+    segments: Cell<usize>,
+    peak_live_segments: Cell<usize>,
+    live: RefCell<Vec<Weak<[u8]>>>,
+}
+
+impl InputStats {
+    /// The number of segments buffered so far.
+    pub fn segments(&self) -> usize { self.segments.get() }
+
+    /// The maximum number of segments buffered at the same time.
+    pub fn peak_live_segments(&self) -> usize { self.peak_live_segments.get() }
+
+    fn record(&self, buffer: &Rc<[u8]>) {
+        let mut live = self.live.borrow_mut();
+        live.retain(|b| b.strong_count() > 0);
+        live.push(Rc::downgrade(buffer));
+        self.segments.set(self.segments.get() + 1);
+        self.peak_live_segments.set(self.peak_live_segments.get().max(live.len()));
+    }
+}
+
+/// How to decode the input.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum Encoding {
+    /// UTF-8, invalid sequences are skipped.
+    #[default]
+    Utf8,
+    /// UTF-8, invalid sequences are decoded as Latin-1 (ISO-8859-1), for legacy sources.
+    Latin1,
+}
+
+/// A problem in the input, reported by [`Input::next`] and `Input::match` when read, with the
+/// offset (in bytes) of the problem in the whole input.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum InputProblem<'a> {
+    /// An invalid UTF-8 sequence, skipped.
+    InvalidUtf8(&'a [u8], usize),
+    /// An invalid UTF-8 sequence, decoded as Latin-1.
+    Latin1(&'a [u8], usize),
+}
+
+/// The first invalid UTF-8 sequence in an input, see [`validate_utf8`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Utf8Error {
+    /// the offset (in bytes) of the sequence in the whole input, a BOM included.
+    pub offset: usize,
+    /// the invalid sequence.
+    pub bytes: Vec<u8>,
+}
+
+impl std::fmt::Display for Utf8Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid UTF-8 sequence {:02X?} at byte {}", self.bytes, self.offset)
+    }
+}
+
+impl std::error::Error for Utf8Error {}
+
+// how to read and decode the input, fixed before reading anything.
+#[derive(Copy, Clone)]
+struct ReadOptions {
+    encoding: Encoding,
+    buf_size: usize,
+    io_retries: u8,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        ReadOptions { encoding: Encoding::default(), buf_size: DEFAULT_BUF_SIZE, io_retries: DEFAULT_IO_RETRIES }
+    }
+}
+
 enum InputSegment<I> {
     EndOfFile {
         io_error: Option<std::io::Error>,
@@ -47,11 +134,21 @@ enum InputSegment<I> {
     },
     Invalid {
         data: RcView<[u8], [u8]>,
+        // decoded as Latin-1 and kept in the next segment.
+        latin1: bool,
+        // in bytes, from the start of the input.
+        offset: usize,
         next: RawInput<I>,
     },
     Delayed {
         remaining: Option<RcView<[u8], [u8]>>,
         input: I,
+        // at the very beginning of the input, subject to BOM detection.
+        start: bool,
+        // of the next byte to read from `input`, in bytes from the start of the input.
+        offset: usize,
+        options: ReadOptions,
+        stats: Rc<InputStats>,
     },
 }
 
@@ -59,13 +156,28 @@ impl<I> Default for InputSegment<I> {
     fn default() -> Self { InputSegment::EndOfFile { io_error: None } }
 }
 
-type DelayedContent<I> = (Option<RcView<[u8], [u8]>>, I);
+type DelayedContent<I> = (Option<RcView<[u8], [u8]>>, I, bool, usize, ReadOptions, Rc<InputStats>);
 
 impl<I> InputSegment<I> {
-    fn new(input: I) -> Self {
+    fn new(input: I, offset: usize, options: ReadOptions, stats: Rc<InputStats>) -> Self {
         InputSegment::Delayed {
             remaining: None,
             input,
+            start: false,
+            offset,
+            options,
+            stats,
+        }
+    }
+
+    fn start(input: I, stats: Rc<InputStats>) -> Self {
+        InputSegment::Delayed {
+            remaining: None,
+            input,
+            start: true,
+            offset: 0,
+            options: ReadOptions::default(),
+            stats,
         }
     }
 
@@ -76,7 +188,8 @@ impl<I> InputSegment<I> {
     fn take_delayed(&mut self) -> Option<DelayedContent<I>> {
         match self {
             Self::Delayed { .. } => match std::mem::take(self) {
-                Self::Delayed { remaining, input } => Some((remaining, input)),
+                Self::Delayed { remaining, input, start, offset, options, stats } =>
+                    Some((remaining, input, start, offset, options, stats)),
                 _ => unreachable!(),
             },
             _ => None,
@@ -87,13 +200,31 @@ impl<I> InputSegment<I> {
 impl<I> RawInput<I> {
     /// Create a new [`RawInput`] from a [`std::io::Read`].
     pub fn new(input: I) -> Self {
-        RawInput(Rc::new(UnsafeCell::new(InputSegment::new(input))))
+        Self::with_stats(input, Rc::default())
+    }
+
+    /// Create a new [`RawInput`] from a [`std::io::Read`], recording statistics to `stats`.
+    pub fn with_stats(input: I, stats: Rc<InputStats>) -> Self {
+        RawInput(Rc::new(UnsafeCell::new(InputSegment::start(input, stats))))
     }
 
     fn wrap(segment: InputSegment<I>) -> Self {
         RawInput(Rc::new(UnsafeCell::new(segment)))
     }
 
+    fn take_unshared_next(&mut self) -> Option<RawInput<I>> {
+        match std::mem::take(Rc::get_mut(&mut self.0)?.get_mut()) {
+            InputSegment::Cons { next, .. } | InputSegment::Invalid { next, .. } => Some(next),
+            _ => None,
+        }
+    }
+
+    // the segment of the only reference to it.
+    fn into_segment(mut self) -> InputSegment<I> {
+        let node = Rc::get_mut(&mut self.0).expect("segment shared unexpectedly");
+        std::mem::take(node.get_mut())
+    }
+
     /// Dump out the content of this raw input.
     pub fn dump(&self) {
         let node = unsafe { &mut *self.0.get() };
@@ -104,7 +235,7 @@ impl<I> RawInput<I> {
                 println!("- {:?}", data);
                 next.dump()
             }
-            InputSegment::Invalid { data, next } => {
+            InputSegment::Invalid { data, next, .. } => {
                 println!("- <invalid> {:?}", data);
                 next.dump()
             }
@@ -117,19 +248,27 @@ impl<I: std::io::Read> RawInput<I> {
         let node = unsafe { &mut *self.0.get() };
         let delayed = node.take_delayed();
         if delayed.is_none() { return; }
-        let (remaining, mut input) = delayed.unwrap();
-        let mut buffer = vec![0u8; DEFAULT_BUF_SIZE];
+        let (remaining, mut input, start, offset, options, stats) = delayed.unwrap();
+        // the offset of the start of the buffer, the bytes carried over included.
+        let base = offset - remaining.as_ref().map_or(0, |xs| xs.len());
+        // room for the BOM at the start, and for at least one byte after those carried over.
+        let min_size = if start { 3 } else { remaining.as_ref().map_or(1, |xs| xs.len() + 1) };
+        let buf_size = options.buf_size.max(min_size);
+        let mut buffer = vec![0u8; buf_size];
         let mut to_read = &mut *buffer;
+        // the bytes carried over go in front of the buffer before reading, so that they are
+        // decoded below however the read ends: completed, or reported invalid if no byte follows.
         if let Some(xs) = remaining {
             let n = xs.len();
             let (head, rest) = to_read.split_at_mut(n);
             head.copy_from_slice(&xs);
             to_read = rest;
         }
-        let mut retry = MAXIMUM_RETRY;
+        let mut retry = options.io_retries;
         let tail = loop {
             match input.read(to_read) {
-                Ok(0) if to_read.is_empty() => break InputSegment::new(input),
+                Ok(0) if to_read.is_empty() =>
+                    break InputSegment::new(input, base + buf_size, options, stats.clone()),
                 Ok(0) => break InputSegment::EndOfFile { io_error: None },
                 Ok(n) => to_read = &mut to_read[n..],
                 Err(e) => match e.kind() {
@@ -138,52 +277,130 @@ impl<I: std::io::Read> RawInput<I> {
                 },
             }
         };
-        let n = DEFAULT_BUF_SIZE - to_read.len();
+        let n = buf_size - to_read.len();
+        // strip a UTF-8 BOM, and reject UTF-16 input (instead of many invalid UTF-8 sequences).
+        let skip = match &buffer[..n] {
+            [0xEF, 0xBB, 0xBF, ..] if start => 3,
+            [0xFF, 0xFE, ..] | [0xFE, 0xFF, ..] if start => {
+                *node = InputSegment::EndOfFile {
+                    io_error: Some(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "UTF-16 input is not supported; re-encode as UTF-8")),
+                };
+                return;
+            }
+            _ => 0,
+        };
         let buffer = Rc::<[u8]>::from(buffer);
-        let to_decode = RcView::new(buffer, |b| &b[..n]);
-        *node = Self::decode(to_decode, tail)
+        if n > skip { stats.record(&buffer) }
+        let to_decode = RcView::new(buffer, |b| &b[skip..n]);
+        *node = Self::decode(to_decode, base + skip, tail, options.encoding);
+        // only part of a character in a small buffer: read on, at most 3 more times.
+        if node.is_delayed() { self.prepare() }
     }
 
-    fn decode(to_decode: RcView<[u8], [u8]>, tail: InputSegment<I>) -> InputSegment<I> {
-        let rest = &*to_decode;
-        if rest.is_empty() { return tail; }
-        match std::str::from_utf8(rest) {
-            Ok(s) => InputSegment::Cons {
-                data: unsafe { to_decode.derive(s) },
-                next: RawInput::wrap(tail),
-            },
-            Err(e) => {
-                let n = e.valid_up_to();
-                let (valid, rest) = rest.split_at(n);
-                let tail = match e.error_len() {
-                    None if tail.is_delayed() => match tail {
-                        InputSegment::Delayed { remaining, input } => {
-                            assert!(matches!(remaining, None));
-                            InputSegment::Delayed {
-                                remaining: Some(unsafe { to_decode.derive(rest) }),
-                                input,
-                            }
-                        }
-                        _ => unreachable!("impossible: no remaining input expected here"),
-                    },
-                    _ => {
-                        let k = e.error_len().unwrap_or_else(|| rest.len());
-                        let (invalid, rest) = rest.split_at(k);
-                        InputSegment::Invalid {
-                            data: unsafe { to_decode.derive(invalid) },
-                            next: RawInput::wrap(Self::decode(
-                                unsafe { to_decode.derive(rest) }, tail)),
-                        }
-                    }
-                };
-                if n == 0 { tail } else {
-                    let valid = unsafe { std::str::from_utf8_unchecked(valid) };
-                    InputSegment::Cons {
-                        data: unsafe { to_decode.derive(valid) },
-                        next: RawInput::wrap(tail),
-                    }
+    // `to_decode` starts at `offset` in bytes, from the start of the input.
+    fn decode(to_decode: RcView<[u8], [u8]>, offset: usize, mut tail: InputSegment<I>, encoding: Encoding)
+              -> InputSegment<I> {
+        // split into valid and invalid pieces first, and then link them up from the back:
+        // recursing on the pieces may overflow the stack with noisy input.
+        let whole = &*to_decode;
+        let mut pieces = Vec::new();
+        let mut k = 0;
+        while k < whole.len() {
+            let rest = &whole[k..];
+            let e = match std::str::from_utf8(rest) {
+                Ok(_) => {
+                    pieces.push((k..whole.len(), true));
+                    break;
+                }
+                Err(e) => e,
+            };
+            let n = e.valid_up_to();
+            if n != 0 { pieces.push((k..k + n, true)) }
+            match (e.error_len(), tail) {
+                // an incomplete character at the end, to be completed by the next read.
+                (None, InputSegment::Delayed { remaining, input, start, offset, options, stats }) => {
+                    assert!(remaining.is_none(), "impossible: no remaining input expected here");
+                    let remaining = Some(unsafe { to_decode.derive(&rest[n..]) });
+                    tail = InputSegment::Delayed { remaining, input, start, offset, options, stats };
+                    break;
+                }
+                (error_len, t) => {
+                    tail = t;
+                    let m = error_len.unwrap_or(rest.len() - n);
+                    pieces.push((k + n..k + n + m, false));
+                    k += n + m;
+                }
+            }
+        }
+        let latin1 = encoding == Encoding::Latin1;
+        pieces.into_iter().rev().fold(tail, |next, (range, valid)| {
+            let piece = &whole[range.clone()];
+            if valid {
+                let valid = unsafe { std::str::from_utf8_unchecked(piece) };
+                InputSegment::Cons { data: unsafe { to_decode.derive(valid) }, next: RawInput::wrap(next) }
+            } else {
+                InputSegment::Invalid {
+                    data: unsafe { to_decode.derive(piece) },
+                    latin1,
+                    offset: offset + range.start,
+                    next: RawInput::wrap(if latin1 {
+                        InputSegment::Cons { data: latin1_decode(piece), next: RawInput::wrap(next) }
+                    } else { next }),
                 }
             }
+        })
+    }
+}
+
+// Latin-1 code points are exactly the byte values.
+fn latin1_decode(bytes: &[u8]) -> RcView<[u8], str> {
+    let s: String = bytes.iter().copied().map(char::from).collect();
+    RcView::new(Rc::from(s.into_bytes()), |b| unsafe { std::str::from_utf8_unchecked(b) })
+}
+
+/// Check that the whole input is valid UTF-8, reading and decoding it as [`Input`] does, but
+/// without going through the characters one by one.
+///
+/// Fails with the I/O error if reading fails (or the input is UTF-16), and otherwise gives the
+/// first invalid sequence, if any.
+pub fn validate_utf8(input: impl std::io::Read) -> std::io::Result<Result<(), Utf8Error>> {
+    let mut raw = RawInput::new(input);
+    loop {
+        raw.prepare();
+        let next = match unsafe { &mut *raw.0.get() } {
+            InputSegment::EndOfFile { io_error } => return match io_error.take() {
+                Some(err) => Err(err),
+                None => Ok(Ok(())),
+            },
+            InputSegment::Cons { next, .. } => next.clone(),
+            InputSegment::Invalid { data, offset, .. } =>
+                return Ok(Err(Utf8Error { offset: *offset, bytes: data.to_vec() })),
+            _ => unreachable!("RawInput::prepare shall not return a Delayed."),
+        };
+        // the segments behind are released as we go.
+        raw = next;
+    }
+}
+
+/// Decode a whole input as the scanner reads it, i.e. as [`Input::next`] does: without the BOM,
+/// and with the invalid UTF-8 sequences skipped or decoded by `encoding`. Character offsets
+/// in the locations of the scanner index into the result.
+pub fn decode(input: impl std::io::Read, encoding: Encoding) -> std::io::Result<String> {
+    let mut input = Input::new(input);
+    input.set_encoding(encoding);
+    let mut res = String::new();
+    loop {
+        match input.next(|_| ()) {
+            Ok((c, rest)) => {
+                res.push(c);
+                input = rest;
+            }
+            Err(err) => return match err.into() {
+                Some(err) => Err(err),
+                None => Ok(res),
+            },
         }
     }
 }
@@ -206,20 +423,53 @@ impl<I> Input<I> {
     pub fn new(input: I) -> Self {
         Input { input: RawInput::new(input), index: 0 }
     }
+
+    /// Decode the input with `encoding`, before reading anything from it.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.options_mut("set_encoding").encoding = encoding
+    }
+
+    /// Read the input in segments of `buf_size` bytes, before reading anything from it:
+    /// [`DEFAULT_BUF_SIZE`] by default.
+    ///
+    /// Segments are never smaller than needed to make progress, i.e. 3 bytes for the first
+    /// one (for the BOM), and one byte more than the incomplete character carried over for
+    /// the others.
+    pub fn set_buf_size(&mut self, buf_size: usize) {
+        self.options_mut("set_buf_size").buf_size = buf_size
+    }
+
+    /// Retry an interrupted read at most `io_retries` times in a row, before reading anything
+    /// from the input: [`DEFAULT_IO_RETRIES`] by default.
+    pub fn set_io_retries(&mut self, io_retries: u8) {
+        self.options_mut("set_io_retries").io_retries = io_retries
+    }
+
+    fn options_mut(&mut self, caller: &str) -> &mut ReadOptions {
+        match unsafe { &mut *self.input.0.get() } {
+            InputSegment::Delayed { options, start: true, .. } => options,
+            _ => panic!("{}: the input is already being read", caller),
+        }
+    }
+
+    /// Create a new [`Input`] from a [`std::io::Read`], recording statistics to `stats`.
+    pub fn with_stats(input: I, stats: Rc<InputStats>) -> Self {
+        Input { input: RawInput::with_stats(input, stats), index: 0 }
+    }
 }
 
 impl<I: std::io::Read> Input<I> {
     /// Get the next character, if any.
     pub fn next(
         mut self,
-        mut report: impl FnMut(&[u8]),
+        mut report: impl FnMut(InputProblem<'_>),
     ) -> std::result::Result<(char, Self), impl Into<Option<std::io::Error>>> {
         loop {
             self.input.prepare();
             let head = unsafe { &mut *self.input.0.get() };
             match head {
                 InputSegment::EndOfFile { io_error } => {
-                    break Err(unsafe { RcView::wrap(self.input.0, io_error) });
+                    break Err(unsafe { RcView::wrap(self.input.0.clone(), io_error) });
                 }
                 InputSegment::Cons { data, next } => {
                     let mut cs = data[self.index..].chars();
@@ -231,13 +481,17 @@ impl<I: std::io::Read> Input<I> {
                         None => self = Self { input: next.clone(), index: 0 },
                     }
                 }
-                InputSegment::Invalid { data, .. } => {
-                    report(data);
+                InputSegment::Invalid { data, latin1, offset, .. } => {
+                    report(if *latin1 {
+                        InputProblem::Latin1(data, *offset)
+                    } else {
+                        InputProblem::InvalidUtf8(data, *offset)
+                    });
                     let next = match std::mem::take(head) {
                         InputSegment::Invalid { next, .. } => next,
                         _ => unreachable!("Already pattern matched."),
                     };
-                    *head = Rc::try_unwrap(next.0).ok().unwrap().into_inner();
+                    *head = next.into_segment();
                 }
                 _ => unreachable!("RawInput::prepare shall not return a Delayed."),
             }
@@ -245,7 +499,7 @@ impl<I: std::io::Read> Input<I> {
     }
 
     /// Match on the input, succeed if the input matches the given string.
-    pub fn r#match(mut self, s: &str, mut report: impl FnMut(&[u8])) -> Option<Self> {
+    pub fn r#match(mut self, s: &str, mut report: impl FnMut(InputProblem<'_>)) -> Option<Self> {
         let mut s = s.as_bytes();
         loop {
             if s.is_empty() { return Some(self); }
@@ -261,13 +515,17 @@ impl<I: std::io::Read> Input<I> {
                     if cs[n..].is_empty() { self = Self { input: next.clone(), index: 0 }; }
                     s = &s[n..];
                 }
-                InputSegment::Invalid { data, .. } => {
-                    report(data);
+                InputSegment::Invalid { data, latin1, offset, .. } => {
+                    report(if *latin1 {
+                        InputProblem::Latin1(data, *offset)
+                    } else {
+                        InputProblem::InvalidUtf8(data, *offset)
+                    });
                     let next = match std::mem::take(head) {
                         InputSegment::Invalid { next, .. } => next,
                         _ => unreachable!("Already pattern matched."),
                     };
-                    *head = Rc::try_unwrap(next.0).ok().unwrap().into_inner();
+                    *head = next.into_segment();
                 }
                 _ => unreachable!("RawInput::prepare shall not return a Delayed."),
             }
@@ -280,3 +538,115 @@ impl<I: std::io::Read> Input<I> {
         self.input.dump();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Input, InputProblem, Encoding, DEFAULT_BUF_SIZE, validate_utf8, decode};
+
+    fn collect(bytes: impl std::io::Read) -> (String, Vec<Vec<u8>>, Option<std::io::Error>) {
+        let mut input = Input::new(bytes);
+        let mut res = String::new();
+        let mut invalid = Vec::new();
+        let err = loop {
+            match input.next(|p| if let InputProblem::InvalidUtf8(s, _) = p { invalid.push(Vec::from(s)) }) {
+                Ok((c, rest)) => {
+                    res.push(c);
+                    input = rest;
+                }
+                Err(e) => break e.into(),
+            }
+        };
+        (res, invalid, err)
+    }
+
+    #[test]
+    fn test_utf8_bom() {
+        let (res, invalid, err) = collect(&b"\xEF\xBB\xBFmodule M where\n\xEF\xBB\xBF"[..]);
+        assert_eq!(res, "module M where\n\u{FEFF}");
+        assert!(invalid.is_empty());
+        assert!(err.is_none());
+    }
+
+    #[test]
+    fn test_utf16_bom() {
+        for bom in &[b"\xFF\xFE", b"\xFE\xFF"] {
+            let mut bytes = bom.to_vec();
+            bytes.extend("module".encode_utf16().flat_map(u16::to_le_bytes));
+            let (res, invalid, err) = collect(bytes.as_slice());
+            assert_eq!(res, "");
+            assert!(invalid.is_empty());
+            let err = err.expect("UTF-16 input should be rejected");
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+            assert_eq!(err.to_string(), "UTF-16 input is not supported; re-encode as UTF-8");
+        }
+    }
+
+    // a reader delivering the chunks one per read, then ending or failing.
+    struct Chunks(Vec<Vec<u8>>, bool);
+
+    impl std::io::Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if buf.is_empty() { return Ok(0); }
+            match self.0.first_mut() {
+                Some(chunk) => {
+                    let n = chunk.len().min(buf.len());
+                    buf[..n].copy_from_slice(&chunk[..n]);
+                    chunk.drain(..n);
+                    if chunk.is_empty() { self.0.remove(0); }
+                    Ok(n)
+                }
+                None if self.1 => Err(std::io::Error::other("broken pipe")),
+                None => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncated_utf8_at_end() {
+        // half of a `€` (E2 82 AC), right before the end or an error, in the first buffer or
+        // carried over to the next one.
+        for &prefix in &[3, DEFAULT_BUF_SIZE - 2] {
+            let mut bytes = vec![b'a'; prefix];
+            bytes.extend_from_slice(b"\xE2\x82");
+            for &fails in &[false, true] {
+                let (res, invalid, err) = collect(Chunks(vec![bytes.clone()], fails));
+                assert_eq!(res.len(), prefix);
+                assert_eq!(invalid, [b"\xE2\x82"]);
+                assert_eq!(err.map(|e| e.to_string()), if fails { Some("broken pipe".into()) } else { None });
+            }
+        }
+        // carried over, and completed by the next read.
+        let mut bytes = vec![b'a'; DEFAULT_BUF_SIZE - 2];
+        bytes.extend_from_slice(b"\xE2\x82");
+        let (res, invalid, err) = collect(Chunks(vec![bytes, b"\xAC!".to_vec()], true));
+        assert!(res.ends_with("a\u{20AC}!"));
+        assert!(invalid.is_empty());
+        assert_eq!(err.map(|e| e.to_string()), Some("broken pipe".into()));
+    }
+
+    #[test]
+    fn test_validate_utf8() {
+        assert!(matches!(validate_utf8(&b"\xEF\xBB\xBFmodule M where\n"[..]), Ok(Ok(()))));
+        // right after a BOM, in the first segment, and across the boundary of two segments.
+        for &offset in &[3, 100, DEFAULT_BUF_SIZE - 1, 3 * DEFAULT_BUF_SIZE + 7] {
+            let mut bytes = b"\xEF\xBB\xBF".to_vec();
+            bytes.resize(offset, b'x');
+            bytes.extend_from_slice(b"\xE2\x82!\xFF");
+            bytes.resize(bytes.len() + DEFAULT_BUF_SIZE, b'y');
+            let err = validate_utf8(bytes.as_slice()).unwrap().unwrap_err();
+            assert_eq!((err.offset, err.bytes.as_slice()), (offset, &b"\xE2\x82"[..]));
+            assert_eq!(err.to_string(), format!("invalid UTF-8 sequence [E2, 82] at byte {}", offset));
+        }
+        let err = validate_utf8(Chunks(vec![b"abc".to_vec()], true)).unwrap_err();
+        assert_eq!(err.to_string(), "broken pipe");
+    }
+
+    #[test]
+    fn test_decode() {
+        let bytes = &b"\xEF\xBB\xBFx = \"\xE9\" \xE2\x82\xAC"[..];
+        assert_eq!(decode(bytes, Encoding::Utf8).unwrap(), "x = \"\" \u{20AC}");
+        assert_eq!(decode(bytes, Encoding::Latin1).unwrap(), "x = \"\u{E9}\" \u{20AC}");
+        let err = decode(Chunks(vec![b"abc".to_vec()], true), Encoding::Utf8).unwrap_err();
+        assert_eq!(err.to_string(), "broken pipe");
+    }
+}
@@ -19,14 +19,26 @@
 //! Haskell lexemes.
 
 /// Haskell `Integer`.
-use std::ops::{Add, Div};
+use core::cmp::Ordering;
+use core::ops::{Add, Sub, Mul, Neg};
+use alloc::{boxed::Box, string::String, vec::Vec};
 use num_bigint::BigInt;
 use num_integer::Integer;
-use std::fmt::{Formatter, Debug, Display};
+use num_traits::ToPrimitive;
+use core::fmt::{Formatter, Debug, Display};
+use core::str::FromStr;
+
+/// Names in lexemes: identifiers, operators, and module name segments.
+#[cfg(not(feature = "small_string"))]
+pub type Name = String;
+
+/// Names in lexemes: identifiers, operators, and module name segments.
+#[cfg(feature = "small_string")]
+pub type Name = crate::utils::small_string::SmallString;
 
 /// Haskell module identifier (`M1.M2.(...).Mn`).
 #[derive(Clone, Eq, PartialEq, Debug)]
-pub struct ModuleId(pub Vec<String>);
+pub struct ModuleId(pub Vec<Name>);
 
 /// Haskell qualified names (`MId.name`).
 #[derive(Clone, Eq, PartialEq, Debug)]
@@ -34,23 +46,33 @@ pub struct QName {
     /// the module name in a qualified identifier.
     pub module: ModuleId,
     /// the identifier name in a qualified identifier.
-    pub name: String,
+    pub name: Name,
 }
 
 impl QName {
     /// Create a new qualified name.
-    pub fn new(name: String) -> Self {
+    pub fn new(name: Name) -> Self {
         QName { module: ModuleId(Vec::new()), name }
     }
 
     /// Append a name segment to a qualified name.
-    pub fn append(&mut self, name: String) {
-        self.module.0.push(std::mem::replace(&mut self.name, name))
+    pub fn append(&mut self, name: Name) {
+        self.module.0.push(core::mem::replace(&mut self.name, name))
+    }
+
+    /// The whole qualified name as a module path, e.g. `N.O` after `module` in an export list.
+    ///
+    /// The lexer does not tell a qualified constructor from a module path: `N.O` is always a
+    /// [`Lexeme::QIdentifier`], and only the parser knows which one is meant.
+    pub fn as_module_path(&self) -> ModuleId {
+        let mut path = self.module.clone();
+        path.0.push(self.name.clone());
+        path
     }
 }
 
 impl Display for QName {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         for m_id in self.module.0.iter() {
             write!(f, "{}.", m_id)?;
         }
@@ -59,19 +81,65 @@ impl Display for QName {
 }
 
 /// Haskell `Ratio`.
+///
+/// Always kept reduced, with a non-negative denominator, so that the structural equality is
+/// the numeric one. A zero denominator stands for an infinity (`±1 % 0`), as a placeholder for
+/// float literals out of bound; arithmetic on infinities never panics, but may give `0 % 0`.
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct Ratio<T> {
     numerator: T,
     denominator: T,
 }
 
-impl<I: Integer + for<'a> Div<&'a I, Output=I>> Ratio<I> {
+impl<I: Integer + Clone> Ratio<I> {
     /// Create a new [`Ratio`].
     pub fn new(numerator: impl Into<I>, denominator: impl Into<I>) -> Self {
-        let numerator = numerator.into();
-        let denominator = denominator.into();
+        Self::reduce(numerator.into(), denominator.into())
+    }
+
+    // the one place to establish the invariant.
+    fn reduce(numerator: I, denominator: I) -> Self {
         let g = numerator.gcd(&denominator);
-        Ratio { numerator: numerator / &g, denominator: denominator / &g }
+        let (numerator, denominator) = if g.is_zero() {
+            (numerator, denominator)
+        } else {
+            (numerator / g.clone(), denominator / g)
+        };
+        if denominator < I::zero() {
+            Ratio { numerator: I::zero() - numerator, denominator: I::zero() - denominator }
+        } else {
+            Ratio { numerator, denominator }
+        }
+    }
+
+    /// The numerator, carrying the sign.
+    pub fn numerator(&self) -> &I { &self.numerator }
+
+    /// The denominator, non-negative.
+    pub fn denominator(&self) -> &I { &self.denominator }
+
+    /// Check whether this is an integer.
+    pub fn is_integer(&self) -> bool { self.denominator.is_one() }
+
+    /// Check whether this is an infinity, i.e. with a zero denominator.
+    pub fn is_infinite(&self) -> bool {
+        self.denominator.is_zero() && !self.numerator.is_zero()
+    }
+}
+
+impl Ratio<BigInt> {
+    /// Convert to the nearest-ish `f64`, infinities included; precision is lost.
+    pub fn to_f64_lossy(&self) -> f64 {
+        // keep the leading 64 bits of each side, and scale back with the exponent.
+        fn split(x: &BigInt) -> (f64, i64) {
+            let shift = x.bits().saturating_sub(64);
+            ((x >> shift).to_f64().unwrap_or(f64::NAN), shift as i64)
+        }
+        let (n, n_exp) = split(&self.numerator);
+        let (d, d_exp) = split(&self.denominator);
+        let exp = (n_exp - d_exp).clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+        // `f64::powi` is only in `std`.
+        n / d * num_traits::float::FloatCore::powi(2f64, exp)
     }
 }
 
@@ -81,19 +149,66 @@ impl<I: Integer> From<I> for Ratio<I> {
     }
 }
 
-impl<I: Integer> Add for Ratio<I> {
+impl<I: Integer + Clone> Add for Ratio<I> {
     type Output = Self;
     fn add(self, rhs: Self) -> Self {
-        let (g, l) = self.denominator.gcd_lcm(&rhs.denominator);
-        Ratio {
-            denominator: l,
-            numerator: (self.numerator * rhs.denominator + rhs.numerator * self.denominator) / g,
+        let numerator = self.numerator * rhs.denominator.clone()
+            + rhs.numerator * self.denominator.clone();
+        Self::reduce(numerator, self.denominator * rhs.denominator)
+    }
+}
+
+impl<I: Integer + Clone> Sub for Ratio<I> {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self { self + -rhs }
+}
+
+impl<I: Integer + Clone> Mul for Ratio<I> {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Self::reduce(self.numerator * rhs.numerator, self.denominator * rhs.denominator)
+    }
+}
+
+impl<I: Integer> Neg for Ratio<I> {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Ratio { numerator: I::zero() - self.numerator, denominator: self.denominator }
+    }
+}
+
+impl<I: Integer + Clone> PartialOrd for Ratio<I> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+/// The numeric order, with `-1 % 0` below and `1 % 0` above all the finite numbers, and
+/// `0 % 0` above everything, so that the order stays total.
+impl<I: Integer + Clone> Ord for Ratio<I> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.rank().cmp(&other.rank()).then_with(|| {
+            if self.denominator.is_zero() { return Ordering::Equal }
+            // denominators are positive: cross-multiplication keeps the order.
+            let lhs = self.numerator.clone() * other.denominator.clone();
+            let rhs = other.numerator.clone() * self.denominator.clone();
+            lhs.cmp(&rhs)
+        })
+    }
+}
+
+impl<I: Integer> Ratio<I> {
+    // -∞, the finite numbers, +∞, then `0 % 0`.
+    fn rank(&self) -> u8 {
+        match (self.denominator.is_zero(), self.numerator.cmp(&I::zero())) {
+            (false, _) => 1,
+            (true, Ordering::Less) => 0,
+            (true, Ordering::Greater) => 2,
+            (true, Ordering::Equal) => 3,
         }
     }
 }
 
 impl<I: Display> Display for Ratio<I> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(f, "{} % {}", self.numerator, self.denominator)
     }
 }
@@ -105,13 +220,15 @@ lexemes! {
     /// Whitespaces.
     Whitespace,
     /// Identifiers.
-    Identifier(String),
+    Identifier(Name),
     /// Operators.
-    Operator(String),
+    Operator(Name),
     /// Qualified Identifiers.
     QIdentifier(QName),
     /// Qualified Operators.
     QOperator(QName),
+    /// Module prefix (`M.`) of a qualified, parenthesised operator (`M.(+)`).
+    QualifiedPrefix(ModuleId),
     /// Integers.
     Integer(BigInt),
     /// Rationals.
@@ -142,10 +259,49 @@ lexemes! {
     OpenSquareBracket,
     /// Close square brackets (`]`).
     CloseSquareBracket,
+    /// An identifier or a literal with `#`s glued under `MagicHash`: `Int#`, `3##`.
+    MagicHash(Box<Lexeme>, u8),
+    /// Open unboxed tuple brackets (`(#`), under `MagicHash`.
+    OpenUnboxedParenthesis,
+    /// Close unboxed tuple brackets (`#)`), under `MagicHash`.
+    CloseUnboxedParenthesis,
+}
+
+impl LexemeType {
+    /// A human-readable description, for error messages.
+    pub fn describe(self) -> &'static str {
+        use LexemeType::*;
+        match self {
+            Whitespace => "whitespace",
+            Identifier => "identifier",
+            Operator => "operator",
+            QIdentifier => "qualified identifier",
+            QOperator => "qualified operator",
+            QualifiedPrefix => "module prefix",
+            Integer => "integer literal",
+            Float => "float literal",
+            CharLiteral => "character literal",
+            StringLiteral => "string literal",
+            ReservedId => "keyword",
+            ReservedOp => "reserved operator",
+            Comma => "`,`",
+            Semicolon => "`;`",
+            Backtick => "`` ` ``",
+            OpenCurlyBracket => "`{`",
+            CloseCurlyBracket => "`}`",
+            OpenParenthesis => "`(`",
+            CloseParenthesis => "`)`",
+            OpenSquareBracket => "`[`",
+            CloseSquareBracket => "`]`",
+            MagicHash => "`#`-suffixed lexeme",
+            OpenUnboxedParenthesis => "`(#`",
+            CloseUnboxedParenthesis => "`#)`",
+        }
+    }
 }
 
 impl Display for Lexeme {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         use Lexeme::*;
         match self {
             Whitespace => write!(f, "<whitespace>"),
@@ -153,6 +309,7 @@ impl Display for Lexeme {
             Operator(op) => write!(f, "{}", op),
             QIdentifier(name) => write!(f, "{}", name),
             QOperator(name) => write!(f, "{}", name),
+            QualifiedPrefix(module) => module.0.iter().try_for_each(|m_id| write!(f, "{}.", m_id)),
             Integer(n) => write!(f, "fromIntegral {}", n),
             Float(q) => write!(f, "fromRational ({})", q),
             CharLiteral(c) => write!(f, "{:?}", c),
@@ -168,6 +325,41 @@ impl Display for Lexeme {
             CloseParenthesis => write!(f, ")"),
             OpenSquareBracket => write!(f, "["),
             CloseSquareBracket => write!(f, "]"),
+            MagicHash(t, n) => {
+                write!(f, "{}", t)?;
+                (0..*n).try_for_each(|_| write!(f, "#"))
+            }
+            OpenUnboxedParenthesis => write!(f, "(#"),
+            CloseUnboxedParenthesis => write!(f, "#)"),
+        }
+    }
+}
+
+impl Lexeme {
+    /// The display width of the source text of this lexeme, see
+    /// [`display_width`](crate::location::display_width).
+    ///
+    /// Only for lexemes spelled in exactly one way: names, operators, and special symbols.
+    /// For literals, use the width of their ranges in [`ColumnMode::DisplayWidth`] instead.
+    ///
+    /// [`ColumnMode::DisplayWidth`]: crate::location::ColumnMode::DisplayWidth
+    pub fn width(&self) -> Option<usize> {
+        use Lexeme::*;
+        struct Width(usize);
+        impl core::fmt::Write for Width {
+            fn write_str(&mut self, s: &str) -> core::fmt::Result {
+                self.0 += s.chars().map(crate::location::display_width).sum::<usize>();
+                Ok(())
+            }
+        }
+        match self {
+            Whitespace | Integer(_) | Float(_) | CharLiteral(_) | StringLiteral(_) => None,
+            MagicHash(t, n) => t.width().map(|w| w + usize::from(*n)),
+            _ => {
+                let mut width = Width(0);
+                core::fmt::Write::write_fmt(&mut width, format_args!("{}", self)).ok()?;
+                Some(width.0)
+            }
         }
     }
 }
@@ -199,12 +391,31 @@ pub enum RId {
     Type,
     Where,
     Wildcard,
+    /// `forall` (or `∀`), only reserved under
+    /// [`GhcExtensions::explicit_forall`](crate::scanner::GhcExtensions::explicit_forall).
+    Forall,
+    /// `\case`, with no space in between, only under
+    /// [`GhcExtensions::lambda_case`](crate::scanner::GhcExtensions::lambda_case).
+    LambdaCase,
+    /// `\cases`, with no space in between, only under
+    /// [`GhcExtensions::lambda_case`](crate::scanner::GhcExtensions::lambda_case).
+    LambdaCases,
 }
 
-impl Display for RId {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+impl RId {
+    /// All the reserved keywords of Haskell 2010, i.e. except those of the extensions:
+    /// [`RId::Forall`], [`RId::LambdaCase`], and [`RId::LambdaCases`].
+    pub const ALL: &'static [Self] = &[
+        RId::Case, RId::Class, RId::Data, RId::Default, RId::Deriving, RId::Do, RId::Else,
+        RId::Foreign, RId::If, RId::Import, RId::In, RId::Infix, RId::Infixl, RId::Infixr,
+        RId::Instance, RId::Let, RId::Module, RId::Newtype, RId::Of, RId::Then, RId::Type,
+        RId::Where, RId::Wildcard,
+    ];
+
+    /// The source text of this reserved keyword.
+    pub fn as_str(self) -> &'static str {
         use RId::*;
-        f.write_str(match self {
+        match self {
             Case => "case",
             Class => "class",
             Data => "data",
@@ -227,8 +438,24 @@ impl Display for RId {
             Then => "then",
             Type => "type",
             Where => "where",
-            Wildcard => "wildcard",
-        })
+            Wildcard => "_",
+            Forall => "forall",
+            LambdaCase => "\\case",
+            LambdaCases => "\\cases",
+        }
+    }
+}
+
+impl FromStr for RId {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Self::ALL.iter().copied().find(|x| x.as_str() == s).ok_or(())
+    }
+}
+
+impl Display for RId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
@@ -247,12 +474,34 @@ pub enum ROp {
     AtSign,
     Tilde,
     DoubleRightArrow,
+    /// `-<`, only reserved under [`GhcExtensions::arrows`](crate::scanner::GhcExtensions::arrows).
+    LeftArrowTail,
+    /// `>-`, only reserved under [`GhcExtensions::arrows`](crate::scanner::GhcExtensions::arrows).
+    RightArrowTail,
+    /// `-<<`, only reserved under [`GhcExtensions::arrows`](crate::scanner::GhcExtensions::arrows).
+    DoubleLeftArrowTail,
+    /// `>>-`, only reserved under [`GhcExtensions::arrows`](crate::scanner::GhcExtensions::arrows).
+    DoubleRightArrowTail,
 }
 
-impl Display for ROp {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+impl ROp {
+    /// All the reserved operators of Haskell 2010, i.e. except the arrow tails of `Arrows`.
+    pub const ALL: &'static [Self] = &[
+        ROp::DotDot, ROp::Colon, ROp::ColonColon, ROp::EqualSign, ROp::Backslash, ROp::Pipe,
+        ROp::LeftArrow, ROp::RightArrow, ROp::AtSign, ROp::Tilde, ROp::DoubleRightArrow,
+    ];
+
+    /// The arrow tails reserved under [`GhcExtensions::arrows`].
+    ///
+    /// [`GhcExtensions::arrows`]: crate::scanner::GhcExtensions::arrows
+    pub const ARROW_TAILS: &'static [Self] = &[
+        ROp::LeftArrowTail, ROp::RightArrowTail, ROp::DoubleLeftArrowTail, ROp::DoubleRightArrowTail,
+    ];
+
+    /// The source text of this reserved operator.
+    pub fn as_str(self) -> &'static str {
         use ROp::*;
-        f.write_str(match self {
+        match self {
             DotDot => "..",
             Colon => ":",
             ColonColon => "::",
@@ -264,6 +513,160 @@ impl Display for ROp {
             AtSign => "@",
             Tilde => "~",
             DoubleRightArrow => "=>",
-        })
+            LeftArrowTail => "-<",
+            RightArrowTail => ">-",
+            DoubleLeftArrowTail => "-<<",
+            DoubleRightArrowTail => ">>-",
+        }
+    }
+}
+
+impl FromStr for ROp {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Self::ALL.iter().copied().find(|x| x.as_str() == s).ok_or(())
+    }
+}
+
+impl Display for ROp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RId, ROp, Rational};
+    use core::cmp::Ordering;
+    use num_bigint::BigInt;
+
+    #[test]
+    fn test_reserved_id() {
+        // exhaustive: a new variant must be spelled here, and then listed in `RId::ALL`.
+        fn spelling(x: RId) -> &'static str {
+            use RId::*;
+            match x {
+                Case => "case", Class => "class", Data => "data", Default => "default",
+                Deriving => "deriving", Do => "do", Else => "else", Foreign => "foreign",
+                If => "if", Import => "import", In => "in", Infix => "infix",
+                Infixl => "infixl", Infixr => "infixr", Instance => "instance", Let => "let",
+                Module => "module", Newtype => "newtype", Of => "of", Then => "then",
+                Type => "type", Where => "where", Wildcard => "_", Forall => "forall",
+                LambdaCase => "\\case", LambdaCases => "\\cases",
+            }
+        }
+        assert_eq!(RId::ALL.len(), 23);
+        for (k, &x) in RId::ALL.iter().enumerate() {
+            assert!(!RId::ALL[..k].contains(&x), "{:?} listed twice", x);
+            assert_eq!(x.as_str(), spelling(x));
+            assert_eq!(x.to_string(), spelling(x));
+            assert_eq!(spelling(x).parse(), Ok(x));
+        }
+        assert_eq!("cases".parse::<RId>(), Err(()));
+        assert_eq!("wildcard".parse::<RId>(), Err(()));
+        // reserved only under an extension.
+        for x in [RId::Forall, RId::LambdaCase, RId::LambdaCases] {
+            assert_eq!(x.to_string(), spelling(x));
+            assert_eq!(spelling(x).parse::<RId>(), Err(()));
+        }
+    }
+
+    #[test]
+    fn test_reserved_op() {
+        // exhaustive: a new variant must be spelled here, and then listed in `ROp::ALL`.
+        fn spelling(x: ROp) -> &'static str {
+            use ROp::*;
+            match x {
+                DotDot => "..", Colon => ":", ColonColon => "::", EqualSign => "=",
+                Backslash => "\\", Pipe => "|", LeftArrow => "<-", RightArrow => "->",
+                AtSign => "@", Tilde => "~", DoubleRightArrow => "=>",
+                LeftArrowTail => "-<", RightArrowTail => ">-",
+                DoubleLeftArrowTail => "-<<", DoubleRightArrowTail => ">>-",
+            }
+        }
+        assert_eq!(ROp::ALL.len(), 11);
+        for (k, &x) in ROp::ALL.iter().enumerate() {
+            assert!(!ROp::ALL[..k].contains(&x), "{:?} listed twice", x);
+            assert_eq!(x.as_str(), spelling(x));
+            assert_eq!(x.to_string(), spelling(x));
+            assert_eq!(spelling(x).parse(), Ok(x));
+        }
+        // reserved only under an extension.
+        for &x in ROp::ARROW_TAILS {
+            assert!(!ROp::ALL.contains(&x));
+            assert_eq!(x.to_string(), spelling(x));
+            assert_eq!(spelling(x).parse::<ROp>(), Err(()));
+        }
+        assert_eq!("^".parse::<ROp>(), Err(()));
+        assert_eq!("-->".parse::<ROp>(), Err(()));
+    }
+
+    // all the fractions with small numerators and denominators, instead of random ones.
+    fn small_fractions() -> Vec<Rational> {
+        let mut res: Vec<Rational> = (-4..=4)
+            .flat_map(|n| (1..=4).map(move |d| Rational::new(n, d)))
+            .collect();
+        res.dedup();
+        res
+    }
+
+    #[test]
+    fn test_rational_field() {
+        let zero = Rational::from(BigInt::from(0));
+        let one = Rational::from(BigInt::from(1));
+        let xs = small_fractions();
+        for a in &xs {
+            assert_eq!(a.clone() + zero.clone(), *a);
+            assert_eq!(a.clone() * one.clone(), *a);
+            assert_eq!(a.clone() - a.clone(), zero);
+            assert_eq!(a.clone() + -a.clone(), zero);
+            if *a != zero {
+                let inverse = Rational::new(a.denominator().clone(), a.numerator().clone());
+                assert_eq!(a.clone() * inverse, one);
+            }
+            for b in &xs {
+                assert_eq!(a.clone() + b.clone(), b.clone() + a.clone());
+                assert_eq!(a.clone() * b.clone(), b.clone() * a.clone());
+                for c in &xs {
+                    let (a, b, c) = (|| a.clone(), || b.clone(), || c.clone());
+                    assert_eq!((a() + b()) + c(), a() + (b() + c()));
+                    assert_eq!((a() * b()) * c(), a() * (b() * c()));
+                    assert_eq!(a() * (b() + c()), a() * b() + a() * c());
+                }
+            }
+        }
+        assert_eq!(Rational::new(1, 2) + Rational::new(1, 2), one);
+        assert!((Rational::new(1, 2) + Rational::new(1, 2)).is_integer());
+        assert_eq!(Rational::new(3, -6), Rational::new(-1, 2));
+    }
+
+    #[test]
+    fn test_rational_order() {
+        let xs = small_fractions();
+        for a in &xs {
+            for b in &xs {
+                let (x, y) = (a.to_f64_lossy(), b.to_f64_lossy());
+                assert_eq!(a.cmp(b), x.partial_cmp(&y).unwrap(), "{} vs {}", a, b);
+                assert_eq!(a.cmp(b), b.cmp(a).reverse());
+                for c in &xs {
+                    assert_eq!(a.cmp(b), (a.clone() + c.clone()).cmp(&(b.clone() + c.clone())));
+                }
+            }
+        }
+        let inf = Rational::new(1, 0);
+        assert!(inf.is_infinite() && !Rational::new(0, 1).is_infinite());
+        assert!(xs.iter().all(|x| *x < inf && -inf.clone() < *x));
+        // the infinities by sign, and `0 % 0` at a fixed place, not equal to everything.
+        let nan = Rational::new(0, 0);
+        assert_eq!(inf.cmp(&-inf.clone()), Ordering::Greater);
+        assert_eq!(inf.cmp(&inf), Ordering::Equal);
+        assert!(inf < nan && xs.iter().all(|x| *x < nan));
+        assert_eq!(nan.cmp(&nan), Ordering::Equal);
+        let mut all = vec![nan.clone(), inf.clone(), Rational::new(1, 2), -inf.clone(), Rational::new(-3, 1)];
+        all.sort();
+        assert_eq!(all, [-inf.clone(), Rational::new(-3, 1), Rational::new(1, 2), inf.clone(), nan]);
+        assert_eq!((-inf).to_f64_lossy(), f64::NEG_INFINITY);
+        let huge = Rational::new(BigInt::from(10).pow(400) + 1, BigInt::from(10).pow(399));
+        assert!((huge.to_f64_lossy() - 10.0).abs() < 1e-12);
     }
 }
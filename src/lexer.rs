@@ -0,0 +1,423 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A minimal lexer over a `&str`, also without the `std` feature.
+//!
+//! Only the lexemes of "Haskell 2010 Report, 2.2 Lexical Program Structure", without any
+//! extension, and without any diagnostics: where the scanner (`scanner::Scanner`, with `std`)
+//! would report an error, the range of the offending text is given instead, and lexing goes on
+//! after it. Locations are counted as in the scanner, in characters.
+//!
+//! ```
+//! use mini_haskell::lexer::lex;
+//! use mini_haskell::lexeme::{Lexeme, RId};
+//! let lexemes: Vec<_> = lex("let x = 1 in x").filter_map(Result::ok).map(|(x, _)| x).collect();
+//! assert_eq!(lexemes[0], Lexeme::ReservedId(RId::Let));
+//! assert_eq!(lexemes.len(), 6);
+//! ```
+
+use core::str::FromStr;
+use alloc::{string::String, vec::Vec};
+use num_bigint::BigInt;
+use num_traits::Num;
+use crate::lexeme::{Lexeme, ModuleId, Name, QName, RId, ROp, Rational};
+use crate::location::{Location, Range};
+use crate::scanner::basic::{Small, Large, Symbol, Graphic, Digit, WhiteChar, Special, Octit, Hexit};
+use crate::utils::char::{CharPredicate, Ascii};
+
+/// Maximum exponent in a float literal, beyond which it is out of bound: the default of the
+/// scanner, see `ScannerConfig::max_float_exponent`.
+const MAXIMUM_EXPONENT: i64 = 4096;
+
+/// The ASCII control character names in escapes, in the order of their codes, but `DEL`;
+/// `SOH` comes before `SO`, so that the longest one matches first.
+pub(crate) const ASCII_NAMES: [&str; 34] = [
+    "NUL", "SOH", "STX", "ETX", "EOT", "ENQ", "ACK", "BEL", "BS", "HT", "LF", "VT", "FF", "CR",
+    "SO", "SI", "DLE", "DC1", "DC2", "DC3", "DC4", "NAK", "SYN", "ETB", "CAN", "EM", "SUB",
+    "ESC", "FS", "GS", "RS", "US", "SP", "DEL",
+];
+
+/// The lexemes of a `&str`, see [`lex`].
+#[derive(Clone, Debug)]
+pub struct Lexer<'a> {
+    rest: &'a str,
+    location: Location,
+}
+
+/// Lex the source: the iterator gives the lexemes with their ranges, or the ranges of the
+/// lexical errors. A leading BOM is skipped, and does not count in the locations.
+pub fn lex(source: &str) -> Lexer<'_> {
+    Lexer { rest: source.strip_prefix('\u{FEFF}').unwrap_or(source), location: Location::new() }
+}
+
+impl<'a> Lexer<'a> {
+    /// The current location.
+    pub fn location(&self) -> Location { self.location }
+
+    fn peek(&self) -> Option<char> { self.rest.chars().next() }
+
+    fn peek_nth(&self, n: usize) -> Option<char> { self.rest.chars().nth(n) }
+
+    fn check(&self, p: impl CharPredicate) -> bool { self.peek().is_some_and(|c| p.check(c)) }
+
+    // step over a character, the same way as the scanner does.
+    fn bump(&mut self) -> Option<char> {
+        let mut chars = self.rest.chars();
+        let c = chars.next()?;
+        self.rest = chars.as_str();
+        self.location.step();
+        match c {
+            '\r' if self.peek() == Some('\n') => (),
+            '\r' | '\n' | '\u{C}' => self.location.newline(),
+            '\t' => self.location.tablise(),
+            _ => (),
+        }
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let matched = self.peek() == Some(c);
+        if matched { self.bump(); }
+        matched
+    }
+
+    fn eat_str(&mut self, s: &str) -> bool {
+        let matched = self.rest.starts_with(s);
+        if matched { s.chars().for_each(|_| { self.bump(); }) }
+        matched
+    }
+
+    fn eat_while(&mut self, p: impl CharPredicate) -> &'a str {
+        let rest = self.rest;
+        while self.check(&p) { self.bump(); }
+        &rest[..rest.len() - self.rest.len()]
+    }
+
+    fn skip_trivia(&mut self) -> Result<(), Range> {
+        loop {
+            if self.check(WhiteChar) {
+                self.bump();
+            } else if self.line_comment() {
+                while !self.check("\r\n\u{C}") && self.bump().is_some() {}
+            } else if self.rest.starts_with("{-") {
+                let begin = self.location;
+                self.nested_comment().ok_or(Range { begin, end: self.location })?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    // dashes, and no symbol right after them.
+    fn line_comment(&self) -> bool {
+        let n = self.rest.chars().take_while(|&c| c == '-').count();
+        n >= 2 && !self.peek_nth(n).is_some_and(|c| Symbol.check(c))
+    }
+
+    fn nested_comment(&mut self) -> Option<()> {
+        let mut depth = 0;
+        loop {
+            if self.eat_str("{-") {
+                depth += 1;
+            } else if self.eat_str("-}") {
+                depth -= 1;
+                if depth == 0 { return Some(()); }
+            } else {
+                self.bump()?;
+            }
+        }
+    }
+
+    // the next lexeme, from `c`: `None` for an error, always after at least one character.
+    fn lexeme(&mut self, c: char) -> Option<Lexeme> {
+        if Special.check(c) {
+            self.bump();
+            return Some(match c {
+                '(' => Lexeme::OpenParenthesis,
+                ')' => Lexeme::CloseParenthesis,
+                ',' => Lexeme::Comma,
+                ';' => Lexeme::Semicolon,
+                '[' => Lexeme::OpenSquareBracket,
+                ']' => Lexeme::CloseSquareBracket,
+                '`' => Lexeme::Backtick,
+                '{' => Lexeme::OpenCurlyBracket,
+                _ => Lexeme::CloseCurlyBracket,
+            });
+        }
+        match c {
+            '"' => self.string(),
+            '\'' => self.char(),
+            _ if c.is_ascii_digit() => self.numeric(),
+            _ if Large.check(c) => Some(self.qualified()),
+            _ if Small.check(c) => Some(self.var_id()),
+            _ if Symbol.check(c) => Some(self.symbol()),
+            _ => {
+                self.bump();
+                None
+            }
+        }
+    }
+
+    fn name(&mut self) -> &'a str {
+        // the first character is checked by the caller.
+        let rest = self.rest;
+        self.bump();
+        self.eat_while(any!(Small, Large, Digit, '\''));
+        &rest[..rest.len() - self.rest.len()]
+    }
+
+    fn var_id(&mut self) -> Lexeme {
+        let name = self.name();
+        RId::from_str(name).map_or_else(|_| Lexeme::Identifier(Name::from(name)), Lexeme::ReservedId)
+    }
+
+    fn symbol(&mut self) -> Lexeme {
+        let name = self.eat_while(Symbol);
+        ROp::from_str(name).map_or_else(|_| Lexeme::Operator(Name::from(name)), Lexeme::ReservedOp)
+    }
+
+    fn qualified(&mut self) -> Lexeme {
+        let mut names = Vec::new();
+        names.push(Name::from(self.name()));
+        while self.peek() == Some('.') {
+            let saved = self.clone();
+            self.bump();
+            let c = self.peek();
+            let name = match c {
+                Some(c) if Large.check(c) => {
+                    names.push(Name::from(self.name()));
+                    continue;
+                }
+                // `M.(+)`: leave the parenthesised operator to the parser.
+                Some('(') => return Lexeme::QualifiedPrefix(ModuleId(names)),
+                Some(c) if Small.check(c) => Some(self.var_id()),
+                Some(c) if Symbol.check(c) => Some(self.symbol()),
+                _ => None,
+            };
+            let module = ModuleId(core::mem::take(&mut names));
+            match name {
+                Some(Lexeme::Identifier(name)) => return Lexeme::QIdentifier(QName { module, name }),
+                Some(Lexeme::Operator(name)) if !name.chars().all(|c| c == '-') =>
+                    return Lexeme::QOperator(QName { module, name }),
+                // a reserved name or dashes: not qualified, the dot is lexed on its own.
+                _ => {
+                    names = module.0;
+                    *self = saved;
+                    break;
+                }
+            }
+        }
+        let name = names.pop().unwrap();
+        if names.is_empty() {
+            Lexeme::Identifier(name)
+        } else {
+            Lexeme::QIdentifier(QName { module: ModuleId(names), name })
+        }
+    }
+
+    fn numeric(&mut self) -> Option<Lexeme> {
+        let radix = match self.peek_nth(1) {
+            Some('x') | Some('X') if self.peek_nth(2).is_some_and(|c| Hexit.check(c)) => 16,
+            Some('o') | Some('O') if self.peek_nth(2).is_some_and(|c| Octit.check(c)) => 8,
+            _ => 10,
+        };
+        if radix != 10 {
+            self.bump();
+            self.bump();
+            let digits = if radix == 16 { self.eat_while(Hexit) } else { self.eat_while(Octit) };
+            return Some(Lexeme::Integer(BigInt::from_str_radix(digits, radix).ok()?));
+        }
+        let integral = self.eat_while(Ascii::Digit);
+        let fraction = if self.peek() == Some('.') && self.peek_nth(1).is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
+            self.eat_while(Ascii::Digit)
+        } else {
+            ""
+        };
+        let exponent = self.exponent();
+        if fraction.is_empty() && exponent.is_none() {
+            return Some(Lexeme::Integer(BigInt::from_str_radix(integral, 10).ok()?));
+        }
+        let mut mantissa = String::from(integral);
+        mantissa.push_str(fraction);
+        let mantissa = BigInt::from_str_radix(&mantissa, 10).ok()?;
+        // out of bound: an error for the scanner, as the digits beyond are not worth reading.
+        let exponent = exponent.unwrap_or(Some(0))?.checked_sub(fraction.len() as i64)
+            .filter(|x| x.abs() <= MAXIMUM_EXPONENT)?;
+        let scale = BigInt::from(10).pow(exponent.unsigned_abs() as u32);
+        Some(Lexeme::Float(if exponent >= 0 {
+            Rational::from(mantissa * scale)
+        } else {
+            Rational::new(mantissa, scale)
+        }))
+    }
+
+    // `None` without an exponent, and `Some(None)` for one which does not fit in an `i64`.
+    fn exponent(&mut self) -> Option<Option<i64>> {
+        if !self.check("eE") { return None; }
+        let sign = match self.peek_nth(1) {
+            Some(c @ '+') | Some(c @ '-') => Some(c),
+            _ => None,
+        };
+        let first = self.peek_nth(if sign.is_some() { 2 } else { 1 })?;
+        if !first.is_ascii_digit() { return None; }
+        self.bump();
+        if sign.is_some() { self.bump(); }
+        let digits = self.eat_while(Ascii::Digit);
+        let exponent = i64::from_str(digits).ok();
+        Some(if sign == Some('-') { exponent.map(|x| -x) } else { exponent })
+    }
+
+    fn char(&mut self) -> Option<Lexeme> {
+        self.bump();
+        let c = match self.peek()? {
+            '\\' => self.escape(),
+            c if (Graphic.check(c) && c != '\'') || c == ' ' => self.bump(),
+            _ => None,
+        };
+        let closed = self.eat('\'');
+        Some(Lexeme::CharLiteral(c.filter(|_| closed)?))
+    }
+
+    fn string(&mut self) -> Option<Lexeme> {
+        self.bump();
+        let mut res = String::new();
+        let mut valid = true;
+        loop {
+            match self.peek() {
+                None | Some('\r') | Some('\n') | Some('\u{C}') => return None,
+                Some('"') => break,
+                Some('\\') if self.rest.starts_with("\\&") => { self.eat_str("\\&"); }
+                Some('\\') if self.peek_nth(1).is_some_and(|c| WhiteChar.check(c)) => {
+                    // a gap.
+                    self.bump();
+                    self.eat_while(WhiteChar);
+                    valid &= self.eat('\\');
+                }
+                Some('\\') => match self.escape() {
+                    Some(c) => res.push(c),
+                    None => valid = false,
+                },
+                Some(c) => {
+                    valid &= Graphic.check(c) || c == ' ';
+                    res.extend(self.bump());
+                }
+            }
+        }
+        self.bump();
+        if valid { Some(Lexeme::StringLiteral(res)) } else { None }
+    }
+
+    fn escape(&mut self) -> Option<char> {
+        self.bump();
+        let c = self.peek()?;
+        let simple = match c {
+            'a' => Some('\u{7}'),
+            'b' => Some('\u{8}'),
+            'f' => Some('\u{C}'),
+            'n' => Some('\n'),
+            'r' => Some('\r'),
+            't' => Some('\t'),
+            'v' => Some('\u{B}'),
+            '\\' | '"' | '\'' => Some(c),
+            _ => None,
+        };
+        if simple.is_some() {
+            self.bump();
+            return simple;
+        }
+        if c == '^' {
+            self.bump();
+            let c = self.peek().filter(|&c| c.is_ascii_uppercase() || "@[\\]^_".contains(c))?;
+            self.bump();
+            return Some(char::from(c as u8 - b'@'));
+        }
+        if let Some(k) = ASCII_NAMES.iter().position(|name| self.rest.starts_with(name)) {
+            self.eat_str(ASCII_NAMES[k]);
+            // DEL is not next to SP, but at the very end of ASCII.
+            return Some(if k == 33 { '\x7f' } else { char::from(k as u8) });
+        }
+        let (radix, digits) = match c {
+            'o' if self.peek_nth(1).is_some_and(|c| Octit.check(c)) => {
+                self.bump();
+                (8, self.eat_while(Octit))
+            }
+            'x' if self.peek_nth(1).is_some_and(|c| Hexit.check(c)) => {
+                self.bump();
+                (16, self.eat_while(Hexit))
+            }
+            _ if c.is_ascii_digit() => (10, self.eat_while(Ascii::Digit)),
+            _ => {
+                self.bump();
+                return None;
+            }
+        };
+        u32::from_str_radix(digits, radix).ok().and_then(char::from_u32)
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Result<(Lexeme, Range), Range>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(range) = self.skip_trivia() { return Some(Err(range)); }
+        let begin = self.location;
+        let c = self.peek()?;
+        let lexeme = self.lexeme(c);
+        let range = Range { begin, end: self.location };
+        Some(lexeme.map(|x| (x, range)).ok_or(range))
+    }
+}
+
+impl core::iter::FusedIterator for Lexer<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::lex;
+    use crate::scanner::layout::FatLexemeIterator;
+
+    #[test]
+    fn test_same_as_scanner() {
+        let source = indoc::indoc! {r#"
+            {- a {- nested -} comment -}
+            module Main (main) where
+            import qualified Data.Map as M
+            data T' = T Int | U { f :: [Char] }
+            main :: IO ()
+            main = do
+            	let xs = [1..10] ++ [0x1F, 0o17, 3.25, 1e3, 2.5E-2, 6e]
+            	    s = "a\n\SOH\SO\&H\^A\DEL\1234\x41\o101 \
+            	        \gap" ++ ['\'', '"', ' ', '\t', 'λ']
+            	print (M.empty, M.., M.where, (M.+) 1 2, M.(+), -- comment
+            	       x-->y, x --| y, `div`, λ→x, _a, a', T.U.v)
+        "#};
+        let expected: Vec<_> = FatLexemeIterator::new(source.as_bytes()).map(Ok).collect();
+        let actual: Vec<_> = lex(source).collect();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_errors() {
+        let source = "x \u{7} 'ab' \"a\u{1}b\" \"\\q\" 1e99999 \"open\ny {- open";
+        let errors: Vec<_> = lex(source).filter_map(Result::err).map(|r| r.to_string()).collect();
+        assert_eq!(errors, ["1:3-1:4", "1:5-1:7", "1:10-1:15", "1:16-1:20", "1:21-1:28", "1:29-1:34",
+            "2:3-2:10"]);
+        // `b'` is an identifier.
+        let lexemes: Vec<_> = lex(source).filter_map(Result::ok).map(|(x, _)| x.to_string()).collect();
+        assert_eq!(lexemes, ["x", "b'", "y"]);
+    }
+}
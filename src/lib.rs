@@ -19,15 +19,65 @@
 //! mini-haskell: light-weight Haskell for fun
 
 #![warn(missing_docs)]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
 #[macro_use]
 pub mod utils;
 
 pub mod lexeme;
+pub mod location;
 pub mod rc_view;
+pub mod lexer;
+#[cfg(feature = "std")]
 pub mod input;
+#[cfg(feature = "std")]
+pub mod source;
+#[cfg(feature = "std")]
 pub mod scanner;
+/// Without `std`, only the character classes of the scanner, for the [`lexer`].
+#[cfg(not(feature = "std"))]
+pub mod scanner {
+    pub mod basic;
+}
+#[cfg(feature = "std")]
 pub mod error;
+#[cfg(feature = "std")]
+pub mod printer;
+#[cfg(feature = "std")]
+pub mod parser;
+#[cfg(feature = "std")]
+pub mod driver;
+#[cfg(feature = "std")]
+pub mod batch;
+#[cfg(feature = "std")]
+pub mod lsp;
+#[cfg(feature = "std")]
+pub mod index;
+#[cfg(feature = "std")]
+pub mod diff;
+#[cfg(all(feature = "std", feature = "serde"))]
+pub mod sarif;
+#[cfg(feature = "std")]
+pub mod term;
+#[cfg(all(feature = "std", any(test, feature = "testing")))]
+pub mod testing;
+
+// The stable surface, importable from one place: `tests/api_surface.rs` keeps track of it.
+pub use lexeme::{Lexeme, LexemeType, Name, QName, ModuleId, RId, ROp, Rational};
+pub use location::{Location, Range, ColumnMode};
+#[cfg(feature = "std")]
+pub use scanner::{Scanner, ScannerConfig, LexError, GhcExtensions};
+#[cfg(feature = "std")]
+pub use scanner::layout::{
+    RawLexemeIterator, FatLexemeIterator, EnrichedLexemeIterator, AugmentedLexemeIterator,
+    EnrichedLexeme, AugmentedLexeme, LayoutConfig,
+};
+#[cfg(feature = "std")]
+pub use error::{Diagnostic, DiagnosticMessage, DiagnosticsEngine, Severity};
+#[cfg(feature = "std")]
+pub use source::{FileId, SourceManager};
 
 #[cfg(test)]
 mod tests {}
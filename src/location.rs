@@ -0,0 +1,305 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2020  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Source locations and ranges.
+
+use core::fmt::{Formatter, Display};
+use alloc::{rc::Rc, vec::Vec};
+use crate::utils::round_to;
+use unicode_width::UnicodeWidthChar;
+
+/// How columns of a [`Location`] are counted.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum ColumnMode {
+    /// One column per character.
+    #[default]
+    Chars,
+    /// By the display width in a terminal or an editor: East Asian wide characters (CJK,
+    /// emoji) take 2 columns, and combining marks take none.
+    DisplayWidth,
+}
+
+/// The display width of a character: 2 for wide characters, 0 for combining marks, and 1
+/// for the others, including control characters.
+pub fn display_width(c: char) -> usize {
+    c.width().unwrap_or(1)
+}
+
+/// Source location.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "LocationRepr"))]
+pub struct Location {
+    /// line number, starting from 1.
+    pub line: usize,
+    /// column number, starting from 1.
+    pub column: usize,
+    /// column number in characters, starting from 1: the same as `column`, unless columns
+    /// are counted in [`ColumnMode::DisplayWidth`]. The layout algorithm uses this one.
+    pub char_column: usize,
+    /// offset into the source file, starting from 0.
+    pub offset: usize,
+}
+
+// a `Location` as deserialized: without `char_column`, it is taken to be `column`.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct LocationRepr {
+    line: usize,
+    column: usize,
+    #[serde(default)]
+    char_column: Option<usize>,
+    #[serde(default)]
+    offset: usize,
+}
+
+#[cfg(feature = "serde")]
+impl From<LocationRepr> for Location {
+    fn from(repr: LocationRepr) -> Self {
+        let LocationRepr { line, column, char_column, offset } = repr;
+        Location { line, column, char_column: char_column.unwrap_or(column), offset }
+    }
+}
+
+impl Default for Location {
+    fn default() -> Self { Location { line: 1, column: 1, char_column: 1, offset: 0 } }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+impl Location {
+    /// Size of a Tab stop.
+    pub const TAB_SIZE: usize = 8;
+
+    /// Create a new location, the same as `Location::default()`.
+    pub fn new() -> Self { Self::default() }
+
+    /// A location at `line` and `column`, `offset` characters into the source.
+    ///
+    /// Columns are counted in characters, as in [`ColumnMode::Chars`].
+    ///
+    /// ```
+    /// use mini_haskell::location::Location;
+    /// let location = Location::at(2, 5, 12);
+    /// assert_eq!(location.to_string(), "2:5");
+    /// assert_eq!(location.char_column, 5);
+    /// ```
+    pub fn at(line: usize, column: usize, offset: usize) -> Self {
+        Location { line, column, char_column: column, offset }
+    }
+
+    /// Step one character.
+    pub fn step(&mut self) {
+        self.column += 1;
+        self.char_column += 1;
+        self.offset += 1;
+    }
+
+    /// Step over the character `c`, counting columns as in `mode`.
+    pub fn step_char(&mut self, c: char, mode: ColumnMode) {
+        match mode {
+            ColumnMode::Chars => self.step(),
+            ColumnMode::DisplayWidth => {
+                self.column += display_width(c);
+                self.char_column += 1;
+                self.offset += 1;
+            }
+        }
+    }
+
+    /// Start a new line.
+    pub fn newline(&mut self) {
+        self.column = 1;
+        self.char_column = 1;
+        self.line += 1;
+    }
+
+    /// Align to the next tab stop, after stepping over a tab character.
+    pub fn tablise(&mut self) {
+        self.column = round_to(self.column - 1, Self::TAB_SIZE) + 1;
+        self.char_column = round_to(self.char_column - 1, Self::TAB_SIZE) + 1;
+    }
+}
+
+/// A half-open source range: a pair of `Location`s.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Range {
+    /// Where the range begins (inclusive).
+    pub begin: Location,
+    /// Where the range ends (non-inclusive).
+    pub end: Location,
+}
+
+/// A `(line, column)` pair, with the offset counted from the beginning of the line, i.e.
+/// exact only on the first line, and only before any tab: see [`Range::single_line`]. Columns
+/// count from 1, so a zero column is taken as 1.
+impl From<(usize, usize)> for Location {
+    fn from((line, column): (usize, usize)) -> Self {
+        let column = column.max(1);
+        Location::at(line, column, column - 1)
+    }
+}
+
+impl Range {
+    /// The range from `begin` to `end`.
+    pub fn new(begin: Location, end: Location) -> Range {
+        Range { begin, end }
+    }
+
+    /// The range from column `begin` to column `end` of `line`.
+    ///
+    /// The source text is unknown here, so the offsets are counted from the beginning of the
+    /// line, one per column: they are exact on the first line before any tab, and wrong
+    /// anywhere else. Compare positions (e.g. with `to_string`) rather than whole ranges there,
+    /// or give the offsets to `range!` (with the `testing` feature).
+    ///
+    /// ```
+    /// use mini_haskell::location::{Location, Range};
+    /// let range = Range::single_line(1, 8, 12);
+    /// assert_eq!(range, Range::new(Location::at(1, 8, 7), Location::at(1, 12, 11)));
+    /// assert_eq!(range.to_string(), "1:8-1:12");
+    /// ```
+    pub fn single_line(line: usize, begin: usize, end: usize) -> Range {
+        Range::new(Location::from((line, begin)), Location::from((line, end)))
+    }
+
+    /// An empty range at `location`, for diagnostics without an extent.
+    pub fn point(location: Location) -> Range {
+        Range { begin: location, end: location }
+    }
+}
+
+impl Display for Range {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}-{}", self.begin, self.end)
+    }
+}
+
+/// A [`Range`] written as in diagnostics, e.g. `range!(1:8-1:12)`, with the offsets as in
+/// [`Range::single_line`]; or with the offsets after `@`, e.g. `range!(2:9@3-2:10@4)`, for
+/// the ranges elsewhere.
+///
+/// ```
+/// use mini_haskell::{range, location::{Location, Range}};
+/// assert_eq!(range!(1:8-1:12), Range::single_line(1, 8, 12));
+/// assert_eq!(range!(2:3-4:1).to_string(), "2:3-4:1");
+/// assert_eq!(range!(2:9@3-2:10@4), Range::new(Location::at(2, 9, 3), Location::at(2, 10, 4)));
+/// ```
+#[cfg(any(test, feature = "testing"))]
+#[macro_export]
+macro_rules! range {
+    ($l1: literal : $c1: literal - $l2: literal : $c2: literal) => {
+        $crate::location::Range::new(
+            $crate::location::Location::from(($l1, $c1)),
+            $crate::location::Location::from(($l2, $c2)))
+    };
+    ($l1: literal : $c1: literal @ $o1: literal - $l2: literal : $c2: literal @ $o2: literal) => {
+        $crate::location::Range::new(
+            $crate::location::Location::at($l1, $c1, $o1),
+            $crate::location::Location::at($l2, $c2, $o2))
+    };
+}
+
+/// A logical source position, after the `LINE` pragmas of preprocessed input.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SourcePos {
+    /// the original file, if any pragma named one.
+    pub file: Option<Rc<str>>,
+    /// line number in the original file, starting from 1.
+    pub line: usize,
+    /// column number, always physical: pragmas do not change it.
+    pub column: usize,
+}
+
+impl Display for SourcePos {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        if let Some(file) = &self.file { write!(f, "{}:", file)?; }
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// A `LINE` pragma, or a CPP line marker: from physical `line` on, lines are numbered from
+/// `logical_line` in `file`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct LineDirective {
+    /// the first physical line affected.
+    pub line: usize,
+    /// the logical line number of that line.
+    pub logical_line: usize,
+    /// the original file, inherited from the previous directive if not named.
+    pub file: Option<Rc<str>>,
+}
+
+/// The line directives found in the input, mapping physical locations to logical ones.
+///
+/// The physical locations are still the ones in lexemes and diagnostics, and the ones the
+/// layout algorithm works with; map them with [`SourceMap::logical`] for reporting.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct SourceMap {
+    directives: Vec<LineDirective>,
+}
+
+impl SourceMap {
+    /// The line directives, sorted by physical lines.
+    pub fn directives(&self) -> &[LineDirective] { &self.directives }
+
+    /// Record a directive for physical `line` and beyond.
+    pub fn push(&mut self, line: usize, logical_line: usize, file: Option<Rc<str>>) {
+        // the last one wins, should the same line be scanned again.
+        while self.directives.last().is_some_and(|d| d.line >= line) { self.directives.pop(); }
+        let file = file.or_else(|| self.directives.last().and_then(|d| d.file.clone()));
+        self.directives.push(LineDirective { line, logical_line, file })
+    }
+
+    /// The logical position of a physical location.
+    pub fn logical(&self, location: Location) -> SourcePos {
+        let k = self.directives.partition_point(|d| d.line <= location.line);
+        match k.checked_sub(1).map(|k| &self.directives[k]) {
+            Some(d) => SourcePos {
+                file: d.file.clone(),
+                line: d.logical_line + (location.line - d.line),
+                column: location.column,
+            },
+            None => SourcePos { file: None, line: location.line, column: location.column },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Location;
+
+    #[test]
+    fn test_from_pair() {
+        assert_eq!(Location::from((2, 5)), Location::at(2, 5, 4));
+        assert_eq!(Location::from((3, 0)), Location::at(3, 1, 0));
+    }
+
+    #[test]
+    fn test_range_with_offsets() {
+        use crate::scanner::layout::FatLexemeIterator;
+        let ranges: Vec<_> = FatLexemeIterator::new("x\n\ty".as_bytes()).map(|(_, range)| range).collect();
+        assert_eq!(ranges, [range!(1:1-1:2), range!(2:9@3-2:10@4)]);
+        assert_ne!(ranges[1], range!(2:9-2:10));
+    }
+}
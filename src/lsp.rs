@@ -0,0 +1,212 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Semantic tokens for the Language Server Protocol, see "LSP 3.17: Semantic Tokens".
+
+use crate::lexeme::Lexeme::{self, *};
+use crate::scanner::Range;
+use crate::scanner::basic::Large;
+use crate::utils::char::CharPredicate;
+
+/// The token types legend: the token type in an encoded token is an index into this array.
+pub const TOKEN_TYPES: [&str; 8] = [
+    "keyword", "variable", "type", "operator", "number", "string", "comment", "namespace",
+];
+
+/// Token types, in the same order as [`TOKEN_TYPES`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[allow(missing_docs)]
+pub enum TokenType {
+    Keyword,
+    Variable,
+    Type,
+    Operator,
+    Number,
+    String,
+    Comment,
+    Namespace,
+}
+
+impl TokenType {
+    /// The name of this token type in the legend.
+    pub fn name(self) -> &'static str { TOKEN_TYPES[self as usize] }
+
+    /// The token type of a lexeme, if it is highlighted at all: special symbols are not.
+    ///
+    /// Constructors are not told from type names, both are [`TokenType::Type`].
+    pub fn classify(lexeme: &Lexeme) -> Option<TokenType> {
+        let name = |s: &str| if s.chars().next().is_some_and(|c| Large.check(c)) {
+            TokenType::Type
+        } else {
+            TokenType::Variable
+        };
+        Some(match lexeme {
+            Identifier(s) => name(s),
+            QIdentifier(q) => name(&q.name),
+            Operator(_) | QOperator(_) | ReservedOp(_) => TokenType::Operator,
+            QualifiedPrefix(_) => TokenType::Namespace,
+            Integer(_) | Float(_) => TokenType::Number,
+            CharLiteral(_) | StringLiteral(_) => TokenType::String,
+            ReservedId(_) => TokenType::Keyword,
+            MagicHash(t, _) => return Self::classify(t),
+            _ => return None,
+        })
+    }
+}
+
+// what to do with the characters walked over.
+#[derive(Copy, Clone)]
+enum Mode {
+    // a token, all of it.
+    Token(TokenType),
+    // trivia, where everything but whitespaces is a comment.
+    Trivia,
+    // nothing to highlight.
+    Skip,
+}
+
+struct Encoder<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    // character offset, and the position in UTF-16 code units, both from 0.
+    offset: usize,
+    line: u32,
+    column: u32,
+    // the current segment on this line: its start and end columns.
+    segment: Option<(u32, u32, TokenType)>,
+    // line and start column of the last encoded token.
+    last: (u32, u32),
+    res: Vec<[u32; 5]>,
+}
+
+impl Encoder<'_> {
+    fn flush(&mut self) {
+        if let Some((start, end, ty)) = self.segment.take() {
+            let (line, column) = self.last;
+            let delta_start = if line == self.line { start - column } else { start };
+            self.res.push([self.line - line, delta_start, end - start, ty as u32, 0]);
+            self.last = (self.line, start);
+        }
+    }
+
+    fn walk(&mut self, end: usize, mode: Mode) {
+        while self.offset < end {
+            let c = match self.chars.next() {
+                Some(c) => c,
+                None => break,
+            };
+            self.offset += 1;
+            match c {
+                // split at the line breaks of LSP, and never include them.
+                '\r' if self.chars.peek() == Some(&'\n') => (),
+                '\r' | '\n' => {
+                    self.flush();
+                    self.line += 1;
+                    self.column = 0;
+                }
+                _ => {
+                    let next = self.column + c.len_utf16() as u32;
+                    let ty = match mode {
+                        Mode::Token(ty) => Some(ty),
+                        Mode::Trivia if !c.is_whitespace() => Some(TokenType::Comment),
+                        _ => None,
+                    };
+                    if let Some(ty) = ty {
+                        let start = self.segment.map_or(self.column, |(start, _, _)| start);
+                        self.segment = Some((start, next, ty));
+                    }
+                    self.column = next;
+                }
+            }
+        }
+        self.flush();
+    }
+}
+
+/// Encode the tokens, in source order, as LSP semantic tokens: `[deltaLine, deltaStartChar,
+/// length, tokenType, tokenModifiers]`, with positions in UTF-16 code units.
+///
+/// `source` is where the tokens come from, for their texts and for the comments: everything
+/// but whitespaces outside of the tokens is taken as comments. A token spanning several lines
+/// is split into one token per line, as most clients cannot handle multi-line tokens.
+pub fn semantic_tokens(source: &str, tokens: &[(Lexeme, Range)]) -> Vec<[u32; 5]> {
+    // the scanner does not count the BOM in its offsets, but it is still on the first line.
+    let (body, column) = match source.strip_prefix('\u{FEFF}') {
+        Some(body) => (body, 1),
+        None => (source, 0),
+    };
+    let mut encoder = Encoder {
+        chars: body.chars().peekable(),
+        offset: 0,
+        line: 0,
+        column,
+        segment: None,
+        last: (0, 0),
+        res: Vec::new(),
+    };
+    for (lexeme, range) in tokens {
+        encoder.walk(range.begin.offset, Mode::Trivia);
+        let mode = TokenType::classify(lexeme).map_or(Mode::Skip, Mode::Token);
+        encoder.walk(range.end.offset, mode);
+    }
+    encoder.walk(usize::MAX, Mode::Trivia);
+    encoder.res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{semantic_tokens, TokenType};
+    use crate::scanner::layout::FatLexemeIterator;
+
+    fn encode(source: &str) -> Vec<[u32; 5]> {
+        let tokens: Vec<_> = FatLexemeIterator::new(source.as_bytes()).collect();
+        semantic_tokens(source, &tokens)
+    }
+
+    #[test]
+    fn test_semantic_tokens() {
+        use TokenType::*;
+        let t = |ty: TokenType| ty as u32;
+        let source = "module M where\n{- a\n   block -} f x = \"\\\n  \\gap\" -- end\n";
+        assert_eq!(encode(source), [
+            [0, 0, 6, t(Keyword), 0],
+            [0, 7, 1, t(Type), 0],
+            [0, 2, 5, t(Keyword), 0],
+            // the block comment, split at the line break, without the indentation.
+            [1, 0, 4, t(Comment), 0],
+            [1, 3, 8, t(Comment), 0],
+            [0, 9, 1, t(Variable), 0],
+            [0, 2, 1, t(Variable), 0],
+            [0, 2, 1, t(Operator), 0],
+            // the string with a gap: the indentation of the gap is in the string as well.
+            [0, 2, 2, t(String), 0],
+            [1, 0, 7, t(String), 0],
+            [0, 8, 6, t(Comment), 0],
+        ]);
+        // lengths in UTF-16: `𝑥` is a surrogate pair; the BOM is a character on the first line.
+        assert_eq!(encode("\u{FEFF}𝑥 = 'é'"), [
+            [0, 1, 2, t(Variable), 0],
+            [0, 3, 1, t(Operator), 0],
+            [0, 2, 3, t(String), 0],
+        ]);
+        // special symbols are not highlighted, and no comment is made of the spaces around.
+        assert_eq!(encode("( M.x , 1 )\r\n"), [
+            [0, 2, 3, t(Variable), 0],
+            [0, 6, 1, t(Number), 0],
+        ]);
+    }
+}
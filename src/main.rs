@@ -19,31 +19,182 @@
 use clap::{Arg, App, SubCommand};
 
 use std::fs::File;
+use mini_haskell::scanner::layout::LayoutEvent;
 use std::path::Path;
+use mini_haskell::printer::{Token, reconstruct, tokens_at};
+use mini_haskell::scanner::metrics::LexMetrics;
+use mini_haskell::scanner::lints;
+use mini_haskell::driver::{check, exit_code, CheckOptions, CheckReport};
+use mini_haskell::driver::{EXIT_CLEAN, EXIT_ERRORS, EXIT_FATAL};
+use mini_haskell::batch::{lex_files_in, LexOptions};
+use mini_haskell::source::SourceManager;
+use mini_haskell::input::{decode, Encoding};
+use mini_haskell::lsp::{semantic_tokens, TokenType, TOKEN_TYPES};
+use mini_haskell::sarif::SarifLog;
+use mini_haskell::term::{highlight, ColorChoice, Palette, Style};
+use mini_haskell::scanner::{Scanner, ScannerConfig};
 use mini_haskell::scanner::layout::{
     RawLexemeIterator,
     FatLexemeIterator,
     EnrichedLexemeIterator,
     AugmentedLexemeIterator,
+    AnnotatedLexemeIterator,
     EnrichedLexeme,
+    render_enriched,
 };
 
 fn print_lexemes(it: impl Iterator<Item=impl std::fmt::Display>) {
     for x in it { println!("{}", x) }
 }
 
+fn print_event(event: LayoutEvent) {
+    println!("-- layout: {}", event)
+}
+
+// the source as the scanner decodes it, so that the character offsets of its locations index
+// into it; the BOM is kept for the columns of the first line, as in `lsp::semantic_tokens`.
+fn scanned_source(path: &str, bytes: &[u8]) -> String {
+    let bom = if bytes.starts_with(b"\xEF\xBB\xBF") { "\u{FEFF}" } else { "" };
+    let source = decode(bytes, Encoding::Utf8).unwrap_or_else(|err| {
+        eprintln!("cannot decode file '{}': {}", path, err);
+        std::process::exit(EXIT_FATAL)
+    });
+    format!("{}{}", bom, source)
+}
+
+fn scanner_config(matches: &clap::ArgMatches<'_>) -> ScannerConfig {
+    let mut config = ScannerConfig::default();
+    if let Some(n) = matches.value_of("max-errors") {
+        config.max_errors = n.parse().unwrap_or_else(|_| {
+            eprintln!("invalid error budget '{}': expected a number", n);
+            std::process::exit(EXIT_FATAL)
+        });
+    }
+    config
+}
+
+fn is_sarif(matches: &clap::ArgMatches<'_>) -> bool {
+    matches.value_of("output") == Some("sarif")
+}
+
+// the style of an output, from `--color` and `--palette`.
+fn style(matches: &clap::ArgMatches<'_>, output: &impl std::io::IsTerminal) -> Style {
+    let choice = matches.value_of("color").map_or(Ok(ColorChoice::Auto), str::parse);
+    let palette = matches.value_of("palette").map_or(Ok(Palette::Default), str::parse);
+    Style::for_output(choice.unwrap(), palette.unwrap(), output)
+}
+
+fn print_sarif(log: &SarifLog) {
+    println!("{}", serde_json::to_string_pretty(log).unwrap())
+}
+
+fn lex_batch(paths: Vec<std::path::PathBuf>, jobs: &str, scanner: ScannerConfig, sarif: bool,
+             style: Style) {
+    let jobs = jobs.parse().unwrap_or_else(|_| {
+        eprintln!("invalid number of jobs '{}': expected a number", jobs);
+        std::process::exit(EXIT_FATAL)
+    });
+    let options = CheckOptions { scanner, ..CheckOptions::default() };
+    // the worst of all the files, and all of them in one run.
+    let mut code = EXIT_CLEAN;
+    let mut log = SarifLog::new();
+    let mut files = SourceManager::new();
+    for result in lex_files_in(&mut files, &paths, &LexOptions { scanner, jobs }) {
+        let path = result.path.display();
+        if !sarif { println!("==> {} <==", path) }
+        if let Some(err) = &result.read_error {
+            eprintln!("cannot open file '{}': {}", path, err);
+            code = EXIT_FATAL;
+            continue;
+        }
+        // read again only for display, as in the single-file case.
+        let bytes = std::fs::read(&result.path).unwrap_or_default();
+        let source = String::from_utf8_lossy(&bytes);
+        if sarif {
+            log.add_file(&result.path, &source, &result.diagnostics, result.lex_error.as_ref());
+        } else {
+            print_lexemes(result.tokens.into_iter().map(EnrichedLexeme::from));
+            for diagnostic in result.diagnostics.iter() {
+                eprint!("{}", diagnostic.render_in(&source, &files, &style))
+            }
+            if let Some(err) = &result.lex_error {
+                eprintln!("{}: lexical error at {}", path, err);
+            }
+        }
+        let report = CheckReport::new(result.diagnostics, result.lex_error, &options);
+        code = code.max(exit_code(&report, &options));
+    }
+    if sarif { print_sarif(&log) }
+    std::process::exit(code)
+}
+
 fn main() {
     let input_file = Arg::with_name("INPUT")
         .help("Haskell source file to process")
         .required(true)
         .index(1);
+    let max_errors = Arg::with_name("max-errors")
+        .long("max-errors")
+        .value_name("N")
+        .takes_value(true)
+        .help("Stop after N errors [default: 1000]");
+    let output = Arg::with_name("output")
+        .long("output")
+        .value_name("FORMAT")
+        .takes_value(true)
+        .possible_values(&["text", "sarif"])
+        .default_value("text")
+        .help("Report the diagnostics as text on stderr, or as a SARIF log on stdout");
+    let color = Arg::with_name("color")
+        .long("color")
+        .value_name("WHEN")
+        .takes_value(true)
+        .possible_values(&["always", "auto", "never"])
+        .help("Use colours: always, never, or only on a terminal without NO_COLOR [default: auto]");
+    let palette = Arg::with_name("palette")
+        .long("palette")
+        .value_name("PALETTE")
+        .takes_value(true)
+        .possible_values(&["default", "high-contrast"])
+        .help("Select the colours [default: default]");
     let matches = App::new("mini-haskell")
         .version(concat!(env!("CARGO_PKG_VERSION")))
         .author(env!("CARGO_PKG_AUTHORS"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
         .subcommand(SubCommand::with_name("compile")
             .about("Compile the Haskell source file")
+            .long_about("Compile the Haskell source file: for now, only check its lexical \
+                syntax and layout, and report the diagnostics")
+            .arg(Arg::with_name("Werror")
+                .long("Werror")
+                .help("Fail on warnings too"))
+            .arg(max_errors.clone())
+            .arg(output.clone())
+            .arg(color.clone())
+            .arg(palette.clone())
+            .arg(input_file.clone()))
+        .subcommand(SubCommand::with_name("highlight")
+            .about("Print the source with its tokens in colours")
+            .arg(color.clone())
+            .arg(palette.clone())
             .arg(input_file.clone()))
+        .subcommand(SubCommand::with_name("tokens")
+            .about("Print the highlighted tokens with their ranges and semantic token types")
+            .arg(Arg::with_name("offsets")
+                .long("offsets")
+                .help("Print the LSP semantic tokens instead, delta-encoded, in UTF-16 code units"))
+            .arg(color.clone())
+            .arg(palette.clone())
+            .arg(input_file.clone()))
+        .subcommand(SubCommand::with_name("untokenize")
+            .about("Reconstruct the source from a JSON array of tokens")
+            .long_about("Reconstruct the source from a JSON array of tokens, each of the form \
+                {\"text\": ..., \"range\": {\"begin\": {\"line\": ..., \"column\": ...}, \
+                \"end\": ...}}, by placing them at their ranges, e.g. from `lex --output json`")
+            .arg(Arg::with_name("INPUT")
+                .help("JSON token stream to process")
+                .required(true)
+                .index(1)))
         .subcommand(SubCommand::with_name("lex")
             .about("Get lexeme stream from the lexer")
             .arg(Arg::with_name("flavour")
@@ -54,23 +205,252 @@ fn main() {
                 .takes_value(true)
                 .possible_values(&["raw", "fat", "enriched", "augmented"])
                 .default_value("raw"))
-            .arg(input_file))
-        .get_matches();
+            .arg(Arg::with_name("trace-layout")
+                .long("trace-layout")
+                .help("Print the decisions of the layout algorithm (enriched and augmented only)"))
+            .arg(Arg::with_name("annotated")
+                .long("annotated")
+                .help("Interleave diagnostics with the lexemes in source order")
+                .conflicts_with("flavour"))
+            .arg(Arg::with_name("lint")
+                .long("lint")
+                .help("Also run the lints, e.g. for adjacent string literals"))
+            .arg(Arg::with_name("stats-json")
+                .long("stats-json")
+                .help("Print lexing metrics as a JSON object, instead of the lexemes")
+                .conflicts_with_all(&["flavour", "trace-layout", "annotated", "lint", "max-errors",
+                    "output"]))
+            .arg(Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .value_name("N")
+                .takes_value(true)
+                .help("Lex the files in parallel with N threads, as fat lexemes [0: all cores]")
+                .conflicts_with_all(&["flavour", "trace-layout", "annotated", "lint", "stats-json"]))
+            .arg(output.clone().possible_values(&["json"]).help("Report the diagnostics as text on \
+                stderr with the lexemes, or as a SARIF log on stdout instead of the lexemes; or print \
+                the tokens as JSON, for untokenize"))
+            .arg(max_errors)
+            .arg(color)
+            .arg(palette)
+            .arg(input_file.clone().multiple(true)
+                .help("Haskell source files to process, as with --jobs 1 if several")))
+        .get_matches_safe()
+        .unwrap_or_else(|err| {
+            // usage errors are fatal, as in `driver::exit_code`; help and version are fine.
+            if !err.use_stderr() {
+                println!("{}", err.message);
+                std::process::exit(EXIT_CLEAN)
+            }
+            eprintln!("{}", err.message);
+            std::process::exit(EXIT_FATAL)
+        });
     if let Some(sub_matches) = matches.subcommand_matches("lex") {
+        let paths: Vec<_> = sub_matches.values_of_os("INPUT").unwrap().map(Into::into).collect();
+        if sub_matches.is_present("jobs") || paths.len() > 1 {
+            // several files are lexed as with `--jobs 1`: as fat lexemes, one after another.
+            let jobs = sub_matches.value_of("jobs").unwrap_or("1");
+            let style = style(sub_matches, &std::io::stderr());
+            if sub_matches.value_of("output") == Some("json") {
+                eprintln!("--output json takes a single file, it cannot be used with --jobs or several \
+                    files");
+                std::process::exit(EXIT_FATAL)
+            }
+            let single = ["flavour", "trace-layout", "annotated", "lint", "stats-json"];
+            if let Some(arg) = single.iter().find(|arg| sub_matches.occurrences_of(arg) > 0) {
+                eprintln!("--{} takes a single file, it cannot be used with several files", arg);
+                std::process::exit(EXIT_FATAL)
+            }
+            return lex_batch(paths, jobs, scanner_config(sub_matches), is_sarif(sub_matches), style);
+        }
+        let path = sub_matches.value_of("INPUT").unwrap();
+        let bytes = std::fs::read(Path::new(path)).unwrap_or_else(|err| {
+            eprintln!("cannot open file '{}': {}", path, err);
+            std::process::exit(EXIT_FATAL)
+        });
+        if sub_matches.is_present("stats-json") {
+            let metrics = LexMetrics::collect(&bytes);
+            println!("{}", serde_json::to_string(&metrics).unwrap());
+            return;
+        }
+        let config = scanner_config(sub_matches);
+        let raw = || RawLexemeIterator::from(Scanner::new(bytes.as_slice()).with_config(config));
+        let trace = sub_matches.is_present("trace-layout");
+        let sarif = is_sarif(sub_matches);
+        let output = sub_matches.value_of("output").unwrap();
+        let style = style(sub_matches, &std::io::stderr());
+        // not with clap's conflicts: those would count the default of `--output` as given.
+        let lexemes = ["flavour", "trace-layout", "annotated"];
+        let given = |arg: &&&str| output != "text" && sub_matches.occurrences_of(arg) > 0;
+        if let Some(arg) = lexemes.iter().find(given) {
+            eprintln!("--output {} prints no lexemes, it cannot be used with --{}", output, arg);
+            std::process::exit(EXIT_FATAL)
+        }
+        let (error, scanner) = if sarif {
+            let mut it = FatLexemeIterator::from(raw());
+            it.by_ref().for_each(drop);
+            it.into_scanner()
+        } else if output == "json" {
+            let mut it = FatLexemeIterator::from(raw());
+            let ranges: Vec<_> = it.by_ref().map(|(_, range)| range).collect();
+            let tokens = tokens_at(&scanned_source(path, &bytes), ranges);
+            println!("{}", serde_json::to_string(&tokens).unwrap());
+            it.into_scanner()
+        } else if sub_matches.is_present("annotated") {
+            let mut it = AnnotatedLexemeIterator::from(FatLexemeIterator::from(raw()));
+            print_lexemes(it.by_ref());
+            it.into_scanner()
+        } else {
+            match sub_matches.value_of("flavour").unwrap() {
+                "raw" => {
+                    let mut it = raw();
+                    print_lexemes(it.by_ref());
+                    it.into_scanner()
+                }
+                "fat" => {
+                    let mut it = FatLexemeIterator::from(raw());
+                    print_lexemes(it.by_ref().map(EnrichedLexeme::from));
+                    it.into_scanner()
+                }
+                "enriched" => {
+                    let mut it = EnrichedLexemeIterator::from(FatLexemeIterator::from(raw()));
+                    if trace {
+                        // print as we go, so that the events come right before their lexemes.
+                        it = it.with_trace(print_event);
+                        print_lexemes(it.by_ref());
+                    } else {
+                        print!("{}", render_enriched(it.by_ref()));
+                    }
+                    it.into_scanner()
+                }
+                "augmented" => {
+                    let mut it = EnrichedLexemeIterator::from(FatLexemeIterator::from(raw()));
+                    if trace { it = it.with_trace(print_event) }
+                    let mut it = AugmentedLexemeIterator::from(it);
+                    if trace { it = it.with_trace(print_event) }
+                    print_lexemes(it.by_ref());
+                    it.into_scanner()
+                }
+                _ => unreachable!(),
+            }
+        };
+        // only for display: invalid UTF-8 gets replaced.
+        let source = String::from_utf8_lossy(&bytes);
+        let mut diagnostics: Vec<_> = scanner.diagnostics().iter().cloned().collect();
+        if sub_matches.is_present("lint") {
+            diagnostics.extend(lints::run_all(&lints::tokens(&source)));
+        }
+        if sarif {
+            let mut log = SarifLog::new();
+            log.add_file(Path::new(path), &source, &diagnostics, error.as_ref());
+            print_sarif(&log);
+        } else {
+            for diagnostic in diagnostics.iter() {
+                eprint!("{}", diagnostic.render_styled(&source, &style))
+            }
+            if let Some(err) = &error {
+                eprintln!("lexical error at {}", err);
+            }
+        }
+        let options = CheckOptions { scanner: config, ..CheckOptions::default() };
+        std::process::exit(exit_code(&CheckReport::new(diagnostics, error, &options), &options))
+    } else if let Some(sub_matches) = matches.subcommand_matches("untokenize") {
         let path = sub_matches.value_of("INPUT").unwrap();
         let file = File::open(Path::new(path)).unwrap_or_else(|err| {
             eprintln!("cannot open file '{}': {}", path, err);
-            std::process::exit(1)
+            std::process::exit(EXIT_FATAL)
+        });
+        let tokens: Vec<Token> = serde_json::from_reader(std::io::BufReader::new(file))
+            .unwrap_or_else(|err| {
+                eprintln!("invalid token stream '{}': {}", path, err);
+                std::process::exit(EXIT_FATAL)
+            });
+        match reconstruct(&tokens) {
+            Ok(source) => print!("{}", source),
+            Err(err) => {
+                eprintln!("cannot reconstruct source: {}", err);
+                std::process::exit(EXIT_ERRORS)
+            }
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("compile") {
+        let path = sub_matches.value_of("INPUT").unwrap();
+        let bytes = std::fs::read(Path::new(path)).unwrap_or_else(|err| {
+            eprintln!("cannot open file '{}': {}", path, err);
+            std::process::exit(EXIT_FATAL)
         });
-        match sub_matches.value_of("flavour").unwrap() {
-            "raw" => print_lexemes(RawLexemeIterator::new(file)),
-            "fat" => print_lexemes(FatLexemeIterator::new(file).map(EnrichedLexeme::from)),
-            "enriched" => print_lexemes(EnrichedLexemeIterator::new(file)),
-            "augmented" => print_lexemes(AugmentedLexemeIterator::new(file)),
-            _ => unreachable!(),
+        let options = CheckOptions {
+            warnings_as_errors: sub_matches.is_present("Werror"),
+            scanner: scanner_config(sub_matches),
+            ..CheckOptions::default()
+        };
+        let report = check(bytes.as_slice(), options);
+        let source = String::from_utf8_lossy(&bytes);
+        let style = style(sub_matches, &std::io::stderr());
+        if is_sarif(sub_matches) {
+            let mut log = SarifLog::new();
+            log.add_file(Path::new(path), &source, &report.diagnostics, report.lex_error.as_ref());
+            print_sarif(&log);
+        } else {
+            for diagnostic in report.diagnostics.iter() {
+                eprint!("{}", diagnostic.render_styled(&source, &style))
+            }
+            if let Some(err) = &report.lex_error {
+                eprintln!("lexical error at {}", err);
+            }
+        }
+        std::process::exit(exit_code(&report, &options))
+    } else if let Some(sub_matches) = matches.subcommand_matches("tokens") {
+        let path = sub_matches.value_of("INPUT").unwrap();
+        let bytes = std::fs::read(Path::new(path)).unwrap_or_else(|err| {
+            eprintln!("cannot open file '{}': {}", path, err);
+            std::process::exit(EXIT_FATAL)
+        });
+        let mut it = FatLexemeIterator::new(bytes.as_slice());
+        let tokens: Vec<_> = it.by_ref().collect();
+        let (error, scanner) = it.into_scanner();
+        if sub_matches.is_present("offsets") {
+            for token in semantic_tokens(&scanned_source(path, &bytes), &tokens) {
+                println!("{:?} {}", token, TOKEN_TYPES[token[3] as usize]);
+            }
+        } else {
+            for (lexeme, range) in tokens.iter() {
+                if let Some(ty) = TokenType::classify(lexeme) {
+                    println!("{} {} {}", range, ty.name(), lexeme);
+                }
+            }
+        }
+        let source = String::from_utf8_lossy(&bytes);
+        let style = style(sub_matches, &std::io::stderr());
+        for diagnostic in scanner.diagnostics().iter() {
+            eprint!("{}", diagnostic.render_styled(&source, &style))
+        }
+        if let Some(err) = &error {
+            eprintln!("lexical error at {}", err);
+        }
+        let options = CheckOptions::default();
+        let report = CheckReport::new(scanner.diagnostics().iter().cloned(), error, &options);
+        std::process::exit(exit_code(&report, &options))
+    } else if let Some(sub_matches) = matches.subcommand_matches("highlight") {
+        let path = sub_matches.value_of("INPUT").unwrap();
+        let bytes = std::fs::read(Path::new(path)).unwrap_or_else(|err| {
+            eprintln!("cannot open file '{}': {}", path, err);
+            std::process::exit(EXIT_FATAL)
+        });
+        let mut it = FatLexemeIterator::new(bytes.as_slice());
+        let tokens: Vec<_> = it.by_ref().collect();
+        let (error, scanner) = it.into_scanner();
+        let source = String::from_utf8_lossy(&bytes);
+        // whatever is left after a lexical error is shown unhighlighted.
+        print!("{}", highlight(&source, &tokens, &style(sub_matches, &std::io::stdout())));
+        let style = style(sub_matches, &std::io::stderr());
+        for diagnostic in scanner.diagnostics().iter() {
+            eprint!("{}", diagnostic.render_styled(&source, &style))
+        }
+        if let Some(err) = &error {
+            eprintln!("lexical error at {}", err);
         }
-    } else if let Some(_sub_matches) = matches.subcommand_matches("compile") {
-        eprintln!("compile not yet supported.");
-        std::process::exit(1)
+        let options = CheckOptions::default();
+        let report = CheckReport::new(scanner.diagnostics().iter().cloned(), error, &options);
+        std::process::exit(exit_code(&report, &options))
     }
 }
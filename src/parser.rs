@@ -0,0 +1,583 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A minimal parser for the declaration headers: the module header, the imports, and the
+//! fixity declarations at the top level. The other declarations are skipped.
+
+use std::fmt::{Display, Formatter};
+use std::iter::Peekable;
+use num_traits::ToPrimitive;
+use crate::lexeme::{Lexeme::{self, *}, ModuleId, QName, Name, RId, ROp};
+use crate::location::{Location, Range};
+use crate::scanner::basic::Large;
+use crate::utils::char::CharPredicate;
+use crate::scanner::layout::AugmentedLexeme::{self, *};
+
+/// `module M (exports) where`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ModuleDecl {
+    /// the module name.
+    pub name: ModuleId,
+    /// the export list, if any.
+    pub exports: Option<Vec<Item>>,
+    /// from `module` to `where`.
+    pub range: Range,
+}
+
+/// `import qualified M as A hiding (names)`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ImportDecl {
+    /// the imported module.
+    pub module: ModuleId,
+    /// whether it is a `qualified` import.
+    pub qualified: bool,
+    /// the name after `as`, if any.
+    pub alias: Option<ModuleId>,
+    /// whether the names are `hiding` ones.
+    pub hiding: bool,
+    /// the import list, if any.
+    pub names: Option<Vec<Item>>,
+    /// from `import` to the end of the declaration.
+    pub range: Range,
+}
+
+/// Associativity in a fixity declaration.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Assoc {
+    /// `infixl`.
+    Left,
+    /// `infixr`.
+    Right,
+    /// `infix`.
+    None,
+}
+
+/// `infixl 6 +, -`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct FixityDecl {
+    /// the associativity.
+    pub assoc: Assoc,
+    /// the precedence, if given.
+    pub precedence: Option<u8>,
+    /// the operators, back-ticked identifiers included (without the back-ticks).
+    pub operators: Vec<Name>,
+    /// from the keyword to the last operator.
+    pub range: Range,
+}
+
+/// An item in an import or export list.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Item {
+    /// a variable, an operator (without parentheses), or a type or a class
+    Name {
+        /// the name, qualified ones are only allowed in export lists.
+        name: QName,
+        /// the constructors, fields, or methods after it.
+        children: Children,
+    },
+    /// `module M`, in export lists.
+    Module(ModuleId),
+}
+
+/// The subordinate names of an [`Item`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Children {
+    /// nothing after the name.
+    None,
+    /// `(..)`.
+    All,
+    /// `(a, b, c)`: operators without parentheses.
+    Some(Vec<Name>),
+}
+
+/// A parse error: what was expected, and what was found instead.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ParseError {
+    /// what was expected.
+    pub expected: &'static str,
+    /// a description of what was found.
+    pub found: String,
+    /// where it was found: empty for phantom lexemes, right after the last real lexeme.
+    pub range: Range,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: expected {}, found {}", self.range, self.expected, self.found)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parse the module header, and the imports and the fixity declarations at the top level.
+///
+/// The other declarations are skipped, by matching the brackets. After a parse error, the
+/// rest of the declaration is skipped, up to the next `;` at the top level: the brackets it
+/// has opened before the error are matched as well.
+#[allow(clippy::type_complexity)]
+pub fn parse_decl_headers(tokens: impl Iterator<Item=AugmentedLexeme>)
+                          -> (Vec<ImportDecl>, Vec<FixityDecl>, Option<ModuleDecl>, Vec<ParseError>) {
+    let mut parser = Parser {
+        tokens: tokens.peekable(),
+        last_end: Location::new(),
+        open: (0, 0),
+        imports: Vec::new(),
+        fixities: Vec::new(),
+        errors: Vec::new(),
+    };
+    let module = parser.module();
+    parser.body();
+    (parser.imports, parser.fixities, module, parser.errors)
+}
+
+type PResult<T> = Result<T, ParseError>;
+
+struct Parser<I: Iterator<Item=AugmentedLexeme>> {
+    tokens: Peekable<I>,
+    // where the last real lexeme ends, for errors on phantom lexemes.
+    last_end: Location,
+    // the blocks and the other brackets opened since the start of the declaration, and not
+    // closed yet: where to resume after an error in it.
+    open: (usize, usize),
+    imports: Vec<ImportDecl>,
+    fixities: Vec<FixityDecl>,
+    errors: Vec<ParseError>,
+}
+
+fn is_open(t: &AugmentedLexeme) -> bool {
+    matches!(t, PhantomOpenCurlyBracket(_) |
+                Real(OpenCurlyBracket | OpenParenthesis | OpenSquareBracket | OpenUnboxedParenthesis, _))
+}
+
+fn is_close(t: &AugmentedLexeme) -> bool {
+    matches!(t, PhantomCloseCurlyBracket(_) |
+                Real(CloseCurlyBracket | CloseParenthesis | CloseSquareBracket | CloseUnboxedParenthesis, _))
+}
+
+fn is_separator(t: &AugmentedLexeme) -> bool {
+    matches!(t, PhantomSemicolon(_) | Real(Semicolon, _))
+}
+
+fn is_block_close(t: &AugmentedLexeme) -> bool {
+    matches!(t, PhantomCloseCurlyBracket(_) | Real(CloseCurlyBracket, _))
+}
+
+// the blocks and the other brackets still open after `t`.
+fn nest((blocks, brackets): (usize, usize), t: &AugmentedLexeme) -> (usize, usize) {
+    match t {
+        PhantomOpenCurlyBracket(_) | Real(OpenCurlyBracket, _) => (blocks + 1, brackets),
+        _ if is_block_close(t) => (blocks.saturating_sub(1), brackets),
+        _ if is_open(t) => (blocks, brackets + 1),
+        _ if is_close(t) => (blocks, brackets.saturating_sub(1)),
+        _ => (blocks, brackets),
+    }
+}
+
+fn is_conid(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| Large.check(c))
+}
+
+// `M.N.T` is lexed as a qualified identifier.
+fn module_id(lexeme: &Lexeme) -> Option<ModuleId> {
+    match lexeme {
+        Identifier(name) if is_conid(name) => Some(ModuleId(vec![name.clone()])),
+        QIdentifier(QName { module, name }) if is_conid(name) => {
+            let mut res = module.clone();
+            res.0.push(name.clone());
+            Some(res)
+        }
+        _ => None,
+    }
+}
+
+impl<I: Iterator<Item=AugmentedLexeme>> Parser<I> {
+    fn peek(&mut self) -> Option<&AugmentedLexeme> { self.tokens.peek() }
+
+    fn peek_lexeme(&mut self) -> Option<&Lexeme> {
+        self.tokens.peek().and_then(AugmentedLexeme::as_lexeme)
+    }
+
+    fn bump(&mut self) -> Option<AugmentedLexeme> {
+        let t = self.tokens.next()?;
+        if let Some(range) = t.range() { self.last_end = range.end }
+        self.open = nest(self.open, &t);
+        Some(t)
+    }
+
+    fn error<T>(&mut self, expected: &'static str) -> PResult<T> {
+        let (found, range) = match self.peek() {
+            Some(Real(lexeme, range)) => (lexeme.get_type().describe().to_string(), *range),
+            Some(PhantomSemicolon(_)) => ("the end of the declaration".to_string(), Range::point(self.last_end)),
+            Some(PhantomOpenCurlyBracket(_)) => ("a new block".to_string(), Range::point(self.last_end)),
+            Some(PhantomCloseCurlyBracket(_)) => ("the end of the block".to_string(), Range::point(self.last_end)),
+            Some(AugmentedLexeme::Eof(location)) => ("the end of input".to_string(), Range::point(*location)),
+            None => ("the end of input".to_string(), Range::point(self.last_end)),
+        };
+        Err(ParseError { expected, found, range })
+    }
+
+    fn eat(&mut self, lexeme: &Lexeme) -> bool {
+        if self.peek_lexeme() == Some(lexeme) {
+            self.bump();
+            true
+        } else { false }
+    }
+
+    fn expect(&mut self, lexeme: &Lexeme, expected: &'static str) -> PResult<()> {
+        if self.eat(lexeme) { Ok(()) } else { self.error(expected) }
+    }
+
+    fn eat_varid(&mut self, keyword: &str) -> bool {
+        if matches!(self.peek_lexeme(), Some(Identifier(x)) if x == keyword) {
+            self.bump();
+            true
+        } else { false }
+    }
+
+    fn module_id(&mut self) -> PResult<ModuleId> {
+        match self.peek_lexeme().and_then(module_id) {
+            Some(m) => {
+                self.bump();
+                Ok(m)
+            }
+            None => self.error("a module name"),
+        }
+    }
+
+    // module -> module modid [exports] where body | body
+    fn module(&mut self) -> Option<ModuleDecl> {
+        let begin = match self.peek() {
+            Some(Real(ReservedId(RId::Module), range)) => range.begin,
+            _ => return None,
+        };
+        self.bump();
+        match self.module_header(begin) {
+            Ok(decl) => Some(decl),
+            Err(err) => {
+                self.errors.push(err);
+                // skip to the body.
+                while !matches!(self.peek(), None | Some(AugmentedLexeme::Eof(_) |
+                                PhantomOpenCurlyBracket(_) | Real(OpenCurlyBracket, _))) {
+                    self.bump();
+                }
+                None
+            }
+        }
+    }
+
+    fn module_header(&mut self, begin: Location) -> PResult<ModuleDecl> {
+        let name = self.module_id()?;
+        let exports = self.item_list(true)?;
+        self.expect(&ReservedId(RId::Where), "`where`")?;
+        Ok(ModuleDecl { name, exports, range: Range { begin, end: self.last_end } })
+    }
+
+    // body -> { impdecls ; topdecls } | { impdecls } | { topdecls }
+    fn body(&mut self) {
+        match self.peek() {
+            Some(PhantomOpenCurlyBracket(_) | Real(OpenCurlyBracket, _)) => { self.bump(); }
+            _ => {
+                if let Err(err) = self.error::<()>("the module body") { self.errors.push(err) }
+                return;
+            }
+        }
+        loop {
+            match self.peek() {
+                None | Some(AugmentedLexeme::Eof(_)) => return,
+                Some(t) if is_block_close(t) => {
+                    self.bump();
+                    return;
+                }
+                Some(t) if is_separator(t) => { self.bump(); }
+                _ => {
+                    self.open = (0, 0);
+                    if let Err(err) = self.top_decl() { self.errors.push(err) }
+                    self.skip_decl();
+                }
+            }
+        }
+    }
+
+    // skip to the end of the declaration, i.e. the next `;` or `}` outside the blocks and the
+    // brackets, counting those the declaration has opened already. The layout algorithm ends a
+    // declaration in a bracket left open, e.g. `import M (` on its own line.
+    fn skip_decl(&mut self) {
+        let (mut blocks, mut brackets) = self.open;
+        while let Some(t) = self.peek() {
+            if matches!(t, AugmentedLexeme::Eof(_)) { return; }
+            let phantom = matches!(t, PhantomSemicolon(_) | PhantomCloseCurlyBracket(_));
+            if blocks == 0 && (brackets == 0 || phantom) && (is_separator(t) || is_block_close(t)) {
+                return;
+            }
+            (blocks, brackets) = nest((blocks, brackets), t);
+            self.bump();
+        }
+    }
+
+    fn top_decl(&mut self) -> PResult<()> {
+        let (assoc, begin) = match self.peek() {
+            Some(Real(ReservedId(RId::Import), range)) => {
+                let begin = range.begin;
+                self.bump();
+                let decl = self.import(begin)?;
+                self.end_of_decl()?;
+                self.imports.push(decl);
+                return Ok(());
+            }
+            Some(Real(ReservedId(RId::Infixl), range)) => (Assoc::Left, range.begin),
+            Some(Real(ReservedId(RId::Infixr), range)) => (Assoc::Right, range.begin),
+            Some(Real(ReservedId(RId::Infix), range)) => (Assoc::None, range.begin),
+            _ => return Ok(()),
+        };
+        self.bump();
+        let decl = self.fixity(assoc, begin)?;
+        self.end_of_decl()?;
+        self.fixities.push(decl);
+        Ok(())
+    }
+
+    fn end_of_decl(&mut self) -> PResult<()> {
+        match self.peek() {
+            None | Some(AugmentedLexeme::Eof(_)) => Ok(()),
+            Some(t) if is_separator(t) || is_close(t) => Ok(()),
+            _ => self.error("the end of the declaration"),
+        }
+    }
+
+    // impdecl -> import [qualified] modid [as modid] [impspec]
+    // impspec -> ( import1 , ... , importn [ , ] ) | hiding ( import1 , ... , importn [ , ] )
+    fn import(&mut self, begin: Location) -> PResult<ImportDecl> {
+        let qualified = self.eat_varid("qualified");
+        let module = self.module_id()?;
+        let alias = if self.eat_varid("as") { Some(self.module_id()?) } else { None };
+        let hiding = self.eat_varid("hiding");
+        let names = self.item_list(false)?;
+        if hiding && names.is_none() { return self.error("`(`"); }
+        Ok(ImportDecl { module, qualified, alias, hiding, names, range: Range { begin, end: self.last_end } })
+    }
+
+    // fixdecl -> infixl | infixr | infix [integer] ops
+    // ops     -> op1 , ... , opn
+    fn fixity(&mut self, assoc: Assoc, begin: Location) -> PResult<FixityDecl> {
+        let precedence = match self.peek_lexeme() {
+            Some(Integer(n)) => match n.to_u8() {
+                Some(n) if n <= 9 => {
+                    self.bump();
+                    Some(n)
+                }
+                _ => return self.error("a precedence from 0 to 9"),
+            },
+            _ => None,
+        };
+        let mut operators = vec![self.operator()?];
+        while self.eat(&Comma) { operators.push(self.operator()?) }
+        Ok(FixityDecl { assoc, precedence, operators, range: Range { begin, end: self.last_end } })
+    }
+
+    // op -> varop | conop, i.e. operators or back-ticked identifiers.
+    fn operator(&mut self) -> PResult<Name> {
+        match self.peek_lexeme() {
+            Some(Operator(op)) => {
+                let op = op.clone();
+                self.bump();
+                Ok(op)
+            }
+            Some(Backtick) => {
+                self.bump();
+                let name = match self.peek_lexeme() {
+                    Some(Identifier(x)) => x.clone(),
+                    _ => return self.error("an identifier"),
+                };
+                self.bump();
+                self.expect(&Backtick, "`` ` ``")?;
+                Ok(name)
+            }
+            _ => self.error("an operator"),
+        }
+    }
+
+    // exports -> ( export1 , ... , exportn [ , ] ), and the same for imports.
+    fn item_list(&mut self, export: bool) -> PResult<Option<Vec<Item>>> {
+        if !self.eat(&OpenParenthesis) { return Ok(None); }
+        self.comma_separated(|this| this.item(export)).map(Some)
+    }
+
+    // the rest of `( x1 , ... , xn [ , ] )`, after the `(`.
+    fn comma_separated<T>(&mut self, mut f: impl FnMut(&mut Self) -> PResult<T>) -> PResult<Vec<T>> {
+        let mut res = Vec::new();
+        while !self.eat(&CloseParenthesis) {
+            res.push(f(self)?);
+            if !self.eat(&Comma) {
+                self.expect(&CloseParenthesis, "`,` or `)`")?;
+                break;
+            }
+        }
+        Ok(res)
+    }
+
+    // export -> qvar | qtycon [(..) | ( cname1 , ... , cnamen )] | module modid
+    // import -> var | tycon [(..) | ( cname1 , ... , cnamen )]
+    fn item(&mut self, export: bool) -> PResult<Item> {
+        if export && self.eat(&ReservedId(RId::Module)) { return Ok(Item::Module(self.module_id()?)); }
+        let name = self.var(export)?;
+        let children = if !self.eat(&OpenParenthesis) {
+            Children::None
+        } else if self.eat(&ReservedOp(ROp::DotDot)) {
+            self.expect(&CloseParenthesis, "`)`")?;
+            Children::All
+        } else {
+            Children::Some(self.comma_separated(|this| Ok(this.var(false)?.name))?)
+        };
+        Ok(Item::Name { name, children })
+    }
+
+    // an identifier, or a parenthesised operator: qualified only if allowed.
+    fn var(&mut self, qualified: bool) -> PResult<QName> {
+        let parenthesised = self.eat(&OpenParenthesis);
+        let name = match self.peek_lexeme() {
+            Some(Identifier(x)) if !parenthesised => QName::new(x.clone()),
+            Some(QIdentifier(q)) if !parenthesised && qualified => q.clone(),
+            Some(Operator(x)) if parenthesised => QName::new(x.clone()),
+            Some(QOperator(q)) if parenthesised && qualified => q.clone(),
+            _ => return self.error(if parenthesised { "an operator" } else { "a name" }),
+        };
+        self.bump();
+        if parenthesised { self.expect(&CloseParenthesis, "`)`")? }
+        Ok(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use super::*;
+    use crate::scanner::layout::AugmentedLexemeIterator;
+
+    fn parse(source: &str) -> (Vec<ImportDecl>, Vec<FixityDecl>, Option<ModuleDecl>, Vec<ParseError>) {
+        parse_decl_headers(AugmentedLexemeIterator::new(source.as_bytes()))
+    }
+
+    fn module(path: &[&str]) -> ModuleId {
+        ModuleId(path.iter().map(|&x| x.into()).collect())
+    }
+
+    fn name(x: &str, children: Children) -> Item {
+        Item::Name { name: QName::new(x.into()), children }
+    }
+
+    #[test]
+    fn test_decl_headers() {
+        let (imports, fixities, header, errors) = parse(indoc! {"
+            module Data.Vec (Vec(..), (.+.), module Data.Vec.Internal) where
+
+            import Prelude hiding (lookup, (++))
+            import qualified Data.Map.Strict as M
+            import Data.List (Ord(compare, (<=)), sortBy,)
+            import Data.Vec.Internal
+
+            infixl 6 .+., `plus`
+            infixr .:
+
+            data Vec = Vec { x :: Int, y :: Int } deriving (Eq)
+
+            (.+.) :: Vec -> Vec -> Vec
+            Vec a b .+. Vec c d = Vec (a + c) (b + d)
+              where infix 4 ===
+        "});
+        assert!(errors.is_empty(), "{:?}", errors);
+        let header = header.expect("should have a module header");
+        assert_eq!(header.name, module(&["Data", "Vec"]));
+        assert_eq!(header.exports, Some(vec![
+            name("Vec", Children::All),
+            name(".+.", Children::None),
+            Item::Module(module(&["Data", "Vec", "Internal"])),
+        ]));
+        assert_eq!(header.range.to_string(), "1:1-1:65");
+        let summary: Vec<_> = imports.iter()
+            .map(|i| (i.module.0.join("."), i.qualified, i.alias.clone(), i.hiding, i.names.clone()))
+            .collect();
+        assert_eq!(summary, [
+            ("Prelude".to_string(), false, None, true,
+             Some(vec![name("lookup", Children::None), name("++", Children::None)])),
+            ("Data.Map.Strict".to_string(), true, Some(module(&["M"])), false, None),
+            ("Data.List".to_string(), false, None, false, Some(vec![
+                name("Ord", Children::Some(vec!["compare".into(), "<=".into()])),
+                name("sortBy", Children::None),
+            ])),
+            ("Data.Vec.Internal".to_string(), false, None, false, None),
+        ]);
+        assert_eq!(imports[1].range.to_string(), "4:1-4:38");
+        // the `infix` in the `where` is not at the top level.
+        assert_eq!(fixities, [
+            FixityDecl {
+                assoc: Assoc::Left,
+                precedence: Some(6),
+                operators: vec![".+.".into(), "plus".into()],
+                range: fixities[0].range,
+            },
+            FixityDecl {
+                assoc: Assoc::Right,
+                precedence: None,
+                operators: vec![".:".into()],
+                range: fixities[1].range,
+            },
+        ]);
+        assert_eq!(fixities[0].range.to_string(), "8:1-8:21");
+    }
+
+    #[test]
+    fn test_decl_header_errors() {
+        let (imports, fixities, header, errors) = parse(indoc! {"
+            import Data.Map (
+            import qualified as M
+            import Data.Set hiding
+            import lowercase
+            import Data.List (sortBy) extra
+            infix 10 +++
+            infixl 3
+            import Data.Maybe (fromMaybe)
+            main = pure ()
+        "});
+        assert!(header.is_none());
+        let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors, [
+            "1:18-1:18: expected a name, found the end of the declaration",
+            "2:18-2:20: expected a module name, found identifier",
+            "3:23-3:23: expected `(`, found the end of the declaration",
+            "4:8-4:17: expected a module name, found identifier",
+            "5:27-5:32: expected the end of the declaration, found identifier",
+            "6:7-6:9: expected a precedence from 0 to 9, found integer literal",
+            "7:9-7:9: expected an operator, found the end of the declaration",
+        ]);
+        // only the well-formed declarations are kept.
+        let modules: Vec<_> = imports.iter().map(|i| i.module.0.join(".")).collect();
+        assert_eq!(modules, ["Data.Maybe"]);
+        assert!(fixities.is_empty());
+    }
+
+    #[test]
+    fn test_error_in_item_list() {
+        // recovery resumes after the `)` of the list, not at it.
+        let (imports, fixities, _, errors) = parse("import A (x, (+) y, z)\nimport B\ninfixl 6 +++\n");
+        let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors, ["1:18-1:19: expected `,` or `)`, found identifier"]);
+        let modules: Vec<_> = imports.iter().map(|i| i.module.0.join(".")).collect();
+        assert_eq!(modules, ["B"]);
+        assert_eq!(fixities.len(), 1);
+    }
+}
@@ -0,0 +1,358 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Printing token streams back to source code.
+
+use std::fmt::{Display, Formatter};
+use crate::scanner::{Scanner, Location, Range, LexError};
+use crate::utils::Result3::{Success, FailFast, RetryLater};
+
+/// A token: its source text, and where it is in the source.
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Token {
+    /// the source text of this token.
+    pub text: String,
+    /// the source range of this token.
+    pub range: Range,
+}
+
+/// Errors from [`reconstruct`], for tokens with inconsistent ranges.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ReconstructError {
+    /// The token begins before the end of the previous token, and after its beginning.
+    Overlapping {
+        /// index of the offending token.
+        index: usize,
+        /// where the previous token ends.
+        previous_end: Location,
+    },
+    /// The token begins before the previous token.
+    OutOfOrder {
+        /// index of the offending token.
+        index: usize,
+        /// where the previous token begins.
+        previous_begin: Location,
+    },
+    /// The text of the token does not end where its range ends.
+    RangeMismatch {
+        /// index of the offending token.
+        index: usize,
+        /// where the text of the token actually ends.
+        actual_end: Location,
+    },
+}
+
+impl Display for ReconstructError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        use ReconstructError::*;
+        match self {
+            Overlapping { index, previous_end } => write!(
+                f, "token #{} overlaps with the previous token, which ends at {}",
+                index, previous_end),
+            OutOfOrder { index, previous_begin } => write!(
+                f, "token #{} is out of order: the previous token begins at {}",
+                index, previous_begin),
+            RangeMismatch { index, actual_end } => write!(
+                f, "token #{} does not fit in its range: the text ends at {}",
+                index, actual_end),
+        }
+    }
+}
+
+impl std::error::Error for ReconstructError {}
+
+// advance the location over the text, the same way as the scanner does.
+fn advance(location: &mut Location, text: &str) {
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        location.step();
+        match c {
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\r' | '\n' | '\u{C}' => location.newline(),
+            '\t' => location.tablise(),
+            _ => (),
+        }
+    }
+}
+
+/// Reconstruct the source from tokens, by placing each token's text at its recorded location,
+/// and filling the gaps with spaces and newlines.
+///
+/// Tokens must come in source order, with no overlaps, and each token's text must end exactly
+/// where its range ends.
+pub fn reconstruct(tokens: &[Token]) -> Result<String, ReconstructError> {
+    let mut res = String::new();
+    let mut current = Location::new();
+    let mut previous: Option<Range> = None;
+    for (index, token) in tokens.iter().enumerate() {
+        let begin = token.range.begin;
+        let position = (begin.line, begin.column);
+        if let Some(prev) = previous {
+            if position < (prev.begin.line, prev.begin.column) {
+                return Err(ReconstructError::OutOfOrder { index, previous_begin: prev.begin });
+            }
+            if position < (current.line, current.column) {
+                return Err(ReconstructError::Overlapping { index, previous_end: prev.end });
+            }
+        }
+        while current.line < begin.line {
+            res.push('\n');
+            current.step();
+            current.newline();
+        }
+        while current.column < begin.column {
+            res.push(' ');
+            current.step();
+        }
+        res += &token.text;
+        advance(&mut current, &token.text);
+        if (current.line, current.column) != (token.range.end.line, token.range.end.column) {
+            return Err(ReconstructError::RangeMismatch { index, actual_end: current });
+        }
+        previous = Some(token.range);
+    }
+    Ok(res)
+}
+
+/// A token, or the trivia (whitespaces and comments) between tokens.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum TriviaToken {
+    /// whitespaces and comments, verbatim.
+    Trivia(String),
+    /// a token.
+    Token(Token),
+}
+
+/// Split the source into tokens and the trivia between them, losslessly: concatenating all the
+/// texts gives back the source.
+pub fn tokenize(source: &str) -> Result<Vec<TriviaToken>, LexError> {
+    let mut res = Vec::new();
+    // the input layer strips the BOM, keep it as trivia instead.
+    let body = match source.strip_prefix('\u{FEFF}') {
+        Some(body) => {
+            res.push(TriviaToken::Trivia(String::from('\u{FEFF}')));
+            body
+        }
+        None => source,
+    };
+    // byte indices of the characters, as locations count characters only.
+    let indices: Vec<usize> = body.char_indices().map(|(k, _)| k)
+        .chain(std::iter::once(body.len())).collect();
+    let text = |begin: Location, end: Location| {
+        String::from(&body[indices[begin.offset]..indices[end.offset]])
+    };
+    let mut scanner = Scanner::new(body.as_bytes());
+    loop {
+        let begin = scanner.location();
+        if let FailFast(err) = scanner.skip_trivia() { return Err(err); }
+        let end = scanner.location();
+        if begin != end { res.push(TriviaToken::Trivia(text(begin, end))) }
+        match scanner.next_lexeme() {
+            Success(_) => {
+                let range = Range { begin: end, end: scanner.location() };
+                res.push(TriviaToken::Token(Token { text: text(range.begin, range.end), range }))
+            }
+            FailFast(err) => return Err(err),
+            RetryLater(()) => return Ok(res),
+        }
+    }
+}
+
+/// The tokens of the source at the given ranges, e.g. of the fat lexemes: the token stream which
+/// [`reconstruct`] prints back, and the one `lex --output json` prints for `untokenize`.
+pub fn tokens_at(source: &str, ranges: impl IntoIterator<Item=Range>) -> Vec<Token> {
+    // as in `tokenize`: no BOM, and locations count characters only.
+    let body = source.strip_prefix('\u{FEFF}').unwrap_or(source);
+    let indices: Vec<usize> = body.char_indices().map(|(k, _)| k)
+        .chain(std::iter::once(body.len())).collect();
+    let index = |location: Location| indices.get(location.offset).copied().unwrap_or(body.len());
+    ranges.into_iter().map(|range| Token {
+        text: String::from(&body[index(range.begin)..index(range.end)]),
+        range,
+    }).collect()
+}
+
+// whether the two tokens, without space in between, would lex differently.
+fn glues(left: &str, right: &str) -> bool {
+    // a line comment must end with a newline.
+    let tokens = match tokenize(&format!("{}{}\n", left, right)) {
+        Ok(tokens) => tokens,
+        Err(_) => return true,
+    };
+    match tokens.as_slice() {
+        [TriviaToken::Token(x), TriviaToken::Token(y), TriviaToken::Trivia(_)] =>
+            x.text != left || y.text != right,
+        _ => true,
+    }
+}
+
+/// Rewrite the tokens with `f`, and print them back.
+///
+/// `f` returns `None` to keep a token, or the tokens (only their texts matter) to replace it
+/// with, possibly none. All the trivia is kept verbatim. A space is inserted between a
+/// replacement token and a token right next to it, when they would lex differently without.
+pub fn rewrite(tokens: Vec<TriviaToken>, mut f: impl FnMut(&Token) -> Option<Vec<Token>>)
+               -> String {
+    // the last token printed, if no trivia follows it, and whether it is next to a replacement.
+    type Last = Option<(String, bool)>;
+    fn emit(res: &mut String, last: &mut Last, text: String, replaced: bool) {
+        if let Some((prev, prev_replaced)) = last {
+            if (replaced || *prev_replaced) && glues(prev, &text) { res.push(' ') }
+        }
+        *res += &text;
+        *last = Some((text, replaced));
+    }
+    let mut res = String::new();
+    let mut last: Last = None;
+    for token in tokens {
+        match token {
+            TriviaToken::Trivia(trivia) => {
+                res += &trivia;
+                last = None;
+            }
+            TriviaToken::Token(token) => match f(&token) {
+                None => emit(&mut res, &mut last, token.text, false),
+                Some(replacement) => {
+                    // a deleted token still separates its neighbours.
+                    if let Some((_, replaced)) = &mut last { *replaced = true }
+                    for token in replacement { emit(&mut res, &mut last, token.text, true) }
+                }
+            },
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Token, reconstruct, ReconstructError, tokenize, tokens_at, rewrite, TriviaToken};
+    use crate::scanner::{Location, Range};
+
+    fn token(text: &str, begin: (usize, usize), end: (usize, usize)) -> Token {
+        let location = |(line, column)| Location { line, column, char_column: column, offset: 0 };
+        Token {
+            text: text.to_string(),
+            range: Range { begin: location(begin), end: location(end) },
+        }
+    }
+
+    #[test]
+    fn test_reconstruct() {
+        let tokens = [
+            token("main", (1, 1), (1, 5)),
+            token("=", (1, 6), (1, 7)),
+            token("do", (1, 8), (1, 10)),
+            token("putStrLn", (2, 3), (2, 11)),
+            token("\"Hello, \\\n  \\world\"", (2, 12), (3, 10)),
+            token("{- a\n   comment -}", (5, 1), (6, 14)),
+        ];
+        assert_eq!(reconstruct(&tokens), Ok(String::from(
+            "main = do\n  putStrLn \"Hello, \\\n  \\world\"\n\n{- a\n   comment -}")));
+        assert_eq!(reconstruct(&[]), Ok(String::new()));
+    }
+
+    #[test]
+    fn test_reconstruct_errors() {
+        let tokens = [token("abc", (1, 1), (1, 4)), token("d", (1, 3), (1, 4))];
+        assert_eq!(reconstruct(&tokens), Err(ReconstructError::Overlapping {
+            index: 1,
+            previous_end: tokens[0].range.end,
+        }));
+        let tokens = [token("abc", (2, 1), (2, 4)), token("d", (1, 3), (1, 4))];
+        assert_eq!(reconstruct(&tokens), Err(ReconstructError::OutOfOrder {
+            index: 1,
+            previous_begin: tokens[0].range.begin,
+        }));
+        let tokens = [token("abc", (1, 1), (1, 5))];
+        let err = reconstruct(&tokens).unwrap_err();
+        assert_eq!(err.to_string(), "token #0 does not fit in its range: the text ends at 1:4");
+    }
+
+    #[test]
+    fn test_tokenize_lossless() {
+        let source = "\u{FEFF}module M where {- λ -}\n\tf x=x  -- id\n";
+        let tokens = tokenize(source).unwrap();
+        let text: String = tokens.iter().map(|t| match t {
+            TriviaToken::Trivia(s) => s.as_str(),
+            TriviaToken::Token(t) => t.text.as_str(),
+        }).collect();
+        assert_eq!(text, source);
+        let names: Vec<_> = tokens.iter().filter_map(|t| match t {
+            TriviaToken::Token(t) => Some(t.text.as_str()),
+            _ => None,
+        }).collect();
+        assert_eq!(names, ["module", "M", "where", "f", "x", "=", "x"]);
+    }
+
+    #[test]
+    fn test_tokens_round_trip() {
+        // the JSON of `lex --output json`, back to the source by `untokenize`.
+        let source = "\u{FEFF}module M where\n\tf x = \"\u{3BB}\" {- gone -}\n  y = 'c'\n";
+        let lexemes = crate::scanner::layout::FatLexemeIterator::new(source.as_bytes());
+        let tokens = tokens_at(source, lexemes.map(|(_, range)| range));
+        #[cfg(feature = "serde")]
+        let tokens: Vec<Token> = serde_json::from_str(&serde_json::to_string(&tokens).unwrap()).unwrap();
+        assert_eq!(reconstruct(&tokens), Ok(String::from(
+            "module M where\n        f x = \"\u{3BB}\"\n  y = 'c'")));
+    }
+
+    #[test]
+    fn test_rewrite_rename() {
+        let source = indoc::indoc! {r#"
+            module Main where
+            -- foo: the answer {- not nested -}
+            foo :: Int
+            foo = {- foo -} 42--foo
+            main = print (foo+foo) >> print"foo"
+        "#};
+        let expected = indoc::indoc! {r#"
+            module Main where
+            -- foo: the answer {- not nested -}
+            bar1 :: Int
+            bar1 = {- foo -} 42--foo
+            main = print (bar1+bar1) >> print"foo"
+        "#};
+        let rename = |t: &Token| match t.text.as_str() {
+            "foo" => Some(vec![token("bar1", (1, 1), (1, 5))]),
+            _ => None,
+        };
+        assert_eq!(rewrite(tokenize(source).unwrap(), rename), expected);
+    }
+
+    #[test]
+    fn test_rewrite_no_reglue() {
+        let replace = |from: &'static str, to: &'static [&'static str]| move |t: &Token| {
+            if t.text != from { return None; }
+            Some(to.iter().map(|s| token(s, (1, 1), (1, 1))).collect())
+        };
+        let run = |source, f| rewrite(tokenize(source).unwrap(), f);
+        // operators glue with operators.
+        assert_eq!(run("a+b", replace("b", &["*"])), "a+ *");
+        // dashes start comments.
+        assert_eq!(run("a+b", replace("+", &["--"])), "a -- b");
+        // deletion makes neighbours adjacent.
+        assert_eq!(run("a+b", replace("+", &[])), "a b");
+        assert_eq!(run("(+)b", replace("+", &[])), "()b");
+        // several tokens in place of one.
+        assert_eq!(run("f(x)", replace("x", &["y", "+", "1"])), "f(y+1)");
+        assert_eq!(run("f(x)", replace("x", &["y", "z"])), "f(y z)");
+        // untouched tokens stay as they are, even if adjacent to each other.
+        assert_eq!(run("x+y", replace("z", &["w"])), "x+y");
+    }
+}
@@ -18,11 +18,11 @@
 
 //! View into an [`Rc`], focus on a part of the whole data.
 
-use std::ops::Deref;
-use std::ptr::NonNull;
-use std::rc::Rc;
-use std::cell::UnsafeCell;
-use std::fmt::Formatter;
+use core::ops::Deref;
+use core::ptr::NonNull;
+use alloc::rc::Rc;
+use core::cell::UnsafeCell;
+use core::fmt::Formatter;
 
 /// A view into an [`Rc`].
 pub struct RcView<T: ?Sized, U: ?Sized> {
@@ -40,12 +40,12 @@ impl<T: ?Sized> From<Rc<T>> for RcView<T, T> {
     }
 }
 
-impl<T: ?Sized, U: std::fmt::Debug + ?Sized> std::fmt::Debug for RcView<T, U> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { self.deref().fmt(f) }
+impl<T: ?Sized, U: core::fmt::Debug + ?Sized> core::fmt::Debug for RcView<T, U> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result { self.deref().fmt(f) }
 }
 
-impl<T: ?Sized, U: std::fmt::Display + ?Sized> std::fmt::Display for RcView<T, U> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { self.deref().fmt(f) }
+impl<T: ?Sized, U: core::fmt::Display + ?Sized> core::fmt::Display for RcView<T, U> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result { self.deref().fmt(f) }
 }
 
 impl<T: ?Sized, U: ?Sized> RcView<T, U> {
@@ -0,0 +1,333 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Diagnostics as a [SARIF 2.1.0](https://docs.oasis-open.org/sarif/sarif/v2.1.0/) log, for the
+//! CI systems to ingest.
+//!
+//! Only the minimal part of the format is produced: one run of this tool, and one result per
+//! diagnostic, with its stable code as the rule, and its primary range as the location. The
+//! labels are left out. Columns are counted in Unicode code points, as SARIF does by default.
+
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+
+use crate::error::{Diagnostic, DiagnosticMessage, Severity};
+use crate::location::Range;
+use crate::scanner::LexError;
+
+/// The schema of the logs produced.
+pub const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+/// The version of SARIF produced.
+pub const VERSION: &str = "2.1.0";
+
+/// A SARIF log, with a single run.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SarifLog {
+    /// the JSON schema, see [`SCHEMA`].
+    #[serde(rename = "$schema")]
+    pub schema: String,
+    /// the SARIF version, see [`VERSION`].
+    pub version: String,
+    /// the runs of the tool: only one.
+    pub runs: Vec<Run>,
+}
+
+/// A run of the tool, on one or more files.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Run {
+    /// the tool producing the results.
+    pub tool: Tool,
+    /// how columns are counted: `"unicodeCodePoints"`.
+    pub column_kind: String,
+    /// the results, file by file, in source order in each file.
+    pub results: Vec<SarifResult>,
+}
+
+/// The tool of a [`Run`].
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Tool {
+    /// the tool itself.
+    pub driver: ToolComponent,
+}
+
+/// The name and version of a tool.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolComponent {
+    /// the name of the tool, from the Cargo metadata.
+    pub name: String,
+    /// the version of the tool, from the Cargo metadata.
+    pub version: String,
+    /// where to find out about the tool.
+    pub information_uri: String,
+}
+
+/// A result: a diagnostic, or the lexical error.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifResult {
+    /// the stable code of the diagnostic, none for the lexical error.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule_id: Option<String>,
+    /// `"note"`, `"warning"`, or `"error"`.
+    pub level: String,
+    /// what is wrong.
+    pub message: Message,
+    /// where: exactly one location.
+    pub locations: Vec<ResultLocation>,
+}
+
+/// The message of a [`SarifResult`].
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Message {
+    /// the message, as plain text.
+    pub text: String,
+}
+
+/// The location of a [`SarifResult`].
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResultLocation {
+    /// the range in the file.
+    pub physical_location: PhysicalLocation,
+}
+
+/// A range in a file.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PhysicalLocation {
+    /// the file.
+    pub artifact_location: ArtifactLocation,
+    /// the range.
+    pub region: Region,
+}
+
+/// A file, by its URI.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ArtifactLocation {
+    /// the URI of the file: relative paths are kept relative.
+    pub uri: String,
+}
+
+/// A range in a file, all 1-based, the end column exclusive.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Region {
+    /// the line the range begins on.
+    pub start_line: usize,
+    /// the column the range begins at.
+    pub start_column: usize,
+    /// the line the range ends on.
+    pub end_line: usize,
+    /// the column right after the range.
+    pub end_column: usize,
+}
+
+impl Region {
+    /// The region of `range` in `source`: the lines are those of the range (after the line
+    /// pragmas), but the columns are counted in code points from the offsets, tabs included.
+    pub fn new(source: &LineStarts, range: Range) -> Self {
+        Region {
+            start_line: range.begin.line,
+            start_column: source.column(range.begin.offset),
+            end_line: range.end.line,
+            end_column: source.column(range.end.offset),
+        }
+    }
+}
+
+/// Where the lines of a source begin, in characters, for [`Region::new`].
+#[derive(Clone, Debug)]
+pub struct LineStarts(Vec<usize>);
+
+impl LineStarts {
+    /// The line starts of `source`, at the same newlines as the scanner: `\r\n`, `\r`, `\n`,
+    /// and form feeds.
+    pub fn new(source: &str) -> Self {
+        let mut starts = vec![0];
+        let mut chars = source.chars().enumerate().peekable();
+        while let Some((k, c)) = chars.next() {
+            match c {
+                '\r' if chars.peek().map(|&(_, c)| c) == Some('\n') => (),
+                '\r' | '\n' | '\u{C}' => starts.push(k + 1),
+                _ => (),
+            }
+        }
+        LineStarts(starts)
+    }
+
+    /// The column of a character offset, starting from 1.
+    pub fn column(&self, offset: usize) -> usize {
+        let line = self.0.partition_point(|&start| start <= offset) - 1;
+        offset - self.0[line] + 1
+    }
+}
+
+impl Default for SarifLog {
+    fn default() -> Self {
+        let driver = ToolComponent {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            information_uri: env!("CARGO_PKG_REPOSITORY").to_string(),
+        };
+        SarifLog {
+            schema: SCHEMA.to_string(),
+            version: VERSION.to_string(),
+            runs: vec![Run {
+                tool: Tool { driver },
+                column_kind: "unicodeCodePoints".to_string(),
+                results: Vec::new(),
+            }],
+        }
+    }
+}
+
+impl SarifLog {
+    /// An empty log: one run of this tool, without any result.
+    pub fn new() -> Self { Self::default() }
+
+    /// Add the results for a file to the run: its diagnostics, and the lexical error if any.
+    pub fn add_file(&mut self, path: &Path, source: &str, diagnostics: &[Diagnostic],
+                    lex_error: Option<&LexError>) {
+        let uri = file_uri(path);
+        let lines = LineStarts::new(source);
+        let location = |range| location(&uri, Region::new(&lines, range));
+        let results = &mut self.runs[0].results;
+        results.extend(diagnostics.iter().map(|d| SarifResult {
+            rule_id: Some(d.code().to_string()),
+            level: level(d.severity()).to_string(),
+            message: Message { text: message_text(d.message()) },
+            locations: vec![location(d.range())],
+        }));
+        results.extend(lex_error.map(|err| SarifResult {
+            rule_id: None,
+            level: level(Severity::Error).to_string(),
+            message: Message { text: format!("lexical error at {}", err) },
+            locations: vec![location(Range::point(err.location))],
+        }));
+    }
+}
+
+fn level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Note => "note",
+        Severity::Warning => "warning",
+        Severity::Error | Severity::Fatal => "error",
+    }
+}
+
+// without the severity in front: that goes to the level.
+fn message_text(message: &DiagnosticMessage) -> String {
+    match message {
+        DiagnosticMessage::Error(err) => err.to_string(),
+        DiagnosticMessage::Warning(warn) => warn.to_string(),
+        DiagnosticMessage::Note(note) => note.to_string(),
+    }
+}
+
+fn location(uri: &str, region: Region) -> ResultLocation {
+    ResultLocation {
+        physical_location: PhysicalLocation {
+            artifact_location: ArtifactLocation { uri: uri.to_string() },
+            region,
+        },
+    }
+}
+
+/// The URI of a file: a relative reference for a relative path, and a `file` URI otherwise,
+/// percent-encoded but for the unreserved characters and the separators.
+pub fn file_uri(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    let path = if cfg!(windows) { path.replace('\\', "/") } else { path.into_owned() };
+    let mut uri = String::new();
+    if path.starts_with('/') {
+        uri.push_str("file://");
+    } else if path.as_bytes().get(1) == Some(&b':') {
+        // a drive letter.
+        uri.push_str("file:///");
+    }
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' =>
+                uri.push(char::from(b)),
+            _ => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    uri
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{file_uri, LineStarts, SarifLog};
+    use crate::driver::{check, CheckOptions};
+
+    #[test]
+    fn test_sarif_log() {
+        let mut log = SarifLog::new();
+        let source = "main = do\n\tpure ()\n";
+        let tabs = check(source.as_bytes(), CheckOptions::default());
+        log.add_file(Path::new("A.hs"), source, &tabs.diagnostics, tabs.lex_error.as_ref());
+        let source = "x = \u{7F}\n";
+        let broken = check(source.as_bytes(), CheckOptions::default());
+        log.add_file(Path::new("dir/B C.hs"), source, &broken.diagnostics, broken.lex_error.as_ref());
+
+        let json: serde_json::Value = serde_json::from_str(&serde_json::to_string(&log).unwrap()).unwrap();
+        assert_eq!(json["version"], "2.1.0");
+        assert_eq!(json["$schema"], super::SCHEMA);
+        let runs = json["runs"].as_array().unwrap();
+        assert_eq!(runs.len(), 1, "all the files in one run");
+        assert_eq!(runs[0]["tool"]["driver"]["name"], "mini-haskell");
+        assert_eq!(runs[0]["tool"]["driver"]["version"], env!("CARGO_PKG_VERSION"));
+
+        let results = runs[0]["results"].as_array().unwrap();
+        let summary: Vec<_> = results.iter().map(|r| {
+            let location = &r["locations"][0]["physicalLocation"];
+            let region = &location["region"];
+            format!("{} {} {} {}:{}-{}:{}", r["ruleId"].as_str().unwrap_or("-"), r["level"].as_str().unwrap(),
+                    location["artifactLocation"]["uri"].as_str().unwrap(),
+                    region["startLine"], region["startColumn"], region["endLine"], region["endColumn"])
+        }).collect();
+        let tab = tabs.diagnostics[0].code();
+        assert_eq!(summary, [
+            format!("{} warning A.hs 2:1-2:2", tab),
+            "- error dir/B%20C.hs 1:5-1:5".to_string(),
+        ]);
+        assert!(results.iter().all(|r| !r["message"]["text"].as_str().unwrap().is_empty()));
+        // and back.
+        assert_eq!(serde_json::from_value::<SarifLog>(json).unwrap(), log);
+    }
+
+    #[test]
+    fn test_line_starts() {
+        let lines = LineStarts::new("ab\r\nc\rd\n\u{C}\tλx");
+        let columns: Vec<_> = (0..12).map(|k| lines.column(k)).collect();
+        assert_eq!(columns, [1, 2, 3, 4, 1, 2, 1, 2, 1, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_file_uri() {
+        assert_eq!(file_uri(Path::new("src/Main.hs")), "src/Main.hs");
+        assert_eq!(file_uri(Path::new("a b/#1.hs")), "a%20b/%231.hs");
+        assert_eq!(file_uri(Path::new("λ.hs")), "%CE%BB.hs");
+        if cfg!(unix) { assert_eq!(file_uri(Path::new("/tmp/M.hs")), "file:///tmp/M.hs") }
+    }
+}
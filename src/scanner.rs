@@ -25,105 +25,194 @@ pub mod numeric;
 pub mod char_string;
 pub mod special;
 pub mod layout;
+pub mod single;
+pub mod metrics;
+pub mod extensions;
+pub mod lints;
+pub mod brackets;
 
 use std::fmt::{Formatter, Display};
 use crate::utils::*;
-use crate::utils::Result3::{FailFast, RetryLater};
+use crate::utils::Result3::{Success, FailFast, RetryLater};
 use crate::utils::char::{CharPredicate, Stream};
-use crate::input::Input;
-use crate::lexeme::{LexemeType, Lexeme};
+use std::rc::Rc;
+use std::sync::Arc;
+use crate::input::{Input, InputStats, InputProblem, Encoding, Utf8Error};
+use crate::input::{DEFAULT_BUF_SIZE, DEFAULT_IO_RETRIES};
+use crate::lexeme::{LexemeType, Lexeme, Name, QName};
+use crate::utils::normalization::{nfc, is_nfc};
+use crate::source::FileId;
 use crate::error::{
-    Diagnostic, DiagnosticsEngine, DiagnosticMessage::Error,
-    Error::{InvalidUTF8, InputFailure, InvalidChar},
+    Checkpoint, Diagnostic, DiagnosticsEngine, DiagnosticMessage::{self, Error}, Note, Warning,
+    Error::{InvalidUTF8, InputFailure, InvalidChar, ControlChar, TokenTooLong, NonProgressingRule},
 };
-use crate::scanner::basic::Any;
+use crate::scanner::basic::{Any, Control, BidiControl};
+pub use crate::location::{Location, Range, ColumnMode, SourceMap};
+pub use layout::find_top_level;
+pub use extensions::GhcExtensions;
+pub use whitespace::{Trivia, TriviaKind};
 
-/// Source location.
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
-pub struct Location {
-    /// line number, starting from 1.
-    pub line: usize,
-    /// column number, starting from 1.
-    pub column: usize,
-    /// offset into the source file, starting from 0.
-    pub offset: usize,
+/// Scanner state to revert to after a failed attempt.
+struct Anchor<I> {
+    input: Input<I>,
+    location: Location,
+    diagnostics: Checkpoint,
 }
 
-impl Default for Location {
-    fn default() -> Self { Location { line: 1, column: 1, offset: 0 } }
+/// How far the scanner has got in the input, for progress reporting: the total size is
+/// unknown, for the input is read lazily.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct Progress {
+    /// bytes of the characters consumed so far, in UTF-8 (invalid sequences not included).
+    pub consumed_bytes: usize,
+    /// characters consumed so far.
+    pub consumed_chars: usize,
+    /// whether the end of input has been observed.
+    pub eof_seen: bool,
 }
 
-impl Display for Location {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}:{}", self.line, self.column)
-    }
+/// Limits and buffering of the scanner, see [`Scanner::with_config`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ScannerConfig {
+    /// the maximum absolute exponent of a float literal, larger ones are reported as
+    /// [`FloatOutOfBound`](crate::error::Error::FloatOutOfBound).
+    pub max_float_exponent: i64,
+    /// the size (in bytes) of the segments the input is read in.
+    pub buf_size: usize,
+    /// how many times in a row an interrupted read is retried.
+    pub io_retries: u8,
+    /// the error budget, see [`DiagnosticsEngine::set_max_errors`]: past it, the scanner
+    /// stops as if at the end of input.
+    pub max_errors: usize,
+    /// what to do with invalid UTF-8 sequences, unless decoded as Latin-1.
+    pub utf8_policy: Utf8Policy,
+    /// how to report the bidirectional control characters.
+    pub bidi_policy: BidiPolicy,
 }
 
-impl Location {
-    /// Size of a Tab stop.
-    pub const TAB_SIZE: usize = 8;
-
-    /// Create a new location, the same as `Location::default()`.
-    pub fn new() -> Self { Self::default() }
-
-    /// Step one character.
-    pub fn step(&mut self) {
-        self.column += 1;
-        self.offset += 1;
-    }
-
-    /// Start a new line.
-    pub fn newline(&mut self) {
-        self.column = 1;
-        self.line += 1;
-    }
-
-    /// Align to the next tab stop.
-    pub fn tablise(&mut self) {
-        self.step();
-        self.column = round_to(self.column, Self::TAB_SIZE);
-    }
+/// What the scanner does with invalid UTF-8 sequences, see [`ScannerConfig::utf8_policy`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum Utf8Policy {
+    /// skip them, and report each as [`InvalidUTF8`].
+    #[default]
+    Recover,
+    /// stop at the first one as if at the end of input, and fail with it: see
+    /// [`LexError::invalid_utf8`]. The lexeme cut there is dropped, with what it reported.
+    Fail,
 }
 
-/// A half-open source range: a pair of `Location`s.
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
-pub struct Range {
-    /// Where the range begins (inclusive).
-    pub begin: Location,
-    /// Where the range ends (non-inclusive).
-    pub end: Location,
+/// How the scanner reports the bidirectional control characters (U+202A to U+202E, and U+2066
+/// to U+2069), see [`ScannerConfig::bidi_policy`]. They are reported wherever they are, in
+/// comments and literals too, and kept as they are.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum BidiPolicy {
+    /// as [`Warning::BidiControl`].
+    #[default]
+    Warn,
+    /// as [`Error::BidiControl`](crate::error::Error::BidiControl).
+    Error,
 }
 
-impl Display for Range {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}-{}", self.begin, self.end)
+impl Default for ScannerConfig {
+    fn default() -> Self {
+        ScannerConfig {
+            max_float_exponent: numeric::MAXIMUM_EXPONENT,
+            buf_size: DEFAULT_BUF_SIZE,
+            io_retries: DEFAULT_IO_RETRIES,
+            max_errors: DiagnosticsEngine::DEFAULT_MAX_ERRORS,
+            utf8_policy: Utf8Policy::default(),
+            bidi_policy: BidiPolicy::default(),
+        }
     }
 }
 
+/// A callback receiving [`Progress`] reports.
+pub type ProgressCallback = Box<dyn FnMut(Progress)>;
+
 /// Scanner with a back buffer.
 pub struct Scanner<I> {
     input: Input<I>,
     location: Location,
     diagnostics: DiagnosticsEngine,
+    // the first tab on the line of the most recent one, for checking the indentation.
+    line_tab: Option<Location>,
+    // the non-ASCII whitespaces in the most recent whitespace run.
+    confusables: Vec<(Location, char)>,
+    // the comments and directives in the most recent whitespace run.
+    trivia: Vec<Trivia>,
+    // blank lines in the most recent whitespace run, and whether the current line is blank.
+    blank_lines: u32,
+    line_blank: bool,
+    max_token_length: usize,
+    max_float_exponent: i64,
+    input_stats: Rc<InputStats>,
+    // whether the Latin-1 fallback is already noted.
+    latin1_noted: bool,
+    // with `Utf8Policy::Fail`, where to keep the first invalid sequence.
+    utf8_failure: Option<Option<Utf8Error>>,
+    bidi_policy: BidiPolicy,
+    column_mode: ColumnMode,
+    extensions: GhcExtensions,
+    line_pragmas: bool,
+    nfc_identifiers: bool,
+    // whether the rules build the payloads of the lexemes, see `next_lexeme_type`.
+    payloads: bool,
+    source_map: SourceMap,
+    progress: Progress,
+    progress_callback: Option<(usize, ProgressCallback)>,
+}
+
+/// Cloning a scanner does not need `I: Clone`: the reader is kept by the shared input
+/// buffer, and read once for all the clones, by whichever needs the next segment first.
+///
+/// The clone has its own copy of the diagnostics, see [`SharedDiagnostics`] for the other
+/// way around, and no progress callback.
+///
+/// [`SharedDiagnostics`]: crate::error::SharedDiagnostics
+impl<I> Clone for Scanner<I> {
+    fn clone(&self) -> Self {
+        Scanner {
+            input: self.input.clone(),
+            location: self.location,
+            diagnostics: self.diagnostics.clone(),
+            line_tab: self.line_tab,
+            confusables: self.confusables.clone(),
+            trivia: self.trivia.clone(),
+            blank_lines: self.blank_lines,
+            line_blank: self.line_blank,
+            max_token_length: self.max_token_length,
+            max_float_exponent: self.max_float_exponent,
+            input_stats: self.input_stats.clone(),
+            latin1_noted: self.latin1_noted,
+            utf8_failure: self.utf8_failure.clone(),
+            bidi_policy: self.bidi_policy,
+            column_mode: self.column_mode,
+            extensions: self.extensions,
+            line_pragmas: self.line_pragmas,
+            nfc_identifiers: self.nfc_identifiers,
+            payloads: self.payloads,
+            source_map: self.source_map.clone(),
+            progress: self.progress,
+            progress_callback: None,
+        }
+    }
 }
 
 impl<I: std::io::Read> Stream for Scanner<I> {
+    #[inline]
     fn peek(&mut self) -> Option<char> {
-        match self.input.clone().next(|s| Diagnostic::new(
-            self.location, Error(InvalidUTF8(Vec::from(s))))
-            .report(&mut self.diagnostics)) {
-            Ok((c, _)) => Some(c),
-            Err(_) => None,
-        }
+        self.read_input().map(|(c, _)| c)
     }
 
     fn next(&mut self) -> Option<char> {
         let res = self.next_input();
         if let Some(x) = res {
-            self.location.step();
+            let begin = self.location;
+            self.location.step_char(x, self.column_mode);
             // ANY        -> graphic | whitechar
-            if !Any.check(x) {
-                Diagnostic::new(self.location, Error(InvalidChar(x)))
+            if !self.check_bidi_control(x, begin) && !Any.check(x) {
+                let error = if Control.check(x) { ControlChar(x, None) } else { InvalidChar(x) };
+                Diagnostic::new(Range { begin, end: self.location }, Error(error))
                     .report(&mut self.diagnostics);
             }
         }
@@ -131,33 +220,118 @@ impl<I: std::io::Read> Stream for Scanner<I> {
     }
 
     fn r#match<'a>(&mut self, s: &'a str) -> Option<&'a str> {
-        self.input.clone().r#match(s, |s|
-            Diagnostic::new(self.location, Error(InvalidUTF8(Vec::from(s))))
-                .report(&mut self.diagnostics),
-        ).map(|rest| {
-            self.input = rest;
-            s
-        })
+        let (diagnostics, noted) = (&mut self.diagnostics, &mut self.latin1_noted);
+        let failure = &mut self.utf8_failure;
+        let location = self.location;
+        let rest = self.input.clone()
+            .r#match(s, |p| report_input_problem(diagnostics, noted, failure, location, p))?;
+        // never match past an invalid sequence, when stopping there.
+        if self.utf8_error().is_some() { return None; }
+        self.input = rest;
+        // keep the location (and the progress) as if the characters were read one by one.
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            self.count_progress(c);
+            self.location.step_char(c, self.column_mode);
+            match c {
+                '\r' if chars.peek() == Some(&'\n') => (),
+                '\r' | '\n' | '\u{C}' => self.location.newline(),
+                '\t' => self.location.tablise(),
+                _ => (),
+            }
+        }
+        Some(s)
+    }
+
+    fn span<T>(&mut self, mut f: impl FnMut(char) -> bool,
+               init: T, mut join: impl FnMut(&mut T, char)) -> T {
+        let mut res = init;
+        while let Some(x) = self.peek() {
+            if !f(x) { break; }
+            join(&mut res, x);
+            let offset = self.location.offset;
+            self.next();
+            if !self.check_progress(offset) { break; }
+        }
+        res
     }
 }
 
+fn report_input_problem(diagnostics: &mut DiagnosticsEngine, latin1_noted: &mut bool,
+                        utf8_failure: &mut Option<Option<Utf8Error>>,
+                        location: Location, problem: InputProblem) {
+    let message = match problem {
+        InputProblem::InvalidUtf8(s, offset) => match utf8_failure {
+            // the first one stops the scanner: nothing after it is ever reported.
+            Some(failure) => {
+                failure.get_or_insert_with(|| Utf8Error { offset, bytes: Vec::from(s) });
+                return;
+            }
+            None => Error(InvalidUTF8(Vec::from(s))),
+        },
+        // decoded fine, noted only once: legacy sources tend to have plenty of these.
+        InputProblem::Latin1(..) if *latin1_noted => return,
+        InputProblem::Latin1(..) => {
+            *latin1_noted = true;
+            DiagnosticMessage::Note(Note::Latin1Fallback)
+        }
+    };
+    Diagnostic::new(Range::point(location), message).report(diagnostics)
+}
+
 impl<I: std::io::Read> Scanner<I> {
+    // every character consumed goes through here, see `BidiPolicy`: whether `c` (just consumed,
+    // from `begin`) is a bidirectional control character.
+    fn check_bidi_control(&mut self, c: char, begin: Location) -> bool {
+        if !BidiControl.check(c) { return false; }
+        let message = match self.bidi_policy {
+            BidiPolicy::Warn => DiagnosticMessage::Warning(Warning::BidiControl(c)),
+            BidiPolicy::Error => Error(crate::error::Error::BidiControl(c)),
+        };
+        Diagnostic::new(Range { begin, end: self.location }, message).report(&mut self.diagnostics);
+        true
+    }
+
     fn next_input(&mut self) -> Option<char> {
-        let diagnostics = &mut self.diagnostics;
+        let (c, rest) = self.read_input()?;
+        self.input = rest;
+        self.count_progress(c);
+        Some(c)
+    }
+
+    // `c` is consumed at the current location.
+    fn count_progress(&mut self, c: char) {
+        // characters read again after a failed attempt are not progress.
+        if self.location.offset >= self.progress.consumed_chars {
+            self.progress.consumed_chars += 1;
+            self.progress.consumed_bytes += c.len_utf8();
+            if let Some((n, callback)) = &mut self.progress_callback {
+                if self.progress.consumed_chars.is_multiple_of(*n) { callback(self.progress) }
+            }
+        }
+    }
+
+    // the input is read only once: problems with it are reported the first time we meet them.
+    fn read_input(&mut self) -> Option<(char, Input<I>)> {
+        // out of the error budget: stop promptly, whatever the input.
+        if self.diagnostics.budget_exceeded() || self.utf8_error().is_some() { return None; }
+        let (diagnostics, noted) = (&mut self.diagnostics, &mut self.latin1_noted);
+        let failure = &mut self.utf8_failure;
         let location = self.location;
-        match self.input.clone().next(move |s| Diagnostic::new(
-            location, Error(InvalidUTF8(Vec::from(s))))
-            .report(diagnostics))
+        match self.input.clone().next(|p| report_input_problem(diagnostics, noted, failure, location, p))
             .map_err(Into::into) {
-            Ok((c, rest)) => {
-                self.input = rest;
-                Some(c)
-            }
+            // stop right at an invalid sequence, when so configured.
+            Ok(_) if self.utf8_error().is_some() => None,
+            Ok(res) => Some(res),
             Err(e) => {
                 if let Some(e) = e {
-                    Diagnostic::new(self.location, Error(InputFailure(e)))
+                    Diagnostic::new(Range::point(self.location), Error(InputFailure(Arc::new(e))))
                         .report(&mut self.diagnostics);
                 }
+                if !self.progress.eof_seen {
+                    self.progress.eof_seen = true;
+                    if let Some((_, callback)) = &mut self.progress_callback { callback(self.progress) }
+                }
                 None
             }
         }
@@ -168,22 +342,58 @@ impl<I: std::io::Read> Scanner<I> {
         FailFast(self.err_expected(t))
     }
 
+    /// Fail fast with any of `ts` as the expected lexeme types.
+    pub fn expected_any<T>(&mut self, ts: &[LexemeType]) -> Result<T> {
+        FailFast(self.err_expected_any(ts))
+    }
+
     /// Fail for future recovery from `alt!`.
     pub fn keep_trying<T>() -> Result<T> { RetryLater(()) }
 
     /// Create a `LexError` with the expected lexeme type.
     pub fn err_expected(&mut self, t: LexemeType) -> LexError {
-        LexError { expected: t, unexpected: self.peek() }
+        self.err_expected_any(&[t])
+    }
+
+    /// Create a `LexError` with any of the expected lexeme types.
+    pub fn err_expected_any(&mut self, ts: &[LexemeType]) -> LexError {
+        LexError {
+            expected: ts.to_vec(),
+            unexpected: self.peek(),
+            location: self.location,
+            invalid_utf8: None,
+        }
     }
 }
 
 /// Lexical error.
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(Clone, Eq, PartialEq, Debug)]
 pub struct LexError {
-    /// The expected lexeme type at the error.
-    pub expected: LexemeType,
+    /// The expected lexeme types at the error, any of them would do; none for end of input.
+    pub expected: Vec<LexemeType>,
     /// The character at which tokenization fails.
     pub unexpected: Option<char>,
+    /// Where tokenization fails.
+    pub location: Location,
+    /// The invalid UTF-8 sequence tokenization stops at, with [`Utf8Policy::Fail`]: nothing
+    /// is expected then.
+    pub invalid_utf8: Option<Utf8Error>,
+}
+
+impl Display for LexError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(err) = &self.invalid_utf8 { return write!(f, "{}: {}", self.location, err); }
+        write!(f, "{}: expected ", self.location)?;
+        if self.expected.is_empty() { f.write_str("end of input")?; }
+        for (k, t) in self.expected.iter().enumerate() {
+            if k != 0 { f.write_str(if k + 1 == self.expected.len() { " or " } else { ", " })?; }
+            f.write_str(t.describe())?;
+        }
+        match self.unexpected {
+            Some(c) => write!(f, ", found {:?}", c),
+            None => write!(f, ", found end of file"),
+        }
+    }
 }
 
 /// Lexer result.
@@ -192,36 +402,258 @@ pub type Result<T> = crate::utils::Result3<T, LexError, ()>;
 impl<I> Scanner<I> {
     /// Create a new scanner from the back buffer.
     pub fn new(input: I) -> Self {
+        let input_stats = Rc::<InputStats>::default();
         Scanner {
-            input: Input::new(input),
+            input: Input::with_stats(input, input_stats.clone()),
             location: Location::new(),
             diagnostics: DiagnosticsEngine::new(),
+            line_tab: None,
+            confusables: Vec::new(),
+            trivia: Vec::new(),
+            blank_lines: 0,
+            line_blank: true,
+            max_token_length: Self::DEFAULT_MAX_TOKEN_LENGTH,
+            max_float_exponent: numeric::MAXIMUM_EXPONENT,
+            input_stats,
+            latin1_noted: false,
+            utf8_failure: None,
+            bidi_policy: BidiPolicy::default(),
+            column_mode: ColumnMode::default(),
+            extensions: GhcExtensions::default(),
+            line_pragmas: false,
+            nfc_identifiers: false,
+            payloads: true,
+            source_map: SourceMap::default(),
+            progress: Progress::default(),
+            progress_callback: None,
+        }
+    }
+
+    /// Create a new scanner for the rest of a source file: `input` starts at `location`, e.g.
+    /// with the [`LayoutState::location`](layout::LayoutState::location) of a snapshot, i.e.
+    /// `location.offset` characters (not bytes) into the file.
+    ///
+    /// The progress counts characters from the start of the file, but bytes from `input`.
+    pub fn resume_at(input: I, location: Location) -> Self {
+        let mut scanner = Self::new(input);
+        scanner.location = location;
+        scanner.progress.consumed_chars = location.offset;
+        scanner
+    }
+
+    /// Default for [`with_max_token_length`](Scanner::with_max_token_length): 1 MiB.
+    pub const DEFAULT_MAX_TOKEN_LENGTH: usize = 1024 * 1024;
+
+    /// Limit the length (in characters) of a single lexeme.
+    ///
+    /// Longer lexemes are still consumed to their natural end, but reported as
+    /// [`TokenTooLong`], and their names or strings are
+    /// truncated to the first `max` characters.
+    pub fn with_max_token_length(self, max: usize) -> Self {
+        Scanner { max_token_length: max, ..self }
+    }
+
+    /// Use the limits and buffering in `config`, see [`ScannerConfig`].
+    ///
+    /// Must be called before anything is read from the input.
+    pub fn with_config(mut self, config: ScannerConfig) -> Self {
+        self.input.set_buf_size(config.buf_size);
+        self.input.set_io_retries(config.io_retries);
+        self.diagnostics.set_max_errors(config.max_errors);
+        let utf8_failure = match config.utf8_policy {
+            Utf8Policy::Recover => None,
+            Utf8Policy::Fail => Some(None),
+        };
+        Scanner {
+            max_float_exponent: config.max_float_exponent,
+            utf8_failure,
+            bidi_policy: config.bidi_policy,
+            ..self
         }
     }
 
+    /// Decode the input with `encoding`: strict UTF-8 by default, or with a Latin-1 fallback
+    /// for invalid sequences, noted once in the diagnostics.
+    ///
+    /// Must be called before anything is read from the input.
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.input.set_encoding(encoding);
+        self
+    }
+
+    /// Count the columns of the reported ranges as in `mode`.
+    ///
+    /// The layout algorithm always works with character columns (see
+    /// [`Location::char_column`]), so this affects only the reported ranges: with
+    /// [`ColumnMode::DisplayWidth`], they line up with the source in terminals and editors.
+    pub fn with_column_mode(self, mode: ColumnMode) -> Self {
+        Scanner { column_mode: mode, ..self }
+    }
+
+    /// Enable GHC language extensions to the lexical syntax.
+    pub fn with_extensions(self, extensions: GhcExtensions) -> Self {
+        Scanner { extensions, ..self }
+    }
+
+    /// Honour `{-# LINE 42 "Original.hs" #-}` pragmas and CPP line markers (`# 42 "file"`) at
+    /// the beginning of lines, for preprocessed input: see [`Scanner::source_map`].
+    ///
+    /// Lexemes and diagnostics still carry physical locations, which the layout algorithm
+    /// works with; the logical lines are for reporting only.
+    pub fn with_line_pragmas(self, enabled: bool) -> Self {
+        Scanner { line_pragmas: enabled, ..self }
+    }
+
+    /// Normalize the identifiers (and the module names qualifying them) to Unicode NFC, so
+    /// that names written with different code points but looking the same are the same.
+    ///
+    /// Off by default: the lexemes then no longer round-trip to the source text.
+    pub fn with_nfc_identifiers(self, enabled: bool) -> Self {
+        Scanner { nfc_identifiers: enabled, ..self }
+    }
+
+    /// Stamp the diagnostics with the file scanned, as registered to a
+    /// [`SourceManager`](crate::source::SourceManager); [`FileId::ANONYMOUS`] by default.
+    pub fn with_file(mut self, file: FileId) -> Self {
+        self.diagnostics.set_file(file);
+        self
+    }
+
+    /// The file scanned, see [`Scanner::with_file`].
+    pub fn file(&self) -> FileId { self.diagnostics.file() }
+
+    /// Call `callback` with the [`Progress`] every `every_n_chars` characters consumed, and
+    /// once more at the end of input.
+    pub fn on_progress(mut self, every_n_chars: usize, callback: impl FnMut(Progress) + 'static) -> Self {
+        self.set_progress_callback(every_n_chars, Box::new(callback));
+        self
+    }
+
+    fn set_progress_callback(&mut self, every_n_chars: usize, callback: ProgressCallback) {
+        assert!(every_n_chars > 0, "on_progress: every_n_chars must be positive");
+        self.progress_callback = Some((every_n_chars, callback))
+    }
+
+    // accumulate at most `max` characters: a character takes at most 4 bytes in UTF-8, the
+    // payload is truncated to exactly `max` characters later in `next_lexeme`.
+    fn bounded_push<S: AsRef<str> + Extend<char>>(max: usize) -> impl Fn(&mut S, char) {
+        move |s, c| if s.as_ref().len() < 4 * max { s.extend(Some(c)) }
+    }
+
+    // a payload starting with `c`, and `bounded_push` for the rest of it: both discard the
+    // characters when scanning without the payloads, see `next_lexeme_type`.
+    fn start_payload(&self, c: char) -> Name {
+        if self.payloads { Name::from(c) } else { Name::new() }
+    }
+    fn push_payload<S: AsRef<str> + Extend<char>>(&self) -> impl Fn(&mut S, char) {
+        Self::bounded_push(if self.payloads { self.max_token_length } else { 0 })
+    }
+
+    /// The current location, where the next character would be.
+    pub fn location(&self) -> Location { self.location }
+
+    /// The number of blank lines in the most recent whitespace run: lines with only
+    /// whitespaces, terminated by a line break.
+    ///
+    /// Comments do not reset the count, but the lines with them are not blank, so that a blank
+    /// line before a comment still separates whatever comes after the comment.
+    pub fn blank_lines(&self) -> u32 { self.blank_lines }
+
+    /// How far the scanner has got in the input.
+    pub fn progress(&self) -> Progress { self.progress }
+
+    /// Statistics on the input buffered so far.
+    pub fn input_stats(&self) -> &InputStats { &self.input_stats }
+
+    /// The line directives met so far, to map physical locations to logical ones.
+    pub fn source_map(&self) -> &SourceMap { &self.source_map }
+
+    /// The invalid UTF-8 sequence the scanner stopped at, with [`Utf8Policy::Fail`].
+    pub fn utf8_error(&self) -> Option<&Utf8Error> { self.utf8_failure.as_ref()?.as_ref() }
+
+    /// The diagnostics reported so far.
+    pub fn diagnostics(&self) -> &DiagnosticsEngine { &self.diagnostics }
+
+    /// The diagnostics reported so far, mutable for draining.
+    pub fn diagnostics_mut(&mut self) -> &mut DiagnosticsEngine { &mut self.diagnostics }
+
+    fn anchor(&self) -> Anchor<I> {
+        Anchor {
+            input: self.input.clone(),
+            location: self.location,
+            diagnostics: self.diagnostics.checkpoint(),
+        }
+    }
+
+    fn revert(&mut self, anchor: Anchor<I>) {
+        self.input = anchor.input;
+        self.location = anchor.location;
+        self.diagnostics.revert(anchor.diagnostics);
+    }
+
     /// Set an anchor for possible revert in future. Use an `Either` for error indication.
     pub fn anchored<R: Either>(&mut self, f: impl FnOnce(&mut Scanner<I>) -> R) -> R {
-        let old_input = self.input.clone();
-        let old_location = self.location;
-        let old_diagnostics_count = self.diagnostics.len();
+        let anchor = self.anchor();
+        match f(self).into_result() {
+            Ok(res) => Either::right(res),
+            Err(err) => {
+                self.revert(anchor);
+                Either::left(err)
+            }
+        }
+    }
+
+    /// Like [`Scanner::anchored`], for rules that report no diagnostics, e.g. those that only
+    /// consume characters they accept: on failure, only the input and the location are reverted.
+    ///
+    /// Diagnostics about the input itself are fine: [`Scanner::anchored`] keeps them anyway.
+    pub fn anchored_pure<R: Either>(&mut self, f: impl FnOnce(&mut Scanner<I>) -> R) -> R {
+        #[cfg(debug_assertions)]
+        let diagnostics = self.diagnostics.checkpoint();
+        let (input, location) = (self.input.clone(), self.location);
         match f(self).into_result() {
             Ok(res) => Either::right(res),
             Err(err) => {
-                self.input = old_input;
-                self.location = old_location;
-                self.diagnostics.truncate(old_diagnostics_count);
+                #[cfg(debug_assertions)]
+                debug_assert!(self.diagnostics.all_since(diagnostics, Diagnostic::is_input_error),
+                              "diagnostics reported in a pure rule");
+                self.input = input;
+                self.location = location;
                 Either::left(err)
             }
         }
     }
 
+    /// Check that the input advanced since `offset`, in a repetition: if not, the repetition
+    /// would never end, so report [`NonProgressingRule`] and tell the caller to stop.
+    ///
+    /// This is a bug in the rule repeated: debug builds panic after the report.
+    fn check_progress(&mut self, offset: usize) -> bool {
+        let stuck = self.location.offset == offset;
+        if stuck {
+            Diagnostic::new(Range::point(self.location), Error(NonProgressingRule))
+                .report(&mut self.diagnostics);
+        }
+        debug_assert!(!stuck, "repeated rule succeeded without consuming input at {}", self.location);
+        !stuck
+    }
+
     /// Match many of this rule.
+    ///
+    /// The repetition stops at the first match consuming no input, with a diagnostic: such
+    /// rules would otherwise loop forever. So do the other repetitions, which are built on
+    /// this one.
     pub fn many<ET: Either<Left=E>, EU: Either<Left=E>, E>(
         &mut self, mut f: impl FnMut(&mut Scanner<I>) -> ET,
         init: EU::Right, mut join: impl FnMut(&mut EU::Right, ET::Right)) -> EU {
         let mut res = init;
-        while let Ok(x) = self.anchored(&mut f).into_result() {
-            join(&mut res, x);
+        loop {
+            let offset = self.location.offset;
+            match self.anchored(&mut f).into_result() {
+                Ok(x) => join(&mut res, x),
+                Err(_) => break,
+            }
+            if !self.check_progress(offset) { break; }
         }
         Either::right(res)
     }
@@ -291,22 +723,631 @@ impl<I> Scanner<I> {
 }
 
 impl<I: std::io::Read> Scanner<I> {
-    /// Get the next lexeme from the [`Scanner`].
+    /// Lexeme types [`next_lexeme`](Scanner::next_lexeme) may start with.
+    pub const LEXEME_STARTS: &'static [LexemeType] = &[
+        LexemeType::Integer, LexemeType::Float,
+        LexemeType::Identifier, LexemeType::Operator,
+        LexemeType::CharLiteral, LexemeType::StringLiteral,
+        LexemeType::Comma, LexemeType::Semicolon, LexemeType::Backtick,
+        LexemeType::OpenCurlyBracket, LexemeType::CloseCurlyBracket,
+        LexemeType::OpenParenthesis, LexemeType::CloseParenthesis,
+        LexemeType::OpenSquareBracket, LexemeType::CloseSquareBracket,
+    ];
+
+    /// Get the next lexeme from the [`Scanner`], right at the current location: skip the
+    /// trivia before it with [`Scanner::skip_trivia`] first.
+    ///
+    /// Fails with `RetryLater` only at the end of input; if no lexeme can be produced from the
+    /// remaining input, fails fast with all the lexeme types that could have been here.
     pub fn next_lexeme(&mut self) -> Result<Lexeme> {
+        let begin = self.location;
+        let res = self.lexeme();
+        self.finish_lexeme(begin, res)
+    }
+
+    /// Like [`Scanner::next_lexeme`], but try a custom rule `extra` before the built-in ones.
+    ///
+    /// The rule is run [`anchored`](Scanner::anchored): if it returns `None`, the input it
+    /// consumed is put back. The [`Stream`] methods, the [`CharPredicate`]s, and combinators
+    /// such as [`Scanner::many`] are all available to it. Lexemes from the rule are treated
+    /// like the built-in ones, e.g. for the maximum token length.
+    ///
+    /// ```
+    /// # use mini_haskell::scanner::Scanner;
+    /// # use mini_haskell::lexeme::Lexeme;
+    /// # use mini_haskell::utils::Result3::Success;
+    /// use mini_haskell::utils::char::{Stream, CharPredicate, Ascii};
+    /// // `#name` as a single identifier, instead of an operator and an identifier.
+    /// fn hash_name(scanner: &mut Scanner<&[u8]>) -> Option<Lexeme> {
+    ///     if scanner.next()? != '#' { return None; }
+    ///     let name = scanner.span_collect_string(|c| Ascii::Alpha.check(c));
+    ///     if name.is_empty() { return None; }
+    ///     Some(Lexeme::Identifier(format!("#{}", name).as_str().into()))
+    /// }
+    /// let mut scanner = Scanner::new("#line 42".as_bytes());
+    /// let name = Lexeme::Identifier("#line".into());
+    /// assert_eq!(scanner.next_lexeme_with(hash_name), Success(name));
+    /// ```
+    pub fn next_lexeme_with(&mut self, mut extra: impl FnMut(&mut Scanner<I>) -> Option<Lexeme>)
+                            -> Result<Lexeme> {
+        let begin = self.location;
+        let res = match self.anchored(&mut extra) {
+            Some(lexeme) => Success(lexeme),
+            None => self.lexeme(),
+        };
+        self.finish_lexeme(begin, res)
+    }
+
+    /// Like [`Scanner::next_lexeme`], but try a custom rule `extra` after the built-in ones,
+    /// only when none of them matches here. See [`Scanner::next_lexeme_with`].
+    pub fn next_lexeme_or_else(&mut self, mut extra: impl FnMut(&mut Scanner<I>) -> Option<Lexeme>)
+                               -> Result<Lexeme> {
+        let begin = self.location;
+        let res = match self.lexeme() {
+            FailFast(err) => self.anchored(&mut extra).map_or(FailFast(err), Success),
+            res => res,
+        };
+        self.finish_lexeme(begin, res)
+    }
+
+    /// Like [`Scanner::next_lexeme`], but only the type of the lexeme: the same rules run,
+    /// with the same diagnostics, but discard the characters instead of collecting payloads.
+    ///
+    /// Nothing is allocated for a lexeme, save for the `#`s glued under `MagicHash`, and the
+    /// diagnostics if any: see [`RawLexemeTypeIterator`](layout::RawLexemeTypeIterator) for an
+    /// iterator.
+    pub fn next_lexeme_type(&mut self) -> Result<LexemeType> {
+        self.payloads = false;
+        let res = self.next_lexeme();
+        self.payloads = true;
+        match res {
+            Success(lexeme) => Success(lexeme.get_type()),
+            FailFast(err) => FailFast(err),
+            RetryLater(()) => RetryLater(()),
+        }
+    }
+
+    fn finish_lexeme(&mut self, begin: Location, mut res: Result<Lexeme>) -> Result<Lexeme> {
+        let n = self.location.offset - begin.offset;
+        if let Success(lexeme) = &mut res {
+            if n > self.max_token_length {
+                let max = self.max_token_length;
+                let cut = |s: &str| s.char_indices().nth(max).map_or(s.len(), |(k, _)| k);
+                let t = lexeme.get_type();
+                let base = match &mut *lexeme {
+                    Lexeme::MagicHash(base, _) => &mut **base,
+                    lexeme => lexeme,
+                };
+                match base {
+                    Lexeme::Identifier(s) | Lexeme::Operator(s) |
+                    Lexeme::QIdentifier(QName { name: s, .. }) |
+                    Lexeme::QOperator(QName { name: s, .. }) => s.truncate(cut(s)),
+                    Lexeme::StringLiteral(s) => s.truncate(cut(s)),
+                    _ => (),
+                }
+                Diagnostic::new(Range { begin, end: self.location },
+                                Error(TokenTooLong(t, n)))
+                    .report(&mut self.diagnostics);
+            }
+            if self.nfc_identifiers { normalize_identifier(lexeme) }
+        }
+        res
+    }
+
+    fn lexeme(&mut self) -> Result<Lexeme> {
+        let extensions = self.extensions;
+        if !extensions.magic_hash && !extensions.lambda_case { return self.plain_lexeme(); }
+        if extensions.magic_hash {
+            if let Some(t) = self.anchored(Self::unboxed_parenthesis) { return Success(t); }
+        }
+        let mut t = match self.plain_lexeme() {
+            Success(t) => t,
+            res => return res,
+        };
+        if extensions.magic_hash { t = self.magic_hash(t) }
+        if extensions.lambda_case { t = self.lambda_case(t) }
+        Success(t)
+    }
+
+    fn plain_lexeme(&mut self) -> Result<Lexeme> {
         alt!(self, Self::numeric_literal,
                    Self::id_or_sym,
                    Self::char_or_string,
                    Self::special);
-        Self::keep_trying()
+        if self.peek().is_none() { return Self::keep_trying(); }
+        self.expected_any(Self::LEXEME_STARTS)
+    }
+}
+
+fn normalize_identifier(lexeme: &mut Lexeme) {
+    fn normalize(s: &mut Name) {
+        if !is_nfc(s) { *s = nfc(s).as_str().into() }
+    }
+    match lexeme {
+        Lexeme::MagicHash(base, _) => normalize_identifier(base),
+        Lexeme::Identifier(s) => normalize(s),
+        Lexeme::QIdentifier(QName { module, name }) => {
+            module.0.iter_mut().for_each(normalize);
+            normalize(name)
+        }
+        Lexeme::QOperator(QName { module, .. }) | Lexeme::QualifiedPrefix(module) =>
+            module.0.iter_mut().for_each(normalize),
+        _ => (),
     }
 }
 
 #[cfg(test)]
-fn test_scanner_on<U: Eq + std::fmt::Debug>(
-    input: &str,
-    f: impl FnOnce(&mut Scanner<&[u8]>) -> U,
-    res: U, next: Option<char>) {
-    let mut scanner = Scanner::new(input.as_bytes());
-    assert_eq!(f(&mut scanner), res);
-    assert_eq!(scanner.next(), next);
+mod tests {
+    use super::Scanner;
+    use crate::utils::Result3::Success;
+    use crate::lexeme::Lexeme::{self, Identifier, StringLiteral};
+
+    #[test]
+    fn test_max_token_length() {
+        const N: usize = 2 * 1024 * 1024;
+        let long_id = "x".repeat(N);
+        let long_string = "λ".repeat(N);
+        let input = format!("{} a \"{}\" b", long_id, long_string);
+        let mut scanner = Scanner::new(input.as_bytes());
+        let mut lexemes = Vec::<Lexeme>::new();
+        loop {
+            let _ = scanner.whitespace();
+            match scanner.next_lexeme() {
+                Success(lexeme) => lexemes.push(lexeme),
+                _ => break,
+            }
+        }
+        let max = Scanner::<&[u8]>::DEFAULT_MAX_TOKEN_LENGTH;
+        assert_eq!(lexemes.len(), 4);
+        assert_eq!(lexemes[0], Identifier("x".repeat(max).as_str().into()));
+        assert_eq!(lexemes[1], Identifier("a".into()));
+        assert_eq!(lexemes[2], StringLiteral("λ".repeat(max)));
+        assert_eq!(lexemes[3], Identifier("b".into()));
+        let diagnostics: Vec<_> = scanner.diagnostics().iter().map(|d| d.to_string()).collect();
+        assert_eq!(diagnostics, [
+            format!("1:1-1:{}: error: identifier too long ({} characters), truncated", N + 1, N),
+            format!("1:{}-1:{}: error: string literal too long ({} characters), truncated",
+                    N + 4, 2 * N + 6, N + 2),
+        ]);
+    }
+
+    #[test]
+    fn test_custom_rules() {
+        use crate::utils::char::{Stream, CharPredicate, Ascii};
+        use crate::lexeme::Lexeme::{Integer, Operator};
+        use crate::utils::Result3::FailFast;
+        // `#line` at the beginning of a line, using only the public API.
+        fn line(scanner: &mut Scanner<&[u8]>) -> Option<Lexeme> {
+            if scanner.location().column != 1 { return None; }
+            scanner.r#match("#line")?;
+            if scanner.peek().is_some_and(|c| Ascii::AlphaNum.check(c)) { return None; }
+            Some(Identifier("#line".into()))
+        }
+        fn kana(scanner: &mut Scanner<&[u8]>) -> Option<Lexeme> {
+            let s = scanner.span_collect_string(|c| ('\u{3041}'..='\u{3096}').check(c));
+            if s.is_empty() { None } else { Some(StringLiteral(s)) }
+        }
+        fn lex(input: &str,
+               mut next: impl FnMut(&mut Scanner<&[u8]>) -> super::Result<Lexeme>) -> Vec<Lexeme> {
+            let mut scanner = Scanner::new(input.as_bytes());
+            let mut lexemes = Vec::new();
+            while let Success(lexeme) = { let _ = scanner.skip_trivia(); next(&mut scanner) } {
+                lexemes.push(lexeme)
+            }
+            lexemes
+        }
+        let hash = || Operator("#".into());
+        assert_eq!(lex("#line 42 #line", |s| s.next_lexeme_with(line)),
+                   [Identifier("#line".into()), Integer(42.into()), hash(), Identifier("line".into())]);
+        assert_eq!(lex("#lines", |s| s.next_lexeme_with(line)), [hash(), Identifier("lines".into())]);
+        // tried only when the built-in rules fail.
+        assert_eq!(lex("#line", |s| s.next_lexeme_or_else(line)), [hash(), Identifier("line".into())]);
+        assert_eq!(lex("x \u{3042}\u{3044} y", |s| s.next_lexeme_or_else(kana)),
+                   [Identifier("x".into()), StringLiteral("\u{3042}\u{3044}".into()), Identifier("y".into())]);
+        let mut scanner = Scanner::new("\u{3042}".as_bytes());
+        assert!(matches!(scanner.next_lexeme_or_else(line), FailFast(_)));
+        assert_eq!(scanner.location().offset, 0);
+    }
+
+    #[test]
+    fn test_nested_anchors() {
+        use crate::utils::char::Stream;
+        // `\u{1}` is reported as invalid once taken: the reverts must drop the reports too.
+        let mut scanner = Scanner::new("a\u{1}b\u{1}c".as_bytes());
+        scanner.next();
+        let res = scanner.anchored(|s| {
+            s.next();
+            s.anchored(|s| {
+                s.next();
+                s.anchored(|s| s.next().filter(|&c| c == '\u{1}'))
+            })?;
+            assert_eq!((s.location().offset, s.diagnostics().len()), (4, 2));
+            s.next().filter(|&c| c == 'x')
+        });
+        assert_eq!(res, None);
+        assert_eq!((scanner.location().offset, scanner.diagnostics().len()), (1, 0));
+        assert_eq!(scanner.next(), Some('\u{1}'));
+        assert_eq!(scanner.diagnostics().len(), 1);
+        // the same with a pure rule inside a failing one.
+        let mut scanner = Scanner::new("abc".as_bytes());
+        let res = scanner.anchored(|s| {
+            s.anchored_pure(|s| s.next().filter(|&c| c == 'a'))?;
+            s.anchored_pure(|s| s.next().filter(|&c| c == 'x'))
+        });
+        assert_eq!(res, None);
+        assert_eq!(scanner.location().offset, 0);
+        assert_eq!(scanner.next(), Some('a'));
+    }
+
+    #[test]
+    fn test_revert_out_of_order() {
+        use crate::error::{Diagnostic, DiagnosticMessage, Error::InvalidChar, Warning};
+        use crate::scanner::{Location, Range};
+        let warning = |offset| Diagnostic::new(Range::point(Location::at(1, offset + 1, offset)),
+                                               DiagnosticMessage::Warning(Warning::AdjacentStringLiterals));
+        let mut scanner = Scanner::new("abcdef".as_bytes());
+        warning(1).report(scanner.diagnostics_mut());
+        warning(4).report(scanner.diagnostics_mut());
+        let res = scanner.anchored(|s| {
+            // both before the last one kept: inserted in the middle, not pushed to the back.
+            warning(0).report(s.diagnostics_mut());
+            warning(2).report(s.diagnostics_mut());
+            None::<()>
+        });
+        assert_eq!(res, None);
+        let offsets: Vec<_> = scanner.diagnostics().iter().map(|d| d.location().offset).collect();
+        assert_eq!(offsets, [1, 4]);
+        // the errors reverted no longer count against the budget, those kept still do.
+        let error = |offset| Diagnostic::new(Range::point(Location::at(1, offset + 1, offset)),
+                                             DiagnosticMessage::Error(InvalidChar('\u{1}')));
+        scanner.diagnostics_mut().set_max_errors(2);
+        error(5).report(scanner.diagnostics_mut());
+        scanner.anchored(|s| {
+            error(3).report(s.diagnostics_mut());
+            None::<()>
+        });
+        error(5).report(scanner.diagnostics_mut());
+        assert!(!scanner.diagnostics().budget_exceeded());
+        error(5).report(scanner.diagnostics_mut());
+        assert!(scanner.diagnostics().budget_exceeded());
+    }
+
+    #[test]
+    fn test_progress() {
+        use std::{rc::Rc, cell::RefCell};
+        use super::{Progress, layout::RawLexemeIterator};
+        // 1 MiB, with a multi-byte character on each line.
+        let line = "x = \"\u{3BB}\" -- .....................................\n";
+        let input = line.repeat(1024 * 1024 / line.len());
+        let reports = Rc::new(RefCell::new(Vec::<Progress>::new()));
+        let sink = reports.clone();
+        let mut it = RawLexemeIterator::new(input.as_bytes())
+            .on_progress(10_000, move |p| sink.borrow_mut().push(p));
+        it.by_ref().for_each(drop);
+        let reports = reports.borrow();
+        let chars = input.chars().count();
+        assert_eq!(reports.len(), chars / 10_000 + 1);
+        assert!(reports.windows(2).all(|w| w[0].consumed_chars < w[1].consumed_chars &&
+                                           w[0].consumed_bytes < w[1].consumed_bytes));
+        assert!(reports[..reports.len() - 1].iter().all(|p| p.consumed_chars % 10_000 == 0 && !p.eof_seen));
+        let last = Progress { consumed_bytes: input.len(), consumed_chars: chars, eof_seen: true };
+        assert_eq!(reports.last(), Some(&last));
+        assert_eq!(it.into_scanner().1.progress(), last);
+    }
+
+    #[test]
+    fn test_nfc_identifiers() {
+        use crate::lexeme::{ModuleId, QName, Lexeme::QIdentifier};
+        fn lex(input: &str, enabled: bool) -> Vec<Lexeme> {
+            let mut scanner = Scanner::new(input.as_bytes()).with_nfc_identifiers(enabled);
+            let mut res = Vec::new();
+            while let (Success(()), Success(lexeme)) = (scanner.skip_trivia(), scanner.next_lexeme()) {
+                res.push(lexeme)
+            }
+            res
+        }
+        // the Angstrom sign and the Kelvin sign, both singletons of Latin letters in NFC.
+        let input = "\u{212B}x \u{212A}.\u{212B} \"\u{212B}\"";
+        let qualified = |m: &str, name: &str| QIdentifier(QName {
+            module: ModuleId(vec![m.into()]), name: name.into() });
+        assert_eq!(lex(input, false), [Identifier("\u{212B}x".into()), qualified("\u{212A}", "\u{212B}"),
+                                       StringLiteral("\u{212B}".to_string())]);
+        assert_eq!(lex(input, true), [Identifier("\u{C5}x".into()), qualified("K", "\u{C5}"),
+                                      StringLiteral("\u{212B}".to_string())]);
+    }
+
+    #[test]
+    fn test_small_buf_size() {
+        use super::{ScannerConfig, layout::RawLexemeIterator};
+        // multi-byte characters span the 3-byte segments, the 4-byte one spans three of them.
+        let input = "\u{3BB}x = \"\u{430}\u{E9}\u{1F600}\" -- \u{E9}\n";
+        let config = ScannerConfig { buf_size: 3, ..ScannerConfig::default() };
+        let scanner = Scanner::new(input.as_bytes()).with_config(config);
+        let lexemes: Vec<_> = RawLexemeIterator::from(scanner).map(|x| x.to_string()).collect();
+        let expected: Vec<_> = RawLexemeIterator::new(input.as_bytes()).map(|x| x.to_string()).collect();
+        assert_eq!(lexemes, expected);
+        assert_eq!(lexemes, ["\u{3BB}x", "=", "\"\u{430}\u{E9}\u{1F600}\""]);
+    }
+
+    #[test]
+    fn test_clone() {
+        use super::{ScannerConfig, layout::{RawLexemeIterator, FatLexemeIterator}};
+        use crate::error::SharedDiagnostics;
+        // not `Clone`, and in small pieces: the clone shares the segments not read yet.
+        struct Trickle<'a>(&'a [u8]);
+        impl std::io::Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = buf.len().min(self.0.len()).min(2);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+        fn rest(mut scanner: Scanner<Trickle>) -> (Vec<String>, Vec<String>) {
+            let mut lexemes = Vec::new();
+            while let (Success(_), Success(t)) = (scanner.skip_trivia(), scanner.next_lexeme()) {
+                lexemes.push(t.to_string());
+            }
+            let diagnostics = scanner.diagnostics().iter().map(|d| d.to_string()).collect();
+            (lexemes, diagnostics)
+        }
+        let input = "x = '\u{7}'\ny = \"a\u{B}b\"\nz = w";
+        let config = ScannerConfig { buf_size: 3, ..ScannerConfig::default() };
+        let mut scanner = Scanner::new(Trickle(input.as_bytes())).with_config(config);
+        for _ in 0..3 {
+            assert!(matches!((scanner.skip_trivia(), scanner.next_lexeme()), (Success(_), Success(_))));
+        }
+        // independent copies of the diagnostics: the one so far is in both.
+        let (lexemes, diagnostics) = rest(scanner.clone());
+        assert_eq!(lexemes, ["y", "=", "\"a\u{fffd}b\"", "z", "=", "w"]);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(scanner.diagnostics().len(), 1);
+        assert_eq!(rest(scanner.clone()), (lexemes.clone(), diagnostics.clone()));
+        // shared diagnostics: what the clone reports to them is seen through every handle.
+        let shared = SharedDiagnostics::new(scanner.diagnostics().clone());
+        let handle = shared.clone();
+        assert!(shared.is_shared());
+        let mut clone = scanner.clone();
+        handle.lend(&mut clone, |s| {
+            while let (Success(_), Success(_)) = (s.skip_trivia(), s.next_lexeme()) {}
+        });
+        let reported: Vec<_> = shared.borrow().iter().map(|d| d.to_string()).collect();
+        assert_eq!(reported, diagnostics);
+        // and the scanners keep their own.
+        assert_eq!((scanner.diagnostics().len(), clone.diagnostics().len()), (1, 1));
+        // the iterators as well, with their ranges.
+        let mut it = FatLexemeIterator::from(RawLexemeIterator::new(input.as_bytes()));
+        it.next();
+        let clone = it.clone();
+        assert_eq!(clone.collect::<Vec<_>>(), it.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_error_budget() {
+        use super::{ScannerConfig, layout::RawLexemeIterator};
+        use crate::error::{DiagnosticMessage, DiagnosticsEngine, Note};
+        // 1 MiB of noise in an unterminated comment, where the scanner keeps on recovering.
+        let mut state = 0x2545_F491_4F6C_DD1D_u64;
+        let mut input = b"x = 1 {-".to_vec();
+        input.extend((0..1024 * 1024).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 56) as u8
+        }));
+        let lex = |config: ScannerConfig| {
+            let mut it = RawLexemeIterator::from(Scanner::new(&input[..]).with_config(config));
+            assert_eq!(it.by_ref().count(), 3);
+            it.into_scanner().1
+        };
+        let scanner = lex(ScannerConfig::default());
+        let max = DiagnosticsEngine::DEFAULT_MAX_ERRORS;
+        assert_eq!(scanner.diagnostics().len(), max + 1);
+        assert!(scanner.diagnostics().budget_exceeded());
+        let last = scanner.diagnostics().iter().last();
+        assert!(matches!(last.as_ref().map(|d| d.message()),
+                         Some(DiagnosticMessage::Note(Note::TooManyErrors(n))) if *n == max));
+        let scanner = lex(ScannerConfig { max_errors: 10, ..ScannerConfig::default() });
+        assert_eq!(scanner.diagnostics().len(), 11);
+    }
+
+    #[test]
+    fn test_utf8_policy() {
+        use super::{ScannerConfig, Utf8Policy, layout::FatLexemeIterator, layout::RawLexemeIterator};
+        use crate::input::Utf8Error;
+        // the corruption in the second segment of the input, in the middle of an identifier.
+        let mut input = "x = 1 ".repeat(1000).into_bytes();
+        let offset = input.len() + 3;
+        input.extend_from_slice(b"abc\xC0\xAFdef y");
+        let lex = |utf8_policy| {
+            let config = ScannerConfig { utf8_policy, ..ScannerConfig::default() };
+            let scanner = Scanner::new(&input[..]).with_config(config);
+            let mut it = FatLexemeIterator::from(RawLexemeIterator::from(scanner));
+            let tokens: Vec<_> = it.by_ref().skip(3000).map(|(t, r)| (t.to_string(), r)).collect();
+            let (error, scanner) = it.into_scanner();
+            let diagnostics: Vec<_> = scanner.diagnostics().iter().map(|d| d.to_string()).collect();
+            (tokens, error, diagnostics)
+        };
+        let (tokens, error, diagnostics) = lex(Utf8Policy::Recover);
+        assert_eq!(tokens.iter().map(|t| t.0.as_str()).collect::<Vec<_>>(), ["abcdef", "y"]);
+        assert_eq!(error, None);
+        assert_eq!(diagnostics, ["1:6004-1:6004: error: invalid UTF-8 sequence [C0]",
+                                 "1:6004-1:6004: error: invalid UTF-8 sequence [AF]"]);
+        // the identifier cut at the sequence is not a lexeme.
+        let (tokens, error, diagnostics) = lex(Utf8Policy::Fail);
+        assert!(tokens.is_empty(), "{:?}", tokens);
+        let error = error.expect("lexing should stop at the invalid sequence");
+        assert_eq!(error.invalid_utf8, Some(Utf8Error { offset, bytes: vec![0xC0] }));
+        assert_eq!(error.to_string(), format!("1:6004: invalid UTF-8 sequence [C0] at byte {}", offset));
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_utf8_failure_cuts() {
+        use super::{ScannerConfig, Utf8Policy, layout::RawLexemeIterator};
+        // neither the partial lexeme, nor the diagnostics of the construct cut.
+        let lex = |input: &[u8]| {
+            let config = ScannerConfig { utf8_policy: Utf8Policy::Fail, ..ScannerConfig::default() };
+            let mut it = RawLexemeIterator::from(Scanner::new(input).with_config(config));
+            let tokens: Vec<_> = it.by_ref().map(|t| t.to_string()).collect();
+            let (error, scanner) = it.into_scanner();
+            let error = error.expect("lexing should stop at the invalid sequence");
+            let diagnostics: Vec<_> = scanner.diagnostics().iter().map(|d| d.to_string()).collect();
+            (tokens, error.to_string(), diagnostics)
+        };
+        let (tokens, error, diagnostics) = lex(b"x = \"ab\xFF\" y");
+        assert_eq!(tokens, ["x", "="]);
+        assert_eq!(error, "1:8: invalid UTF-8 sequence [FF] at byte 7");
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        let (tokens, error, diagnostics) = lex(b"x {- ab\xFF -} y");
+        assert_eq!(tokens, ["x"]);
+        assert_eq!(error, "1:8: invalid UTF-8 sequence [FF] at byte 7");
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        // those before are kept.
+        let (tokens, _, diagnostics) = lex(b"x = '\x07' {- \xFF");
+        assert_eq!(tokens, ["x", "=", "'\u{FFFD}'"]);
+        assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+    }
+
+    #[test]
+    fn test_bidi_controls() {
+        use super::{BidiPolicy, ScannerConfig, layout::FatLexemeIterator, layout::RawLexemeIterator};
+        use crate::error::Severity;
+        let lex = |source: &str, bidi_policy| {
+            let config = ScannerConfig { bidi_policy, ..ScannerConfig::default() };
+            let scanner = Scanner::new(source.as_bytes()).with_config(config);
+            let mut it = FatLexemeIterator::from(RawLexemeIterator::from(scanner));
+            let tokens: Vec<_> = it.by_ref().map(|(t, _)| t).collect();
+            let (error, scanner) = it.into_scanner();
+            assert_eq!(error, None, "{:?}", source);
+            let diagnostics: Vec<_> = scanner.diagnostics().iter()
+                .map(|d| (d.severity(), d.to_string())).collect();
+            (tokens, diagnostics)
+        };
+        let warning = |range: &str, name: &str| (Severity::Warning, format!(
+            "{}: warning: bidirectional control character {}: the source may display differently \
+             from how it is read", range, name));
+        // the "Trojan Source" example: the string looks like it ends before the comment.
+        let source = "s = \"user\u{202E} \u{2066}-- is admin\u{2069} \u{2066}\"\n";
+        let (tokens, diagnostics) = lex(source, BidiPolicy::Warn);
+        assert_eq!(tokens[2], StringLiteral("user\u{202E} \u{2066}-- is admin\u{2069} \u{2066}".into()));
+        assert_eq!(diagnostics, [
+            warning("1:10-1:11", "U+202E RIGHT-TO-LEFT OVERRIDE (RLO)"),
+            warning("1:12-1:13", "U+2066 LEFT-TO-RIGHT ISOLATE (LRI)"),
+            warning("1:24-1:25", "U+2069 POP DIRECTIONAL ISOLATE (PDI)"),
+            warning("1:26-1:27", "U+2066 LEFT-TO-RIGHT ISOLATE (LRI)"),
+        ]);
+        // each exactly once, in comments and character literals too.
+        let source = "x = 1 -- \u{202E}yes\ny = '\u{202E}' {- \u{202B} -}\n";
+        let (tokens, diagnostics) = lex(source, BidiPolicy::Warn);
+        assert!(tokens.contains(&Lexeme::CharLiteral('\u{202E}')));
+        assert_eq!(diagnostics, [
+            warning("1:10-1:11", "U+202E RIGHT-TO-LEFT OVERRIDE (RLO)"),
+            warning("2:6-2:7", "U+202E RIGHT-TO-LEFT OVERRIDE (RLO)"),
+            warning("2:12-2:13", "U+202B RIGHT-TO-LEFT EMBEDDING (RLE)"),
+        ]);
+        let (_, diagnostics) = lex(source, BidiPolicy::Error);
+        assert_eq!(diagnostics.len(), 3);
+        assert!(diagnostics.iter().all(|(severity, d)|
+            *severity == Severity::Error && d.contains(": error: bidirectional control character U+")));
+    }
+
+    #[test]
+    fn test_column_mode() {
+        use super::ColumnMode;
+        fn columns(input: &str, mode: ColumnMode) -> Vec<(usize, usize)> {
+            let mut scanner = Scanner::new(input.as_bytes()).with_column_mode(mode);
+            let mut res = Vec::new();
+            loop {
+                let _ = scanner.whitespace();
+                let begin = scanner.location();
+                match scanner.next_lexeme() {
+                    Success(_) => res.push((begin.column, scanner.location().column)),
+                    _ => break,
+                }
+            }
+            res
+        }
+        // combining marks are not allowed in identifiers, but fine in comments.
+        let input = "s = \"日本語\" ++ {- e\u{301} -} t";
+        assert_eq!(columns(input, ColumnMode::Chars),
+                   [(1, 2), (3, 4), (5, 10), (11, 13), (23, 24)]);
+        assert_eq!(columns(input, ColumnMode::DisplayWidth),
+                   [(1, 2), (3, 4), (5, 13), (14, 16), (25, 26)]);
+        assert_eq!(Identifier("e\u{301}".into()).width(), Some(1));
+        assert_eq!(Identifier("日本".into()).width(), Some(4));
+        assert_eq!(StringLiteral("日本語".into()).width(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_location_serde() {
+        use super::{ColumnMode, Location};
+        let mut scanner = Scanner::new("\"日本\"\t".as_bytes()).with_column_mode(ColumnMode::DisplayWidth);
+        assert!(matches!(scanner.next_lexeme(), Success(_)));
+        let location = scanner.location();
+        assert_eq!((location.column, location.char_column), (7, 5));
+        let mut back: Location = serde_json::from_str(&serde_json::to_string(&location).unwrap()).unwrap();
+        assert_eq!(back, location);
+        back.tablise();
+        assert_eq!((back.column, back.char_column), (9, 9));
+        // without the character column: the same as the column.
+        let mut back: Location = serde_json::from_str(r#"{"line": 2, "column": 3}"#).unwrap();
+        assert_eq!(back, Location::at(2, 3, 0));
+        back.tablise();
+        assert_eq!(back.char_column, 9);
+    }
+
+    #[test]
+    fn test_non_progressing_rule() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        use crate::utils::char::{Stream, CharPredicate, Ascii};
+        let mut scanner = Scanner::new("12abc".as_bytes());
+        // matches zero digits forever after the first two, a bug: debug builds panic.
+        let res = catch_unwind(AssertUnwindSafe(|| scanner.many_::<_, Option<()>, _>(|scanner| {
+            analyse!(scanner, *Ascii::Digit);
+            Some(())
+        })));
+        assert_eq!(res.is_err(), cfg!(debug_assertions));
+        if let Ok(res) = res { assert_eq!(res, Some(())) }
+        assert_eq!(scanner.peek(), Some('a'));
+        let diagnostics: Vec<_> = scanner.diagnostics().iter().map(|d| d.to_string()).collect();
+        assert_eq!(diagnostics, ["1:3-1:3: error: internal error: a repeated rule succeeded without consuming input"]);
+        assert_eq!(scanner.diagnostics().peek().unwrap().message().code(), "L0019");
+    }
+
+    #[test]
+    fn test_lexeme_types() {
+        use super::{GhcExtensions, LexemeType, Result};
+        use crate::utils::Result3::{FailFast, RetryLater};
+        fn lex<'a>(input: &'a str, next: impl Fn(&mut Scanner<&'a [u8]>) -> Result<LexemeType>)
+               -> (Vec<LexemeType>, Vec<String>) {
+            let extensions = GhcExtensions {
+                magic_hash: true, explicit_forall: true, arrows: true, lambda_case: true,
+                numeric_underscores: true,
+            };
+            let mut scanner = Scanner::new(input.as_bytes()).with_extensions(extensions);
+            let mut types = Vec::new();
+            while let Success(()) = scanner.skip_trivia() {
+                match next(&mut scanner) {
+                    Success(t) => types.push(t),
+                    _ => break,
+                }
+            }
+            (types, scanner.diagnostics().iter().map(|d| d.to_string()).collect())
+        }
+        // the full names decide the reserved ones: not only their first characters.
+        let input = "deriving derivingX instances forall forall' ∀ ∀. \\case \\cases \\casesX \
+                     --> ---------> -< -<< >>- :: ::: M.where M.-- M.(+) Int# 3## \
+                     0x_ff 1_ 1e5000 1.5e-99999999999999999999 '\\1114112' \"\\x41\\SOH\\&\\  \\\"";
+        let (types, diagnostics) = lex(input, Scanner::next_lexeme_type);
+        let expected = lex(input, |scanner| match scanner.next_lexeme() {
+            Success(lexeme) => Success(lexeme.get_type()),
+            FailFast(err) => FailFast(err),
+            RetryLater(()) => RetryLater(()),
+        });
+        // the misplaced separators, the floats and the character out of bound.
+        assert_eq!((types.len(), diagnostics.len()), (35, 5));
+        assert_eq!((types, diagnostics), expected);
+    }
 }
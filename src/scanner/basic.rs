@@ -18,16 +18,88 @@
 
 //! basic character classes in "Haskell 2010 Report, 2.2 Lexical Program Structure".
 
-use crate::utils::char::{Ascii, Unicode};
+use crate::utils::char::CharPredicate;
 
-alias! {
+const SMALL: u8 = 1 << 0;
+const LARGE: u8 = 1 << 1;
+const DIGIT: u8 = 1 << 2;
+const SYMBOL: u8 = 1 << 3;
+const WHITE: u8 = 1 << 4;
+const GRAPHIC: u8 = 1 << 5;
+
+/// Character classes for ASCII characters, so that the hot predicates below need not consult
+/// the Unicode tables for plain ASCII source.
+static ASCII_CLASSES: [u8; 128] = ascii_classes();
+
+const fn ascii_classes() -> [u8; 128] {
+    let mut table = [0; 128];
+    let mut i = 0;
+    while i < 128 {
+        let c = i as u8;
+        table[i] = match c {
+            b'a'..=b'z' | b'_' => SMALL,
+            b'A'..=b'Z' => LARGE,
+            b'0'..=b'9' => DIGIT,
+            b'!' | b'#' | b'$' | b'%' | b'&' | b'*' | b'+' | b'.' | b'/' | b'<' | b'=' | b'>'
+            | b'?' | b'@' | b'\\' | b'^' | b'|' | b'-' | b'~' | b':' => SYMBOL,
+            b'\r' | b'\n' | 0x0C | 0x0B | b' ' | b'\t' => WHITE,
+            _ => 0,
+        };
+        // everything visible is graphic: the above, special, '"' and '\''.
+        if c > b' ' && c < 0x7F { table[i] |= GRAPHIC }
+        i += 1;
+    }
+    table
+}
+
+macro_rules! ascii_fast_path {
+    { $( $(#[$meta: meta])* pub $p: ident = $class: ident or $slow: path);* $(;)? } => {
+        $(
+            $(#[$meta])*
+            pub struct $p;
+            impl CharPredicate for $p {
+                fn check(&self, x: char) -> bool {
+                    match ASCII_CLASSES.get(x as usize) {
+                        Some(c) => c & $class != 0,
+                        None => $slow.check(x),
+                    }
+                }
+            }
+        )+
+    }
+}
+
+/// The full predicates, consulting the Unicode tables.
+mod unicode {
+    use crate::utils::char::{Ascii, Unicode};
+    use super::Special;
+
+    alias! {
+        /// see [`super::Small`].
+        pub Small = any!(Ascii::Lower, Unicode::Lower, '_');
+        /// see [`super::Large`].
+        pub Large = any!(Ascii::Upper, Unicode::Upper, Unicode::Titlecase);
+        /// see [`super::Symbol`].
+        pub Symbol = any!(r"!#$%&*+./<=>?@\^|-~:",
+                          all!(any!(Unicode::Symbol, Unicode::Punct),
+                               not!("_\"'"), not!(Special)));
+        /// see [`super::Graphic`].
+        pub Graphic = any!(Small, Large, Symbol, Digit, Special, '"', '\'');
+        /// see [`super::Digit`].
+        pub Digit = any!(Ascii::Digit, Unicode::Digit);
+        /// see [`super::WhiteChar`].
+        pub WhiteChar = any!("\r\n\u{C}\u{B} \t", Unicode::White);
+    }
+}
+
+ascii_fast_path! {
     /// see "Haskell 2010 Report, 2.2 Lexical Program Structure".
     /// ```text
     /// small       -> ascSmall | uniSmall | _
     /// ascSmall    -> a | b | ... | z
     /// uniSmall    -> any Unicode lowercase letter
     /// ```
-    pub Small = any!(Ascii::Lower, Unicode::Lower, '_');
+    pub Small = SMALL or unicode::Small;
 
     /// see "Haskell 2010 Report, 2.2 Lexical Program Structure".
     /// ```text
@@ -35,7 +107,7 @@ alias! {
     /// ascLarge    -> A | B | ... | Z
     /// uniLarge    -> any uppercase or titlecase Unicode letter
     /// ```
-    pub Large = any!(Ascii::Upper, Unicode::Upper);
+    pub Large = LARGE or unicode::Large;
 
     /// see "Haskell 2010 Report, 2.2 Lexical Program Structure".
     /// ```text
@@ -45,21 +117,13 @@ alias! {
     /// uniSymbol   -> any Unicode symbol or punctuation
     /// special     -> ( | ) | , | ; | [ | ] | ` | { | }
     /// ```
-    pub Symbol = any!(r"!#$%&*+./<=>?@\^|-~:",
-                      all!(any!(Unicode::Symbol, Unicode::Punct),
-                           not!("_\"'"), not!(Special)));
+    pub Symbol = SYMBOL or unicode::Symbol;
 
     /// see "Haskell 2010 Report, 2.2 Lexical Program Structure".
     /// ```text
     /// graphic     -> small | large | symbol | digit | special | " | '
     /// ```
-    pub Graphic = any!(Small, Large, Symbol, Digit, Special, '"', '\'');
-
-    /// see "Haskell 2010 Report, 2.2 Lexical Program Structure".
-    /// ```text
-    /// special     -> ( | ) | , | ; | [ | ] | ` | { | }
-    /// ```
-    pub Special = "(),;[]`{}";
+    pub Graphic = GRAPHIC or unicode::Graphic;
 
     /// see "Haskell 2010 Report, 2.2 Lexical Program Structure".
     /// ```text
@@ -68,19 +132,7 @@ alias! {
     /// uniDigit    -> any Unicode decimal digit
     /// ```
     /// TODO: Properly handle Unicode digits.
-    pub Digit = any!(Ascii::Digit, Unicode::Digit);
-
-    /// see "Haskell 2010 Report, 2.2 Lexical Program Structure".
-    /// ```text
-    /// octit       -> 0 | 1 | ... | 7
-    /// ```
-    pub Octit = '0'..='7';
-
-    /// see "Haskell 2010 Report, 2.2 Lexical Program Structure".
-    /// ```text
-    /// hexit       -> digit | A | ... | F | a | ... | f
-    /// ```
-    pub Hexit = any!(Digit, 'A'..='F', 'a'..='f');
+    pub Digit = DIGIT or unicode::Digit;
 
     /// see "Haskell 2010 Report, 2.2 Lexical Program Structure".
     /// ```text
@@ -93,11 +145,107 @@ alias! {
     /// linefeed    -> a line feed
     /// formfeed    -> a form feed
     /// ```
-    pub WhiteChar = any!("\r\n\u{C}\u{B} \t", Unicode::White);
+    pub WhiteChar = WHITE or unicode::WhiteChar;
+}
+
+alias! {
+    /// see "Haskell 2010 Report, 2.2 Lexical Program Structure".
+    /// ```text
+    /// special     -> ( | ) | , | ; | [ | ] | ` | { | }
+    /// ```
+    pub Special = "(),;[]`{}";
+
+    /// see "Haskell 2010 Report, 2.2 Lexical Program Structure".
+    /// ```text
+    /// octit       -> 0 | 1 | ... | 7
+    /// ```
+    pub Octit = '0'..='7';
+
+    /// see "Haskell 2010 Report, 2.2 Lexical Program Structure".
+    /// ```text
+    /// hexit       -> digit | A | ... | F | a | ... | f
+    /// ```
+    pub Hexit = any!(Digit, 'A'..='F', 'a'..='f');
 
     /// see "Haskell 2010 Report, 2.2 Lexical Program Structure".
     /// ```text
     /// ANY         -> graphic | whitechar
     /// ```
     pub Any = any!(Graphic, WhiteChar);
+
+    /// Not in the Report: the C0 control characters, except those in whitechar. None is
+    /// allowed anywhere in the source.
+    pub Control = all!('\0'..='\u{1F}', not!("\t\n\r\u{B}\u{C}"));
+
+    /// Not in the Report: the Unicode bidirectional embedding, override, and isolate controls,
+    /// which can make the source display differently from how it is read ("Trojan Source").
+    pub BidiControl = any!('\u{202A}'..='\u{202E}', '\u{2066}'..='\u{2069}');
+}
+
+/// The Unicode name of a [`BidiControl`] character, with its abbreviation.
+pub fn bidi_control_name(c: char) -> Option<&'static str> {
+    Some(match c {
+        '\u{202A}' => "LEFT-TO-RIGHT EMBEDDING (LRE)",
+        '\u{202B}' => "RIGHT-TO-LEFT EMBEDDING (RLE)",
+        '\u{202C}' => "POP DIRECTIONAL FORMATTING (PDF)",
+        '\u{202D}' => "LEFT-TO-RIGHT OVERRIDE (LRO)",
+        '\u{202E}' => "RIGHT-TO-LEFT OVERRIDE (RLO)",
+        '\u{2066}' => "LEFT-TO-RIGHT ISOLATE (LRI)",
+        '\u{2067}' => "RIGHT-TO-LEFT ISOLATE (RLI)",
+        '\u{2068}' => "FIRST STRONG ISOLATE (FSI)",
+        '\u{2069}' => "POP DIRECTIONAL ISOLATE (PDI)",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::char::CharPredicate;
+
+    fn assert_same(fast: impl CharPredicate, slow: impl CharPredicate, name: &str) {
+        for x in (0..0x300).filter_map(std::char::from_u32) {
+            assert_eq!(fast.check(x), slow.check(x), "{} disagrees on {:?}", name, x);
+        }
+    }
+
+    #[test]
+    fn test_ascii_fast_path() {
+        use super::unicode;
+        assert_same(super::Small, unicode::Small, "Small");
+        assert_same(super::Large, unicode::Large, "Large");
+        assert_same(super::Symbol, unicode::Symbol, "Symbol");
+        assert_same(super::Graphic, unicode::Graphic, "Graphic");
+        assert_same(super::Digit, unicode::Digit, "Digit");
+        assert_same(super::WhiteChar, unicode::WhiteChar, "WhiteChar");
+    }
+
+    // straight from the grammar in the Report, with the general categories for "any Unicode
+    // lowercase letter" and the like, and the `White_Space` property for uniWhite.
+    fn report_classes(c: char) -> [(&'static str, bool); 7] {
+        use unic_ucd_category::GeneralCategory::{self, *};
+        let category = GeneralCategory::of(c);
+        let special = "(),;[]`{}".contains(c);
+        let asc_symbol = r"!#$%&*+./<=>?@\^|-~:".contains(c);
+        let uni_symbol = category.is_symbol() || category.is_punctuation();
+        let small = c.is_ascii_lowercase() || category == LowercaseLetter || c == '_';
+        let large = c.is_ascii_uppercase() || category == UppercaseLetter || category == TitlecaseLetter;
+        let symbol = asc_symbol || uni_symbol && !special && !"_\"'".contains(c);
+        let digit = c.is_ascii_digit() || category == DecimalNumber;
+        let white = "\r\n\u{C}\u{B} \t".contains(c) || c.is_whitespace();
+        let graphic = small || large || symbol || digit || special || c == '"' || c == '\'';
+        [("Small", small), ("Large", large), ("Symbol", symbol), ("Digit", digit),
+         ("WhiteChar", white), ("Graphic", graphic), ("Special", special)]
+    }
+
+    #[test]
+    fn test_report_classes() {
+        use super::*;
+        for c in (0..0x2100).filter_map(std::char::from_u32) {
+            let classes = [Small.check(c), Large.check(c), Symbol.check(c), Digit.check(c),
+                WhiteChar.check(c), Graphic.check(c), Special.check(c)];
+            for ((name, expected), actual) in report_classes(c).iter().zip(classes.iter()) {
+                assert_eq!(actual, expected, "{} disagrees with the Report on {:?}", name, c);
+            }
+        }
+    }
 }
@@ -0,0 +1,284 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Bracket matching in one pass over the tokens, for the parser and the editors alike.
+//!
+//! The brackets are kept on an explicit stack: nesting is as deep as memory allows.
+
+use std::fmt::{Display, Formatter};
+
+use super::{Location, Range};
+use super::layout::AugmentedLexeme;
+use crate::lexeme::Lexeme;
+
+/// The kinds of brackets.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum BracketKind {
+    /// `(` and `)`.
+    Parenthesis,
+    /// `[` and `]`.
+    SquareBracket,
+    /// Real `{` and `}`.
+    CurlyBracket,
+    /// Phantom `{` and `}`, from the layout algorithm: never paired with the real ones.
+    PhantomCurlyBracket,
+}
+
+impl BracketKind {
+    /// The opening bracket.
+    pub fn open(self) -> char {
+        match self {
+            BracketKind::Parenthesis => '(',
+            BracketKind::SquareBracket => '[',
+            BracketKind::CurlyBracket | BracketKind::PhantomCurlyBracket => '{',
+        }
+    }
+
+    /// The closing bracket.
+    pub fn close(self) -> char {
+        match self {
+            BracketKind::Parenthesis => ')',
+            BracketKind::SquareBracket => ']',
+            BracketKind::CurlyBracket | BracketKind::PhantomCurlyBracket => '}',
+        }
+    }
+
+    // the kind of a lexeme, and whether it opens.
+    fn of(lexeme: &Lexeme) -> Option<(BracketKind, bool)> {
+        Some(match lexeme {
+            Lexeme::OpenParenthesis => (BracketKind::Parenthesis, true),
+            Lexeme::CloseParenthesis => (BracketKind::Parenthesis, false),
+            Lexeme::OpenSquareBracket => (BracketKind::SquareBracket, true),
+            Lexeme::CloseSquareBracket => (BracketKind::SquareBracket, false),
+            Lexeme::OpenCurlyBracket => (BracketKind::CurlyBracket, true),
+            Lexeme::CloseCurlyBracket => (BracketKind::CurlyBracket, false),
+            _ => return None,
+        })
+    }
+}
+
+/// A bracket in the tokens.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Bracket {
+    /// the kind of bracket.
+    pub kind: BracketKind,
+    /// the index of the bracket in the tokens.
+    pub index: usize,
+    /// the source range of the bracket: a point for the phantom ones, see [`match_augmented`].
+    pub range: Range,
+}
+
+/// A matching pair of brackets.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct BracketPair {
+    /// the kind of both brackets.
+    pub kind: BracketKind,
+    /// the index of the opening bracket in the tokens.
+    pub open: usize,
+    /// the index of the closing bracket in the tokens.
+    pub close: usize,
+    /// the source range of the opening bracket.
+    pub open_range: Range,
+    /// the source range of the closing bracket.
+    pub close_range: Range,
+}
+
+/// A bracket left unmatched.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum BracketError {
+    /// An opening bracket never closed.
+    Unclosed(Bracket),
+    /// A closing bracket never opened.
+    Unopened(Bracket),
+    /// A closing bracket for an outer opening one, before the inner one `open` is closed, e.g.
+    /// the `]` in `[ ( ] )`. The `]` and the `[` are still paired, and so are the `(` and the
+    /// `)`: the crossing is reported only once.
+    Crossed {
+        /// the inner opening bracket, still open.
+        open: Bracket,
+        /// the closing bracket crossing it.
+        close: Bracket,
+    },
+}
+
+impl Display for BracketError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BracketError::Unclosed(b) => write!(f, "{}: unclosed '{}'", b.range.begin, b.kind.open()),
+            BracketError::Unopened(b) => write!(f, "{}: unopened '{}'", b.range.begin, b.kind.close()),
+            BracketError::Crossed { open, close } =>
+                write!(f, "{}: '{}' closed before the '{}' at {}",
+                       close.range.begin, close.kind.close(), open.kind.open(), open.range.begin),
+        }
+    }
+}
+
+/// Pair the real brackets of the (fat) tokens.
+///
+/// The pairs are sorted by their opening brackets; the errors come in the order they are
+/// found, the unclosed brackets last.
+pub fn match_brackets(tokens: &[(Lexeme, Range)]) -> Result<Vec<BracketPair>, Vec<BracketError>> {
+    match_with(tokens.iter().enumerate().filter_map(|(index, (lexeme, range))| {
+        let (kind, opens) = BracketKind::of(lexeme)?;
+        Some((Bracket { kind, index, range: *range }, opens))
+    }))
+}
+
+/// Pair the brackets of the augmented tokens, the phantom `{` and `}` as
+/// [`BracketKind::PhantomCurlyBracket`], and otherwise as [`match_brackets`] does.
+///
+/// A real bracket may close an outer one across phantom `{`s still open: the layout algorithm
+/// leaves out the `parse-error(t)` rule, e.g. in `(case x of y -> z)` the phantom `}` comes
+/// after the `)`. This is no error, and the phantom brackets are paired all the same.
+///
+/// The phantom brackets are given an empty range, right after the real lexeme before them.
+pub fn match_augmented(tokens: &[AugmentedLexeme]) -> Result<Vec<BracketPair>, Vec<BracketError>> {
+    let mut last_end = Location::new();
+    match_with(tokens.iter().enumerate().filter_map(move |(index, lexeme)| {
+        let phantom = Range::point(last_end);
+        use BracketKind::PhantomCurlyBracket;
+        let (kind, opens, range) = match lexeme {
+            AugmentedLexeme::Real(lexeme, range) => {
+                last_end = range.end;
+                let (kind, opens) = BracketKind::of(lexeme)?;
+                (kind, opens, *range)
+            }
+            AugmentedLexeme::PhantomOpenCurlyBracket(_) => (PhantomCurlyBracket, true, phantom),
+            AugmentedLexeme::PhantomCloseCurlyBracket(_) => (PhantomCurlyBracket, false, phantom),
+            _ => return None,
+        };
+        Some((Bracket { kind, index, range }, opens))
+    }))
+}
+
+fn match_with(brackets: impl Iterator<Item=(Bracket, bool)>) -> Result<Vec<BracketPair>, Vec<BracketError>> {
+    let mut stack: Vec<Bracket> = Vec::new();
+    // the inner brackets crossed: their closing brackets are expected still.
+    let mut crossed: Vec<Bracket> = Vec::new();
+    let mut pairs = Vec::new();
+    let mut errors = Vec::new();
+    for (bracket, opens) in brackets {
+        if opens {
+            stack.push(bracket);
+            continue;
+        }
+        let open = match stack.iter().rposition(|open| open.kind == bracket.kind) {
+            Some(k) => {
+                for open in stack.drain(k + 1..).rev() {
+                    if open.kind != BracketKind::PhantomCurlyBracket {
+                        errors.push(BracketError::Crossed { open, close: bracket });
+                    }
+                    crossed.push(open);
+                }
+                stack.pop().unwrap()
+            }
+            None => match crossed.iter().rposition(|open| open.kind == bracket.kind) {
+                Some(k) => crossed.remove(k),
+                None => {
+                    errors.push(BracketError::Unopened(bracket));
+                    continue;
+                }
+            },
+        };
+        pairs.push(BracketPair {
+            kind: bracket.kind,
+            open: open.index,
+            close: bracket.index,
+            open_range: open.range,
+            close_range: bracket.range,
+        });
+    }
+    errors.extend(stack.into_iter().map(BracketError::Unclosed));
+    if !errors.is_empty() { return Err(errors); }
+    pairs.sort_unstable_by_key(|pair| pair.open);
+    Ok(pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{match_brackets, match_augmented, BracketError, BracketKind};
+    use crate::lexeme::Lexeme;
+    use crate::scanner::layout::{AugmentedLexeme, AugmentedLexemeIterator, FatLexemeIterator};
+    use crate::scanner::Range;
+
+    fn tokens(source: &str) -> Vec<(Lexeme, Range)> {
+        FatLexemeIterator::new(source.as_bytes()).collect()
+    }
+
+    #[test]
+    fn test_match_brackets() {
+        let source = "f = [(x, {y})] (z)";
+        let pairs = match_brackets(&tokens(source)).unwrap();
+        let spans: Vec<_> = pairs.iter().map(|p| (p.kind.open(), p.open, p.close)).collect();
+        assert_eq!(spans, [('[', 2, 10), ('(', 3, 9), ('{', 6, 8), ('(', 11, 13)]);
+        assert_eq!(pairs[0].open_range.begin.column, 5);
+        assert_eq!(pairs[0].close_range.begin.column, 14);
+        assert_eq!(match_brackets(&[]), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        let depth = 10_000;
+        let source = "(".repeat(depth) + &"[]".repeat(2) + &")".repeat(depth);
+        let pairs = match_brackets(&tokens(&source)).unwrap();
+        assert_eq!(pairs.len(), depth + 2);
+        let n = depth * 2 + 4;
+        assert!(pairs.iter().take(depth).enumerate().all(|(k, p)| p.open == k && p.close == n - 1 - k));
+    }
+
+    #[test]
+    fn test_bracket_errors() {
+        let source = "f = (g (x)))\ny = h";
+        let errors = match_brackets(&tokens(source)).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        match errors[0] {
+            BracketError::Unopened(b) => {
+                assert_eq!((b.kind, b.index), (BracketKind::Parenthesis, 8));
+                assert_eq!((b.range.begin.line, b.range.begin.column), (1, 12));
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+        // crossed: reported once, with both sites.
+        let errors = match_brackets(&tokens("[ ( ] )")).unwrap_err();
+        assert_eq!(errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+                   ["1:5: ']' closed before the '(' at 1:3"]);
+        let errors = match_brackets(&tokens("( [ ( )")).unwrap_err();
+        assert_eq!(errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+                   ["1:3: unclosed '['", "1:1: unclosed '('"].iter().rev().copied().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_match_augmented() {
+        let source = "main = do\n  x <- f (case y of z -> z)\n  pure [x]\n";
+        let tokens: Vec<_> = AugmentedLexemeIterator::new(source.as_bytes()).collect();
+        let pairs = match_augmented(&tokens).unwrap();
+        let kinds: Vec<_> = pairs.iter().map(|p| p.kind).collect();
+        use BracketKind::*;
+        assert_eq!(kinds, [PhantomCurlyBracket, PhantomCurlyBracket, Parenthesis,
+                           PhantomCurlyBracket, SquareBracket]);
+        // the `of` block closed by the `)`, but still paired with its own phantom `}`.
+        assert!(pairs[3].close > pairs[2].close);
+        assert!(matches!(tokens[pairs[3].close], AugmentedLexeme::PhantomCloseCurlyBracket(_)));
+        // the phantom `{` of `do` is right after it.
+        assert_eq!(pairs[1].open_range, Range::point(pairs[1].open_range.begin));
+        assert_eq!((pairs[1].open_range.begin.line, pairs[1].open_range.begin.column), (1, 10));
+        // a real `}` never closes a phantom `{`.
+        let tokens: Vec<_> = AugmentedLexemeIterator::new("x = y }".as_bytes()).collect();
+        assert!(match_augmented(&tokens).is_err());
+    }
+}
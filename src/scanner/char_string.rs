@@ -18,16 +18,18 @@
 
 //! chars and strings: see "Haskell 2010 Report: 2.6 Character and String Literals".
 
-use std::convert::identity;
-use num_bigint::BigInt;
-use num_traits::ToPrimitive;
+use std::convert::{identity, TryFrom};
 
-use super::{Scanner, Result, basic::*};
+use super::{Scanner, Result, Range, basic::*};
+use super::numeric::{CompactInt, Digits};
 use crate::utils::char::{Stream, CharPredicate, Ascii};
 use crate::error::Diagnostic;
 use crate::error::DiagnosticMessage::Error;
-use crate::error::Error::CharOutOfBound;
-use crate::lexeme::Lexeme::{self, CharLiteral, StringLiteral};
+use crate::error::Construct;
+use crate::error::Error::{CharOutOfBound, ControlChar, IncompleteLexeme, InvalidCharInString};
+use crate::error::Error::{UnknownEscape, MissingEscapeDigits};
+use crate::lexer::ASCII_NAMES;
+use crate::lexeme::{LexemeType, Lexeme::{self, CharLiteral, StringLiteral}};
 
 impl<I: std::io::Read> Scanner<I> {
     /// Character literals or string literals.
@@ -39,45 +41,113 @@ impl<I: std::io::Read> Scanner<I> {
     fn char(&mut self) -> Option<Lexeme> {
         // char     -> ' ( graphic<’ | \> | space | escape<\&> ) '
         analyse!(self, '\'');
-        let c = simple_alt!(self, choice!(c; c: all!(Graphic, not!("'\\"))), Self::escape)?;
+        let c = simple_alt!(self,
+            choice!(c; c: any!(all!(Graphic, not!("'\\")), ' ')),
+            Self::escape,
+            Self::unknown_escape,
+            |this| this.invalid_literal_char(LexemeType::CharLiteral))?;
         analyse!(self, '\'');
         Some(CharLiteral(c))
     }
 
     fn string(&mut self) -> Option<Lexeme> {
         // string   -> " {graphic<" | \>  | space | escape | gap} "
+        let begin = self.location;
         analyse!(self, '"');
+        let opening = self.location;
+        let push = self.push_payload();
         let s = identity::<Option<_>>(self.many(
             |this| {
                 alt!(this, seq!("\\&" => None),
                            choice!(Some(c); c: any!(all!(Graphic, not!("\"\\")), ' ')),
                            |this| this.escape().map(Some),
-                           |this| this.gap().map(|_| None));
+                           |this| this.invalid_literal_char(LexemeType::StringLiteral).map(Some),
+                           |this| this.gap().map(|_| None),
+                           |this| this.unknown_escape().map(Some));
                 None
             },
             String::new(),
-            |res: &mut String, c| if let Some(c) = c { res.push(c) }))?;
-        analyse!(self, '"');
+            |res: &mut String, c| if let Some(c) = c { push(res, c) }))?;
+        match self.peek() {
+            Some('"') => { self.next(); }
+            // recover as if the string were closed before the line break.
+            None | Some('\r') | Some('\n') | Some('\u{C}') => {
+                let message = Error(IncompleteLexeme(Construct::Lexeme(LexemeType::StringLiteral)));
+                Diagnostic::new(Range::point(self.location), message)
+                    .with_label(Range { begin, end: opening }, "string literal starts here")
+                    .report(&mut self.diagnostics)
+            }
+            _ => return None,
+        }
         Some(StringLiteral(s))
     }
 
+    fn invalid_literal_char(&mut self, literal: LexemeType) -> Option<char> {
+        // neither graphic nor space, and not a line break: recover with a replacement character.
+        let begin = self.location;
+        let c = self.peek().filter(|&c| all!(not!(Graphic), not!(" \r\n\u{C}")).check(c))?;
+        // consumed without the generic `InvalidChar`: a more precise error is reported below.
+        self.next_input();
+        self.location.step_char(c, self.column_mode);
+        if c == '\t' { self.location.tablise(); }
+        // reported on their own, and kept.
+        if self.check_bidi_control(c, begin) { return Some(c); }
+        let error = if Control.check(c) { ControlChar(c, Some(literal)) } else { InvalidCharInString(c) };
+        Diagnostic::new(Range { begin, end: self.location }, Error(error))
+            .report(&mut self.diagnostics);
+        Some('\u{FFFD}')
+    }
+
     fn escape(&mut self) -> Option<char> {
         // escape   -> \ ( charesc | ascii | decimal | o octal | x hexadecimal )
         analyse!(self, '\\');
         simple_alt!(self,
             Self::char_esc,
             Self::ascii,
-            |this| this.numeric_escape(10),
-            |this| { analyse!(this, 'o'); this.numeric_escape(8) },
-            |this| { analyse!(this, 'x'); this.numeric_escape(16) })
+            |this| this.numeric_escape(10, Ascii::Digit),
+            |this| { analyse!(this, 'o'); this.numeric_escape(8, Octit) },
+            |this| { analyse!(this, 'x'); this.numeric_escape(16, Hexit) })
+    }
+
+    fn unknown_escape(&mut self) -> Option<char> {
+        // a backslash starting no valid escape (nor a gap): recover with a replacement
+        // character, rather than the raw one, as for the other invalid characters.
+        let begin = self.location;
+        analyse!(self, '\\');
+        let error = match self.peek() {
+            // known escapes, only without their digits: what follows is read on as usual,
+            // e.g. the `g` of `\xg`.
+            Some(c @ 'x') | Some(c @ 'o') => {
+                self.next();
+                MissingEscapeDigits(c)
+            }
+            Some(c) if Graphic.check(c) => {
+                self.next();
+                UnknownEscape(c)
+            }
+            // taken with the backslash, without another error for it, but a line break: the
+            // literal ends there.
+            Some(c) if !"\r\n\u{C}".contains(c) => {
+                self.next_input();
+                self.location.step_char(c, self.column_mode);
+                if c == '\t' { self.location.tablise(); }
+                IncompleteLexeme(Construct::Escape)
+            }
+            _ => IncompleteLexeme(Construct::Escape),
+        };
+        Diagnostic::new(Range { begin, end: self.location }, Error(error))
+            .report(&mut self.diagnostics);
+        Some('\u{FFFD}')
     }
 
-    fn numeric_escape(&mut self, base: u32) -> Option<char> {
+    fn numeric_escape(&mut self, base: u32, digit: impl CharPredicate) -> Option<char> {
         let start_loc = self.location;
-        analyse!(self, d: {BigInt::from(0)}{Self::app_int(base)} +Digit);
-        Some(d.to_u32().and_then(std::char::from_u32).unwrap_or_else(|| {
-            Diagnostic::new(self.location, Error(CharOutOfBound(d)))
-                .within(start_loc, self.location)
+        let push = |d: &mut CompactInt, c| d.push_digit(base, c);
+        analyse!(self, d: {CompactInt::default()}{push} +digit);
+        let code = d.to_i64().and_then(|x| u32::try_from(x).ok());
+        Some(code.and_then(std::char::from_u32).unwrap_or_else(|| {
+            Diagnostic::new(Range { begin: start_loc, end: self.location },
+                            Error(CharOutOfBound(d.into_big())))
                 .report(&mut self.diagnostics);
             '�'
         }))
@@ -103,9 +173,18 @@ impl<I: std::io::Read> Scanner<I> {
 
     fn gap(&mut self) -> Option<()> {
         // gap      -> \ whitechar {whitechar} \
+        let begin = self.location;
         analyse!(self, '\\');
-        identity::<Option<_>>(self.some_(Self::whitechar))?;
-        analyse!(self, '\\');
+        let opening = self.location;
+        identity::<Option<()>>(self.some_(Self::whitechar))?;
+        if self.peek() == Some('\\') {
+            self.next();
+        } else {
+            // recover as if the gap were closed here.
+            Diagnostic::new(Range::point(self.location), Error(IncompleteLexeme(Construct::StringGap)))
+                .with_label(Range { begin, end: opening }, "string gap opened here")
+                .report(&mut self.diagnostics);
+        }
         Some(())
     }
 
@@ -131,13 +210,10 @@ impl<I: std::io::Read> Scanner<I> {
         //           | BEL | BS | HT | LF | VT | FF | CR | SO | SI | DLE
         //           | DC1 | DC2 | DC3 | DC4 | NAK | SYN | ETB | CAN
         //           | EM | SUB | ESC | FS | GS | RS | US | SP | DEL
-        let names = ["NUL", "SOH", "STX", "ETX", "EOT", "ENQ", "ACK",
-            "BEL", "BS", "HT", "LF", "VT", "FF", "CR", "SO", "SI", "DLE",
-            "DC1", "DC2", "DC3", "DC4", "NAK", "SYN", "ETB", "CAN",
-            "EM", "SUB", "ESC", "FS", "GS", "RS", "US", "SP", "DEL"];
-        for (k, nm) in names.iter().copied().enumerate() {
-            if let Some(r) = self.anchored(seq!(nm => k)) {
-                return Some(char::from(r as u8));
+        for (k, nm) in ASCII_NAMES.iter().copied().enumerate() {
+            if let Some(r) = self.anchored_pure(seq!(nm => k)) {
+                // DEL is not next to SP, but at the very end of ASCII.
+                return Some(if nm == "DEL" { '\x7f' } else { char::from(r as u8) });
             }
         }
         None
@@ -146,7 +222,7 @@ impl<I: std::io::Read> Scanner<I> {
 
 #[cfg(test)]
 mod tests {
-    use crate::scanner::test_scanner_on;
+    use crate::testing::test_scanner_on;
     use crate::utils::setup_logger;
     use crate::utils::Result3::Success;
     use crate::lexeme::Lexeme::{self, CharLiteral, StringLiteral};
@@ -162,9 +238,120 @@ mod tests {
         test(r"'\r'", CharLiteral('\r'));
         test(r"'\ESC'", CharLiteral('\x1b'));
         test(r"'\^X'", CharLiteral('\x18'));
+        test(r"'\DEL'", CharLiteral('\x7f'));
         test(r#""A\r\ESC\^X""#, StringLiteral("A\r\x1b\x18".to_string()));
         test(r#""\SO\&H\SOH\4\&2\
                       \Some\&Other\nText""#,
              StringLiteral("\x0eH\x01\x042SomeOther\nText".to_string()));
+        test("' '", CharLiteral(' '));
+    }
+
+    #[test]
+    fn test_raw_control_chars() {
+        use crate::scanner::Scanner;
+        fn test(input: &str, res: Lexeme, diagnostics: &[&str]) {
+            let mut scanner = Scanner::new(input.as_bytes());
+            assert_eq!(scanner.char_or_string(), Success(res));
+            let actual: Vec<_> = scanner.diagnostics().iter().map(|d| d.to_string()).collect();
+            assert_eq!(actual, diagnostics);
+        }
+        // C0 controls are named as such, the other characters not allowed raw are not.
+        test("'\u{7}'", CharLiteral('\u{FFFD}'),
+             &[r"1:2-1:3: error: control character '\x07' not allowed raw in a character literal, use an escape"]);
+        test("'\u{B}'", CharLiteral('\u{FFFD}'),
+             &[r"1:2-1:3: error: character '\u{b}' not allowed raw in a literal, use an escape"]);
+        test("\"a\u{B}b\u{7}c\"", StringLiteral("a\u{FFFD}b\u{FFFD}c".to_string()), &[
+            r"1:3-1:4: error: character '\u{b}' not allowed raw in a literal, use an escape",
+            r"1:5-1:6: error: control character '\x07' not allowed raw in a string literal, use an escape",
+        ]);
+        test("\"\0a\u{1}\"", StringLiteral("\u{FFFD}a\u{FFFD}".to_string()), &[
+            r"1:2-1:3: error: control character '\x00' not allowed raw in a string literal, use an escape",
+            r"1:4-1:5: error: control character '\x01' not allowed raw in a string literal, use an escape",
+        ]);
+        test("'\0'", CharLiteral('\u{FFFD}'),
+             &[r"1:2-1:3: error: control character '\x00' not allowed raw in a character literal, use an escape"]);
+    }
+
+    #[test]
+    fn test_latin1_fallback() {
+        use crate::scanner::Scanner;
+        use crate::input::Encoding;
+        fn test(encoding: Encoding, res: Lexeme, diagnostics: &[&str]) {
+            let input: &[u8] = b"\"caf\xE9 cr\xE8me\"";
+            let mut scanner = Scanner::new(input).with_encoding(encoding);
+            assert_eq!(scanner.char_or_string(), Success(res));
+            let actual: Vec<_> = scanner.diagnostics().iter().map(|d| d.to_string()).collect();
+            assert_eq!(actual, diagnostics);
+        }
+        test(Encoding::Latin1, StringLiteral("caf\u{E9} cr\u{E8}me".to_string()),
+             &["1:5-1:5: note: invalid UTF-8 decoded as Latin-1, here and in the rest of the input"]);
+        test(Encoding::Utf8, StringLiteral("caf crme".to_string()), &[
+            "1:5-1:5: error: invalid UTF-8 sequence [E9]",
+            "1:8-1:8: error: invalid UTF-8 sequence [E8]",
+        ]);
+    }
+
+    #[test]
+    fn test_escape_ranges() {
+        use crate::scanner::layout::FatLexemeIterator;
+        use crate::range;
+        // the named escapes are matched as a whole, and the location must follow them.
+        let input = "'\\ESC' x \"a\\SOH\\&\\DEL\" y";
+        let ranges: Vec<_> = FatLexemeIterator::new(input.as_bytes()).map(|(t, r)| (t.to_string(), r)).collect();
+        assert_eq!(ranges, [
+            ("'\\u{1b}'".to_string(), range!(1:1-1:7)),
+            ("x".to_string(), range!(1:8-1:9)),
+            (r#""a\u{1}\u{7f}""#.to_string(), range!(1:10-1:23)),
+            ("y".to_string(), range!(1:24-1:25)),
+        ]);
+    }
+
+    #[test]
+    fn test_unknown_escape() {
+        use crate::scanner::layout::FatLexemeIterator;
+        use crate::range;
+        let input = r#""a\qb" x '\q' "\xg\Q""#;
+        let mut it = FatLexemeIterator::new(input.as_bytes());
+        let tokens: Vec<_> = it.by_ref().map(|(t, r)| (t.to_string(), r)).collect();
+        assert_eq!(tokens, [
+            ("\"a\u{FFFD}b\"".to_string(), range!(1:1-1:7)),
+            ("x".to_string(), range!(1:8-1:9)),
+            ("'\u{FFFD}'".to_string(), range!(1:10-1:14)),
+            ("\"\u{FFFD}g\u{FFFD}\"".to_string(), range!(1:15-1:22)),
+        ]);
+        let (error, scanner) = it.into_scanner();
+        assert!(error.is_none());
+        let diagnostics: Vec<_> = scanner.diagnostics().iter().map(|d| d.to_string()).collect();
+        assert_eq!(diagnostics, [
+            r"1:3-1:5: error: unknown escape sequence `\q`",
+            r"1:11-1:13: error: unknown escape sequence `\q`",
+            r"1:16-1:18: error: escape sequence `\x` without hexadecimal digits",
+            r"1:19-1:21: error: unknown escape sequence `\Q`",
+        ]);
+    }
+
+    #[test]
+    fn test_incomplete_escape() {
+        use crate::scanner::layout::FatLexemeIterator;
+        use crate::range;
+        // a space, or a control character, right after a backslash; and digits of the wrong base.
+        let input = "'\\ ' \"a\\\u{1}b\" \"\\o9\\xff\\\u{663}\" x";
+        let mut it = FatLexemeIterator::new(input.as_bytes());
+        let tokens: Vec<_> = it.by_ref().map(|(t, r)| (t.to_string(), r)).collect();
+        assert_eq!(tokens, [
+            ("'\u{FFFD}'".to_string(), range!(1:1-1:5)),
+            ("\"a\u{FFFD}b\"".to_string(), range!(1:6-1:12)),
+            ("\"\u{FFFD}9\u{FF}\u{FFFD}\"".to_string(), range!(1:13-1:24)),
+            ("x".to_string(), range!(1:25-1:26)),
+        ]);
+        let (error, scanner) = it.into_scanner();
+        assert!(error.is_none());
+        let diagnostics: Vec<_> = scanner.diagnostics().iter().map(|d| d.to_string()).collect();
+        assert_eq!(diagnostics, [
+            "1:2-1:4: error: unterminated escape sequence",
+            "1:8-1:10: error: unterminated escape sequence",
+            r"1:14-1:16: error: escape sequence `\o` without octal digits",
+            "1:21-1:23: error: unknown escape sequence `\\\u{663}`",
+        ]);
     }
 }
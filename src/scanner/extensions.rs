@@ -0,0 +1,206 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2020  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! GHC extensions to the lexical syntax, all off by default, as in Haskell 2010.
+
+use super::Scanner;
+use crate::utils::char::{Stream, CharPredicate};
+use crate::lexeme::{RId, ROp, Lexeme::{self, *}};
+
+/// GHC language extensions affecting the lexical syntax, see [`Scanner::with_extensions`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct GhcExtensions {
+    /// `MagicHash`: `#` or `##` right after an identifier or a literal glues onto it, as in
+    /// `Int#`, `3#`, or `"foo"#`. Also enables the `(#` and `#)` of unboxed tuples.
+    pub magic_hash: bool,
+    /// `ExplicitForAll` (implied by `ScopedTypeVariables` and others): `forall` is a keyword,
+    /// and so is a standalone `∀`.
+    pub explicit_forall: bool,
+    /// `Arrows`: `-<`, `>-`, `-<<`, and `>>-` are reserved operators, see
+    /// [`ROp::ARROW_TAILS`](crate::lexeme::ROp::ARROW_TAILS).
+    pub arrows: bool,
+    /// `LambdaCase`: `\case` and `\cases` are keywords, when nothing is between the `\` and
+    /// the `case`; `\ case` is still a lambda of a `case` expression.
+    pub lambda_case: bool,
+    /// `NumericUnderscores`: underscores between the digits of a numeric literal, as in
+    /// `1_000_000` or `0xff_ff`, are ignored. Those right after a base prefix (`0x_ff`) or at
+    /// the end (`1_`) are reported as misplaced, but still part of the literal.
+    pub numeric_underscores: bool,
+}
+
+impl<I: std::io::Read> Scanner<I> {
+    /// Unboxed tuple brackets: `(#` and `#)`.
+    pub(super) fn unboxed_parenthesis(&mut self) -> Option<Lexeme> {
+        // as in GHC, sections of operators starting with `#` need spaces then: `( # )`.
+        simple_alt!(self,
+            choice!(OpenUnboxedParenthesis; '(', '#'),
+            choice!(CloseUnboxedParenthesis; '#', ')'))
+    }
+
+    /// Glue the `#`s right after `lexeme`, if it is an identifier or a literal.
+    pub(super) fn magic_hash(&mut self, lexeme: Lexeme) -> Lexeme {
+        if !matches!(lexeme, Identifier(_) | QIdentifier(_) | Integer(_) | Float(_) |
+                             CharLiteral(_) | StringLiteral(_)) {
+            return lexeme;
+        }
+        let mut hashes = 0;
+        while hashes < 2 && self.peek() == Some('#') {
+            self.next();
+            hashes += 1;
+        }
+        if hashes == 0 { lexeme } else { MagicHash(Box::new(lexeme), hashes) }
+    }
+
+    /// Glue `case` or `cases` right after `lexeme`, if it is a `\`.
+    pub(super) fn lambda_case(&mut self, lexeme: Lexeme) -> Lexeme {
+        if lexeme != ReservedOp(ROp::Backslash) { return lexeme; }
+        // a whole identifier, not `\caseX`.
+        let keyword = self.anchored(|this| match this.var_id()?.as_str() {
+            "case" => Some(RId::LambdaCase),
+            "cases" => Some(RId::LambdaCases),
+            _ => None,
+        });
+        keyword.map_or(lexeme, ReservedId)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GhcExtensions;
+    use crate::scanner::Scanner;
+    use crate::utils::Result3::Success;
+    use crate::lexeme::Lexeme::{self, *};
+
+    fn lex(input: &str, extensions: GhcExtensions) -> Vec<Lexeme> {
+        let mut scanner = Scanner::new(input.as_bytes()).with_extensions(extensions);
+        let mut res = Vec::new();
+        loop {
+            let _ = scanner.whitespace();
+            match scanner.next_lexeme() {
+                Success(lexeme) => res.push(lexeme),
+                _ => break,
+            }
+        }
+        assert_eq!(scanner.diagnostics().iter().count(), 0, "diagnostics on {:?}", input);
+        res
+    }
+
+    #[test]
+    fn test_magic_hash() {
+        let test = |input: &str, off: &[Lexeme], on: &[Lexeme]| {
+            let magic_hash = GhcExtensions { magic_hash: true, ..GhcExtensions::default() };
+            assert_eq!(lex(input, GhcExtensions::default()), off, "{:?} without MagicHash", input);
+            assert_eq!(lex(input, magic_hash), on, "{:?} with MagicHash", input);
+        };
+        let hashed = |t, n| MagicHash(Box::new(t), n);
+        let int = |n: i32| Integer(n.into());
+        let hash = || Operator("#".into());
+        test("Int#", &[Identifier("Int".into()), hash()], &[hashed(Identifier("Int".into()), 1)]);
+        test("3#", &[int(3), hash()], &[hashed(int(3), 1)]);
+        test("3##", &[int(3), Operator("##".into())], &[hashed(int(3), 2)]);
+        test(r##""foo"#"##, &[StringLiteral("foo".into()), hash()],
+             &[hashed(StringLiteral("foo".into()), 1)]);
+        // not glued across whitespaces.
+        let spaced = [Identifier("x".into()), hash(), Identifier("y".into())];
+        test("x #y", &spaced, &spaced);
+        test("(# , #)", &[OpenParenthesis, hash(), Comma, hash(), CloseParenthesis],
+             &[OpenUnboxedParenthesis, Comma, CloseUnboxedParenthesis]);
+        // operator sections need spaces then.
+        test("(#)", &[OpenParenthesis, hash(), CloseParenthesis],
+             &[OpenUnboxedParenthesis, CloseParenthesis]);
+        let section = [OpenParenthesis, hash(), CloseParenthesis];
+        test("( # )", &section, &section);
+    }
+
+    #[test]
+    fn test_explicit_forall() {
+        use crate::lexeme::{RId::Forall, ROp::RightArrow};
+        let explicit_forall = GhcExtensions { explicit_forall: true, ..GhcExtensions::default() };
+        let id = |s: &str| Identifier(s.into());
+        let op = |s: &str| Operator(s.into());
+        let rest = [id("a"), op("."), id("a"), ReservedOp(RightArrow), id("a")];
+        let keyword: Vec<_> = std::iter::once(ReservedId(Forall)).chain(rest.iter().cloned()).collect();
+        assert_eq!(lex("forall a. a -> a", explicit_forall), keyword);
+        assert_eq!(lex("\u{2200} a. a -> a", explicit_forall), keyword);
+        let plain: Vec<_> = std::iter::once(id("forall")).chain(rest.iter().cloned()).collect();
+        assert_eq!(lex("forall a. a -> a", GhcExtensions::default()), plain);
+        assert_eq!(lex("\u{2200} a. a -> a", GhcExtensions::default())[0], op("\u{2200}"));
+        // a function named `forall` is fine without the extension.
+        assert_eq!(lex("forall p xs = all p xs", GhcExtensions::default())[..2], [id("forall"), id("p")]);
+        // not a standalone `∀`, nor `foralls`.
+        assert_eq!(lex("\u{2200}. foralls", explicit_forall), [op("\u{2200}."), id("foralls")]);
+    }
+
+    #[test]
+    fn test_arrows() {
+        use crate::lexeme::ROp::{self, *};
+        let arrows = GhcExtensions { arrows: true, ..GhcExtensions::default() };
+        let id = |s: &str| Identifier(s.into());
+        let op = |s: &str| Operator(s.into());
+        for (s, tail) in [("-<", LeftArrowTail), (">-", RightArrowTail),
+                          ("-<<", DoubleLeftArrowTail), (">>-", DoubleRightArrowTail)] {
+            let input = format!("f {} x", s);
+            assert_eq!(lex(&input, GhcExtensions::default()), [id("f"), op(s), id("x")]);
+            assert_eq!(lex(&input, arrows), [id("f"), ReservedOp(tail), id("x")]);
+        }
+        // maximal munch first: longer operators are not arrow tails.
+        assert_eq!(lex("f -<- x >-> y", arrows), [id("f"), op("-<-"), id("x"), op(">->"), id("y")]);
+        assert_eq!(lex("x <- f -< y", arrows),
+                   [id("x"), ReservedOp(ROp::LeftArrow), id("f"), ReservedOp(LeftArrowTail), id("y")]);
+    }
+
+    #[test]
+    fn test_lambda_case() {
+        use crate::lexeme::{RId, ROp::Backslash};
+        let lambda_case = GhcExtensions { lambda_case: true, ..GhcExtensions::default() };
+        let test = |input: &str, off: &[Lexeme], on: &[Lexeme]| {
+            assert_eq!(lex(input, GhcExtensions::default()), off, "{:?} without LambdaCase", input);
+            assert_eq!(lex(input, lambda_case), on, "{:?} with LambdaCase", input);
+        };
+        let id = |s: &str| Identifier(s.into());
+        let case = || ReservedId(RId::Case);
+        test(r"\case x", &[ReservedOp(Backslash), case(), id("x")], &[ReservedId(RId::LambdaCase), id("x")]);
+        test(r"\cases", &[ReservedOp(Backslash), id("cases")], &[ReservedId(RId::LambdaCases)]);
+        // never glued across whitespaces, nor onto another identifier.
+        let spaced = [ReservedOp(Backslash), case(), id("x")];
+        test(r"\ case x", &spaced, &spaced);
+        let lambda = [ReservedOp(Backslash), id("casey"), ReservedOp(crate::lexeme::ROp::RightArrow)];
+        test(r"\casey ->", &lambda, &lambda);
+        // nor after a longer operator.
+        test(r"\\case", &[Operator(r"\\".into()), case()], &[Operator(r"\\".into()), case()]);
+        // with MagicHash as well.
+        let both = GhcExtensions { magic_hash: true, ..lambda_case };
+        assert_eq!(lex(r"\case x#", both), [ReservedId(RId::LambdaCase), MagicHash(Box::new(id("x")), 1)]);
+    }
+
+    #[test]
+    fn test_lambda_case_layout() {
+        use crate::scanner::layout::{AugmentedLexemeIterator, EnrichedLexemeIterator,
+                                     FatLexemeIterator, RawLexemeIterator};
+        let layout = |extensions: GhcExtensions| {
+            let scanner = Scanner::new(r"f = \case x -> y".as_bytes()).with_extensions(extensions);
+            let fat = FatLexemeIterator::from(RawLexemeIterator::from(scanner));
+            let it = AugmentedLexemeIterator::from(EnrichedLexemeIterator::from(fat));
+            it.map(|t| t.to_string().rsplit(": ").next().unwrap().to_string()).collect::<Vec<_>>()
+        };
+        // a block after `\case`, as after `of`.
+        let lambda_case = GhcExtensions { lambda_case: true, ..GhcExtensions::default() };
+        assert_eq!(layout(lambda_case), ["{", "f", "=", r"\case", "{", "x", "->", "y", "}", "}", "<eof>"]);
+        assert_eq!(layout(GhcExtensions::default()), ["{", "f", "=", r"\", "case", "x", "->", "y", "}", "<eof>"]);
+    }
+}
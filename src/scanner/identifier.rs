@@ -20,8 +20,87 @@
 
 use super::{Scanner, Result, basic::*};
 use crate::utils::char::{CharPredicate, Stream};
-use crate::lexeme::{RId, ROp, Lexeme, QName, ModuleId};
-use crate::lexeme::Lexeme::{ReservedId, ReservedOp, Identifier, Operator, QIdentifier, QOperator};
+use crate::lexeme::{Lexeme, Name, QName, ModuleId, RId, ROp};
+use crate::lexeme::Lexeme::{
+    ReservedId, ReservedOp, Identifier, Operator, QIdentifier, QOperator, QualifiedPrefix,
+};
+
+/// `dashes -> -- {-}`: two or more dashes, which start a comment instead of forming an operator.
+pub(crate) fn is_dashes(name: &str) -> bool {
+    name.len() >= 2 && name.chars().all(|c| c == '-')
+}
+
+// one more character than the longest reserved spelling (`deriving`, `instance`): a longer
+// name has more characters than that in its sketch, and is never reserved.
+const SKETCH_LENGTH: usize = 9;
+
+// the first characters of a name, on the stack, for classifying it without the payloads.
+#[derive(Copy, Clone)]
+pub(super) struct Sketch {
+    buf: [u8; 4 * SKETCH_LENGTH],
+    len: usize,
+    chars: usize,
+    // whether all the characters (not only those kept) are dashes.
+    dashes: bool,
+}
+
+impl Sketch {
+    fn push(&mut self, c: char) {
+        self.dashes &= c == '-';
+        if self.chars == SKETCH_LENGTH { return; }
+        self.len += c.encode_utf8(&mut self.buf[self.len..]).len();
+        self.chars += 1;
+    }
+
+    fn as_str(&self) -> &str {
+        // only whole characters are ever pushed.
+        std::str::from_utf8(&self.buf[..self.len]).unwrap()
+    }
+}
+
+// a name as scanned: in full, or only sketched when scanning without the payloads.
+pub(super) enum Spelling {
+    Full(Name),
+    Sketch(Sketch),
+}
+
+impl Spelling {
+    fn sketch(c: char) -> Self {
+        let mut sketch = Sketch { buf: [0; 4 * SKETCH_LENGTH], len: 0, chars: 0, dashes: true };
+        sketch.push(c);
+        Spelling::Sketch(sketch)
+    }
+
+    // in full, at most `max` characters, as with `Scanner::bounded_push`.
+    fn push(&mut self, c: char, max: usize) {
+        match self {
+            Spelling::Full(name) => if name.len() < 4 * max { name.push(c) },
+            Spelling::Sketch(sketch) => sketch.push(c),
+        }
+    }
+
+    pub(super) fn as_str(&self) -> &str {
+        match self {
+            Spelling::Full(name) => name,
+            Spelling::Sketch(sketch) => sketch.as_str(),
+        }
+    }
+
+    fn is_dashes(&self) -> bool {
+        match self {
+            Spelling::Full(name) => is_dashes(name),
+            Spelling::Sketch(sketch) => sketch.dashes && sketch.chars >= 2,
+        }
+    }
+
+    // the name itself: empty when only sketched.
+    fn into_name(self) -> Name {
+        match self {
+            Spelling::Full(name) => name,
+            Spelling::Sketch(_) => Name::new(),
+        }
+    }
+}
 
 impl<I: std::io::Read> Scanner<I> {
     /// Identifiers or operators.
@@ -39,90 +118,85 @@ impl<I: std::io::Read> Scanner<I> {
         self.con_id().map(Identifier)
     }
 
-    fn con_id(&mut self) -> Option<String> {
+    fn con_id(&mut self) -> Option<Name> {
         // conid    -> large { small | large | digit | ' }
-        analyse!(self, c: Large, name: {c.to_string()}{String::push} *any!(Small, Large, Digit, '\''));
+        let push = self.push_payload();
+        analyse!(self, c: Large, name: {self.start_payload(c)}{push}
+                       *any!(Small, Large, Digit, '\''));
         Some(name)
     }
 
-    fn var_id_or_reserved_id(&mut self) -> Option<Lexeme> {
+    // a name starting with `c`, then the characters satisfying `rest`.
+    fn spelling(&mut self, c: char, rest: impl CharPredicate) -> Spelling {
+        let init = if self.payloads { Spelling::Full(Name::from(c)) } else { Spelling::sketch(c) };
+        let max = self.max_token_length;
+        self.span(|x| rest.check(x), init, |spelling, x| spelling.push(x, max))
+    }
+
+    // varid, before telling the reserved ones apart.
+    pub(super) fn var_id(&mut self) -> Option<Spelling> {
+        analyse!(self, c: Small);
+        Some(self.spelling(c, any!(Small, Large, Digit, '\'')))
+    }
+
+    pub(super) fn var_id_or_reserved_id(&mut self) -> Option<Lexeme> {
         // varid      -> (small { small | large | digit | ' })<reservedid>
-        analyse!(self, c: Small, name: {c.to_string()}{String::push} *any!(Small, Large, Digit, '\''));
+        let name = self.var_id()?;
         // reservedid -> case | class | data | default | deriving | do | else
         //             | foreign | if | import | in | infix | infixl
         //             | infixr | instance | let | module | newtype | of
         //             | then | type | where | _
-        Some(match name.as_str() {
-            "case" => ReservedId(RId::Case),
-            "class" => ReservedId(RId::Class),
-            "data" => ReservedId(RId::Data),
-            "default" => ReservedId(RId::Default),
-            "deriving" => ReservedId(RId::Deriving),
-            "do" => ReservedId(RId::Do),
-            "else" => ReservedId(RId::Else),
-            "foreign" => ReservedId(RId::Foreign),
-            "if" => ReservedId(RId::If),
-            "import" => ReservedId(RId::Import),
-            "in" => ReservedId(RId::In),
-            "infix" => ReservedId(RId::Infix),
-            "infixl" => ReservedId(RId::Infixl),
-            "infixr" => ReservedId(RId::Infixr),
-            "instance" => ReservedId(RId::Instance),
-            "let" => ReservedId(RId::Let),
-            "module" => ReservedId(RId::Module),
-            "newtype" => ReservedId(RId::Newtype),
-            "of" => ReservedId(RId::Of),
-            "then" => ReservedId(RId::Then),
-            "type" => ReservedId(RId::Type),
-            "where" => ReservedId(RId::Where),
-            "_" => ReservedId(RId::Wildcard),
-            _ => Identifier(name),
-        })
+        if self.extensions.explicit_forall && name.as_str() == "forall" {
+            return Some(ReservedId(RId::Forall));
+        }
+        Some(name.as_str().parse().map_or_else(|_| Identifier(name.into_name()), ReservedId))
     }
 
     fn mod_id(&mut self) -> Option<ModuleId> {
         // modid    -> { conid . } conid
-        let names: Option<Vec<String>> = self.sep_by(
-            Self::con_id, choice!('.'), Vec::new(), Vec::push);
+        let payloads = self.payloads;
+        let names: Option<Vec<Name>> = self.sep_by(
+            Self::con_id, choice!('.'), Vec::new(),
+            |names: &mut Vec<Name>, name| if payloads { names.push(name) });
         names.map(ModuleId)
     }
 
     fn var_sym_or_reserved_op(&mut self) -> Option<Lexeme> {
         // varsym       -> ( symbol<:> {symbol} )<reservedop | dashes>
         // reservedop   -> .. | : | :: | = | \ | | | <- | -> | @ | ~ | =>
-        analyse!(self, c: all!(Symbol, not!(':')), name: {c.to_string()}{String::push} *Symbol);
-        Some(match name.as_str() {
-            ".." => ReservedOp(ROp::DotDot),
-            "=" => ReservedOp(ROp::EqualSign),
-            "\\" => ReservedOp(ROp::Backslash),
-            "|" => ReservedOp(ROp::Pipe),
-            "<-" => ReservedOp(ROp::LeftArrow),
-            "->" => ReservedOp(ROp::RightArrow),
-            "@" => ReservedOp(ROp::AtSign),
-            "^" => ReservedOp(ROp::Tilde),
-            "=>" => ReservedOp(ROp::DoubleRightArrow),
-            _ => Operator(name),
-        })
+        analyse!(self, c: all!(Symbol, not!(':')));
+        let name = self.spelling(c, Symbol);
+        // left to the comments: `whitestuff` normally takes them first, e.g. `(--)` opens a
+        // comment right after the parenthesis, as in GHC; `(-)` and `(-->)` are operators.
+        if name.is_dashes() { return None; }
+        // only a standalone `∀`: it is a symbol character, as in `∀.` for an operator.
+        if self.extensions.explicit_forall && name.as_str() == "∀" {
+            return Some(ReservedId(RId::Forall));
+        }
+        if self.extensions.arrows {
+            if let Some(&op) = ROp::ARROW_TAILS.iter().find(|op| name.as_str() == op.as_str()) {
+                return Some(ReservedOp(op));
+            }
+        }
+        Some(name.as_str().parse().map_or_else(|_| Operator(name.into_name()), ReservedOp))
     }
 
     fn con_sym_or_reserved_op(&mut self) -> Option<Lexeme> {
         // consym       -> ( : {symbol} )<reservedop>
         // reservedop   -> .. | : | :: | = | \ | | | <- | -> | @ | ~ | =>
-        analyse!(self, ':', name: {':'.to_string()}{String::push} *Symbol);
-        Some(match name.as_str() {
-            ":" => ReservedOp(ROp::Colon),
-            "::" => ReservedOp(ROp::ColonColon),
-            _ => Operator(name),
-        })
+        analyse!(self, ':');
+        let name = self.spelling(':', Symbol);
+        Some(name.as_str().parse().map_or_else(|_| Operator(name.into_name()), ReservedOp))
     }
 
     fn q_con_id(&mut self) -> Option<Lexeme> {
         let init = QName::new(self.con_id()?);
+        let payloads = self.payloads;
         Option::map(
             self.some(|scanner| {
                 analyse!(scanner, '.');
                 scanner.con_id()
-            }, init, QName::append),
+            }, init, |qname: &mut QName, name| if payloads { qname.append(name) }),
             QIdentifier,
         )
     }
@@ -130,11 +204,15 @@ impl<I: std::io::Read> Scanner<I> {
     fn q_var_id_or_q_sym(&mut self) -> Option<Lexeme> {
         let module = self.mod_id()?;
         analyse!(self, '.');
+        // `M.(+)`: leave the parenthesised operator to the parser.
+        if self.peek() == Some('(') { return Some(QualifiedPrefix(module)); }
         Some(match simple_alt!(self,
             Self::var_id_or_reserved_id,
             Self::var_sym_or_reserved_op,
             Self::con_sym_or_reserved_op)? {
             Identifier(name) => QIdentifier(QName { module, name }),
+            // varsym       -> ( symbol<:> {symbol} )<reservedop | dashes>
+            Operator(name) if is_dashes(&name) => return None,
             Operator(name) => QOperator(QName { module, name }),
             _ => return None,
         })
@@ -143,11 +221,13 @@ impl<I: std::io::Read> Scanner<I> {
 
 #[cfg(test)]
 mod tests {
-    use crate::scanner::test_scanner_on;
+    use crate::testing::test_scanner_on;
     use crate::utils::setup_logger;
-    use crate::utils::Result3::Success;
+    use crate::utils::Result3::{Success, RetryLater};
     use crate::lexeme::{Lexeme, QName, ModuleId};
-    use crate::lexeme::Lexeme::{Identifier, QIdentifier, QOperator};
+    use crate::lexeme::Lexeme::{Identifier, QIdentifier, QOperator, QualifiedPrefix};
+    use crate::scanner::Scanner;
+    use expect_test::expect;
 
     #[test]
     fn test_identifier() {
@@ -156,16 +236,83 @@ mod tests {
             trace!(scanner, "test on {:?} ...", input);
             test_scanner_on(input, method!(id_or_sym), Success(res), next);
         }
-        test("some'Identifier_42", Identifier("some'Identifier_42".to_string()), None);
-        test("Ctor_''233'_", Identifier("Ctor_''233'_".to_string()), None);
+        test("some'Identifier_42", Identifier("some'Identifier_42".into()), None);
+        test("Ctor_''233'_", Identifier("Ctor_''233'_".into()), None);
         test("Mod.SubMod.Class", QIdentifier(QName {
-            module: ModuleId(vec!["Mod".to_string(), "SubMod".to_string()]),
-            name: "Class".to_string(),
+            module: ModuleId(vec!["Mod".into(), "SubMod".into()]),
+            name: "Class".into(),
         }), None);
         test("F..", QOperator(QName {
-            module: ModuleId(vec!["F".to_string()]),
-            name: ".".to_string(),
+            module: ModuleId(vec!["F".into()]),
+            name: ".".into(),
         }), None);
-        test("F.", Identifier("F".to_string()), Some('.'));
+        test("F.", Identifier("F".into()), Some('.'));
+        test("F.-", QOperator(QName {
+            module: ModuleId(vec!["F".into()]),
+            name: "-".into(),
+        }), None);
+        // never an operator on their own: left to the comments.
+        for dashes in ["--", "---"] {
+            let next = dashes.chars().next();
+            test_scanner_on(dashes, method!(id_or_sym), RetryLater(()), next);
+        }
+        test("M.N.(+)", QualifiedPrefix(ModuleId(vec!["M".into(), "N".into()])),
+             Some('('));
+        // titlecase letters are large: `ǅ` starts a conid, and so a module name.
+        test("\u{1C5}ungla", Identifier("\u{1C5}ungla".into()), None);
+        test("x\u{1C5}", Identifier("x\u{1C5}".into()), None);
+        test("\u{1C5}.x", QIdentifier(QName {
+            module: ModuleId(vec!["\u{1C5}".into()]),
+            name: "x".into(),
+        }), None);
+    }
+
+    #[test]
+    fn test_module_paths() {
+        use crate::lexeme::{Lexeme::ReservedId, RId};
+        // `module` and then the path as a single lexeme, whatever comes after it.
+        fn path(input: &str) -> ModuleId {
+            let mut scanner = Scanner::new(input.as_bytes());
+            assert_eq!(scanner.next_lexeme(), Success(ReservedId(RId::Module)));
+            let _ = scanner.whitespace();
+            match scanner.next_lexeme() {
+                Success(Identifier(name)) => QName::new(name).as_module_path(),
+                Success(QIdentifier(name)) => name.as_module_path(),
+                res => panic!("{:?} is not a module path", res),
+            }
+        }
+        for rest in [")", " where", ""] {
+            for segments in [&["N"][..], &["N", "O"], &["N", "O", "P"]] {
+                let input = format!("module {}{}", segments.join("."), rest);
+                let expected = ModuleId(segments.iter().map(|&s| s.into()).collect());
+                assert_eq!(path(&input), expected, "on {:?}", input);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parenthesised_operators() {
+        fn lex(input: &str) -> String {
+            let mut scanner = Scanner::new(input.as_bytes());
+            let mut res = Vec::new();
+            loop {
+                let _ = scanner.whitespace();
+                match scanner.next_lexeme() {
+                    Success(lexeme) => res.push(format!("{:?}", lexeme)),
+                    _ => break res.join(" "),
+                }
+            }
+        }
+        expect![[r#"QualifiedPrefix(ModuleId(["M"])) OpenParenthesis Operator("+") CloseParenthesis"#]]
+            .assert_eq(&lex("M.(+)"));
+        expect![[r#"QualifiedPrefix(ModuleId(["M"])) OpenParenthesis Operator("+") CloseParenthesis"#]]
+            .assert_eq(&lex("M.( + )"));
+        expect![[r#"OpenParenthesis Operator(":+") CloseParenthesis"#]].assert_eq(&lex("(:+)"));
+        expect![[r#"OpenParenthesis Operator("+") CloseParenthesis"#]].assert_eq(&lex("(+)"));
+        // dashes: a single `-` is an operator, two or more open a comment, as in GHC.
+        expect![[r#"OpenParenthesis Operator("-") CloseParenthesis"#]].assert_eq(&lex("(-)"));
+        expect![[r#"OpenParenthesis"#]].assert_eq(&lex("(--)"));
+        expect![[r#"OpenParenthesis Operator("-->") CloseParenthesis"#]].assert_eq(&lex("(-->)"));
+        expect![[r#"Identifier("x") Operator("-") Operator("-") Identifier("y")"#]].assert_eq(&lex("x - -y"));
     }
 }
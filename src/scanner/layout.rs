@@ -18,22 +18,86 @@
 
 //! Haskell layout: see "Haskell 2010 Report, 10.3 Layout".
 
-use super::{Range, LexError, Scanner, Location};
-use crate::lexeme::{Lexeme, Lexeme::*, RId::Module};
+use super::{Range, LexError, Scanner, Location, Progress};
+use crate::lexeme::{Lexeme, LexemeType, Lexeme::*, RId::Module};
 use crate::utils::Result3::*;
 use std::fmt::{Display, Formatter};
 use crate::scanner::layout::AugmentedLexeme::{PhantomCloseCurlyBracket, PhantomSemicolon, PhantomOpenCurlyBracket, Real};
 use crate::utils::iter::IterStream;
+use crate::error::Diagnostic;
 use std::collections::VecDeque;
+use crate::source::FileId;
+
+/// What to do with tabs in the indentation, i.e. the whitespace before the first token of a
+/// line, which determines its column under the assumption of 8-column tab stops.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum TabPolicy {
+    /// accept tabs silently.
+    #[default]
+    Allow,
+    /// report a warning for each line indented with tabs.
+    Warn,
+    /// report an error for each line indented with tabs.
+    Error,
+}
+
+/// Where to report non-ASCII whitespaces (e.g. U+00A0 NO-BREAK SPACE) as errors: they look
+/// like spaces, but change the columns invisibly.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum ConfusablePolicy {
+    /// only in the indentation, where they affect the layout.
+    #[default]
+    Indentation,
+    /// anywhere between the lexemes, but not in comments or string gaps.
+    Anywhere,
+}
+
+/// Configuration for the layout algorithm.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct LayoutConfig {
+    /// what to do with tabs in the indentation.
+    pub tabs_in_indentation: TabPolicy,
+    /// where to report non-ASCII whitespaces.
+    pub confusable_whitespace: ConfusablePolicy,
+    /// no phantom `;` before a line continuing the previous one, i.e. starting with an
+    /// operator, a `,`, or a closing bracket, as in leading-operator style. Off by default,
+    /// as the Report inserts it (and relies on `parse-error(t)` to recover); note that a line
+    /// starting with a bang pattern (`!x = ...`) is taken as a continuation when on.
+    pub continuation_friendly: bool,
+}
 
 /// An iterator of lexemes from an [`Input`](crate::input::Input) stream.
 pub struct RawLexemeIterator<I: std::io::Read> {
     scanner: Scanner<I>,
     error: Option<LexError>,
+    config: LayoutConfig,
+    // the line where the last lexeme ends.
+    last_line: usize,
+    // blank lines before the last lexeme.
+    blank_lines: u32,
+    // lines, and those beginning with a bird track, among the first ones with lexemes.
+    bird_tracks: Option<(usize, usize, Location)>,
+}
+
+// the number of lines to look at for bird tracks, see `check_bird_tracks`.
+const BIRD_TRACK_LINES: usize = 20;
+
+impl<I: std::io::Read> Clone for RawLexemeIterator<I> {
+    fn clone(&self) -> Self {
+        RawLexemeIterator {
+            scanner: self.scanner.clone(),
+            error: self.error.clone(),
+            config: self.config,
+            last_line: self.last_line,
+            blank_lines: self.blank_lines,
+            bird_tracks: self.bird_tracks,
+        }
+    }
 }
 
 impl<I: std::io::Read> Iterator for RawLexemeIterator<I> {
     type Item = Lexeme;
+    #[inline]
     fn next(&mut self) -> Option<Lexeme> {
         self.enriched_next(|_| ()).map(|t| t.0)
     }
@@ -43,6 +107,10 @@ impl<I: std::io::Read> From<Scanner<I>> for RawLexemeIterator<I> {
     fn from(scanner: Scanner<I>) -> Self {
         Self {
             error: None,
+            config: LayoutConfig::default(),
+            last_line: 0,
+            blank_lines: 0,
+            bird_tracks: Some((0, 0, Location::default())),
             scanner,
         }
     }
@@ -53,39 +121,223 @@ impl<I: std::io::Read> RawLexemeIterator<I> {
     pub fn new(input: I) -> Self { Self::from(Scanner::new(input)) }
     /// Get back the internal scanner of this iterator.
     pub fn into_scanner(self) -> (Option<LexError>, Scanner<I>) { (self.error, self.scanner) }
+    /// The file scanned, see [`Scanner::with_file`].
+    pub fn file(&self) -> FileId { self.scanner.file() }
+    /// Use the layout configuration for checking the indentation.
+    pub fn with_config(self, config: LayoutConfig) -> Self { Self { config, ..self } }
+    /// The number of blank lines right before the last lexeme, see [`Scanner::blank_lines`].
+    pub fn blank_lines_before(&self) -> u32 { self.blank_lines }
+    /// Report the progress of the scanner, see [`Scanner::on_progress`].
+    pub fn on_progress(mut self, every_n_chars: usize, callback: impl FnMut(Progress) + 'static) -> Self {
+        self.scanner.set_progress_callback(every_n_chars, Box::new(callback));
+        self
+    }
     fn enriched_next<T>(&mut self, proc: impl FnOnce(&Scanner<I>) -> T) -> Option<(Lexeme, T)> {
+        self.scan_next(proc, Scanner::next_lexeme, Lexeme::get_type)
+    }
+    // with `next` for the lexeme, full or only its type.
+    fn scan_next<L, T>(&mut self, proc: impl FnOnce(&Scanner<I>) -> T,
+                       next: impl FnOnce(&mut Scanner<I>) -> super::Result<L>,
+                       get_type: impl FnOnce(&L) -> LexemeType) -> Option<(L, T)> {
+        use crate::utils::char::Stream;
         if self.error.is_some() { return None; }
-        // possibly consume whitespaces and ignore errors.
-        let _ = self.scanner.whitespace();
+        let reported = self.scanner.diagnostics.checkpoint();
+        // possibly consume whitespaces, problems in them are reported as diagnostics.
+        let trivia = self.scanner.skip_trivia();
+        if let (FailFast(err), None) = (trivia, self.scanner.utf8_error()) {
+            self.error = Some(err);
+            return None;
+        }
+        self.blank_lines = self.scanner.blank_lines();
         // for the fat iterator to insert a statement to get the location.
         let val = proc(&mut self.scanner);
         // produce a lexeme.
-        match self.scanner.next_lexeme() {
-            Success(x) => Some((x, val)),
-            RetryLater(_) => None,
+        let begin = self.scanner.location;
+        let head = self.scanner.peek();
+        let res = next(&mut self.scanner);
+        if let Some(err) = self.scanner.utf8_error() {
+            // stopped at an invalid UTF-8 sequence, not at the end of input: the whitespaces
+            // and the lexeme cut there are dropped, and so is what they reported, e.g. an
+            // unterminated string literal.
+            let err = err.clone();
+            self.scanner.diagnostics.revert(reported);
+            self.check_confusables(false, begin);
+            self.check_bird_tracks(None);
+            self.error = Some(LexError {
+                expected: Vec::new(),
+                unexpected: None,
+                location: self.scanner.location,
+                invalid_utf8: Some(err),
+            });
+            return None;
+        }
+        match res {
+            Success(x) => {
+                self.check_confusables(begin.line > self.last_line, begin);
+                if begin.line > self.last_line {
+                    self.check_indentation(begin);
+                    // the operator `>`, for the bird tracks.
+                    let lone = get_type(&x) == LexemeType::Operator && head == Some('>')
+                        && self.scanner.location.offset == begin.offset + 1;
+                    self.check_bird_tracks(Some((lone, begin)));
+                }
+                self.last_line = self.scanner.location.line;
+                Some((x, val))
+            }
+            RetryLater(_) => {
+                self.check_confusables(false, begin);
+                self.check_bird_tracks(None);
+                None
+            }
             FailFast(err) => {
                 self.error = Some(err);
                 None
             }
         }
     }
+    // the confusables right before a lexeme at `begin`, which may be the first on its line.
+    fn check_confusables(&mut self, first: bool, begin: Location) {
+        use crate::error::{DiagnosticMessage, Error};
+        let anywhere = self.config.confusable_whitespace == ConfusablePolicy::Anywhere;
+        // those after `begin` are in a string gap of the lexeme.
+        for &(location, c) in self.scanner.confusables.iter() {
+            if location.offset >= begin.offset { break; }
+            if anywhere || first && location.line == begin.line {
+                let mut end = location;
+                end.step();
+                Diagnostic::new(Range { begin: location, end },
+                                DiagnosticMessage::Error(Error::ConfusableWhitespace(c)))
+                    .report(&mut self.scanner.diagnostics)
+            }
+        }
+    }
+    // the first lexeme on a line, or `None` at the end of input: decide after enough lines.
+    fn check_bird_tracks(&mut self, first: Option<(bool, Location)>) {
+        use crate::error::{DiagnosticMessage, Note};
+        use crate::utils::char::Stream;
+        let (lines, tracks, track) = match &mut self.bird_tracks {
+            Some(state) => state,
+            None => return,
+        };
+        if let Some((lone, begin)) = first.filter(|(_, begin)| begin.line <= BIRD_TRACK_LINES) {
+            *lines += 1;
+            // `> ` at column 1, or a `>` alone.
+            if begin.column == 1 && lone && self.scanner.peek().is_none_or(char::is_whitespace) {
+                if *tracks == 0 { *track = begin }
+                *tracks += 1;
+            }
+            return;
+        }
+        if *tracks * 2 > *lines {
+            let mut end = *track;
+            end.step();
+            Diagnostic::new(Range { begin: *track, end }, DiagnosticMessage::Note(Note::BirdTracks))
+                .report(&mut self.scanner.diagnostics)
+        }
+        self.bird_tracks = None;
+    }
+    fn config_mut(&mut self) -> &mut LayoutConfig { &mut self.config }
+    fn check_indentation(&mut self, begin: Location) {
+        use crate::error::{DiagnosticMessage, Error, Warning, TabInIndentation};
+        let location = match self.scanner.line_tab {
+            Some(tab) if tab.line == begin.line => tab,
+            _ => return,
+        };
+        // no tabs before the first tab on this line, assume one column per character.
+        let tab = TabInIndentation {
+            column: begin.char_column,
+            naive_column: location.char_column + (begin.offset - location.offset),
+        };
+        let message = match self.config.tabs_in_indentation {
+            TabPolicy::Allow => return,
+            TabPolicy::Warn => DiagnosticMessage::Warning(Warning::TabInIndentation(tab)),
+            TabPolicy::Error => DiagnosticMessage::Error(Error::TabInIndentation(tab)),
+        };
+        Diagnostic::new(Range { begin: location, end: begin }, message)
+            .report(&mut self.scanner.diagnostics)
+    }
+}
+
+/// An iterator of the types of the lexemes only: as a [`RawLexemeIterator`], with the same
+/// diagnostics, but without the payloads, see [`Scanner::next_lexeme_type`].
+pub struct RawLexemeTypeIterator<I: std::io::Read>(RawLexemeIterator<I>);
+
+impl<I: std::io::Read> Clone for RawLexemeTypeIterator<I> {
+    fn clone(&self) -> Self { RawLexemeTypeIterator(self.0.clone()) }
+}
+
+impl<I: std::io::Read> Iterator for RawLexemeTypeIterator<I> {
+    type Item = LexemeType;
+    #[inline]
+    fn next(&mut self) -> Option<LexemeType> {
+        self.0.scan_next(|_| (), Scanner::next_lexeme_type, |&t| t).map(|t| t.0)
+    }
+}
+
+impl<I: std::io::Read> From<Scanner<I>> for RawLexemeTypeIterator<I> {
+    fn from(scanner: Scanner<I>) -> Self { RawLexemeTypeIterator(RawLexemeIterator::from(scanner)) }
+}
+
+impl<I: std::io::Read> RawLexemeTypeIterator<I> {
+    /// Create a new lexeme type iterator from raw input.
+    pub fn new(input: I) -> Self { Self::from(Scanner::new(input)) }
+    /// Get back the internal scanner of this iterator.
+    pub fn into_scanner(self) -> (Option<LexError>, Scanner<I>) { self.0.into_scanner() }
+    /// The file scanned, see [`Scanner::with_file`].
+    pub fn file(&self) -> FileId { self.0.file() }
+    /// Use the layout configuration for checking the indentation.
+    pub fn with_config(self, config: LayoutConfig) -> Self {
+        RawLexemeTypeIterator(self.0.with_config(config))
+    }
 }
 
 /// A "fat" lexeme iterator, i.e. iterator for lexemes with their location ranges.
 pub struct FatLexemeIterator<I: std::io::Read> {
     iterator: RawLexemeIterator<I>,
     location: Location,
+    // the number of lexemes so far.
+    count: usize,
+    // the source text of the literals by their indices, if kept at all.
+    spellings: Option<Vec<(usize, String)>>,
+}
+
+impl<I: std::io::Read> Clone for FatLexemeIterator<I> {
+    fn clone(&self) -> Self {
+        FatLexemeIterator {
+            iterator: self.iterator.clone(),
+            location: self.location,
+            count: self.count,
+            spellings: self.spellings.clone(),
+        }
+    }
 }
 
 impl<I: std::io::Read> Iterator for FatLexemeIterator<I> {
     type Item = (Lexeme, Range);
+    #[inline]
     fn next(&mut self) -> Option<(Lexeme, Range)> {
-        let (x, location) = self.iterator.enriched_next(|s| s.location)?;
+        let keep = self.spellings.is_some();
+        let (x, (location, input)) = self.iterator.enriched_next(
+            |s| (s.location, if keep { Some(s.input.clone()) } else { None }))?;
         self.location = location;
-        Some((x, Range {
-            begin: location,
-            end: self.iterator.scanner.location,
-        }))
+        let range = Range { begin: location, end: self.iterator.scanner.location };
+        if let (Some(spellings), Some(mut input), CharLiteral(_) | StringLiteral(_)) =
+            (&mut self.spellings, input, &x) {
+            // read the literal again, from a copy of the input before it.
+            let mut text = String::new();
+            for _ in location.offset..range.end.offset {
+                match input.next(|_| ()) {
+                    Ok((c, rest)) => {
+                        text.push(c);
+                        input = rest;
+                    }
+                    Err(_) => break,
+                }
+            }
+            spellings.push((self.count, text));
+        }
+        self.count += 1;
+        Some((x, range))
     }
 }
 
@@ -93,6 +345,8 @@ impl<I: std::io::Read> From<RawLexemeIterator<I>> for FatLexemeIterator<I> {
     fn from(iterator: RawLexemeIterator<I>) -> Self {
         Self {
             location: iterator.scanner.location,
+            count: 0,
+            spellings: None,
             iterator,
         }
     }
@@ -103,18 +357,109 @@ impl<I: std::io::Read> FatLexemeIterator<I> {
     pub fn new(input: I) -> Self { Self::from(RawLexemeIterator::<I>::new(input)) }
     /// Get back the internal scanner of this iterator.
     pub fn into_scanner(self) -> (Option<LexError>, Scanner<I>) { self.iterator.into_scanner() }
+    /// The file scanned, see [`Scanner::with_file`].
+    pub fn file(&self) -> FileId { self.iterator.file() }
+    /// Use the layout configuration for checking the indentation.
+    pub fn with_config(mut self, config: LayoutConfig) -> Self {
+        *self.config_mut() = config;
+        self
+    }
+    /// Keep the source text of the character and string literals, see
+    /// [`FatLexemeIterator::literal_spelling`].
+    pub fn with_literal_spellings(self) -> Self { Self { spellings: Some(Vec::new()), ..self } }
+    /// The source text of the `n`-th lexeme (from 0), if it is a character or string literal
+    /// and [`FatLexemeIterator::with_literal_spellings`] is used: escapes and gaps as written.
+    pub fn literal_spelling(&self, n: usize) -> Option<&str> {
+        let spellings = self.spellings.as_ref()?;
+        let k = spellings.binary_search_by_key(&n, |(k, _)| *k).ok()?;
+        Some(&spellings[k].1)
+    }
+    /// The number of blank lines right before the last lexeme, see [`Scanner::blank_lines`].
+    ///
+    /// Not available from the enriched iterators: they read ahead for the layout algorithm.
+    pub fn blank_lines_before(&self) -> u32 { self.iterator.blank_lines_before() }
+    /// Report the progress of the scanner, see [`Scanner::on_progress`].
+    pub fn on_progress(mut self, every_n_chars: usize, callback: impl FnMut(Progress) + 'static) -> Self {
+        self.scanner_mut().set_progress_callback(every_n_chars, Box::new(callback));
+        self
+    }
+    fn config_mut(&mut self) -> &mut LayoutConfig { self.iterator.config_mut() }
+    fn scanner_mut(&mut self) -> &mut Scanner<I> { &mut self.iterator.scanner }
+}
+
+/// Lexemes annotated with diagnostics: either a lexeme, or a diagnostic.
+#[derive(Debug)]
+pub enum Annotated {
+    /// a normal lexeme with a source range.
+    Token(Lexeme, Range),
+    /// a diagnostic reported by the scanner.
+    Diag(Diagnostic),
+}
+
+impl Display for Annotated {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Annotated::Token(lexeme, range) => write!(f, "{}: {}", range, lexeme),
+            Annotated::Diag(diagnostic) => write!(f, "{}", diagnostic),
+        }
+    }
+}
+
+/// Lexemes interleaved with diagnostics in source order.
+///
+/// Every diagnostic appears after the last token that ends at or before the diagnostic's
+/// location, and before the next token.
+pub struct AnnotatedLexemeIterator<I: std::io::Read> {
+    iterator: FatLexemeIterator<I>,
+    pending: Option<(Lexeme, Range)>,
+}
+
+impl<I: std::io::Read> AnnotatedLexemeIterator<I> {
+    /// Create a new annotated lexeme iterator from raw input.
+    pub fn new(input: I) -> Self { Self::from(FatLexemeIterator::<I>::new(input)) }
+    /// Get back the internal scanner of this iterator.
+    pub fn into_scanner(self) -> (Option<LexError>, Scanner<I>) { self.iterator.into_scanner() }
+}
+
+impl<I: std::io::Read> From<FatLexemeIterator<I>> for AnnotatedLexemeIterator<I> {
+    fn from(iterator: FatLexemeIterator<I>) -> Self {
+        Self { iterator, pending: None }
+    }
+}
+
+impl<I: std::io::Read> Iterator for AnnotatedLexemeIterator<I> {
+    type Item = Annotated;
+    fn next(&mut self) -> Option<Annotated> {
+        if self.pending.is_none() {
+            self.pending = self.iterator.next();
+        }
+        let diagnostics = &mut self.iterator.iterator.scanner.diagnostics;
+        match &self.pending {
+            // diagnostics before the end of the pending token go first
+            Some((_, range)) => match diagnostics.pop_before(range.end) {
+                Some(d) => Some(Annotated::Diag(d)),
+                None => self.pending.take().map(|(t, r)| Annotated::Token(t, r)),
+            },
+            // the remaining diagnostics after all tokens
+            None => diagnostics.pop().map(Annotated::Diag),
+        }
+    }
 }
 
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 enum LastLexeme {
     LetWhereDoOf,
     StartOfFile,
     // this means we have already handled the following lexeme.
     PassThrough,
     Other,
+    // the end of file is already emitted.
+    EndOfFile,
 }
 
-/// Enriched lexemes: a normal lexeme, a `{n}`, or an `<n>`.
-#[derive(Debug, Eq, PartialEq)]
+/// Enriched lexemes: a normal lexeme, a `{n}`, an `<n>`, or the end of file.
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum EnrichedLexeme {
     /// a `{n}`.
     CurlyN(usize),
@@ -122,6 +467,8 @@ pub enum EnrichedLexeme {
     AngleN(usize),
     /// a normal lexeme with a source range.
     Normal(Lexeme, Range),
+    /// the end of file, exactly once after all the other lexemes.
+    Eof(Location),
 }
 
 impl Display for EnrichedLexeme {
@@ -130,7 +477,26 @@ impl Display for EnrichedLexeme {
         match self {
             CurlyN(n) => write!(f, "{{{}}}", n),
             AngleN(n) => write!(f, "<{}>", n),
-            Normal(lexeme, range) => write!(f, "{}: {}", range, lexeme)
+            Normal(lexeme, range) => write!(f, "{}: {}", range, lexeme),
+            Eof(location) => write!(f, "{}: <eof>", location),
+        }
+    }
+}
+
+impl EnrichedLexeme {
+    /// The normal lexeme, if it is one.
+    pub fn as_lexeme(&self) -> Option<&Lexeme> {
+        match self {
+            EnrichedLexeme::Normal(lexeme, _) => Some(lexeme),
+            _ => None,
+        }
+    }
+
+    /// The source range of the normal lexeme, if it is one.
+    pub fn range(&self) -> Option<Range> {
+        match self {
+            EnrichedLexeme::Normal(_, range) => Some(*range),
+            _ => None,
         }
     }
 }
@@ -141,76 +507,108 @@ impl From<(Lexeme, Range)> for EnrichedLexeme {
     }
 }
 
-/// Lexeme stream enriched with `{n}` and `<n>`.
-/// See "Haskell 2010 Report, 10.3 Layout".
-pub struct EnrichedLexemeIterator<I: std::io::Read> {
-    iterator: IterStream<FatLexemeIterator<I>>,
+/// Render enriched lexemes one per line, the format used by the CLI and the conformance tests.
+pub fn render_enriched(it: impl IntoIterator<Item=EnrichedLexeme>) -> String {
+    it.into_iter().map(|x| format!("{}\n", x)).collect()
+}
+
+/// The `{n}` and `<n>` insertion of "Haskell 2010 Report, 10.3 Layout", on any stream of
+/// lexemes with their ranges: see [`enrich`].
+pub struct Enrich<J: Iterator<Item=(Lexeme, Range)>> {
+    iterator: IterStream<J>,
     last_lexeme: LastLexeme,
     last_line: usize,
+    // the underlying iterator has ended: never ask it again, in case it is not fused.
+    exhausted: bool,
+    // where the last lexeme ends, for the end of file.
+    end: Location,
+    tracer: Option<LayoutTracer>,
 }
 
-impl<I: std::io::Read> EnrichedLexemeIterator<I> {
-    /// Create a new enriched lexeme iterator from raw input.
-    pub fn new(input: I) -> Self { Self::from(FatLexemeIterator::<I>::new(input)) }
-    /// Get back the internal scanner of this iterator.
-    pub fn into_scanner(self) -> (Option<LexError>, Scanner<I>) { self.iterator.unwrap().into_scanner() }
+/// Enrich a lexeme stream with `{n}` and `<n>`, without any IO.
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub fn enrich<J: IntoIterator<Item=(Lexeme, Range)>>(tokens: J) -> Enrich<J::IntoIter> {
+    Enrich {
+        iterator: IterStream::from(tokens.into_iter()),
+        last_lexeme: LastLexeme::StartOfFile,
+        last_line: 0,
+        exhausted: false,
+        end: Location::new(),
+        tracer: None,
+    }
 }
 
-impl<I: std::io::Read> From<FatLexemeIterator<I>> for EnrichedLexemeIterator<I> {
-    fn from(iterator: FatLexemeIterator<I>) -> Self {
-        Self {
-            iterator: IterStream::from(iterator),
-            last_lexeme: LastLexeme::StartOfFile,
-            last_line: 0,
+impl<J: Iterator<Item=(Lexeme, Range)>> Enrich<J> {
+    /// Record every `{n}` and `<n>` decision to the tracer.
+    pub fn with_trace(self, tracer: impl FnMut(LayoutEvent) + 'static) -> Self {
+        Self { tracer: Some(Box::new(tracer)), ..self }
+    }
+
+    fn trace(&mut self, rule: LayoutRule, range: Option<Range>) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer(LayoutEvent { rule, range, before: Vec::new(), after: Vec::new() })
         }
     }
 }
 
-impl<I: std::io::Read> Iterator for EnrichedLexemeIterator<I> {
+impl<J: Iterator<Item=(Lexeme, Range)>> Iterator for Enrich<J> {
     type Item = EnrichedLexeme;
     fn next(&mut self) -> Option<Self::Item> {
         use LastLexeme::*;
         use EnrichedLexeme::*;
-        let next = self.iterator.peek(0);
-        match self.last_lexeme {
+        let next = if self.exhausted { None } else { self.iterator.peek(0) };
+        self.exhausted = next.is_none();
+        match (self.last_lexeme, next) {
             // If a `let`, `where`, `do`, or `of` keyword is not followed by the lexeme `{`
-            LetWhereDoOf if next.is_none() || next.unwrap().0 != OpenCurlyBracket => {
+            (LetWhereDoOf, next) if !matches!(next, Some((OpenCurlyBracket, _))) => {
                 self.last_lexeme = PassThrough;
                 // where n is the indentation of the next lexeme if there is one
                 // or 0 if the end of file has been reached
-                let n = next.map_or(0, |t| t.1.begin.column);
+                let range = next.map(|&(_, range)| range);
+                let n = range.map_or(0, |r| r.begin.char_column);
+                self.trace(LayoutRule::RuleLetWhereDoOf, range);
                 // the token `{n}` is inserted after the keyword
                 Some(CurlyN(n))
             }
+            (EndOfFile, None) => None,
+            (_, None) => {
+                self.last_lexeme = EndOfFile;
+                Some(Eof(self.end))
+            }
             // If the first lexeme of a module is not `{` or `module`
-            StartOfFile if next.is_some()
-                && ![OpenCurlyBracket, ReservedId(Module)]
-                .contains(&next.unwrap().0) => {
+            (StartOfFile, Some(&(ref lexeme, range)))
+                if ![OpenCurlyBracket, ReservedId(Module)].contains(lexeme) => {
                 self.last_lexeme = PassThrough;
                 // where n is the indentation of the lexeme
-                let n = next.unwrap().1.begin.column;
+                let n = range.begin.char_column;
+                self.trace(LayoutRule::RuleFirstLexeme, Some(range));
                 // then it is preceded by `{n}`
                 Some(CurlyN(n))
             }
             // Where the start of a lexeme is preceded only by white space on the same line
             // provided that it is not, as a consequence of the first two rules, preceded by `{n}`
-            Other if next.is_some() && next.unwrap().1.begin.line > self.last_line => {
-                self.last_line = next.unwrap().1.begin.line;
+            (Other, Some(&(_, range))) if range.begin.line > self.last_line => {
+                self.last_line = range.begin.line;
                 // where n is the indentation of the lexeme
-                let n = next.unwrap().1.begin.column;
+                let n = range.begin.char_column;
+                self.trace(LayoutRule::RuleNewLine, Some(range));
                 // this lexeme is preceded by `<n>`
                 Some(AngleN(n))
             }
-            // otherwise we just return the normal lexeme
+            // otherwise we just return the normal lexeme, already peeked above
             _ => {
-                let (lexeme, range) = self.iterator.next()?;
-                // update last line for "preceded only by white space on the same line" test
-                self.last_line = range.end.line;
-                // update last lexeme for "4 keywords not followed by {" test
+                let (lexeme, range) = self.iterator.next_peeked()?;
+                // update last line for "preceded only by white space on the same line" test,
+                // with the end of the lexeme: a string gap may span lines, but the whitespace
+                // (and comments) after it should not count, for they are white space.
+                self.last_line = self.last_line.max(range.end.line);
+                self.end = range.end;
+                // update last lexeme for "4 keywords not followed by {" test, and `\case`
+                // and `\cases` of `LambdaCase` (only lexed under the extension)
                 use crate::lexeme::Lexeme::ReservedId as R;
                 use crate::lexeme::RId::*;
                 self.last_lexeme = match lexeme {
-                    R(Let) | R(Where) | R(Do) | R(Of) => LetWhereDoOf,
+                    R(Let) | R(Where) | R(Do) | R(Of) | R(LambdaCase) | R(LambdaCases) => LetWhereDoOf,
                     _ => Other,
                 };
                 // return as a normal lexeme
@@ -220,78 +618,389 @@ impl<I: std::io::Read> Iterator for EnrichedLexemeIterator<I> {
     }
 }
 
-/// Augmented lexemes: normal lexemes or phantom `{`s, `;`s, and `}`s.
+// `None` once the underlying iterator is exhausted, even if it is not fused itself.
+impl<J: Iterator<Item=(Lexeme, Range)>> std::iter::FusedIterator for Enrich<J> {}
+
+/// Lexeme stream enriched with `{n}` and `<n>`.
+/// See "Haskell 2010 Report, 10.3 Layout".
+pub struct EnrichedLexemeIterator<I: std::io::Read> {
+    inner: Enrich<FatLexemeIterator<I>>,
+}
+
+impl<I: std::io::Read> EnrichedLexemeIterator<I> {
+    /// Create a new enriched lexeme iterator from raw input.
+    pub fn new(input: I) -> Self { Self::from(FatLexemeIterator::<I>::new(input)) }
+    /// Get back the internal scanner of this iterator.
+    ///
+    /// Lexemes already peeked for the layout algorithm but not yielded yet are discarded.
+    pub fn into_scanner(self) -> (Option<LexError>, Scanner<I>) { self.inner.iterator.unwrap_full().0.into_scanner() }
+    /// The file scanned, see [`Scanner::with_file`].
+    pub fn file(&self) -> FileId { self.inner.iterator.get().file() }
+    /// Use the layout configuration, before consuming any lexemes.
+    pub fn with_config(mut self, config: LayoutConfig) -> Self {
+        *self.config_mut() = config;
+        self
+    }
+    /// Report the progress of the scanner, see [`Scanner::on_progress`].
+    pub fn on_progress(mut self, every_n_chars: usize, callback: impl FnMut(Progress) + 'static) -> Self {
+        self.scanner_mut().set_progress_callback(every_n_chars, Box::new(callback));
+        self
+    }
+    fn config_mut(&mut self) -> &mut LayoutConfig { self.inner.iterator.get_mut().config_mut() }
+    fn scanner_mut(&mut self) -> &mut Scanner<I> { self.inner.iterator.get_mut().scanner_mut() }
+    /// Record every `{n}` and `<n>` decision to the tracer.
+    pub fn with_trace(self, tracer: impl FnMut(LayoutEvent) + 'static) -> Self {
+        Self { inner: self.inner.with_trace(tracer) }
+    }
+    /// Resume from a snapshot, on a scanner resumed at [`LayoutState::location`]: see
+    /// [`AugmentedLexemeIterator::state`].
+    pub fn resume(mut iterator: FatLexemeIterator<I>, state: &LayoutState) -> Self {
+        iterator.iterator.last_line = state.last_line;
+        let mut inner = enrich(iterator);
+        inner.last_lexeme = state.last_lexeme;
+        inner.last_line = state.last_line;
+        inner.end = state.location;
+        Self { inner }
+    }
+}
+
+impl<I: std::io::Read> From<FatLexemeIterator<I>> for EnrichedLexemeIterator<I> {
+    fn from(iterator: FatLexemeIterator<I>) -> Self {
+        Self { inner: enrich(iterator) }
+    }
+}
+
+impl<I: std::io::Read> Iterator for EnrichedLexemeIterator<I> {
+    type Item = EnrichedLexeme;
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.inner.next()? {
+            // the scanner knows better: there may be whitespaces after the last lexeme.
+            EnrichedLexeme::Eof(_) => Some(EnrichedLexeme::Eof(self.scanner_mut().location())),
+            t => Some(t),
+        }
+    }
+}
+
+impl<I: std::io::Read> std::iter::FusedIterator for EnrichedLexemeIterator<I> {}
+
+/// Rules in "Haskell 2010 Report, 10.3 Layout", for tracing the layout algorithm.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum LayoutRule {
+    /// `{n}` after a `let`, `where`, `do`, or `of` not followed by `{`.
+    RuleLetWhereDoOf,
+    /// `{n}` before the first lexeme of a module, if it is not `{` or `module`.
+    RuleFirstLexeme,
+    /// `<n>` before the first lexeme on a new line.
+    RuleNewLine,
+    /// `L (<n>: ts) (m : ms) = ; : (L ts (m : ms)) if m = n`
+    LRuleSameIndent,
+    /// `L (<n>: ts) (m : ms) = } : (L (<n>: ts) ms) if n < m`
+    LRuleDedent,
+    /// `L (<n>: ts) ms = L ts ms`
+    LRuleIndent,
+    /// `L ({n} : ts) (m : ms) = { : (L ts (n : m : ms)) if n > m`, and `L ({n} : ts) [] = ...`
+    LRuleNewContext,
+    /// `L ({n} : ts) ms = { : } : (L (<n>: ts) ms)`
+    LRuleEmptyBlock,
+    /// `L ({ : ts) ms = { : (L ts (0 : ms))`
+    LRuleExplicitOpen,
+    /// `L (} : ts) (0 : ms) = } : (L ts ms)`
+    LRuleExplicitClose,
+    /// `L [] (m : ms) = } : L [] ms if m /= 0`
+    LRuleEof,
+    /// `L (t : ts) (m : ms) = } : (L (t : ts) ms) if m /= 0 and parse-error(t)`, for `t = }`
+    LRuleParseError,
+    /// `L (<n>: t : ts) (m : ms) = L (t : ts) (m : ms) if m = n`, for `t` continuing the
+    /// previous line: not in the Report, see [`LayoutConfig::continuation_friendly`].
+    LRuleContinuation,
+}
+
+/// A decision made by the layout algorithm.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct LayoutEvent {
+    /// The rule fired.
+    pub rule: LayoutRule,
+    /// Range of the lexeme triggering this rule, if any.
+    pub range: Option<Range>,
+    /// The layout context stack before this rule, always empty for `{n}` and `<n>` decisions.
+    pub before: Vec<usize>,
+    /// The layout context stack after this rule, always empty for `{n}` and `<n>` decisions.
+    pub after: Vec<usize>,
+}
+
+impl Display for LayoutEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.rule)?;
+        if let Some(range) = self.range { write!(f, " at {}", range)?; }
+        if self.before != self.after { write!(f, ": {:?} -> {:?}", self.before, self.after)?; }
+        Ok(())
+    }
+}
+
+/// A callback receiving [`LayoutEvent`]s.
+pub type LayoutTracer = Box<dyn FnMut(LayoutEvent)>;
+
+/// Identifier for an implicit layout context, unique within a lexeme stream.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockId(pub u32);
+
+impl Display for BlockId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Augmented lexemes: normal lexemes, phantom `{`s, `;`s, and `}`s, or the end of file.
+///
+/// Phantom lexemes carry the [`BlockId`] of the implicit layout context they open, separate,
+/// or close, so that a parser can pair them up.
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum AugmentedLexeme {
     /// Real lexemes.
     Real(Lexeme, Range),
     /// Phantom `{`.
-    PhantomOpenCurlyBracket,
+    PhantomOpenCurlyBracket(BlockId),
     /// Phantom `}`.
-    PhantomCloseCurlyBracket,
+    PhantomCloseCurlyBracket(BlockId),
     /// Phantom `;`.
-    PhantomSemicolon,
+    PhantomSemicolon(BlockId),
+    /// The end of file, exactly once after all the other lexemes, including the phantom `}`s
+    /// closing the layout contexts still open.
+    Eof(Location),
+}
+
+impl AugmentedLexeme {
+    /// The real lexeme, if it is one.
+    pub fn as_lexeme(&self) -> Option<&Lexeme> {
+        match self {
+            Real(lexeme, _) => Some(lexeme),
+            _ => None,
+        }
+    }
+
+    /// The source range of the real lexeme, if it is one.
+    pub fn range(&self) -> Option<Range> {
+        match self {
+            Real(_, range) => Some(*range),
+            _ => None,
+        }
+    }
 }
 
 impl Display for AugmentedLexeme {
+    /// With the alternate flag (`{:#}`), block ids are appended to phantom lexemes.
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (c, id) = match self {
+            Real(t, range) => return write!(f, "{}: {}", range, t),
+            AugmentedLexeme::Eof(location) => return write!(f, "{}: <eof>", location),
+            PhantomOpenCurlyBracket(id) => ('{', id),
+            PhantomCloseCurlyBracket(id) => ('}', id),
+            PhantomSemicolon(id) => (';', id),
+        };
+        write!(f, "<phantom>: {}", c)?;
+        if f.alternate() { write!(f, " {}", id)?; }
+        Ok(())
+    }
+}
+
+/// Errors in the layout algorithm, about explicit curly brackets.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum LayoutError {
+    /// An explicit `}` outside any layout context.
+    UnmatchedCloseBracket(Range),
+    /// An explicit `{` still open at the end of input.
+    UnclosedOpenBracket(Range),
+    /// A lexeme of a [`FragmentKind::ModuleBody`] fragment closing its enclosing layout
+    /// context, e.g. on a line indented less than it: see [`lex_fragment`].
+    LeavesEnclosingContext(Range),
+}
+
+impl LayoutError {
+    /// Range of the curly bracket at fault.
+    pub fn range(&self) -> Range {
         match self {
-            Real(t, range) => write!(f, "{}: {}", range, t),
-            PhantomOpenCurlyBracket => write!(f, "<phantom>: {{"),
-            PhantomCloseCurlyBracket => write!(f, "<phantom>: }}"),
-            PhantomSemicolon => write!(f, "<phantom>: ;"),
+            LayoutError::UnmatchedCloseBracket(range) => *range,
+            LayoutError::UnclosedOpenBracket(range) => *range,
+            LayoutError::LeavesEnclosingContext(range) => *range,
         }
     }
 }
 
-/// Lexeme streams augmented with phantom `{`, `;`, and `}`.
-pub struct AugmentedLexemeIterator<I: std::io::Read> {
-    iterator: IterStream<EnrichedLexemeIterator<I>>,
-    indents: Vec<usize>,
-    buffer: VecDeque<AugmentedLexeme>,
+impl Display for LayoutError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayoutError::UnmatchedCloseBracket(_) => write!(f, "unmatched `}}`"),
+            LayoutError::UnclosedOpenBracket(_) => write!(f, "`{{` not closed at the end of input"),
+            LayoutError::LeavesEnclosingContext(_) =>
+                write!(f, "indented less than the enclosing layout context of the fragment"),
+        }
+    }
 }
 
-impl<'a, I: std::io::Read> AugmentedLexemeIterator<I> {
-    /// Create a new enriched lexeme iterator from raw input.
-    pub fn new(input: I) -> Self { Self::from(EnrichedLexemeIterator::new(input)) }
-    /// Get back the internal scanner of this iterator.
-    pub fn into_scanner(self) -> (Option<LexError>, Scanner<I>) { self.iterator.unwrap().into_scanner() }
-
-    fn prepare_next(&mut self) {
-        let t = self.iterator.next();
-        // L [] []                = []
-        // L [] (m : ms)          = } : L [] ms if m /= 0 (Note 6)
-        // Note 6. At the end of the input, any pending close-braces are inserted.
-        // It is an error at this point to be within a non-layout context (i.e. m = 0).
-        if t.is_none() {
-            if let Some(k) = self.indents.pop() {
-                if k == 0 { panic!("mismatched curly brackets.") }
-                self.buffer.push_back(PhantomCloseCurlyBracket)
-            }
-            return;
+// a layout context: implicit ones have an indentation, explicit ones are at indentation 0.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum Context {
+    Implicit(usize, BlockId),
+    Explicit(Range),
+}
+
+impl Context {
+    fn indent(self) -> usize {
+        match self {
+            Context::Implicit(n, _) => n,
+            Context::Explicit(_) => 0,
+        }
+    }
+}
+
+/// The layout algorithm `L` of "Haskell 2010 Report, 10.3 Layout", on any stream of enriched
+/// lexemes: see [`apply_layout`].
+pub struct Layout<J: Iterator<Item=EnrichedLexeme>> {
+    iterator: IterStream<J>,
+    indents: Vec<Context>,
+    next_block: u32,
+    buffer: VecDeque<Result<AugmentedLexeme, LayoutError>>,
+    // where the last lexeme ends, for streams without an end of file.
+    end: Location,
+    // the end of file is already emitted.
+    finished: bool,
+    continuation_friendly: bool,
+    tracer: Option<LayoutTracer>,
+}
+
+/// Apply the layout algorithm `L` to an enriched lexeme stream, without any IO.
+///
+/// Errors are yielded in place, and the algorithm goes on as if they were not there.
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub fn apply_layout<J: IntoIterator<Item=EnrichedLexeme>>(tokens: J) -> Layout<J::IntoIter> {
+    Layout {
+        iterator: IterStream::from(tokens.into_iter()),
+        indents: Vec::new(),
+        next_block: 0,
+        buffer: VecDeque::new(),
+        end: Location::new(),
+        finished: false,
+        continuation_friendly: false,
+        tracer: None,
+    }
+}
+
+impl<J: Iterator<Item=EnrichedLexeme>> Layout<J> {
+    /// Insert no phantom `;` before lines continuing the previous one, see
+    /// [`LayoutConfig::continuation_friendly`].
+    pub fn with_continuation_friendly(self, enabled: bool) -> Self {
+        Self { continuation_friendly: enabled, ..self }
+    }
+
+    /// Record every decision of the layout algorithm `L` to the tracer.
+    pub fn with_trace(self, tracer: impl FnMut(LayoutEvent) + 'static) -> Self {
+        Self { tracer: Some(Box::new(tracer)), ..self }
+    }
+
+    fn trace(&mut self, rule: LayoutRule, range: Option<Range>, before: &[usize]) {
+        if let Some(tracer) = &mut self.tracer {
+            let after = self.indents.iter().map(|c| c.indent()).collect();
+            tracer(LayoutEvent { rule, range, before: before.to_vec(), after })
+        }
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.next_block += 1;
+        BlockId(self.next_block - 1)
+    }
+
+    fn next_range(&mut self) -> Option<Range> {
+        match self.iterator.peek(0) {
+            Some(EnrichedLexeme::Normal(_, range)) => Some(*range),
+            _ => None,
         }
+    }
+
+    fn emit(&mut self, t: AugmentedLexeme) { self.buffer.push_back(Ok(t)) }
+
+    // whether the next lexeme continues the previous line, if so configured.
+    fn continues_line(&mut self) -> bool {
+        self.continuation_friendly && matches!(self.iterator.peek(0), Some(EnrichedLexeme::Normal(
+            Operator(_) | QOperator(_) | Comma |
+            CloseParenthesis | CloseSquareBracket | CloseCurlyBracket, _)))
+    }
+
+    // returns false if the input is exhausted.
+    fn prepare_next(&mut self) -> bool {
         use EnrichedLexeme::*;
-        match (t.unwrap(), self.indents.last().copied()) {
+        if self.finished { return false; }
+        // streams made by hand may lack the end of file.
+        let t = self.iterator.next().unwrap_or(Eof(self.end));
+        if let Normal(_, range) = &t { self.end = range.end }
+        let before: Vec<usize> = if self.tracer.is_some() {
+            self.indents.iter().map(|c| c.indent()).collect()
+        } else { Vec::new() };
+        let top = self.indents.last().copied();
+        match (t, top) {
+            // L [] []                = []
+            // L [] (m : ms)          = } : L [] ms if m /= 0 (Note 6)
+            // Note 6. At the end of the input, any pending close-braces are inserted.
+            // It is an error at this point to be within a non-layout context (i.e. m = 0).
+            (Eof(location), Some(context)) => {
+                self.indents.pop();
+                match context {
+                    Context::Implicit(_, block) => {
+                        self.trace(LayoutRule::LRuleEof, None, &before);
+                        self.emit(PhantomCloseCurlyBracket(block))
+                    }
+                    Context::Explicit(range) =>
+                        self.buffer.push_back(Err(LayoutError::UnclosedOpenBracket(range))),
+                }
+                self.iterator.put_back(Eof(location))
+            }
+            // the end of file itself, after all the pending close-braces.
+            (Eof(location), None) => {
+                self.finished = true;
+                self.emit(AugmentedLexeme::Eof(location))
+            }
             // L (<n>: ts) (m : ms)   = ; : (L ts (m : ms)) if m = n
             //                        = } : (L (<n>: ts) ms) if n < m
-            (AngleN(n), Some(m)) if m == n =>
-                self.buffer.push_back(PhantomSemicolon),
-            (AngleN(n), Some(m)) if n < m => {
+            // m = n > 0 or m > n >= 0, so these are always implicit contexts
+            (AngleN(n), Some(Context::Implicit(m, _))) if m == n && self.continues_line() => {
+                let range = self.next_range();
+                self.trace(LayoutRule::LRuleContinuation, range, &before);
+            }
+            (AngleN(n), Some(Context::Implicit(m, block))) if m == n => {
+                let range = self.next_range();
+                self.trace(LayoutRule::LRuleSameIndent, range, &before);
+                self.emit(PhantomSemicolon(block))
+            }
+            (AngleN(n), Some(Context::Implicit(m, block))) if n < m => {
+                let range = self.next_range();
                 self.iterator.put_back(AngleN(n));
                 self.indents.pop();
-                self.buffer.push_back(PhantomCloseCurlyBracket)
+                self.trace(LayoutRule::LRuleDedent, range, &before);
+                self.emit(PhantomCloseCurlyBracket(block))
             }
             // L (<n>: ts) ms         = L ts ms
-            (AngleN(_), _) => self.prepare_next(),
+            (AngleN(_), _) => {
+                let range = self.next_range();
+                self.trace(LayoutRule::LRuleIndent, range, &before);
+            }
             // L ({n} : ts) (m : ms)  = { : (L ts (n : m : ms)) if n > m (Note 1)
             // L ({n} : ts) []        = { : (L ts [n]) if n > 0 (Note 1)
-            (CurlyN(n), m) if m.is_none() || n > m.unwrap() => {
-                self.indents.push(n);
-                self.buffer.push_back(PhantomOpenCurlyBracket)
+            (CurlyN(n), m) if m.is_none_or(|m| n > m.indent()) => {
+                let range = self.next_range();
+                let block = self.new_block();
+                self.indents.push(Context::Implicit(n, block));
+                self.trace(LayoutRule::LRuleNewContext, range, &before);
+                self.emit(PhantomOpenCurlyBracket(block))
             }
             // L ({n} : ts) ms        = { : } : (L (<n>: ts) ms) (Note 2)
             (CurlyN(n), _) => {
-                self.buffer.push_back(PhantomOpenCurlyBracket);
-                self.buffer.push_back(PhantomCloseCurlyBracket);
+                let range = self.next_range();
+                let block = self.new_block();
+                self.trace(LayoutRule::LRuleEmptyBlock, range, &before);
+                self.emit(PhantomOpenCurlyBracket(block));
+                self.emit(PhantomCloseCurlyBracket(block));
                 self.iterator.put_back(AngleN(n))
             }
             // L (} : ts) (0 : ms)    = } : (L ts ms) (Note 3)
@@ -299,42 +1008,288 @@ impl<'a, I: std::io::Read> AugmentedLexemeIterator<I> {
             // Note 3.By matching against 0 for the current layout context, we ensure that an
             // explicit close brace can only match an explicit open brace. A parse error results
             // if an explicit close brace matches an implicit open brace.
-            (Normal(CloseCurlyBracket, loc), Some(k)) => {
-                assert_eq!(k, 0, "mismatched curly brackets.");
+            (Normal(CloseCurlyBracket, loc), Some(Context::Explicit(_))) => {
+                self.indents.pop();
+                self.trace(LayoutRule::LRuleExplicitClose, Some(loc), &before);
+                self.emit(Real(CloseCurlyBracket, loc))
+            }
+            // the parse error closes the implicit context, by Note 5 below.
+            (Normal(CloseCurlyBracket, loc), Some(Context::Implicit(_, block))) => {
+                self.iterator.put_back(Normal(CloseCurlyBracket, loc));
                 self.indents.pop();
-                self.buffer.push_back(Real(CloseCurlyBracket, loc))
+                self.trace(LayoutRule::LRuleParseError, Some(loc), &before);
+                self.emit(PhantomCloseCurlyBracket(block))
+            }
+            (Normal(CloseCurlyBracket, loc), None) => {
+                self.buffer.push_back(Err(LayoutError::UnmatchedCloseBracket(loc)));
+                self.emit(Real(CloseCurlyBracket, loc))
             }
             // L ({ : ts) ms          = { : (L ts (0 : ms)) (Note 4)
             (Normal(OpenCurlyBracket, loc), _) => {
-                self.indents.push(0);
-                self.buffer.push_back(Real(OpenCurlyBracket, loc))
+                self.indents.push(Context::Explicit(loc));
+                self.trace(LayoutRule::LRuleExplicitOpen, Some(loc), &before);
+                self.emit(Real(OpenCurlyBracket, loc))
             }
             // L (t : ts) (m : ms)    = } : (L (t : ts) ms) if m /= 0 and parse-error(t) (Note 5)
-            // TODO: implement this `parse-error(t)` rule.
+            // TODO: implement this `parse-error(t)` rule in general.
             // L (t : ts) ms          = t : (L ts ms)
-            (Normal(t, loc), _) => {
-                self.buffer.push_back(Real(t, loc))
-            }
+            (Normal(t, loc), _) => self.emit(Real(t, loc)),
         }
+        true
     }
 }
 
-impl<'a, I: std::io::Read> From<EnrichedLexemeIterator<I>> for AugmentedLexemeIterator<I> {
-    fn from(iterator: EnrichedLexemeIterator<I>) -> Self {
-        AugmentedLexemeIterator {
-            iterator: IterStream::from(iterator),
-            buffer: VecDeque::new(),
-            indents: Vec::new(),
-        }
+impl<J: Iterator<Item=EnrichedLexeme>> Iterator for Layout<J> {
+    type Item = Result<AugmentedLexeme, LayoutError>;
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        // an `<n>` outside any layout context produces nothing, so keep going.
+        while self.buffer.is_empty() && self.prepare_next() {}
+        self.buffer.pop_front()
     }
 }
 
-impl<'a, I: std::io::Read> Iterator for AugmentedLexemeIterator<I> {
+/// A snapshot of the layout algorithm between two lexemes, for resuming it from there later:
+/// see [`AugmentedLexemeIterator::state`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LayoutState {
+    /// where the last lexeme ends, for the scanner to resume from: see [`Scanner::resume_at`].
+    ///
+    /// Its offset counts characters, not bytes: the input to resume on is the source past
+    /// that many characters, e.g. cut at `source.char_indices().nth(offset)`.
+    pub location: Location,
+    last_lexeme: LastLexeme,
+    last_line: usize,
+    indents: Vec<Context>,
+    next_block: u32,
+}
+
+impl LayoutState {
+    /// The indentations of the layout contexts, innermost last: 0 for explicit ones.
+    pub fn contexts(&self) -> Vec<usize> { self.indents.iter().map(|c| c.indent()).collect() }
+}
+
+/// Lexeme streams augmented with phantom `{`, `;`, and `}`.
+///
+/// Layout errors are reported as diagnostics to the scanner.
+pub struct AugmentedLexemeIterator<I: std::io::Read> {
+    inner: Layout<EnrichedLexemeIterator<I>>,
+}
+
+impl<I: std::io::Read> AugmentedLexemeIterator<I> {
+    /// Create a new enriched lexeme iterator from raw input.
+    pub fn new(input: I) -> Self { Self::from(EnrichedLexemeIterator::new(input)) }
+    /// Get back the internal scanner of this iterator.
+    ///
+    /// Lexemes already peeked for the layout algorithm but not yielded yet are discarded.
+    pub fn into_scanner(self) -> (Option<LexError>, Scanner<I>) { self.inner.iterator.unwrap_full().0.into_scanner() }
+    /// The file scanned, see [`Scanner::with_file`].
+    pub fn file(&self) -> FileId { self.inner.iterator.get().file() }
+    /// Use the layout configuration, before consuming any lexemes.
+    pub fn with_config(mut self, config: LayoutConfig) -> Self {
+        *self.config_mut() = config;
+        self.inner.continuation_friendly = config.continuation_friendly;
+        self
+    }
+    /// Report the progress of the scanner, see [`Scanner::on_progress`].
+    pub fn on_progress(mut self, every_n_chars: usize, callback: impl FnMut(Progress) + 'static) -> Self {
+        self.inner.iterator.get_mut().scanner_mut().set_progress_callback(every_n_chars, Box::new(callback));
+        self
+    }
+    fn config_mut(&mut self) -> &mut LayoutConfig { self.inner.iterator.get_mut().config_mut() }
+    /// Record every decision of the layout algorithm `L` to the tracer.
+    ///
+    /// To also trace the `{n}` and `<n>` decisions, call
+    /// [`EnrichedLexemeIterator::with_trace`] before wrapping it into this iterator.
+    pub fn with_trace(self, tracer: impl FnMut(LayoutEvent) + 'static) -> Self {
+        Self { inner: self.inner.with_trace(tracer) }
+    }
+    /// A snapshot of the layout algorithm right after the last lexeme, if there is nothing
+    /// read ahead: e.g. after a real lexeme, but not after a phantom one, nor after a lexical
+    /// error, nor at the end of file.
+    ///
+    /// To resume from it, resume a scanner at [`LayoutState::location`], and then
+    /// [`EnrichedLexemeIterator::resume`] and [`AugmentedLexemeIterator::resume`] with it.
+    pub fn state(&mut self) -> Option<LayoutState> {
+        let layout = &mut self.inner;
+        if !layout.buffer.is_empty() || layout.iterator.buffered() > 0 { return None; }
+        let enrich = &mut layout.iterator.get_mut().inner;
+        let last_lexeme = enrich.last_lexeme;
+        if enrich.iterator.buffered() > 0 ||
+            !matches!(last_lexeme, LastLexeme::LetWhereDoOf | LastLexeme::Other) { return None; }
+        let last_line = enrich.last_line;
+        let raw = &enrich.iterator.get_mut().iterator;
+        if raw.error.is_some() { return None; }
+        Some(LayoutState {
+            location: raw.scanner.location,
+            last_lexeme,
+            last_line,
+            indents: layout.indents.clone(),
+            next_block: layout.next_block,
+        })
+    }
+    /// Resume from a snapshot taken by [`AugmentedLexemeIterator::state`], on an enriched
+    /// iterator resumed from the same snapshot.
+    pub fn resume(iterator: EnrichedLexemeIterator<I>, state: &LayoutState) -> Self {
+        let mut res = Self::from(iterator);
+        res.inner.indents = state.indents.clone();
+        res.inner.next_block = state.next_block;
+        res.inner.end = state.location;
+        res
+    }
+    /// Only the real lexemes, in layout order: the phantom `{`, `;`, and `}` are dropped, and
+    /// so is the end of file.
+    pub fn real_tokens(self) -> impl Iterator<Item=(Lexeme, Range)> {
+        self.filter_map(|lexeme| match lexeme {
+            Real(lexeme, range) => Some((lexeme, range)),
+            _ => None,
+        })
+    }
+    /// The real lexemes of the top-level declarations, one group per declaration: split at the
+    /// `;`s (phantom or real) of the outermost layout context, as [`find_top_level`] sees it.
+    ///
+    /// The `{`, `;`, and `}` of the outermost context themselves are in no group, nor is the
+    /// module header before it; nested blocks stay within their declaration, explicit braces
+    /// included. Empty declarations give no group.
+    pub fn split_top_level(mut self) -> impl Iterator<Item=Vec<(Lexeme, Range)>> {
+        let mut depth = 0usize;
+        std::iter::from_fn(move || {
+            let mut group = Vec::new();
+            for lexeme in self.by_ref() {
+                let opens = matches!(lexeme, PhantomOpenCurlyBracket(_) | Real(OpenCurlyBracket, _));
+                let closes = matches!(lexeme, PhantomCloseCurlyBracket(_) | Real(CloseCurlyBracket, _));
+                let separates = depth == 1 && matches!(lexeme, PhantomSemicolon(_) | Real(Semicolon, _));
+                // the outermost `{` is seen at depth 0, and its `}` once back at depth 0.
+                if closes { depth = depth.saturating_sub(1) }
+                let inside = depth > 0 && !separates;
+                if opens { depth += 1 }
+                match lexeme {
+                    Real(lexeme, range) if inside => group.push((lexeme, range)),
+                    _ => (),
+                }
+                if (separates || closes && depth == 0) && !group.is_empty() { return Some(group); }
+            }
+            Some(group).filter(|group| !group.is_empty())
+        })
+    }
+}
+
+impl<I: std::io::Read> From<EnrichedLexemeIterator<I>> for AugmentedLexemeIterator<I> {
+    fn from(mut iterator: EnrichedLexemeIterator<I>) -> Self {
+        let friendly = iterator.config_mut().continuation_friendly;
+        Self { inner: apply_layout(iterator).with_continuation_friendly(friendly) }
+    }
+}
+
+impl<I: std::io::Read> Iterator for AugmentedLexemeIterator<I> {
     type Item = AugmentedLexeme;
+    #[inline]
     fn next(&mut self) -> Option<AugmentedLexeme> {
-        self.prepare_next();
-        self.buffer.pop_front()
+        loop {
+            match self.inner.next()? {
+                Ok(t) => return Some(t),
+                Err(err) => {
+                    use crate::error::{DiagnosticMessage, Error};
+                    Diagnostic::new(err.range(), DiagnosticMessage::Error(Error::Layout(err)))
+                        .report(&mut self.inner.iterator.get_mut().scanner_mut().diagnostics)
+                }
+            }
+        }
+    }
+}
+
+/// Find the first top-level declaration of `name`: an identifier at the beginning of a
+/// declaration (right after a `{` or a `;`) in the outermost layout context.
+///
+/// Lexing stops as soon as the declaration is found, and the rest of the input is never read.
+/// Type signatures (`name :: ...`) count as declarations.
+pub fn find_top_level(input: impl std::io::Read, name: &str) -> Option<Range> {
+    let mut depth = 0usize;
+    let mut at_head = false;
+    for lexeme in AugmentedLexemeIterator::new(input) {
+        match lexeme {
+            PhantomOpenCurlyBracket(_) | Real(OpenCurlyBracket, _) => depth += 1,
+            PhantomCloseCurlyBracket(_) | Real(CloseCurlyBracket, _) =>
+                depth = depth.saturating_sub(1),
+            Real(Identifier(x), range) if at_head && depth == 1 && x == name => return Some(range),
+            _ => (),
+        }
+        at_head = matches!(lexeme, PhantomOpenCurlyBracket(_) | PhantomSemicolon(_) |
+                                   Real(OpenCurlyBracket | Semicolon, _));
     }
+    None
+}
+
+/// What a fragment given to [`lex_fragment`] is, for the layout context it is lexed in.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum FragmentKind {
+    /// An expression, in no layout context: no `{n}` before it, and no `;` between its lines,
+    /// but the blocks of its own `let`, `where`, `do`, and `of` as usual.
+    Expression,
+    /// A single declaration, in an implicit layout context at column 1 of its own: it is
+    /// wrapped in a phantom `{` and `}`.
+    Declaration,
+    /// A part of the body of a module (or of any block), whose implicit layout context,
+    /// opened and closed outside the fragment, is at `enclosing_indent` (from 1): the lines
+    /// there are separated by phantom `;`s, but there is no phantom `{` or `}` of it.
+    ModuleBody {
+        /// the indentation of the enclosing layout context.
+        enclosing_indent: usize,
+    },
+}
+
+/// Lex an isolated fragment of source, e.g. from a REPL or a template, into augmented
+/// lexemes, as if spliced into a layout context given by `kind` instead of starting a module:
+/// the first lexeme gets neither `{n}` nor `<n>`.
+///
+/// The phantom lexemes of the enclosing context (if any) have the block id 0, and the blocks
+/// within the fragment count from 1 on. The diagnostics come along with the lexemes, in source
+/// order; only a lexical error fails.
+pub fn lex_fragment(input: &str, kind: FragmentKind)
+                    -> Result<(Vec<AugmentedLexeme>, Vec<Diagnostic>), LexError> {
+    use crate::error::{DiagnosticMessage, Error};
+    let enclosing = BlockId(0);
+    let indents = match kind {
+        FragmentKind::Expression => Vec::new(),
+        FragmentKind::Declaration => vec![Context::Implicit(1, enclosing)],
+        FragmentKind::ModuleBody { enclosing_indent } =>
+            vec![Context::Implicit(enclosing_indent, enclosing)],
+    };
+    // right after a lexeme already handled, as after a `{n}`.
+    let state = LayoutState {
+        location: Location::new(),
+        last_lexeme: LastLexeme::PassThrough,
+        last_line: 0,
+        indents,
+        next_block: 1,
+    };
+    let iterator = EnrichedLexemeIterator::resume(FatLexemeIterator::new(input.as_bytes()), &state);
+    let mut iterator = AugmentedLexemeIterator::resume(iterator, &state);
+    let mut lexemes = Vec::new();
+    if kind == FragmentKind::Declaration { lexemes.push(PhantomOpenCurlyBracket(enclosing)) }
+    lexemes.extend(iterator.by_ref());
+    let (lex_error, mut scanner) = iterator.into_scanner();
+    if let Some(err) = lex_error { return Err(err); }
+    if let FragmentKind::ModuleBody { .. } = kind {
+        // the enclosing context goes on after the fragment: its `}` is not the fragment's,
+        // neither right before the end of file, nor (in error) in the middle.
+        while let Some(k) = lexemes.iter().position(|t| *t == PhantomCloseCurlyBracket(enclosing)) {
+            lexemes.remove(k);
+            let next = lexemes[k..].iter().find_map(|t| t.as_lexeme().zip(t.range()));
+            match next {
+                // an explicit `}` closing the context is reported as unmatched already.
+                None | Some((CloseCurlyBracket, _)) => (),
+                Some((_, range)) => {
+                    let err = LayoutError::LeavesEnclosingContext(range);
+                    Diagnostic::new(range, DiagnosticMessage::Error(Error::Layout(err)))
+                        .report(scanner.diagnostics_mut())
+                }
+            }
+        }
+    }
+    let diagnostics = std::iter::from_fn(|| scanner.diagnostics_mut().pop()).collect();
+    Ok((lexemes, diagnostics))
 }
 
 #[cfg(test)]
@@ -342,6 +1297,7 @@ mod tests {
     use indoc::indoc;
     use super::RawLexemeIterator;
     use super::EnrichedLexemeIterator;
+    use super::AnnotatedLexemeIterator;
     use crate::lexeme::Lexeme::*;
     use crate::lexeme::RId::*;
     use crate::lexeme::ROp::*;
@@ -358,42 +1314,39 @@ mod tests {
 
     #[test]
     fn test_raw_iterator() {
-        let mut it = RawLexemeIterator::new(TEST_SOURCE.as_bytes());
-        assert!(it.by_ref().eq([
+        crate::testing::assert_lexes_to(TEST_SOURCE, &[
             ReservedId(Module),
-            Identifier("Main".to_string()),
+            Identifier("Main".into()),
             ReservedId(Where),
             ReservedId(Import),
-            Identifier("Prelude".to_string()),
-            Identifier("hiding".to_string()),
+            Identifier("Prelude".into()),
+            Identifier("hiding".into()),
             OpenParenthesis,
-            Identifier("Integer".to_string()),
+            Identifier("Integer".into()),
             CloseParenthesis,
-            Identifier("main".to_string()),
+            Identifier("main".into()),
             ReservedOp(ColonColon),
-            Identifier("IO".to_string()),
+            Identifier("IO".into()),
             OpenParenthesis,
             CloseParenthesis,
-            Identifier("main".to_string()),
+            Identifier("main".into()),
             ReservedOp(EqualSign),
             ReservedId(Do),
-            Identifier("name".to_string()),
+            Identifier("name".into()),
             ReservedOp(LeftArrow),
-            Identifier("getLine".to_string()),
-            Identifier("putStrLn".to_string()),
+            Identifier("getLine".into()),
+            Identifier("putStrLn".into()),
             OpenParenthesis,
             StringLiteral("Hello, ".to_string()),
-            Operator("<>".to_string()),
-            Identifier("name".to_string()),
-            Operator("<>".to_string()),
+            Operator("<>".into()),
+            Identifier("name".into()),
+            Operator("<>".into()),
             StringLiteral("!".to_string()),
             CloseParenthesis,
-            Identifier("pure".to_string()),
+            Identifier("pure".into()),
             OpenParenthesis,
             CloseParenthesis,
-        ].iter().cloned()));
-        let (err, _) = it.into_scanner();
-        assert_eq!(err, None);
+        ]);
     }
 
     #[test]
@@ -440,8 +1393,795 @@ mod tests {
             7:5-7:9: pure
             7:10-7:11: (
             7:11-7:12: )
+            8:1: <eof>
+        "#]].assert_eq(&res);
+        let (err, _) = it.into_scanner();
+        assert_eq!(err, None);
+    }
+
+    #[test]
+    fn test_layout_trace() {
+        use std::rc::Rc;
+        use std::cell::RefCell;
+        use expect_test::expect;
+        use super::AugmentedLexemeIterator;
+        let events = Rc::new(RefCell::new(String::new()));
+        let (enriched_events, augmented_events) = (events.clone(), events.clone());
+        let it = EnrichedLexemeIterator::new(TEST_SOURCE.as_bytes())
+            .with_trace(move |e| *enriched_events.borrow_mut() += &format!("{}\n", e));
+        let it = AugmentedLexemeIterator::from(it)
+            .with_trace(move |e| *augmented_events.borrow_mut() += &format!("{}\n", e));
+        it.for_each(drop);
+        expect![[r#"
+            RuleLetWhereDoOf at 2:1-2:7
+            LRuleNewContext at 2:1-2:7: [] -> [1]
+            RuleNewLine at 3:1-3:5
+            LRuleSameIndent at 3:1-3:5
+            RuleNewLine at 4:1-4:5
+            LRuleSameIndent at 4:1-4:5
+            RuleLetWhereDoOf at 5:5-5:9
+            LRuleNewContext at 5:5-5:9: [1] -> [1, 5]
+            RuleNewLine at 6:5-6:13
+            LRuleSameIndent at 6:5-6:13
+            RuleNewLine at 7:5-7:9
+            LRuleSameIndent at 7:5-7:9
+            LRuleEof: [1, 5] -> [1]
+            LRuleEof: [1] -> []
+        "#]].assert_eq(&events.borrow());
+    }
+
+    #[test]
+    fn test_block_ids() {
+        use expect_test::expect;
+        use super::AugmentedLexemeIterator;
+        use super::AugmentedLexeme::*;
+        let source = indoc! {r#"
+            f = do
+              g
+              let x = { y }
+              h x
+              where g = do
+                      pure ()
+        "#};
+        let mut blocks = Vec::new();
+        let mut res = String::new();
+        for t in AugmentedLexemeIterator::new(source.as_bytes()) {
+            match &t {
+                PhantomOpenCurlyBracket(id) => blocks.push(*id),
+                PhantomCloseCurlyBracket(id) => assert_eq!(blocks.pop(), Some(*id)),
+                PhantomSemicolon(id) => assert_eq!(blocks.last(), Some(id)),
+                Real(..) | Eof(_) => (),
+            }
+            res += &format!("{:#}\n", t)
+        }
+        assert!(blocks.is_empty());
+        expect![[r#"
+            <phantom>: { #0
+            1:1-1:2: f
+            1:3-1:4: =
+            1:5-1:7: do
+            <phantom>: { #1
+            2:3-2:4: g
+            <phantom>: ; #1
+            3:3-3:6: let
+            <phantom>: { #2
+            3:7-3:8: x
+            3:9-3:10: =
+            3:11-3:12: {
+            3:13-3:14: y
+            3:15-3:16: }
+            <phantom>: } #2
+            <phantom>: ; #1
+            4:3-4:4: h
+            4:5-4:6: x
+            <phantom>: ; #1
+            5:3-5:8: where
+            <phantom>: { #3
+            5:9-5:10: g
+            5:11-5:12: =
+            5:13-5:15: do
+            <phantom>: { #4
+            6:11-6:15: pure
+            6:16-6:17: (
+            6:17-6:18: )
+            <phantom>: } #4
+            <phantom>: } #3
+            <phantom>: } #1
+            <phantom>: } #0
+            7:1: <eof>
         "#]].assert_eq(&res);
+    }
+
+    #[test]
+    fn test_partial_into_scanner() {
+        use super::{EnrichedLexemeIterator, AugmentedLexemeIterator};
+        // the enriched iterator peeks the lexeme after `where`.
+        let mut it = EnrichedLexemeIterator::new(TEST_SOURCE.as_bytes());
+        it.by_ref().take(4).for_each(drop);
+        let (err, _) = it.into_scanner();
+        assert_eq!(err, None);
+        // the augmented iterator peeks the lexeme after `{n}`.
+        let mut it = AugmentedLexemeIterator::new(TEST_SOURCE.as_bytes());
+        it.by_ref().take(5).for_each(drop);
         let (err, _) = it.into_scanner();
         assert_eq!(err, None);
     }
+
+    #[test]
+    fn test_tabs_in_indentation() {
+        use expect_test::{expect, Expect};
+        use super::{AugmentedLexemeIterator, LayoutConfig, TabPolicy};
+        // tabs inside a line (after `print`) are not about the indentation.
+        const SOURCE: &str = "main = do\n\tputStrLn \"Hello\"\n  \tprint\t42\n";
+        fn test(policy: TabPolicy, expect: Expect) {
+            let config = LayoutConfig { tabs_in_indentation: policy, ..LayoutConfig::default() };
+            let mut it = AugmentedLexemeIterator::new(SOURCE.as_bytes()).with_config(config);
+            let tokens = it.by_ref().count();
+            assert_eq!(tokens, 12 + 1);
+            let (err, scanner) = it.into_scanner();
+            assert_eq!(err, None);
+            let res: String = scanner.diagnostics().iter()
+                .map(|d| format!("{}\n", d)).collect();
+            expect.assert_eq(&res);
+        }
+        test(TabPolicy::Allow, expect![[r#""#]]);
+        test(TabPolicy::Warn, expect![[r#"
+            2:1-2:9: warning: tab in indentation: the first token is at column 9 with 8-column tab stops, but at column 2 if tabs count as one column
+            3:3-3:9: warning: tab in indentation: the first token is at column 9 with 8-column tab stops, but at column 4 if tabs count as one column
+        "#]]);
+        test(TabPolicy::Error, expect![[r#"
+            2:1-2:9: error: tab in indentation: the first token is at column 9 with 8-column tab stops, but at column 2 if tabs count as one column
+            3:3-3:9: error: tab in indentation: the first token is at column 9 with 8-column tab stops, but at column 4 if tabs count as one column
+        "#]]);
+    }
+
+    #[test]
+    fn test_confusable_whitespace() {
+        use expect_test::{expect, Expect};
+        use super::{EnrichedLexemeIterator, LayoutConfig, ConfusablePolicy, render_enriched};
+        // U+00A0 in the indentation, U+2007 inside a line, U+202F and U+1680 at the end.
+        const SOURCE: &str = "main = do\n  print 1\n \u{A0}print\u{2007}2\n\u{202F}\u{1680}";
+        fn test(policy: ConfusablePolicy, diagnostics: Expect) {
+            let config = LayoutConfig { confusable_whitespace: policy, ..LayoutConfig::default() };
+            let mut it = EnrichedLexemeIterator::new(SOURCE.as_bytes()).with_config(config);
+            // U+00A0 counts as one column: the second statement lines up with the first.
+            expect![[r#"
+                {1}
+                1:1-1:5: main
+                1:6-1:7: =
+                1:8-1:10: do
+                {3}
+                2:3-2:8: print
+                2:9-2:10: fromIntegral 1
+                <3>
+                3:3-3:8: print
+                3:9-3:10: fromIntegral 2
+                4:3: <eof>
+            "#]].assert_eq(&render_enriched(it.by_ref()));
+            let (err, scanner) = it.into_scanner();
+            assert_eq!(err, None);
+            let res: String = scanner.diagnostics().iter()
+                .map(|d| format!("{}\n", d)).collect();
+            diagnostics.assert_eq(&res);
+        }
+        test(ConfusablePolicy::Indentation, expect![[r#"
+            3:2-3:3: error: non-ASCII whitespace U+00A0 counted as one column, use spaces
+        "#]]);
+        test(ConfusablePolicy::Anywhere, expect![[r#"
+            3:2-3:3: error: non-ASCII whitespace U+00A0 counted as one column, use spaces
+            3:8-3:9: error: non-ASCII whitespace U+2007 counted as one column, use spaces
+            4:1-4:2: error: non-ASCII whitespace U+202F counted as one column, use spaces
+            4:2-4:3: error: non-ASCII whitespace U+1680 counted as one column, use spaces
+        "#]]);
+    }
+
+    // `{n}`, `<n>`, `'{'`, `'}'`, or an identifier, separated by spaces.
+    fn enriched(tokens: &str) -> Vec<super::EnrichedLexeme> {
+        use super::EnrichedLexeme::*;
+        use crate::scanner::{Location, Range};
+        let range = Range::point(Location::new());
+        tokens.split_whitespace().map(|t| match t {
+            "'{'" => Normal(OpenCurlyBracket, range),
+            "'}'" => Normal(CloseCurlyBracket, range),
+            _ if t.starts_with('{') => CurlyN(t[1..t.len() - 1].parse().unwrap()),
+            _ if t.starts_with('<') => AngleN(t[1..t.len() - 1].parse().unwrap()),
+            _ => Normal(Identifier(t.into()), range),
+        }).collect()
+    }
+
+    #[test]
+    fn test_multi_line_whitespace_and_lexemes() {
+        use expect_test::expect;
+        use super::render_enriched;
+        let test = |source: &str| render_enriched(EnrichedLexemeIterator::new(source.as_bytes()));
+        // comments are white space: `g` is the first lexeme on its line.
+        expect![[r#"
+            {1}
+            1:1-1:2: f
+            1:3-1:4: =
+            1:5-1:6: fromIntegral 1
+            <19>
+            4:19-4:20: g
+            4:21-4:22: =
+            4:23-4:24: fromIntegral 2
+            5:1: <eof>
+        "#]].assert_eq(&test("f = 1\n{- a block comment\n   spanning\n   three lines -} g = 2\n"));
+        // `++` follows the string on the line where it ends, `x` is on a line of its own.
+        expect![[r#"
+            {1}
+            1:1-1:2: h
+            1:3-1:4: =
+            1:5-3:10: "abcdef"
+            3:11-3:13: ++
+            <3>
+            4:3-4:4: x
+            5:1: <eof>
+        "#]].assert_eq(&test("h = \"abc\\\n\n    \\def\" ++\n  x\n"));
+    }
+
+    #[test]
+    fn test_apply_layout() {
+        use super::{apply_layout, AugmentedLexeme::*};
+        fn test(input: &str, output: &str) {
+            let mut res: Vec<_> = apply_layout(enriched(input)).map(|t| match t {
+                Ok(Real(t, _)) if matches!(t, OpenCurlyBracket | CloseCurlyBracket) =>
+                    format!("'{}'", t),
+                Ok(Real(t, _)) => t.to_string(),
+                Ok(PhantomOpenCurlyBracket(_)) => "{".to_string(),
+                Ok(PhantomSemicolon(_)) => ";".to_string(),
+                Ok(PhantomCloseCurlyBracket(_)) => "}".to_string(),
+                Ok(Eof(_)) => "<eof>".to_string(),
+                Err(_) => "error".to_string(),
+            }).collect();
+            // exactly once, after everything else.
+            assert_eq!(res.pop().as_deref(), Some("<eof>"), "L ({})", input);
+            assert_eq!(res.join(" "), output, "L ({})", input);
+        }
+        // L (<n>: ts) [] = L ts []: no layout context to close or separate.
+        test("<1> a", "a");
+        // Note 1: a nested context must be indented more than the enclosing one.
+        test("{1} a <1> b", "{ a ; b }");
+        test("{1} a {3} b <3> c <1> d", "{ a { b ; c } ; d }");
+        // Note 2: otherwise the block is empty, and `<n>` is processed after it.
+        test("{3} a {2} b", "{ a { } } b");
+        test("{1} a {0}", "{ a { } }");
+        // Note 3: an explicit `}` matches an explicit `{` ...
+        test("{1} a '{' b '}' <1> c", "{ a '{' b '}' ; c }");
+        // ... and closes implicit contexts by the parse-error(t) rule in Note 5.
+        test("'{' {2} b '}'", "'{' { b } '}'");
+        // Note 4: inside an explicit context, no `;` or `}` is inserted.
+        test("{1} a '{' <2> b <1> c '}'", "{ a '{' b c '}' }");
+        // Note 6: at the end of the input, pending implicit contexts are closed ...
+        test("{1} a {3} b", "{ a { b } }");
+        // ... and it is an error to be within an explicit one.
+        test("'{' a", "'{' a error");
+        test("a '}'", "a error '}'");
+    }
+
+    #[test]
+    fn test_layout_error_diagnostics() {
+        use super::AugmentedLexemeIterator;
+        let mut it = AugmentedLexemeIterator::new("x = }\ny = {".as_bytes());
+        // `{ x = } } y = {`: the implicit context is closed by the `}`, which then matches nothing.
+        assert_eq!(it.by_ref().count(), 8 + 1);
+        let (err, scanner) = it.into_scanner();
+        assert_eq!(err, None);
+        let diagnostics: Vec<_> = scanner.diagnostics().iter().map(|d| d.to_string()).collect();
+        assert_eq!(diagnostics, [
+            "1:5-1:6: error: unmatched `}`",
+            "2:5-2:6: error: `{` not closed at the end of input",
+        ]);
+    }
+
+    #[test]
+    fn test_line_pragmas() {
+        use super::{FatLexemeIterator, AugmentedLexemeIterator, AugmentedLexeme::*};
+        use crate::scanner::Scanner;
+        let source = "f = do\n  g\n{-# LINE 100 \"Original.hs\" #-}\n  h '\\1114112'\n\
+                      # 7 \"Other.hs\" 2\n  k\n";
+        let scanner = Scanner::new(source.as_bytes()).with_line_pragmas(true);
+        let mut it = AugmentedLexemeIterator::from(EnrichedLexemeIterator::from(
+            FatLexemeIterator::from(RawLexemeIterator::from(scanner))));
+        let tokens: Vec<_> = it.by_ref().collect();
+        let (err, scanner) = it.into_scanner();
+        assert_eq!(err, None);
+        let map = scanner.source_map();
+        // layout works on the physical lines: the directives are only whitespaces.
+        let res: Vec<_> = tokens.iter().map(|t| match t {
+            Real(t, range) => format!("{} @ {}", t, map.logical(range.begin)),
+            t => t.to_string(),
+        }).collect();
+        assert_eq!(res, [
+            "<phantom>: {", "f @ 1:1", "= @ 1:3", "do @ 1:5", "<phantom>: {", "g @ 2:3",
+            "<phantom>: ;", "h @ Original.hs:100:3", "'\u{FFFD}' @ Original.hs:100:5",
+            "<phantom>: ;", "k @ Other.hs:7:3", "<phantom>: }", "<phantom>: }", "7:1: <eof>",
+        ]);
+        let diagnostics: Vec<_> = scanner.diagnostics().iter()
+            .map(|d| d.render_mapped(source, map)).collect();
+        expect_test::expect![[r#"
+            error[L0005]: character literal out of bound (1114112)
+             --> Original.hs:100:7
+              |
+            4 |   h '\1114112'
+              |       ^^^^^^^
+        "#]].assert_eq(&diagnostics.concat());
+    }
+
+    #[test]
+    fn test_augmented_values() {
+        use super::{AugmentedLexemeIterator, AugmentedLexeme::{self, *}, BlockId};
+        use crate::scanner::{Location, Range};
+        let loc = Location::at;
+        let at = |line, column, offset, len| Range {
+            begin: loc(line, column, offset),
+            end: loc(line, column + len, offset + len),
+        };
+        let res: Vec<_> = AugmentedLexemeIterator::new("f = do\n  g\n".as_bytes()).collect();
+        assert_eq!(res, vec![
+            PhantomOpenCurlyBracket(BlockId(0)),
+            Real(Identifier("f".into()), at(1, 1, 0, 1)),
+            Real(ReservedOp(EqualSign), at(1, 3, 2, 1)),
+            Real(ReservedId(Do), at(1, 5, 4, 2)),
+            PhantomOpenCurlyBracket(BlockId(1)),
+            Real(Identifier("g".into()), at(2, 3, 9, 1)),
+            PhantomCloseCurlyBracket(BlockId(1)),
+            PhantomCloseCurlyBracket(BlockId(0)),
+            AugmentedLexeme::Eof(loc(3, 1, 11)),
+        ]);
+        assert_eq!(res[1].as_lexeme(), Some(&Identifier("f".into())));
+        assert_eq!(res[1].range(), Some(at(1, 1, 0, 1)));
+        assert_eq!(res[0].as_lexeme(), None);
+        assert_eq!(res[8].range(), None);
+    }
+
+    #[test]
+    fn test_continuation_friendly() {
+        use super::{AugmentedLexemeIterator, AugmentedLexeme::*, LayoutConfig};
+        let source = indoc! {"
+            main = do
+              check x
+              .&&. y
+              , z
+              pure (a
+                  <> b)
+             where
+                (.&&.) = (&&)
+                p = q
+        "};
+        fn render(source: &str, continuation_friendly: bool) -> String {
+            let config = LayoutConfig { continuation_friendly, ..LayoutConfig::default() };
+            let it = AugmentedLexemeIterator::new(source.as_bytes()).with_config(config);
+            let tokens: Vec<_> = it.filter_map(|t| match t {
+                Real(_, r) => Some(&source[r.begin.offset..r.end.offset]),
+                PhantomOpenCurlyBracket(_) => Some("{"),
+                PhantomSemicolon(_) => Some(";"),
+                PhantomCloseCurlyBracket(_) => Some("}"),
+                Eof(_) => None,
+            }).collect();
+            tokens.join(" ")
+        }
+        // as in the Report: a `;` before each line at the same indentation.
+        assert_eq!(render(source, false),
+                   "{ main = do { check x ; .&&. y ; , z ; pure ( a <> b ) } \
+                    where { ( .&&. ) = ( && ) ; p = q } }");
+        // the leading operator and comma continue the statement, the definitions are intact.
+        assert_eq!(render(source, true),
+                   "{ main = do { check x .&&. y , z ; pure ( a <> b ) } \
+                    where { ( .&&. ) = ( && ) ; p = q } }");
+    }
+
+    #[test]
+    fn test_eof_without_trailing_newline() {
+        use super::{AugmentedLexemeIterator, AugmentedLexeme::*};
+        use crate::scanner::Location;
+        let source = "f = do\n  g x  ";
+        let res: Vec<_> = AugmentedLexemeIterator::new(source.as_bytes())
+            .map(|t| t.to_string()).collect();
+        // after the phantom `}`s, at the end of the trailing whitespace.
+        assert_eq!(res[res.len() - 3..], ["<phantom>: }", "<phantom>: }", "2:8: <eof>"]);
+        let last = AugmentedLexemeIterator::new(source.as_bytes()).last();
+        assert!(matches!(last, Some(Eof(Location { line: 2, column: 8, offset: 14, .. }))));
+    }
+
+    #[test]
+    fn test_enrich() {
+        use super::{enrich, render_enriched};
+        use crate::scanner::Range;
+        // `f` at 1:1, `where` at 1:3, and `g` at 2:3.
+        let at = |line, column| Range::single_line(line, column, column + 1);
+        let tokens = vec![
+            (Identifier("f".into()), at(1, 1)),
+            (ReservedId(Where), at(1, 3)),
+            (Identifier("g".into()), at(2, 3)),
+        ];
+        expect_test::expect![[r#"
+            {1}
+            1:1-1:2: f
+            1:3-1:4: where
+            {3}
+            2:3-2:4: g
+            2:4: <eof>
+        "#]].assert_eq(&render_enriched(enrich(tokens)));
+    }
+
+    #[test]
+    fn test_enrich_long_line_and_unfused() {
+        use super::{enrich, EnrichedLexeme::*};
+        use crate::scanner::{Location, Range};
+        use crate::lexeme::Lexeme;
+        let at = |column| Range::single_line(1, column, column + 1);
+        // a single line of 200k lexemes: one `{1}`, then all of them.
+        const N: usize = 200_000;
+        let tokens = (0..N).map(|k| (Identifier("x".into()), at(2 * k + 1)));
+        let mut it = enrich(tokens);
+        assert_eq!(it.next(), Some(CurlyN(1)));
+        assert_eq!(it.by_ref().filter(|t| matches!(t, Normal(..))).count(), N);
+        assert_eq!(it.next(), None);
+
+        // ends after `where`, then yields more: `where` closed with `{0}`, and the end of file.
+        struct Unfused(usize);
+        impl Iterator for Unfused {
+            type Item = (Lexeme, Range);
+            fn next(&mut self) -> Option<Self::Item> {
+                self.0 += 1;
+                match self.0 {
+                    1 => Some((ReservedId(Where), Range::point(Location::new()))),
+                    2 => None,
+                    _ => Some((Identifier("ghost".into()), Range::point(Location::new()))),
+                }
+            }
+        }
+        let res: Vec<_> = enrich(Unfused(0)).take(5).collect();
+        assert_eq!(res, [CurlyN(1), Normal(ReservedId(Where), Range::point(Location::new())),
+                         CurlyN(0), Eof(Location::new())]);
+    }
+
+    #[test]
+    fn test_bom() {
+        use crate::scanner::Location;
+        let mut it = super::FatLexemeIterator::new("\u{FEFF}module M where".as_bytes());
+        let (_, range) = it.next().unwrap();
+        assert_eq!(range.begin, Location::new());
+        assert_eq!(it.count(), 2);
+        let mut it = super::FatLexemeIterator::new(&b"\xFF\xFEm\x00"[..]);
+        assert_eq!(it.next(), None);
+        let (err, scanner) = it.into_scanner();
+        assert_eq!(err, None);
+        let diagnostics: Vec<_> = scanner.diagnostics().iter().map(|d| d.to_string()).collect();
+        assert_eq!(diagnostics, ["1:1-1:1: error: input failure: \
+            UTF-16 input is not supported; re-encode as UTF-8"]);
+    }
+
+    #[test]
+    fn test_many_top_level_items() {
+        use super::AugmentedLexemeIterator;
+        const N: usize = 100_000;
+        // with a small stack, any recursion per lexeme shows up as a stack overflow.
+        let count = std::thread::Builder::new()
+            .stack_size(64 * 1024)
+            .spawn(|| {
+                let source = "x = 1\n\n".repeat(N);
+                AugmentedLexemeIterator::new(source.as_bytes()).count()
+            })
+            .unwrap().join().unwrap();
+        // x = 1 ; x = 1 ; ... with a pair of phantom braces, and the end of file.
+        assert_eq!(count, 4 * N + 2);
+    }
+
+    // ranges of lexemes, in the "begin-end: lexeme" format.
+    fn ranges(input: impl std::io::Read) -> Vec<String> {
+        let mut it = super::FatLexemeIterator::new(input);
+        let mut res: Vec<_> = it.by_ref().map(|(t, r)| format!("{}: {}", r, t)).collect();
+        let (err, scanner) = it.into_scanner();
+        assert_eq!(err, None);
+        res.extend(scanner.diagnostics().iter().map(|d| d.to_string()));
+        res
+    }
+
+    // an input yielding one byte per read.
+    struct OneByte<'a>(&'a [u8]);
+
+    impl std::io::Read for OneByte<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match (self.0.split_first(), buf.first_mut()) {
+                (Some((x, rest)), Some(b)) => {
+                    *b = *x;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn test_special_ranges() {
+        // invalid UTF-8 counts as no column.
+        assert_eq!(ranges(&b"(\xFF)\xFF\xFE,"[..]),
+                   ["1:1-1:2: (", "1:2-1:3: )", "1:3-1:4: ,",
+                    "1:2-1:2: error: invalid UTF-8 sequence [FF]",
+                    "1:3-1:3: error: invalid UTF-8 sequence [FF]",
+                    "1:3-1:3: error: invalid UTF-8 sequence [FE]"]);
+        // specials right after reverted alternatives: exponent, octal and hexadecimal.
+        assert_eq!(ranges(&b"1e)0o]0x}"[..]), [
+            "1:1-1:2: fromIntegral 1", "1:2-1:3: e", "1:3-1:4: )",
+            "1:4-1:5: fromIntegral 0", "1:5-1:6: o", "1:6-1:7: ]",
+            "1:7-1:8: fromIntegral 0", "1:8-1:9: x", "1:9-1:10: }",
+        ]);
+        // specials at buffer refill boundaries, with one byte per read.
+        let n = 4 * 1024 - 1;
+        let mut source = " ".repeat(n) + "()";
+        assert_eq!(ranges(OneByte(source.as_bytes())), [
+            format!("1:{}-1:{}: (", n + 1, n + 2), format!("1:{}-1:{}: )", n + 2, n + 3)]);
+        // a character split by the boundary.
+        source = " ".repeat(n) + "\u{3BB}[";
+        let mut bytes = source.into_bytes();
+        bytes.push(0xFF);
+        assert_eq!(ranges(OneByte(&bytes)), [
+            format!("1:{}-1:{}: \u{3BB}", n + 1, n + 2), format!("1:{}-1:{}: [", n + 2, n + 3),
+            format!("1:{0}-1:{0}: error: invalid UTF-8 sequence [FF]", n + 3)]);
+    }
+
+    #[test]
+    fn test_invalid_lexeme() {
+        use crate::scanner::Location;
+        // DEL is neither a C0 control (skipped with an error) nor graphic: lexing fails on it.
+        let mut it = RawLexemeIterator::new("\u{7F}".as_bytes());
+        assert_eq!(it.next(), None);
+        let (err, _) = it.into_scanner();
+        let err = err.expect("should fail on an invalid lexeme");
+        assert_eq!(err.location, Location::new());
+        assert_eq!(err.unexpected, Some('\u{7F}'));
+        assert!(err.expected.len() >= 2);
+
+        assert_eq!(crate::testing::assert_lex_fails_at("x \u{7F}", 1, 3), [Identifier("x".into())]);
+    }
+
+    #[test]
+    fn test_diagnostics_in_trivia() {
+        let mut it = RawLexemeIterator::new("x {- {- -} y".as_bytes());
+        assert_eq!(it.next(), Some(Identifier("x".into())));
+        assert_eq!(it.next(), None);
+        let (err, scanner) = it.into_scanner();
+        assert!(err.is_none());
+        let diagnostics: Vec<_> = scanner.diagnostics().iter().map(|d| d.to_string()).collect();
+        assert_eq!(diagnostics, ["1:13-1:13: error: unterminated block comment"]);
+    }
+
+    #[test]
+    fn test_annotated_iterator() {
+        use expect_test::expect;
+        let mut it = AnnotatedLexemeIterator::new("x = 1 -- \u{1}\ny = \"\\1234567\"".as_bytes());
+        let mut res = String::new();
+        for t in it.by_ref() { res += &format!("{}\n", t) }
+        expect![[r#"
+            1:1-1:2: x
+            1:3-1:4: =
+            1:5-1:6: fromIntegral 1
+            1:10-1:11: error: control character '\x01' not allowed in source
+            2:1-2:2: y
+            2:3-2:4: =
+            2:7-2:14: error: character literal out of bound (1234567)
+            2:5-2:15: "�"
+        "#]].assert_eq(&res);
+        let (err, _) = it.into_scanner();
+        assert_eq!(err, None);
+    }
+
+    #[test]
+    fn test_find_top_level() {
+        use super::find_top_level;
+        const SOURCE: &str = indoc! {r#"
+            module Main where
+            -- main = undefined
+            helper = putStrLn "main = 42" >> main' where
+              main = pure ()
+            other = do { main ; main }
+            main :: IO ()
+            main = helper
+        "#};
+        let range = find_top_level(SOURCE.as_bytes(), "main").unwrap();
+        assert_eq!((range.begin.line, range.begin.column), (6, 1));
+        assert_eq!((range.end.line, range.end.column), (6, 5));
+        assert_eq!(find_top_level(SOURCE.as_bytes(), "helper").map(|r| r.begin.line), Some(3));
+        assert_eq!(find_top_level(SOURCE.as_bytes(), "main'"), None);
+        assert_eq!(find_top_level("x = 1\n{- main -}\nmain = x".as_bytes(), "main")
+                       .map(|r| r.begin.line), Some(3));
+        // lexing stops at the declaration: the rest of the input is not even valid.
+        let broken = b"main = pure ()\n\xFF\xFE '";
+        assert_eq!(find_top_level(&broken[..], "main").map(|r| r.begin.line), Some(1));
+    }
+
+    #[test]
+    fn test_split_top_level() {
+        use super::AugmentedLexemeIterator;
+        const SOURCE: &str = indoc! {r#"
+            module Main where
+            main = print (f 1) >> g
+            f x = y + z where
+              y = x
+              z = let { a = y; b = x } in a + b
+            g = do { pure () ; pure () }
+        "#};
+        let groups: Vec<_> = AugmentedLexemeIterator::new(SOURCE.as_bytes()).split_top_level().collect();
+        let heads: Vec<_> = groups.iter().map(|g| (g[0].0.to_string(), g[0].1.begin.line)).collect();
+        assert_eq!(heads, [("main".to_string(), 2), ("f".to_string(), 3), ("g".to_string(), 6)]);
+        // the nested blocks stay in their declaration, with their real braces and semicolons.
+        let f: Vec<_> = groups[1].iter().map(|(x, _)| x.to_string()).collect();
+        assert_eq!(f.join(" "), "f x = y + z where y = x z = let { a = y ; b = x } in a + b");
+        assert_eq!(groups[2].last().map(|(x, _)| x), Some(&CloseCurlyBracket));
+
+        // explicit braces for the module too, with empty declarations, and no module header.
+        let split = |source: &str| -> Vec<usize> {
+            AugmentedLexemeIterator::new(source.as_bytes()).split_top_level().map(|g| g.len()).collect()
+        };
+        assert_eq!(split("module M where { x = 1 ;; y = { } ; }"), [3, 4]);
+        assert_eq!(split("x = 1\ny = 2\n"), [3, 3]);
+        assert_eq!(split(""), Vec::<usize>::new());
+
+        let reals: Vec<_> = AugmentedLexemeIterator::new(SOURCE.as_bytes()).real_tokens().collect();
+        assert_eq!(reals, super::FatLexemeIterator::new(SOURCE.as_bytes()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_resume_layout() {
+        use super::{AugmentedLexemeIterator, FatLexemeIterator};
+        use crate::scanner::Scanner;
+        // the offsets count characters: some take several bytes here.
+        let source = indoc! {"
+            module Main where
+            f λ = do
+              let z = λ
+                  w = z -- « »
+              pure { w }
+            data T = T { a, b :: Int } -- trailing
+            g = \"日本\" where h = 3
+        "};
+        let full: Vec<_> = AugmentedLexemeIterator::new(source.as_bytes()).collect();
+        let mut it = AugmentedLexemeIterator::new(source.as_bytes());
+        let mut states = Vec::new();
+        // a snapshot right after each real lexeme: the phantom ones need reading ahead.
+        for k in 1..=full.len() {
+            assert!(it.next().is_some());
+            let state = it.state();
+            assert_eq!(state.is_some(), matches!(full[k - 1], super::Real(..)), "after {:?}", full[k - 1]);
+            states.extend(state.map(|state| (k, state)));
+        }
+        for (k, state) in states {
+            // and through a round trip, as kept for later.
+            #[cfg(feature = "serde")]
+            let state: super::LayoutState = serde_json::to_string(&state)
+                .and_then(|json| serde_json::from_str(&json)).unwrap();
+            let cut = source.char_indices().nth(state.location.offset).map_or(source.len(), |(n, _)| n);
+            let rest = &source.as_bytes()[cut..];
+            let raw = RawLexemeIterator::from(Scanner::resume_at(rest, state.location));
+            let enriched = EnrichedLexemeIterator::resume(FatLexemeIterator::from(raw), &state);
+            let resumed: Vec<_> = AugmentedLexemeIterator::resume(enriched, &state).collect();
+            assert_eq!(resumed, full[k..], "resumed at {} with {:?}", state.location, state.contexts());
+        }
+    }
+
+    #[test]
+    fn test_bird_tracks() {
+        use crate::error::{DiagnosticMessage, Note};
+        fn lex(source: &str) -> (Vec<String>, Vec<String>) {
+            let mut it = super::FatLexemeIterator::new(source.as_bytes());
+            let lexemes = it.by_ref().map(|(x, _)| x.to_string()).collect();
+            let (err, scanner) = it.into_scanner();
+            assert_eq!(err, None);
+            let notes = scanner.diagnostics().iter()
+                .filter(|d| matches!(d.message(), DiagnosticMessage::Note(Note::BirdTracks)))
+                .map(|d| d.to_string()).collect();
+            (lexemes, notes)
+        }
+        // reported once, the lexemes are the same.
+        let (lexemes, notes) = lex(indoc! {"
+            Some prose.
+
+            > main :: IO ()
+            > main = print x
+            >
+        "});
+        assert_eq!(lexemes, ["Some", "prose", ".", ">", "main", "::", "IO", "(", ")",
+                             ">", "main", "=", "print", "x", ">"]);
+        assert_eq!(notes, ["3:1-3:2: note: this looks like literate Haskell (bird tracks), \
+                            which is not supported: remove the `>` marks first"]);
+        // also decided after enough lines.
+        let source = "> x\n".repeat(30);
+        assert_eq!(lex(&source).1.len(), 1);
+        // a few `>` operators.
+        let (_, notes) = lex(indoc! {"
+            f x y = x
+              > y
+            >>= g
+            > h
+        "});
+        assert!(notes.is_empty());
+    }
+
+    #[test]
+    fn test_literal_spellings() {
+        use super::FatLexemeIterator;
+        // numeric escapes, a gap over two lines, and `\&`.
+        let source = indoc! {r#"
+            s = "\x41\&1 \SOH\
+                \ end" ++ ['\65', '\'', 'A'] ++ "\1234\&5"
+        "#};
+        let mut it = FatLexemeIterator::new(source.as_bytes()).with_literal_spellings();
+        let tokens: Vec<_> = it.by_ref().collect();
+        assert_eq!(tokens[2].0, StringLiteral("A1 \u{1} end".into()));
+        assert_eq!(tokens[5].0, tokens[9].0);
+        // re-emitting the literals as written gives back the source.
+        let chars: Vec<char> = source.chars().collect();
+        let mut output = String::new();
+        let mut last = 0;
+        for (n, (x, range)) in tokens.iter().enumerate() {
+            output.extend(&chars[last..range.begin.offset]);
+            let text: String = chars[range.begin.offset..range.end.offset].iter().collect();
+            match x {
+                CharLiteral(_) | StringLiteral(_) => {
+                    assert_eq!(it.literal_spelling(n), Some(text.as_str()));
+                    output.push_str(it.literal_spelling(n).unwrap());
+                }
+                _ => {
+                    assert_eq!(it.literal_spelling(n), None);
+                    output.push_str(&text);
+                }
+            }
+            last = range.end.offset;
+        }
+        output.extend(&chars[last..]);
+        assert_eq!(output, source);
+        // not kept by default.
+        let mut it = FatLexemeIterator::new(source.as_bytes());
+        assert_eq!(it.nth(2).map(|t| t.0), Some(tokens[2].0.clone()));
+        assert_eq!(it.literal_spelling(2), None);
+    }
+
+    #[test]
+    fn test_lex_fragment() {
+        use super::{lex_fragment, AugmentedLexemeIterator, AugmentedLexeme, FragmentKind};
+        // the real lexemes without their ranges, the phantom ones with their block ids.
+        fn shapes(lexemes: impl IntoIterator<Item=AugmentedLexeme>) -> Vec<String> {
+            lexemes.into_iter().filter_map(|t| match t {
+                AugmentedLexeme::Real(x, _) => Some(x.to_string()),
+                AugmentedLexeme::Eof(_) => None,
+                t => Some(format!("{:#}", t)),
+            }).collect()
+        }
+        let lex = |input, kind| {
+            let (lexemes, diagnostics) = lex_fragment(input, kind).unwrap();
+            assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+            shapes(lexemes)
+        };
+        let module = shapes(AugmentedLexemeIterator::new("main = do\n  x <- f\n  g x\n".as_bytes()));
+        let expression = lex("do\n  x <- f\n  g x", FragmentKind::Expression);
+        assert_eq!(expression, module[3..module.len() - 1]);
+        assert_eq!(expression.join(" "), "do <phantom>: { #1 x <- f <phantom>: ; #1 g x <phantom>: } #1");
+        // no `;` between the lines of an expression, only within its blocks.
+        assert_eq!(lex("f\n  x\ny", FragmentKind::Expression), ["f", "x", "y"]);
+        assert_eq!(lex("f x = y\n  where y = x", FragmentKind::Declaration).join(" "),
+                   "<phantom>: { #0 f x = y where <phantom>: { #1 y = x <phantom>: } #1 <phantom>: } #0");
+        let body = FragmentKind::ModuleBody { enclosing_indent: 3 };
+        assert_eq!(lex("  f = a\n  g = do\n    a", body).join(" "),
+                   "f = a <phantom>: ; #0 g = do <phantom>: { #1 a <phantom>: } #1");
+        assert_eq!(lex("", FragmentKind::Declaration), ["<phantom>: { #0", "<phantom>: } #0"]);
+        assert!(lex_fragment("x = '", FragmentKind::Expression).is_err());
+        // the diagnostics come along.
+        let diagnostics = |input, kind| -> Vec<String> {
+            lex_fragment(input, kind).unwrap().1.iter().map(|d| d.to_string()).collect()
+        };
+        assert_eq!(diagnostics("x {- oops", FragmentKind::Expression),
+                   ["1:10-1:10: error: unterminated block comment"]);
+        assert_eq!(diagnostics(r#"x = "a\qb""#, FragmentKind::Declaration),
+                   [r"1:7-1:9: error: unknown escape sequence `\q`"]);
+        assert_eq!(diagnostics(r"'\1114112'", FragmentKind::Expression),
+                   ["1:3-1:10: error: character literal out of bound (1114112)"]);
+        // leaving the enclosing context is an error, and so is the stray `}` closing it.
+        assert_eq!(diagnostics("  f = a\ng = b\n  h = c", body),
+                   ["2:1-2:2: error: indented less than the enclosing layout context of the fragment"]);
+        let (lexemes, _) = lex_fragment("  f = a\ng = b", body).unwrap();
+        assert_eq!(shapes(lexemes).join(" "), "f = a g = b");
+        assert_eq!(diagnostics("  f = a }", body), ["1:9-1:10: error: unmatched `}`"]);
+    }
 }
@@ -0,0 +1,202 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2020  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Opt-in lints over the fat token stream, for code that lexes fine but is likely a mistake.
+
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use super::{Range, layout::FatLexemeIterator};
+use crate::lexeme::Lexeme;
+use crate::error::{Diagnostic, DiagnosticMessage, Warning};
+use crate::utils::normalization::{nfc, is_nfc};
+
+/// A token for the lints: a lexeme with its range, and what comes right before it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LintToken {
+    /// the lexeme.
+    pub lexeme: Lexeme,
+    /// the source range of the lexeme.
+    pub range: Range,
+    /// whether the trivia before this token has a comment (or a pragma) in it.
+    pub after_comment: bool,
+    /// the number of blank lines in the trivia before this token, see
+    /// [`Scanner::blank_lines`](super::Scanner::blank_lines).
+    pub blank_lines_before: u32,
+}
+
+/// Lex the source into tokens for the lints, stopping at the first lexical error.
+pub fn tokens(source: &str) -> Vec<LintToken> {
+    // the input layer strips the BOM, and locations count characters only.
+    let body = source.strip_prefix('\u{FEFF}').unwrap_or(source);
+    let indices: Vec<usize> = body.char_indices().map(|(k, _)| k)
+        .chain(std::iter::once(body.len())).collect();
+    let mut last_end = 0;
+    let mut it = FatLexemeIterator::new(body.as_bytes());
+    let mut res = Vec::new();
+    while let Some((lexeme, range)) = it.next() {
+        // trivia is only whitespaces and comments: anything else in it is a comment.
+        let trivia = &body[indices[last_end]..indices[range.begin.offset]];
+        last_end = range.end.offset;
+        let after_comment = trivia.chars().any(|c| !c.is_whitespace());
+        res.push(LintToken { lexeme, range, after_comment, blank_lines_before: it.blank_lines_before() })
+    }
+    res
+}
+
+/// A lint, run on every window of consecutive tokens.
+pub trait TokenLint {
+    /// The number of tokens in each window, 2 by default.
+    fn window(&self) -> NonZeroUsize { NonZeroUsize::new(2).unwrap() }
+    /// Check a window of consecutive tokens, and push the diagnostics to `sink`.
+    fn check(&mut self, window: &[LintToken], sink: &mut Vec<Diagnostic>);
+}
+
+/// Run one lint on the tokens.
+pub fn run(lint: &mut dyn TokenLint, tokens: &[LintToken]) -> Vec<Diagnostic> {
+    let mut sink = Vec::new();
+    tokens.windows(lint.window().get()).for_each(|w| lint.check(w, &mut sink));
+    sink
+}
+
+/// Run all the lints (with their default configurations) on the tokens, in source order.
+pub fn run_all(tokens: &[LintToken]) -> Vec<Diagnostic> {
+    let lints: Vec<Box<dyn TokenLint>> = vec![
+        Box::new(AdjacentStringLiterals::default()),
+        Box::new(IdentifierNormalization::default()),
+    ];
+    let mut res: Vec<_> = lints.into_iter().flat_map(|mut lint| run(lint.as_mut(), tokens)).collect();
+    res.sort_by_key(|d| d.range().begin.offset);
+    res
+}
+
+/// Two string literals with only whitespace in between, usually a missing `++` or `<>`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct AdjacentStringLiterals {
+    /// also warn when there is a comment between the two literals.
+    pub across_comments: bool,
+}
+
+impl TokenLint for AdjacentStringLiterals {
+    fn check(&mut self, window: &[LintToken], sink: &mut Vec<Diagnostic>) {
+        if let [first, second] = window {
+            if !matches!((&first.lexeme, &second.lexeme),
+                         (Lexeme::StringLiteral(_), Lexeme::StringLiteral(_))) { return; }
+            if second.after_comment && !self.across_comments { return; }
+            let gap = Range { begin: first.range.end, end: second.range.begin };
+            sink.push(Diagnostic::new(gap, DiagnosticMessage::Warning(Warning::AdjacentStringLiterals))
+                .with_label(first.range, "first string literal")
+                .with_label(second.range, "second string literal"));
+        }
+    }
+}
+
+/// Warn about adjacent string literals, see [`AdjacentStringLiterals`].
+pub fn adjacent_string_literals(tokens: &[LintToken]) -> Vec<Diagnostic> {
+    run(&mut AdjacentStringLiterals::default(), tokens)
+}
+
+/// Identifiers with the same NFC form but different code points, e.g. `café` written with a
+/// precomposed `é` in one place, and with `e` and a combining accent in another.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct IdentifierNormalization {
+    // the NFC forms seen so far, with the first identifier (and its range) of each.
+    seen: HashMap<String, (String, Range)>,
+}
+
+impl TokenLint for IdentifierNormalization {
+    fn window(&self) -> NonZeroUsize { NonZeroUsize::new(1).unwrap() }
+
+    fn check(&mut self, window: &[LintToken], sink: &mut Vec<Diagnostic>) {
+        let token = &window[0];
+        let name = match &token.lexeme {
+            Lexeme::Identifier(name) => name.to_string(),
+            Lexeme::QIdentifier(name) => name.to_string(),
+            _ => return,
+        };
+        let normalized = if is_nfc(&name) { name.clone() } else { nfc(&name) };
+        match self.seen.get(&normalized) {
+            Some((first, _)) if *first == name => (),
+            Some((_, range)) => sink.push(Diagnostic::new(
+                token.range, DiagnosticMessage::Warning(Warning::IdentifierNormalization(name)))
+                .with_label(*range, "first written here")
+                .with_label(token.range, "then with other code points here")),
+            None => { self.seen.insert(normalized, (name, token.range)); }
+        }
+    }
+}
+
+/// Warn about identifiers differing only in Unicode normalization, see
+/// [`IdentifierNormalization`].
+pub fn identifier_normalization(tokens: &[LintToken]) -> Vec<Diagnostic> {
+    run(&mut IdentifierNormalization::default(), tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(diagnostics: &[Diagnostic]) -> Vec<String> {
+        diagnostics.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn test_adjacent_string_literals() {
+        let tokens = tokens("x = [\"foo\"\n     \"bar\", \"baz\" <> \"qux\"]\n");
+        let diagnostics = adjacent_string_literals(&tokens);
+        assert_eq!(messages(&diagnostics), [
+            "1:11-2:6: warning: adjacent string literals, missing `++` or `<>`?"]);
+        let labels: Vec<_> = diagnostics[0].labels().iter().map(|l| l.range).collect();
+        assert_eq!(labels, [tokens[3].range, tokens[4].range]);
+        assert_eq!(messages(&run_all(&tokens)), messages(&diagnostics));
+    }
+
+    #[test]
+    fn test_adjacent_string_literals_across_comments() {
+        let tokens = tokens("x = \"foo\" {- becomes -} \"bar\"\n");
+        assert!(adjacent_string_literals(&tokens).is_empty());
+        let mut lint = AdjacentStringLiterals { across_comments: true };
+        assert_eq!(messages(&run(&mut lint, &tokens)), [
+            "1:10-1:25: warning: adjacent string literals, missing `++` or `<>`?"]);
+    }
+
+    #[test]
+    fn test_identifier_normalization() {
+        // the Angstrom sign and the Kelvin sign are both NFC-normalized to Latin letters.
+        let tokens = tokens("\u{C5}x = M.K\n\u{212B}x = M.\u{212A} \u{C5}x\n");
+        let diagnostics = identifier_normalization(&tokens);
+        assert_eq!(messages(&diagnostics), [
+            "2:1-2:3: warning: identifier `\u{212B}x` differs from an earlier one only in Unicode normalization",
+            "2:6-2:9: warning: identifier `M.\u{212A}` differs from an earlier one only in Unicode normalization",
+        ]);
+        let labels: Vec<_> = diagnostics[0].labels().iter().map(|l| l.range).collect();
+        assert_eq!(labels, [tokens[0].range, tokens[3].range]);
+        assert!(identifier_normalization(&super::tokens("caf\u{E9} = caf\u{E9}\n")).is_empty());
+    }
+
+    #[test]
+    fn test_blank_lines_before() {
+        let source = "\n\na = 1\nb = 2\n\nc = 3\n  \n\t\nd = 4\n\n-- | e\n\n\ne = 5 {-\n\n-}\nf = 6";
+        let counts: Vec<_> = tokens(source).iter()
+            .filter(|t| t.range.begin.column == 1).map(|t| t.blank_lines_before).collect();
+        // the comment lines are not blank, but do not reset the count either.
+        assert_eq!(counts, [2, 0, 1, 2, 3, 0]);
+        // a string gap is not trivia.
+        let tokens = tokens("s = \"a\\\n\n   \\b\"\nt = s");
+        assert!(tokens.iter().all(|t| t.blank_lines_before == 0));
+    }
+}
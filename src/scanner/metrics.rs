@@ -0,0 +1,135 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2020  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Lexing metrics, for tracking the performance of the lexer across commits.
+
+use std::time::{Duration, Instant};
+
+use super::Scanner;
+use super::layout::{
+    RawLexemeIterator,
+    FatLexemeIterator,
+    EnrichedLexemeIterator,
+    AugmentedLexemeIterator,
+};
+
+/// Token counts, for each flavour of the lexer output.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FlavourCounts {
+    /// Lexemes from [`RawLexemeIterator`].
+    pub raw: usize,
+    /// Lexemes from [`FatLexemeIterator`].
+    pub fat: usize,
+    /// Lexemes from [`EnrichedLexemeIterator`], layout annotations included.
+    pub enriched: usize,
+    /// Lexemes from [`AugmentedLexemeIterator`], virtual braces and semicolons included.
+    pub augmented: usize,
+}
+
+/// Metrics on lexing an input in all the flavours.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LexMetrics {
+    /// Size of the input, in bytes.
+    pub input_bytes: usize,
+    /// Lines in the input, a last line without a line break included.
+    pub input_lines: usize,
+    /// Token counts, for each flavour.
+    pub tokens: FlavourCounts,
+    /// Diagnostics reported in the augmented flavour, which runs all the passes.
+    pub diagnostics: usize,
+    /// Wall-clock time to lex in all the flavours.
+    pub elapsed: Duration,
+    /// The maximum number of input segments buffered at the same time, in any flavour.
+    pub peak_segments: usize,
+}
+
+impl LexMetrics {
+    /// Lex `input` in all the flavours, and collect the metrics.
+    pub fn collect(input: &[u8]) -> Self {
+        let mut res = LexMetrics {
+            input_bytes: input.len(),
+            input_lines: count_lines(input),
+            ..LexMetrics::default()
+        };
+        let start = Instant::now();
+        let mut it = RawLexemeIterator::new(input);
+        res.tokens.raw = it.by_ref().count();
+        res.record(&it.into_scanner().1);
+        let mut it = FatLexemeIterator::new(input);
+        res.tokens.fat = it.by_ref().count();
+        res.record(&it.into_scanner().1);
+        let mut it = EnrichedLexemeIterator::new(input);
+        res.tokens.enriched = it.by_ref().count();
+        res.record(&it.into_scanner().1);
+        let mut it = AugmentedLexemeIterator::new(input);
+        res.tokens.augmented = it.by_ref().count();
+        let scanner = it.into_scanner().1;
+        res.record(&scanner);
+        res.diagnostics = scanner.diagnostics().iter().count();
+        res.elapsed = start.elapsed();
+        res
+    }
+
+    fn record<I>(&mut self, scanner: &Scanner<I>) {
+        self.peak_segments = self.peak_segments.max(scanner.input_stats().peak_live_segments());
+    }
+}
+
+// newline -> return linefeed | return | linefeed | formfeed
+fn count_lines(input: &[u8]) -> usize {
+    let mut lines = 0;
+    let mut at_line_start = true;
+    for (k, &b) in input.iter().enumerate() {
+        at_line_start = match b {
+            b'\r' if input.get(k + 1) == Some(&b'\n') => continue,
+            b'\r' | b'\n' | b'\x0C' => {
+                lines += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+    if at_line_start { lines } else { lines + 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LexMetrics, FlavourCounts};
+    use std::time::Duration;
+
+    #[test]
+    fn test_lex_metrics() {
+        let mut src = String::from("module Main where\n");
+        for k in 0..500 { src += &format!("x{} = do\n  print {}\r\n", k, k) }
+        src += "{- unterminated";
+        let metrics = LexMetrics::collect(src.as_bytes());
+        assert_eq!(LexMetrics { elapsed: Duration::ZERO, ..metrics }, LexMetrics {
+            input_bytes: src.len(),
+            input_lines: 1002,
+            tokens: FlavourCounts { raw: 2503, fat: 2503, enriched: 3504, augmented: 4005 },
+            diagnostics: 1,
+            elapsed: Duration::ZERO,
+            // 3 segments of 4 KiB in all, but at most 2 alive, when a lexeme crosses them.
+            peak_segments: 2,
+        });
+        assert_eq!(super::count_lines(b""), 0);
+        assert_eq!(super::count_lines(b"a\r\n\rb"), 3);
+    }
+}
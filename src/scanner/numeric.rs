@@ -20,6 +20,7 @@
 
 use super::{Scanner, Result, basic::*};
 
+use std::convert::TryFrom;
 use num_bigint::BigInt;
 use num_traits::{identities::Zero, ToPrimitive, Signed};
 
@@ -28,10 +29,11 @@ use crate::lexeme::{Rational, Lexeme};
 use crate::lexeme::Lexeme::{Integer, Float};
 use crate::error::Diagnostic;
 use crate::error::DiagnosticMessage::Error;
-use crate::error::Error::FloatOutOfBound;
-use crate::scanner::Location;
+use crate::error::Error::{FloatOutOfBound, MisplacedDigitSeparator};
+use crate::scanner::{Location, Range};
 
-/// Maximum allowed exponent in a floating number.
+/// Maximum allowed exponent in a floating number, by default: see
+/// [`ScannerConfig::max_float_exponent`](super::ScannerConfig::max_float_exponent).
 pub const MAXIMUM_EXPONENT: i64 = 4096;
 
 impl<I: std::io::Read> Scanner<I> {
@@ -41,25 +43,54 @@ impl<I: std::io::Read> Scanner<I> {
         Self::keep_trying()
     }
 
-    pub(super) fn app_int(base: u32) -> impl Fn(&mut BigInt, char) {
-        move |r, x| {
-            *r *= base;
-            *r += x.to_digit(base).unwrap()
+    // digits in `base` continuing `x`, and their number (underscores not counted): under
+    // `NumericUnderscores`, with underscores between them, or misplaced right after a base
+    // prefix (if `prefixed`) or at the end.
+    fn digits<D: Digits>(&mut self, digit: impl CharPredicate, base: u32,
+                         prefixed: bool, mut x: D) -> Option<(usize, D)> {
+        let underscores = self.extensions.numeric_underscores;
+        let mut n = 0;
+        // where the underscores since the last digit begin.
+        let mut separator = None;
+        loop {
+            match self.peek() {
+                Some(c) if digit.check(c) => {
+                    if let (0, Some(begin)) = (n, separator) { self.misplaced_separator(begin) }
+                    separator = None;
+                    x.push_digit(base, c);
+                    n += 1;
+                }
+                Some('_') if underscores && (n > 0 || prefixed) => {
+                    separator.get_or_insert(self.location);
+                }
+                _ => break,
+            }
+            self.next();
         }
+        if n == 0 { return None; }
+        if let Some(begin) = separator { self.misplaced_separator(begin) }
+        Some((n, x))
+    }
+
+    // the underscores from `begin` to here.
+    fn misplaced_separator(&mut self, begin: Location) {
+        Diagnostic::new(Range { begin, end: self.location }, Error(MisplacedDigitSeparator))
+            .report(&mut self.diagnostics)
     }
 
-    fn decimal_cont(&mut self, x: BigInt) -> Option<(usize, BigInt)> {
+    fn decimal_cont<D: Digits>(&mut self, x: D) -> Option<(usize, D)> {
         // decimal      -> digit{digit}
-        let cont = |(n, d): &mut (usize, BigInt), c: char| {
-            Self::app_int(10)(d, c);
-            *n += 1
-        };
-        analyse!(self, d: {(0, x)}{cont} +Digit);
-        Some(d)
+        self.digits(Digit, 10, false, x)
     }
 
-    fn decimal(&mut self) -> Option<BigInt> {
-        self.decimal_cont(BigInt::from(0)).map(|(_, x)| x)
+    // the value of a literal, or `None` without the payloads, see `Scanner::next_lexeme_type`.
+    fn value(&self) -> Option<BigInt> {
+        if self.payloads { Some(BigInt::zero()) } else { None }
+    }
+
+    fn decimal(&mut self) -> Option<Option<BigInt>> {
+        let x = self.value();
+        self.decimal_cont(x).map(|(_, x)| x)
     }
 
     fn integer(&mut self) -> Option<Lexeme> {
@@ -68,26 +99,45 @@ impl<I: std::io::Read> Scanner<I> {
         // integer      -> decimal
         //               | 0o octal | 0O octal
         //               | 0x hexadecimal | 0X hexadecimal
-        simple_alt!(self,
-            choice!(d; '0', "oO", d: {BigInt::from(0)}{Self::app_int(8)} +Octit),
-            choice!(d; '0', "xX", d: {BigInt::from(0)}{Self::app_int(16)} +Hexit),
-            Self::decimal).map(Integer)
+        simple_alt!(self, Self::octal, Self::hexadecimal, Self::decimal)
+            .map(|x: Option<BigInt>| Integer(x.unwrap_or_default()))
+    }
+
+    fn octal(&mut self) -> Option<Option<BigInt>> {
+        analyse!(self, '0', "oO");
+        let x = self.value();
+        self.digits(Octit, 8, true, x).map(|(_, x)| x)
     }
 
-    fn make_float(&mut self, d: BigInt, n: usize, mut exp: BigInt,
+    fn hexadecimal(&mut self) -> Option<Option<BigInt>> {
+        analyse!(self, '0', "xX");
+        let x = self.value();
+        self.digits(Hexit, 16, true, x).map(|(_, x)| x)
+    }
+
+    fn make_float(&mut self, d: Option<BigInt>, n: usize, exp: CompactInt,
                   start_loc: Location) -> Option<Rational> {
-        exp -= n;
-        Some(match exp.to_i64() {
-            Some(x) if (0..=MAXIMUM_EXPONENT).contains(&x) =>
-                Rational::from(d * BigInt::from(10).pow(x as u32)),
-            Some(x) if (-MAXIMUM_EXPONENT..0).contains(&x) =>
-                Rational::new(d, BigInt::from(10).pow((-x) as u32)),
-            _ => {
-                let signum = exp.signum();
-                Diagnostic::new(self.location, Error(FloatOutOfBound(exp)))
-                    .within(start_loc, self.location)
+        let exp = exp.sub(n);
+        // powers of 10 take an `u32` exponent.
+        let max = self.max_float_exponent.clamp(0, i64::from(u32::MAX));
+        Some(match (exp.to_i64().filter(|x| (-max..=max).contains(x)), d) {
+            (Some(x), Some(d)) if x >= 0 => Rational::from(d * BigInt::from(10).pow(x as u32)),
+            (Some(x), Some(d)) => Rational::new(d, BigInt::from(10).pow((-x) as u32)),
+            // without the payloads: a placeholder, only its type is kept.
+            (Some(_), None) => Rational::new(BigInt::zero(), BigInt::zero()),
+            (None, d) => {
+                let d = d.unwrap_or_default();
+                let exp = exp.into_big();
+                // too large: an infinity (unless zero); too small: zero.
+                let res = if exp.is_positive() && !d.is_zero() {
+                    Rational::new(d.signum(), BigInt::zero())
+                } else {
+                    Rational::from(BigInt::zero())
+                };
+                Diagnostic::new(Range { begin: start_loc, end: self.location },
+                                Error(FloatOutOfBound(exp)))
                     .report(&mut self.diagnostics);
-                Rational::new(signum, BigInt::zero())
+                res
             }
         })
     }
@@ -97,8 +147,9 @@ impl<I: std::io::Read> Scanner<I> {
         // float    -> decimal . decimal [exponent]
         let d = self.decimal()?;
         analyse!(self, '.');
+        // digits required after the dot: `1..`, `1.e5` fail here, and get reverted to `1`.
         let (n, d) = self.decimal_cont(d)?;
-        let exp = self.exponent().unwrap_or_else(BigInt::zero);
+        let exp = self.anchored(Self::exponent).unwrap_or_default();
         self.make_float(d, n, exp, start_loc)
     }
 
@@ -114,18 +165,96 @@ impl<I: std::io::Read> Scanner<I> {
         simple_alt!(self, Self::float1, Self::float2).map(Float)
     }
 
-    fn exponent(&mut self) -> Option<BigInt> {
+    fn exponent(&mut self) -> Option<CompactInt> {
         // exponent -> (e | E) [+ | -] decimal
         analyse!(self, "eE");
         let sign = self.anchored(choice!(c; c: "+-")).unwrap_or('+');
-        self.decimal().map(|x| if sign == '+' { x } else { -x })
+        let (_, x) = self.decimal_cont(CompactInt::default())?;
+        Some(if sign == '+' { x } else { x.neg() })
+    }
+}
+
+/// What the digits of a numeric literal are accumulated into.
+pub(super) trait Digits {
+    /// Append a digit in `base`.
+    fn push_digit(&mut self, base: u32, c: char);
+}
+
+impl Digits for BigInt {
+    fn push_digit(&mut self, base: u32, c: char) {
+        *self *= base;
+        *self += c.to_digit(base).unwrap()
+    }
+}
+
+/// `None` discards the digits.
+impl<D: Digits> Digits for Option<D> {
+    fn push_digit(&mut self, base: u32, c: char) {
+        if let Some(x) = self { x.push_digit(base, c) }
+    }
+}
+
+/// An integer in an `i64` while it fits, and in a [`BigInt`] only beyond: exponents and
+/// character codes are checked against small bounds, but reported exactly when out of them.
+#[derive(Debug)]
+pub(super) enum CompactInt {
+    Small(i64),
+    Big(BigInt),
+}
+
+impl Default for CompactInt {
+    fn default() -> Self { CompactInt::Small(0) }
+}
+
+impl CompactInt {
+    /// The value, if it fits in an `i64`.
+    pub(super) fn to_i64(&self) -> Option<i64> {
+        match self {
+            CompactInt::Small(x) => Some(*x),
+            CompactInt::Big(x) => x.to_i64(),
+        }
+    }
+
+    /// The value as a [`BigInt`].
+    pub(super) fn into_big(self) -> BigInt {
+        match self {
+            CompactInt::Small(x) => BigInt::from(x),
+            CompactInt::Big(x) => x,
+        }
+    }
+
+    fn neg(self) -> Self {
+        match self {
+            CompactInt::Small(x) => CompactInt::Small(-x),
+            CompactInt::Big(x) => CompactInt::Big(-x),
+        }
+    }
+
+    fn sub(self, n: usize) -> Self {
+        if let (CompactInt::Small(x), Ok(n)) = (&self, i64::try_from(n)) {
+            if let Some(x) = x.checked_sub(n) { return CompactInt::Small(x); }
+        }
+        CompactInt::Big(self.into_big() - n)
+    }
+}
+
+impl Digits for CompactInt {
+    fn push_digit(&mut self, base: u32, c: char) {
+        let d = c.to_digit(base).unwrap();
+        if let CompactInt::Small(x) = self {
+            match x.checked_mul(i64::from(base)).and_then(|x| x.checked_add(i64::from(d))) {
+                Some(y) => *x = y,
+                None => *self = CompactInt::Big(BigInt::from(*x)),
+            }
+        }
+        if let CompactInt::Big(x) = self { x.push_digit(base, c) }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use num_bigint::BigInt;
-    use crate::scanner::test_scanner_on;
+    use crate::testing::test_scanner_on;
     use crate::utils::setup_logger;
     use crate::utils::Result3::Success;
     use crate::lexeme::Lexeme::{self, Integer, Float};
@@ -145,5 +274,116 @@ mod tests {
         test("1.5e4", Float(Rational::from(BigInt::from(15000))));
         test("1.5e+3", Float(Rational::from(BigInt::from(1500))));
         test("1.5e-2", Float(Rational::new(15, 1000)));
+        // no exponent: stop right after the fraction.
+        test_scanner_on("1.5\n", method!(numeric_literal),
+                        Success(Float(Rational::new(3, 2))), Some('\n'));
+    }
+
+    #[test]
+    fn test_float_out_of_bound() {
+        use crate::scanner::Scanner;
+        fn test(input: &str, check: impl Fn(&Rational) -> bool) {
+            let mut scanner = Scanner::new(input.as_bytes());
+            match scanner.numeric_literal() {
+                Success(Float(q)) => assert!(check(&q), "{}: {}", input, q),
+                res => panic!("{}: {:?}", input, res),
+            }
+            assert_eq!(scanner.diagnostics().len(), 1);
+        }
+        test("1.0e99999", |q| q.is_infinite() && q.to_f64_lossy() == f64::INFINITY);
+        test("1.0e-99999", |q| *q == Rational::from(BigInt::from(0)));
+        test("0.0e99999", |q| *q == Rational::from(BigInt::from(0)));
+    }
+
+    #[test]
+    fn test_huge_exponent_diagnostic() {
+        use crate::scanner::Scanner;
+        let input = format!("1e{}", "1234567890".repeat(1000));
+        let mut scanner = Scanner::new(input.as_bytes());
+        assert!(matches!(scanner.numeric_literal(), Success(Float(q)) if q.is_infinite()));
+        let diagnostics: Vec<_> = scanner.diagnostics().iter().map(|d| d.to_string()).collect();
+        assert_eq!(diagnostics, [
+            "1:1-1:10003: error: float literal out of bound (exponent 1234567890…1234567890 (10000 digits))",
+        ]);
+    }
+
+    #[test]
+    fn test_max_float_exponent() {
+        use crate::scanner::{Scanner, ScannerConfig};
+        let lex = |config: ScannerConfig| {
+            let mut scanner = Scanner::new(&b"1.0e5000"[..]).with_config(config);
+            let res = scanner.numeric_literal();
+            (res, scanner.diagnostics().len())
+        };
+        let raised = ScannerConfig { max_float_exponent: 8192, ..ScannerConfig::default() };
+        let expected = Float(Rational::from(BigInt::from(10).pow(5000u32)));
+        assert_eq!(lex(raised), (Success(expected), 0));
+        match lex(ScannerConfig::default()) {
+            (Success(Float(q)), 1) => assert!(q.is_infinite()),
+            res => panic!("{:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_numeric_dot_adjacency() {
+        use crate::scanner::layout::FatLexemeIterator;
+        fn test(input: &str, res: &[&str]) {
+            let mut it = FatLexemeIterator::new(input.as_bytes());
+            let actual: Vec<_> = it.by_ref().map(|(x, r)| {
+                format!("{:?} {}", x.get_type(), &input[r.begin.offset..r.end.offset])
+            }).collect();
+            assert_eq!(actual, res, "{:?}", input);
+            assert!(it.into_scanner().0.is_none(), "{:?}", input);
+        }
+        test("1..10", &["Integer 1", "ReservedOp ..", "Integer 10"]);
+        test("[1..]", &["OpenSquareBracket [", "Integer 1", "ReservedOp ..", "CloseSquareBracket ]"]);
+        test("1.", &["Integer 1", "Operator ."]);
+        // the Report requires digits after the dot: `1.e5` is not a float.
+        test("1.e5", &["Integer 1", "Operator .", "Identifier e5"]);
+        test("1.5e", &["Float 1.5", "Identifier e"]);
+        test("1.5.6", &["Float 1.5", "Operator .", "Integer 6"]);
+        test("1e5.5", &["Float 1e5", "Operator .", "Integer 5"]);
+        test("x.y", &["Identifier x", "Operator .", "Identifier y"]);
+        test("x.1", &["Identifier x", "Operator .", "Integer 1"]);
+    }
+
+    #[test]
+    fn test_numeric_underscores() {
+        use crate::scanner::{Scanner, GhcExtensions};
+        use crate::scanner::layout::{RawLexemeIterator, FatLexemeIterator};
+        fn test(input: &str, enabled: bool, res: &[&str], diagnostics: &[&str]) {
+            let extensions = GhcExtensions { numeric_underscores: enabled, ..GhcExtensions::default() };
+            let scanner = Scanner::new(input.as_bytes()).with_extensions(extensions);
+            let mut it = FatLexemeIterator::from(RawLexemeIterator::from(scanner));
+            let actual: Vec<_> = it.by_ref().map(|(x, r)| match x {
+                Integer(n) => format!("Integer {} {}", n, &input[r.begin.offset..r.end.offset]),
+                x => format!("{:?} {}", x.get_type(), &input[r.begin.offset..r.end.offset]),
+            }).collect();
+            assert_eq!(actual, res, "{:?}", input);
+            let (err, scanner) = it.into_scanner();
+            assert!(err.is_none(), "{:?}", input);
+            let actual: Vec<_> = scanner.diagnostics().iter().map(|d| d.to_string()).collect();
+            assert_eq!(actual, diagnostics, "{:?}", input);
+        }
+        const MISPLACED: &str = "error: misplaced digit separator '_', only allowed between digits";
+        test("1_000", true, &["Integer 1000 1_000"], &[]);
+        test("1__000", true, &["Integer 1000 1__000"], &[]);
+        test("1_000", false, &["Integer 1 1", "Identifier _000"], &[]);
+        test("0xff_ff", true, &["Integer 65535 0xff_ff"], &[]);
+        test("0o7_7", true, &["Integer 63 0o7_7"], &[]);
+        test("1_000.000_1e1_0", true, &["Float 1_000.000_1e1_0"], &[]);
+        // misplaced, but still in the literal: no silent splitting.
+        test("0x_ff", true, &["Integer 255 0x_ff"], &[&format!("1:3-1:4: {}", MISPLACED)]);
+        test("1_", true, &["Integer 1 1_"], &[&format!("1:2-1:3: {}", MISPLACED)]);
+        test("1_000__ x", true, &["Integer 1000 1_000__", "Identifier x"],
+             &[&format!("1:6-1:8: {}", MISPLACED)]);
+        // not numeric literals at all, or not from here on.
+        test("_1", true, &["Identifier _1"], &[]);
+        test("x1_000", true, &["Identifier x1_000"], &[]);
+        test("0x_", true, &["Integer 0 0", "Identifier x_"], &[]);
+        test("1e_5", true, &["Integer 1 1", "Identifier e_5"], &[]);
+        test("1._5", true, &["Integer 1 1", "Operator .", "Identifier _5"], &[]);
+        // `_foo` is a varid: qualified as any other.
+        test("M._foo", true, &["QIdentifier M._foo"], &[]);
     }
 }
@@ -0,0 +1,186 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Lexing a whole string as exactly one lexeme, for quick syntax checks.
+//!
+//! ```
+//! # use mini_haskell::scanner::single::{parse_identifier, parse_operator};
+//! assert!(parse_identifier("Data.Map.lookup").is_ok());
+//! assert!(parse_identifier("F.").is_err());
+//! assert!(parse_operator(">>=").is_ok());
+//! ```
+//!
+//! Recoverable problems (e.g. an out-of-bound character escape) are reported as diagnostics by
+//! the scanner, and do not fail these functions.
+
+use super::{Scanner, Result, LexError, Location};
+use crate::utils::Result3::{Success, FailFast, RetryLater};
+use crate::utils::char::Stream;
+use crate::lexeme::{Lexeme, LexemeType};
+
+type Rule<'s> = fn(&mut Scanner<&'s [u8]>) -> Result<Lexeme>;
+
+fn rule_for<'s>(t: LexemeType) -> Rule<'s> {
+    use LexemeType::*;
+    match t {
+        Integer | Float => Scanner::numeric_literal,
+        Identifier | Operator | QIdentifier | QOperator | QualifiedPrefix |
+        ReservedId | ReservedOp =>
+            Scanner::id_or_sym,
+        CharLiteral | StringLiteral => Scanner::char_or_string,
+        Comma | Semicolon | Backtick | OpenCurlyBracket | CloseCurlyBracket |
+        OpenParenthesis | CloseParenthesis | OpenSquareBracket | CloseSquareBracket =>
+            Scanner::special,
+        MagicHash | OpenUnboxedParenthesis | CloseUnboxedParenthesis => |scanner| {
+            scanner.extensions.magic_hash = true;
+            scanner.lexeme()
+        },
+        Whitespace => |scanner| match scanner.whitespace() {
+            Success(()) => Success(Lexeme::Whitespace),
+            FailFast(err) => FailFast(err),
+            RetryLater(()) => RetryLater(()),
+        },
+    }
+}
+
+/// Lex all of `s` with `rule`, and accept the lexeme if its type is any of `accepted`.
+/// Without `accepted`, any lexeme would do.
+fn parse_with<'s>(rule: Rule<'s>, accepted: Option<&[LexemeType]>, s: &'s str)
+                  -> std::result::Result<Lexeme, LexError> {
+    let expected = accepted.unwrap_or(Scanner::<&[u8]>::LEXEME_STARTS);
+    let mut scanner = Scanner::new(s.as_bytes());
+    let lexeme = match rule(&mut scanner) {
+        Success(lexeme) => lexeme,
+        FailFast(err) => return Err(err),
+        RetryLater(()) => return Err(scanner.err_expected_any(expected)),
+    };
+    if accepted.is_some_and(|ts| !ts.contains(&lexeme.get_type())) {
+        return Err(LexError {
+            expected: expected.to_vec(),
+            unexpected: s.chars().next(),
+            location: Location::new(),
+            invalid_utf8: None,
+        });
+    }
+    match scanner.peek() {
+        // no expected lexeme types: the input should have ended here.
+        Some(_) => Err(scanner.err_expected_any(&[])),
+        None => Ok(lexeme),
+    }
+}
+
+/// Lex all of `s` as exactly one lexeme, of type `kind_hint` if specified.
+///
+/// Trailing characters are an error, located at the first unconsumed one.
+pub fn parse_single(kind_hint: Option<LexemeType>, s: &str)
+                    -> std::result::Result<Lexeme, LexError> {
+    match kind_hint {
+        Some(t) => parse_with(rule_for(t), Some(&[t]), s),
+        None => parse_with(Scanner::next_lexeme, None, s),
+    }
+}
+
+/// Lex all of `s` as a (possibly qualified) identifier, reserved words excluded.
+pub fn parse_identifier(s: &str) -> std::result::Result<Lexeme, LexError> {
+    parse_with(Scanner::id_or_sym, Some(&[LexemeType::Identifier, LexemeType::QIdentifier]), s)
+}
+
+/// Lex all of `s` as a (possibly qualified) operator, reserved operators excluded.
+pub fn parse_operator(s: &str) -> std::result::Result<Lexeme, LexError> {
+    parse_with(Scanner::id_or_sym, Some(&[LexemeType::Operator, LexemeType::QOperator]), s)
+}
+
+/// Lex all of `s` as a numeric literal: an integer or a float.
+pub fn parse_numeric(s: &str) -> std::result::Result<Lexeme, LexError> {
+    parse_with(Scanner::numeric_literal, Some(&[LexemeType::Integer, LexemeType::Float]), s)
+}
+
+/// Lex all of `s` as a string literal, quotes included.
+pub fn parse_string_literal(s: &str) -> std::result::Result<Lexeme, LexError> {
+    parse_with(Scanner::char_or_string, Some(&[LexemeType::StringLiteral]), s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_bigint::BigInt;
+    use crate::lexeme::{QName, ModuleId, RId};
+
+    fn error_at(res: std::result::Result<Lexeme, LexError>) -> (usize, Option<char>) {
+        let err = res.expect_err("should fail");
+        (err.location.offset, err.unexpected)
+    }
+
+    #[test]
+    fn test_parse_identifier() {
+        assert_eq!(parse_identifier("foo'"), Ok(Lexeme::Identifier("foo'".into())));
+        assert_eq!(parse_identifier("Foo"), Ok(Lexeme::Identifier("Foo".into())));
+        assert_eq!(parse_identifier("M.N.x"), Ok(Lexeme::QIdentifier(QName {
+            module: ModuleId(vec!["M".into(), "N".into()]),
+            name: "x".into(),
+        })));
+        assert_eq!(error_at(parse_identifier("F.")), (1, Some('.')));
+        assert_eq!(error_at(parse_identifier("foo bar")), (3, Some(' ')));
+        assert_eq!(error_at(parse_identifier("case")), (0, Some('c')));
+        assert_eq!(error_at(parse_identifier("")), (0, None));
+        assert_eq!(error_at(parse_identifier("1x")), (0, Some('1')));
+    }
+
+    #[test]
+    fn test_parse_operator() {
+        assert_eq!(parse_operator(">>="), Ok(Lexeme::Operator(">>=".into())));
+        assert_eq!(parse_operator(":+"), Ok(Lexeme::Operator(":+".into())));
+        assert!(matches!(parse_operator("M.+"), Ok(Lexeme::QOperator(_))));
+        assert_eq!(error_at(parse_operator("->")), (0, Some('-')));
+        assert_eq!(error_at(parse_operator("+x")), (1, Some('x')));
+        assert_eq!(error_at(parse_operator("x")), (0, Some('x')));
+    }
+
+    #[test]
+    fn test_parse_numeric() {
+        assert_eq!(parse_numeric("0x1F"), Ok(Lexeme::Integer(BigInt::from(31))));
+        assert!(matches!(parse_numeric("1.5e3"), Ok(Lexeme::Float(_))));
+        assert_eq!(error_at(parse_numeric("1.")), (1, Some('.')));
+        assert_eq!(error_at(parse_numeric("12ab")), (2, Some('a')));
+        assert_eq!(error_at(parse_numeric("-1")), (0, Some('-')));
+    }
+
+    #[test]
+    fn test_parse_string_literal() {
+        assert_eq!(parse_string_literal(r#""a\nb""#), Ok(Lexeme::StringLiteral("a\nb".to_string())));
+        assert_eq!(error_at(parse_string_literal(r#""ab"c"#)), (4, Some('c')));
+        // recovered as if closed at the end of input, and reported as a diagnostic.
+        assert_eq!(parse_string_literal(r#""ab"#), Ok(Lexeme::StringLiteral("ab".to_string())));
+        assert_eq!(error_at(parse_string_literal("'a'")), (0, Some('\'')));
+    }
+
+    #[test]
+    fn test_parse_single() {
+        assert_eq!(parse_single(None, "where"), Ok(Lexeme::ReservedId(RId::Where)));
+        assert_eq!(parse_single(Some(LexemeType::ReservedId), "where"),
+                   Ok(Lexeme::ReservedId(RId::Where)));
+        assert_eq!(parse_single(Some(LexemeType::Comma), ","), Ok(Lexeme::Comma));
+        assert_eq!(parse_single(Some(LexemeType::CharLiteral), "'x'"), Ok(Lexeme::CharLiteral('x')));
+        assert_eq!(error_at(parse_single(None, "x y")), (1, Some(' ')));
+        assert_eq!(error_at(parse_single(Some(LexemeType::Integer), "1.0")), (0, Some('1')));
+        let err = parse_single(None, "F.").unwrap_err();
+        assert_eq!(err.to_string(), "1:2: expected end of input, found '.'");
+        let err = parse_operator("x").unwrap_err();
+        assert_eq!(err.to_string(), "1:1: expected operator or qualified operator, found 'x'");
+    }
+}
@@ -18,14 +18,16 @@
 
 //! special: see "Haskell 2010 Report: 2.2 Lexical Program Structure".
 
-use super::{Scanner, Result};
+use super::{Scanner, Result, basic::Special};
 use crate::utils::char::{Stream, CharPredicate};
 use crate::lexeme::Lexeme::{self, *};
 
 impl<I: std::io::Read> Scanner<I> {
     /// Special: delimiters.
     pub fn special(&mut self) -> Result<Lexeme> {
-        alt!(self, choice!(Comma; ','),
+        // a special character is valid, so consuming it reports nothing: the rules are pure.
+        if !self.peek().is_some_and(|c| Special.check(c)) { return Self::keep_trying(); }
+        alt!(pure self, choice!(Comma; ','),
                    choice!(Semicolon; ';'),
                    choice!(Backtick; '`'),
                    choice!(OpenCurlyBracket; '{'),
@@ -19,24 +19,80 @@
 //! whitespaces: see "Haskell 2010 Report: 2.2 Lexical Program Structure" and
 //! "Haskell 2010 Report: 2.3 Comments".
 
-use super::{Result, Scanner, basic::Symbol};
-use crate::utils::char::{CharPredicate, Unicode, Stream};
-use crate::error::{DiagnosticMessage::Error, Error::IncompleteLexeme, Diagnostic};
-use crate::lexeme::LexemeType::Whitespace;
+use std::rc::Rc;
+use super::{Result, Scanner, Range, Location, basic::{Symbol, Control}};
+use crate::utils::char::{CharPredicate, Ascii, Unicode, Stream};
+use crate::utils::Result3::{Success, FailFast};
+use crate::error::{DiagnosticMessage::Error, Error::IncompleteLexeme, Diagnostic, Construct};
+
+/// Kinds of [`Trivia`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum TriviaKind {
+    /// `-- ...` to the end of the line.
+    LineComment,
+    /// `{- ... -}`, the nested ones in it included.
+    BlockComment,
+    /// a `LINE` pragma, or a line marker from CPP, see [`Scanner::with_line_pragmas`].
+    LineDirective,
+}
+
+/// A comment (or a directive) skipped as whitespace, see [`Scanner::take_trivia`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Trivia {
+    /// what this is.
+    pub kind: TriviaKind,
+    /// where it is, the newline ending a line comment excluded.
+    pub range: Range,
+    /// where its text begins, after the `--` or `{-`.
+    pub text_start_offset: usize,
+}
 
 impl<I: std::io::Read> Scanner<I> {
     /// Haskell 2010 Report (2.2.whitespace)
     pub fn whitespace(&mut self) -> Result<()> {
         // whitespace -> whitestuff {whitestuff}
+        self.confusables.clear();
+        self.trivia.clear();
+        // the line of the previous lexeme is not blank, unless there is none.
+        self.blank_lines = 0;
+        self.line_blank = self.location.offset == 0;
         self.some_(method!(whitestuff))
     }
 
+    /// Skip the trivia (whitespaces and comments) before the next lexeme, if any.
+    ///
+    /// [`Scanner::next_lexeme`] does not skip trivia itself: call this one before it. Problems
+    /// found in the trivia are reported as diagnostics; only fail-fast errors are returned.
+    pub fn skip_trivia(&mut self) -> Result<()> {
+        match self.whitespace() {
+            FailFast(err) => FailFast(err),
+            _ => Success(()),
+        }
+    }
+
+    /// Take the comments and directives skipped by the last [`Scanner::skip_trivia`] (or
+    /// [`Scanner::whitespace`]), in source order.
+    pub fn take_trivia(&mut self) -> Vec<Trivia> { std::mem::take(&mut self.trivia) }
+
     fn whitestuff(&mut self) -> Result<()> {
         // whitestuff -> whitechar | comment | ncomment
-        alt!(self, method!(whitechar), method!(comment), method!(ncomment));
+        alt!(self, method!(whitechar), method!(comments), method!(control_char));
         Self::keep_trying()
     }
 
+    fn comments(&mut self) -> Option<()> {
+        let trivia = simple_alt!(self, method!(comment),
+                                 method!(line_pragma), method!(line_marker), method!(ncomment))?;
+        self.trivia.push(trivia);
+        Some(())
+    }
+
+    fn control_char(&mut self) -> Option<()> {
+        // not whitestuff, but reported and skipped as if it were, instead of failing.
+        analyse!(self, Control);
+        Some(())
+    }
+
     pub(super) fn whitechar(&mut self) -> Option<()> {
         // whitechar  -> newline | vertab | space | tab | uniWhite
         // vertab     -> a vertical tab
@@ -44,7 +100,16 @@ impl<I: std::io::Read> Scanner<I> {
         // uniWhite   -> any Unicode character defined as whitespace
         simple_alt!(self,
             method!(newline), method!(tab),
-            choice!(any!('\u{B}', ' ', Unicode::White)))
+            choice!(any!('\u{B}', ' ')),
+            method!(uni_white))
+    }
+
+    fn uni_white(&mut self) -> Option<()> {
+        // non-ASCII whitespaces look like spaces, recorded for the layout algorithm to check.
+        let begin = self.location;
+        analyse!(self, c: Unicode::White);
+        self.confusables.push((begin, c));
+        Some(())
     }
 
     fn newline(&mut self) -> Option<()> {
@@ -57,26 +122,76 @@ impl<I: std::io::Read> Scanner<I> {
                 choice!(any!('\r', '\n', '\u{C}')));
         if res.is_some() {
             self.location.newline();
+            if self.line_blank { self.blank_lines += 1 }
+            self.line_blank = true;
         }
         res
     }
 
     fn tab(&mut self) -> Option<()> {
         // tab        -> a horizontal tab
+        let begin = self.location;
         analyse!(self, '\t');
+        if self.line_tab.is_none_or(|t| t.line != begin.line) {
+            self.line_tab = Some(begin);
+        }
         self.location.tablise();
         Some(())
     }
 
-    fn comment(&mut self) -> Option<()> {
+    fn comment(&mut self) -> Option<Trivia> {
         // comment    -> dashes [ any<symbol> {any} ] newline
+        let begin = self.location;
         analyse!(self, '-', '-', *'-');
-        if Symbol.check(self.peek()?) { return None; }
+        let text_start_offset = self.location.offset;
+        // `--` and `---` at the end of input are comments too.
+        if self.peek().is_some_and(|c| Symbol.check(c)) { return None; }
         analyse!(self, *not!("\r\n\u{C}"));
-        self.newline()
+        self.line_blank = false;
+        let range = Range { begin, end: self.location };
+        // the last line of the input may end without a newline.
+        if self.peek().is_some() { self.newline()? }
+        Some(Trivia { kind: TriviaKind::LineComment, range, text_start_offset })
+    }
+
+    // `{-# LINE 42 "file" #-}` at the beginning of a line: the next line is line 42 of "file".
+    fn line_pragma(&mut self) -> Option<Trivia> {
+        if !self.line_pragmas || self.location.column != 1 { return None; }
+        let begin = self.location;
+        analyse!(self, '{', '-', '#', *' ', 'L', 'I', 'N', 'E', +' ',
+                 line: {String::new()}{String::push} +Ascii::Digit, *' ');
+        let file = if self.peek() == Some('"') { Some(self.file_name()?) } else { None };
+        analyse!(self, *' ', '#', '-', '}');
+        self.line_directive(&line, file, begin)
     }
 
-    fn ncomment(&mut self) -> Option<()> {
+    // `# 42 "file" flags...` from CPP, the same as a `LINE` pragma: the flags are ignored.
+    fn line_marker(&mut self) -> Option<Trivia> {
+        if !self.line_pragmas || self.location.column != 1 { return None; }
+        let begin = self.location;
+        analyse!(self, '#', *' ', line: {String::new()}{String::push} +Ascii::Digit);
+        if !matches!(self.peek(), None | Some(' ' | '\r' | '\n' | '\u{C}')) { return None; }
+        analyse!(self, *' ');
+        let file = if self.peek() == Some('"') { Some(self.file_name()?) } else { None };
+        analyse!(self, *not!("\r\n\u{C}"));
+        self.line_directive(&line, file, begin)
+    }
+
+    fn file_name(&mut self) -> Option<Rc<str>> {
+        analyse!(self, '"', name: {String::new()}{String::push} *not!("\"\r\n\u{C}"), '"');
+        Some(name.into())
+    }
+
+    fn line_directive(&mut self, line: &str, file: Option<Rc<str>>, begin: Location) -> Option<Trivia> {
+        let logical_line = line.parse().ok()?;
+        // the directive is about the next line.
+        self.source_map.push(self.location.line + 1, logical_line, file);
+        self.line_blank = false;
+        let range = Range { begin, end: self.location };
+        Some(Trivia { kind: TriviaKind::LineDirective, range, text_start_offset: begin.offset })
+    }
+
+    fn ncomment(&mut self) -> Option<Trivia> {
         // ncomment   -> opencom ANYseq {ncomment ANYseq} closecom
         // opencom    -> {-
         // closecom   -> -}
@@ -90,6 +205,13 @@ impl<I: std::io::Read> Scanner<I> {
         let mut last = WHATEVER;
         let mut depth = 1;
         while let Some(x) = self.next() {
+            // keep the location right, for the lexemes after the comment.
+            match x {
+                '\r' if self.peek() == Some('\n') => (),
+                '\r' | '\n' | '\u{C}' => self.location.newline(),
+                '\t' => self.location.tablise(),
+                _ => (),
+            }
             match (last, x) {
                 ('-', '}') => {
                     last = x;
@@ -104,17 +226,22 @@ impl<I: std::io::Read> Scanner<I> {
             if depth == 0 { break; }
         }
         if depth != 0 {
-            let end = self.location;
-            Diagnostic::new(self.location, Error(IncompleteLexeme(Whitespace)))
-                .within(begin, end).report(&mut self.diagnostics)
+            let mut opening = begin;
+            opening.step();
+            opening.step();
+            Diagnostic::new(Range::point(self.location), Error(IncompleteLexeme(Construct::BlockComment)))
+                .with_label(Range { begin, end: opening }, "comment opened here")
+                .report(&mut self.diagnostics)
         }
-        Some(())
+        self.line_blank = false;
+        let range = Range { begin, end: self.location };
+        Some(Trivia { kind: TriviaKind::BlockComment, range, text_start_offset: begin.offset + 2 })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::scanner::test_scanner_on;
+    use crate::testing::test_scanner_on;
     use crate::utils::setup_logger;
     use crate::utils::Result3::Success;
 
@@ -129,5 +256,105 @@ mod tests {
         test("\n");
         test("--- Comment123!@#$%^&*()-=_+[]{}\\|;:'\",<.>/?`~\n");
         test("{- {--- AA -} B--}");
+        test("-- no newline at the end of input");
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        use crate::scanner::{Scanner, Range};
+        let mut scanner = Scanner::new("  {- {- -} abc".as_bytes());
+        assert_eq!(scanner.whitespace(), Success(()));
+        let diagnostics: Vec<_> = scanner.diagnostics().iter().collect();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range(), Range::point((1, 15).into()));
+        assert_eq!(diagnostics[0].labels()[0].range, crate::range!(1:3-1:5));
+        assert_eq!(diagnostics[0].to_string(), "1:15-1:15: error: unterminated block comment");
+    }
+
+    #[test]
+    fn test_control_chars() {
+        use crate::scanner::{Scanner, layout::FatLexemeIterator};
+        use crate::lexeme::Lexeme::{Identifier, StringLiteral};
+        use crate::range;
+        // skipped between lexemes, as if whitespaces, and splitting lexemes.
+        let mut it = FatLexemeIterator::new("x\0y \u{1}\"\0\"\0".as_bytes());
+        let lexemes: Vec<_> = it.by_ref().collect();
+        assert_eq!(lexemes, [
+            (Identifier("x".into()), range!(1:1-1:2)),
+            (Identifier("y".into()), range!(1:3-1:4)),
+            (StringLiteral("\u{FFFD}".into()), range!(1:6-1:9)),
+        ]);
+        let (err, scanner) = it.into_scanner();
+        assert_eq!(err, None);
+        let diagnostics: Vec<_> = scanner.diagnostics().iter().map(|d| d.to_string()).collect();
+        assert_eq!(diagnostics, [
+            r"1:2-1:3: error: control character '\x00' not allowed in source",
+            r"1:5-1:6: error: control character '\x01' not allowed in source",
+            r"1:7-1:8: error: control character '\x00' not allowed raw in a string literal, use an escape",
+            r"1:9-1:10: error: control character '\x00' not allowed in source",
+        ]);
+        // as in comments.
+        let mut scanner = Scanner::new("-- \0\n{- \u{1} -}".as_bytes());
+        assert_eq!(scanner.whitespace(), Success(()));
+        let codes: Vec<_> = scanner.diagnostics().iter().map(|d| d.message().code()).collect();
+        assert_eq!(codes, ["L0018", "L0018"]);
+    }
+
+    #[test]
+    fn test_dashes() {
+        use crate::scanner::layout::FatLexemeIterator;
+        // dashes (two or more) start a comment, unless they are a part of an operator,
+        // i.e. followed by a symbol, or after one. Not an operator on their own.
+        fn test(input: &str, res: &[&str]) {
+            let mut it = FatLexemeIterator::new(input.as_bytes());
+            let actual: Vec<_> = it.by_ref().map(|(x, r)| {
+                let n = r.end.offset - r.begin.offset;
+                let text: String = input.chars().skip(r.begin.offset).take(n).collect();
+                format!("{:?} {}", x.get_type(), text)
+            }).collect();
+            assert_eq!(actual, res, "{:?}", input);
+            assert!(it.into_scanner().0.is_none(), "{:?}", input);
+        }
+        test("--", &[]);
+        test("---", &[]);
+        test("-- |", &[]);
+        test("--- |", &[]);
+        test("--|", &["Operator --|"]);
+        test("-->", &["Operator -->"]);
+        test("--\u{2192}", &["Operator --\u{2192}"]);
+        test("--+ x", &["Operator --+", "Identifier x"]);
+        test("a--b", &["Identifier a"]);
+        test("a -- b", &["Identifier a"]);
+        test("a---", &["Identifier a"]);
+        test("x--\ny", &["Identifier x", "Identifier y"]);
+        test("-- --> x\ny", &["Identifier y"]);
+        test("!--x", &["Operator !--", "Identifier x"]);
+        test("<--", &["Operator <--"]);
+        test("|--", &["Operator |--"]);
+        // not a qualified varsym: `F`, and then an operator starting with `.`.
+        test("F.--", &["Identifier F", "Operator .--"]);
+        test("F.--x", &["Identifier F", "Operator .--", "Identifier x"]);
+        test("F.-->", &["QOperator F.-->"]);
+    }
+
+    #[test]
+    fn test_trivia() {
+        use crate::scanner::{Scanner, Trivia, TriviaKind::*, Location, Range};
+        use crate::lexeme::Lexeme::Identifier;
+        let mut scanner = Scanner::new("--- doc\n  {- a {- b -} c -}\tx".as_bytes());
+        assert_eq!(scanner.skip_trivia(), Success(()));
+        assert_eq!(scanner.take_trivia(), [
+            Trivia { kind: LineComment, range: crate::range!(1:1-1:8), text_start_offset: 3 },
+            Trivia {
+                kind: BlockComment,
+                range: Range { begin: Location::at(2, 3, 10), end: Location::at(2, 20, 27) },
+                text_start_offset: 12,
+            },
+        ]);
+        assert_eq!(scanner.next_lexeme(), Success(Identifier("x".into())));
+        assert!(scanner.take_trivia().is_empty());
+        // cleared on each run.
+        assert_eq!(scanner.skip_trivia(), Success(()));
+        assert!(scanner.take_trivia().is_empty());
     }
 }
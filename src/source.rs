@@ -0,0 +1,105 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Files of a multi-file session, identified by [`FileId`]s.
+//!
+//! A [`Scanner`](crate::Scanner) made [`with_file`](crate::Scanner::with_file) stamps its
+//! diagnostics with the id, and [`Diagnostic::render_in`](crate::Diagnostic::render_in) resolves it
+//! back to the path through the [`SourceManager`] that handed it out.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// A file registered to a [`SourceManager`], stable for the whole session.
+///
+/// The default is [`FileId::ANONYMOUS`]: the single input, when there is no manager at all.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+pub struct FileId(pub u32);
+
+impl FileId {
+    /// The anonymous input, never handed out by a [`SourceManager`].
+    pub const ANONYMOUS: FileId = FileId(0);
+
+    /// Check whether this is [`FileId::ANONYMOUS`].
+    pub fn is_anonymous(self) -> bool { self == Self::ANONYMOUS }
+}
+
+/// The files of a session: each path registered gets a [`FileId`], from 1 up.
+#[derive(Clone, Debug, Default)]
+pub struct SourceManager {
+    paths: Vec<PathBuf>,
+    ids: HashMap<PathBuf, FileId>,
+}
+
+impl SourceManager {
+    /// Create an empty source manager.
+    pub fn new() -> Self { Self::default() }
+
+    /// Register a path, or get back its id if already registered.
+    pub fn add(&mut self, path: impl Into<PathBuf>) -> FileId {
+        let path = path.into();
+        if let Some(&id) = self.ids.get(&path) { return id; }
+        self.paths.push(path.clone());
+        let id = FileId(self.paths.len() as u32);
+        self.ids.insert(path, id);
+        id
+    }
+
+    /// The path of a file, or `None` for [`FileId::ANONYMOUS`] and the ids of other managers.
+    pub fn path(&self, file: FileId) -> Option<&Path> {
+        let n = (file.0 as usize).checked_sub(1)?;
+        self.paths.get(n).map(PathBuf::as_path)
+    }
+
+    /// The id of a path already registered.
+    pub fn id(&self, path: &Path) -> Option<FileId> { self.ids.get(path).copied() }
+
+    /// Number of files registered.
+    pub fn len(&self) -> usize { self.paths.len() }
+
+    /// Check whether no files are registered.
+    pub fn is_empty(&self) -> bool { self.paths.is_empty() }
+
+    /// Iterate through the files, in the order they are registered.
+    pub fn iter(&self) -> impl Iterator<Item=(FileId, &Path)> {
+        self.paths.iter().enumerate().map(|(n, p)| (FileId(n as u32 + 1), p.as_path()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_manager() {
+        let mut files = SourceManager::new();
+        assert!(files.is_empty());
+        let a = files.add("A.hs");
+        let b = files.add("B.hs");
+        assert_eq!((a, b), (FileId(1), FileId(2)));
+        assert_eq!(files.add("A.hs"), a);
+        assert_eq!(files.len(), 2);
+        assert_eq!(files.path(b), Some(Path::new("B.hs")));
+        assert_eq!(files.id(Path::new("A.hs")), Some(a));
+        assert_eq!(files.path(FileId::ANONYMOUS), None);
+        assert_eq!(files.path(FileId(3)), None);
+        assert!(FileId::default().is_anonymous());
+        let all: Vec<_> = files.iter().map(|(id, p)| (id, p.to_str().unwrap())).collect();
+        assert_eq!(all, vec![(a, "A.hs"), (b, "B.hs")]);
+    }
+}
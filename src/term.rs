@@ -0,0 +1,274 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Terminal output: whether to use colours, and which ones, decided in one place for all the
+//! renderers, see [`Style`].
+//!
+//! Colours are used by default only on a terminal, and never with `NO_COLOR` set (see
+//! <https://no-color.org>), unless asked for with [`ColorChoice::Always`]. The palettes tell
+//! most classes apart by hue only: just the errors stand out without it, underlined in the
+//! high-contrast one.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::error::Severity;
+use crate::lexeme::Lexeme;
+use crate::lsp::TokenType;
+use crate::scanner::Range;
+
+/// When to use colours, e.g. from `--color`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum ColorChoice {
+    /// Always, whatever the output and the environment.
+    Always,
+    /// Only on a terminal, and without `NO_COLOR`.
+    #[default]
+    Auto,
+    /// Never.
+    Never,
+}
+
+impl ColorChoice {
+    /// All the choices, as in `--color`.
+    pub const ALL: [ColorChoice; 3] = [ColorChoice::Always, ColorChoice::Auto, ColorChoice::Never];
+
+    /// How this choice is spelt in `--color`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ColorChoice::Always => "always",
+            ColorChoice::Auto => "auto",
+            ColorChoice::Never => "never",
+        }
+    }
+
+    /// Whether to use colours, given whether `NO_COLOR` is set and whether the output is a
+    /// terminal.
+    pub fn use_color(self, no_color: bool, is_tty: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Auto => !no_color && is_tty,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Self::ALL.iter().copied().find(|x| x.as_str() == s).ok_or(())
+    }
+}
+
+/// The colours to use.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum Palette {
+    /// The 8 basic colours, bold for the severities.
+    #[default]
+    Default,
+    /// The bright colours, all bold, and the errors underlined too.
+    HighContrast,
+}
+
+impl Palette {
+    /// All the palettes, as in `--palette`.
+    pub const ALL: [Palette; 2] = [Palette::Default, Palette::HighContrast];
+
+    /// How this palette is spelt in `--palette`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Palette::Default => "default",
+            Palette::HighContrast => "high-contrast",
+        }
+    }
+}
+
+impl FromStr for Palette {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Self::ALL.iter().copied().find(|x| x.as_str() == s).ok_or(())
+    }
+}
+
+/// What a piece of output is, for its colour.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Class {
+    /// The severity of a diagnostic, and its primary marks.
+    Severity(Severity),
+    /// The line numbers and the `|` and `-->` around them.
+    Gutter,
+    /// The secondary marks and their labels.
+    Label,
+    /// A highlighted token, see [`highlight`].
+    Token(TokenType),
+}
+
+/// Whether to use colours, and which ones.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct Style {
+    enabled: bool,
+    palette: Palette,
+}
+
+impl Style {
+    /// No colours at all.
+    pub fn plain() -> Self { Self::default() }
+
+    /// The style for `choice`, given whether `NO_COLOR` is set and whether the output is a
+    /// terminal.
+    pub fn decide(choice: ColorChoice, palette: Palette, no_color: bool, is_tty: bool) -> Self {
+        Style { enabled: choice.use_color(no_color, is_tty), palette }
+    }
+
+    /// The style for `choice` on an output, looking up `NO_COLOR` in the environment.
+    pub fn for_output(choice: ColorChoice, palette: Palette, output: &impl std::io::IsTerminal) -> Self {
+        Self::decide(choice, palette, no_color(), output.is_terminal())
+    }
+
+    /// Whether colours are used.
+    pub fn is_enabled(&self) -> bool { self.enabled }
+
+    /// The SGR parameters for a class, e.g. `"1;31"` for bold red.
+    pub fn sgr(&self, class: Class) -> Option<&'static str> {
+        if !self.enabled { return None; }
+        use TokenType::*;
+        let default = self.palette == Palette::Default;
+        Some(match class {
+            Class::Severity(Severity::Error | Severity::Fatal) => if default { "1;31" } else { "1;4;91" },
+            Class::Severity(Severity::Warning) => if default { "1;33" } else { "1;93" },
+            Class::Severity(Severity::Note) => if default { "1;36" } else { "1;96" },
+            Class::Gutter => if default { "34" } else { "1;94" },
+            Class::Label => if default { "1" } else { "1;97" },
+            Class::Token(Keyword) => if default { "1;35" } else { "1;95" },
+            Class::Token(Type | Namespace) => if default { "33" } else { "1;93" },
+            Class::Token(Operator) => if default { "36" } else { "1;96" },
+            Class::Token(Number | String) => if default { "32" } else { "1;92" },
+            Class::Token(Comment) => if default { "2" } else { "1;37" },
+            Class::Token(Variable) => return None,
+        })
+    }
+
+    /// Paint `text` as a `class`: unchanged without colours, or if empty.
+    pub fn paint(&self, class: Class, text: &str) -> String {
+        match self.sgr(class) {
+            Some(sgr) if !text.is_empty() => format!("\x1b[{}m{}\x1b[0m", sgr, text),
+            _ => text.to_string(),
+        }
+    }
+}
+
+/// Whether `NO_COLOR` is set (to anything but the empty string).
+pub fn no_color() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty())
+}
+
+/// Highlight the source with its tokens, classified by [`TokenType::classify`]: everything
+/// but whitespaces outside of the tokens is taken as comments, as in
+/// [`semantic_tokens`](crate::lsp::semantic_tokens). The source is unchanged without colours.
+///
+/// Each line is painted on its own, so that the output can be cut into lines.
+pub fn highlight(source: &str, tokens: &[(Lexeme, Range)], style: &Style) -> String {
+    // the scanner does not count the BOM in its offsets.
+    let (bom, body) = match source.strip_prefix('\u{FEFF}') {
+        Some(body) => ("\u{FEFF}", body),
+        None => ("", source),
+    };
+    let mut res = String::from(bom);
+    let mut chars = body.chars();
+    let mut offset = 0;
+    let mut paint = |res: &mut String, end: usize, class: Option<Class>| {
+        let text: String = chars.by_ref().take(end.saturating_sub(offset)).collect();
+        offset = offset.max(end);
+        for (k, line) in text.split('\n').enumerate() {
+            if k != 0 { res.push('\n') }
+            let _ = match class {
+                None => write!(res, "{}", line),
+                // the comments only where there is something.
+                Some(class @ Class::Token(TokenType::Comment)) => {
+                    let trimmed = line.trim();
+                    let start = line.len() - line.trim_start().len();
+                    let end = start + trimmed.len();
+                    write!(res, "{}{}{}", &line[..start], style.paint(class, trimmed), &line[end..])
+                }
+                Some(class) => write!(res, "{}", style.paint(class, line)),
+            };
+        }
+    };
+    for (lexeme, range) in tokens {
+        paint(&mut res, range.begin.offset, Some(Class::Token(TokenType::Comment)));
+        paint(&mut res, range.end.offset, TokenType::classify(lexeme).map(Class::Token));
+    }
+    paint(&mut res, usize::MAX, Some(Class::Token(TokenType::Comment)));
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{highlight, ColorChoice, Palette, Style, Class};
+    use crate::error::Severity;
+    use crate::scanner::layout::FatLexemeIterator;
+
+    #[test]
+    fn test_decision_matrix() {
+        use ColorChoice::*;
+        // (choice, NO_COLOR set, on a terminal) => colours.
+        let matrix = [
+            (Always, false, false, true), (Always, false, true, true),
+            (Always, true, false, true), (Always, true, true, true),
+            (Auto, false, false, false), (Auto, false, true, true),
+            (Auto, true, false, false), (Auto, true, true, false),
+            (Never, false, false, false), (Never, false, true, false),
+            (Never, true, false, false), (Never, true, true, false),
+        ];
+        for (choice, no_color, is_tty, expected) in matrix {
+            for palette in Palette::ALL {
+                let style = Style::decide(choice, palette, no_color, is_tty);
+                assert_eq!(style.is_enabled(), expected, "{:?} {} {}", choice, no_color, is_tty);
+                let painted = style.paint(Class::Severity(Severity::Error), "error");
+                assert_eq!(painted != "error", expected);
+            }
+        }
+        assert_eq!(ColorChoice::ALL.map(|c| c.as_str().parse()), ColorChoice::ALL.map(Ok));
+        assert_eq!(Palette::ALL.map(|p| p.as_str().parse()), Palette::ALL.map(Ok));
+        assert_eq!("sometimes".parse::<ColorChoice>(), Err(()));
+    }
+
+    #[test]
+    fn test_palettes() {
+        let style = |palette| Style::decide(ColorChoice::Always, palette, false, false);
+        let error = Class::Severity(Severity::Error);
+        assert_eq!(style(Palette::Default).paint(error, "x"), "\x1b[1;31mx\x1b[0m");
+        assert_eq!(style(Palette::HighContrast).paint(error, "x"), "\x1b[1;4;91mx\x1b[0m");
+        assert_eq!(style(Palette::Default).paint(error, ""), "");
+        // fatal errors are shown as errors.
+        assert_eq!(style(Palette::Default).sgr(Class::Severity(Severity::Fatal)),
+                   style(Palette::Default).sgr(error));
+        assert_eq!(Style::plain().paint(error, "x"), "x");
+    }
+
+    #[test]
+    fn test_highlight() {
+        let source = "\u{FEFF}main = do -- hi\n  print 42\n{- a\n b -} x";
+        let tokens: Vec<_> = FatLexemeIterator::new(source.as_bytes()).collect();
+        assert_eq!(highlight(source, &tokens, &Style::plain()), source);
+        let style = Style::decide(ColorChoice::Always, Palette::Default, false, false);
+        expect_test::expect![[r#"
+            "\u{feff}main \u{1b}[36m=\u{1b}[0m \u{1b}[1;35mdo\u{1b}[0m \u{1b}[2m-- hi\u{1b}[0m\n  print \u{1b}[32m42\u{1b}[0m\n\u{1b}[2m{- a\u{1b}[0m\n \u{1b}[2mb -}\u{1b}[0m x"
+        "#]].assert_debug_eq(&highlight(source, &tokens, &style));
+    }
+}
@@ -0,0 +1,110 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Testing utilities, for scanner extensions and downstream crates: enable the `testing`
+//! feature to use them.
+//!
+//! All of them panic with the input and what was produced on failure, at the caller's
+//! location.
+
+use std::fmt::Debug;
+use crate::lexeme::Lexeme;
+use crate::scanner::Scanner;
+use crate::scanner::layout::RawLexemeIterator;
+use crate::utils::char::Stream;
+
+// at most this many characters of the remaining input are shown on failure.
+const REMAINING_SHOWN: usize = 40;
+
+fn remaining(scanner: &mut Scanner<&[u8]>) -> String {
+    let mut res: String = std::iter::from_fn(|| scanner.next()).take(REMAINING_SHOWN + 1).collect();
+    if res.chars().count() > REMAINING_SHOWN {
+        res.pop();
+        res += "...";
+    }
+    res
+}
+
+/// Run `f` on a scanner over `input`, and check its result, and the character right after.
+///
+/// ```
+/// use mini_haskell::testing::test_scanner_on;
+/// use mini_haskell::utils::Result3::Success;
+/// test_scanner_on("  -- comment\nx", |s| s.whitespace(), Success(()), Some('x'));
+/// ```
+#[track_caller]
+pub fn test_scanner_on<U: Eq + Debug>(
+    input: &str,
+    f: impl FnOnce(&mut Scanner<&[u8]>) -> U,
+    res: U, next: Option<char>) {
+    let mut scanner = Scanner::new(input.as_bytes());
+    let actual = f(&mut scanner);
+    let location = scanner.location();
+    let actual_next = scanner.next();
+    if actual != res || actual_next != next {
+        let rest: String = actual_next.into_iter().collect::<String>() + &remaining(&mut scanner);
+        panic!("scanning {:?}:\n  produced: {:?}\n  expected: {:?}\n  \
+                remaining at {}: {:?}\n  expected next: {:?}",
+               input, actual, res, location, rest, next)
+    }
+}
+
+/// Lex the whole `input`, and check the lexemes: lexing must succeed to the end.
+///
+/// ```
+/// use mini_haskell::testing::assert_lexes_to;
+/// use mini_haskell::lexeme::Lexeme::{Identifier, ReservedOp};
+/// use mini_haskell::lexeme::ROp::EqualSign;
+/// assert_lexes_to("x = y", &[Identifier("x".into()), ReservedOp(EqualSign), Identifier("y".into())]);
+/// ```
+#[track_caller]
+pub fn assert_lexes_to(input: &str, expected: &[Lexeme]) {
+    let mut it = RawLexemeIterator::new(input.as_bytes());
+    let actual: Vec<_> = it.by_ref().collect();
+    let (err, mut scanner) = it.into_scanner();
+    if let Some(err) = err {
+        panic!("lexing {:?}: error at {}\n  produced: {:?}\n  expected: {:?}\n  remaining: {:?}",
+               input, err, actual, expected, remaining(&mut scanner))
+    }
+    if let Some(k) = (0..actual.len().max(expected.len())).find(|&k| actual.get(k) != expected.get(k)) {
+        panic!("lexing {:?}: lexeme #{} differs\n  produced: {:?}\n  expected: {:?}",
+               input, k, actual, expected)
+    }
+}
+
+/// Lex the whole `input`, and check that lexing fails at `line` and `column`: the lexemes
+/// before the failure are returned, to be checked as well.
+///
+/// ```
+/// use mini_haskell::testing::assert_lex_fails_at;
+/// use mini_haskell::lexeme::Lexeme::Identifier;
+/// assert_eq!(assert_lex_fails_at("x \u{7F}", 1, 3), [Identifier("x".into())]);
+/// ```
+#[track_caller]
+pub fn assert_lex_fails_at(input: &str, line: usize, column: usize) -> Vec<Lexeme> {
+    let mut it = RawLexemeIterator::new(input.as_bytes());
+    let actual: Vec<_> = it.by_ref().collect();
+    let (err, mut scanner) = it.into_scanner();
+    match err {
+        Some(err) if (err.location.line, err.location.column) == (line, column) => actual,
+        Some(err) => panic!("lexing {:?}: error at {}, expected at {}:{}\n  produced: {:?}\n  \
+                             remaining: {:?}", input, err, line, column, actual, remaining(&mut scanner)),
+        None => panic!("lexing {:?}: no error, expected at {}:{}\n  produced: {:?}",
+                       input, line, column, actual),
+    }
+}
@@ -18,7 +18,12 @@
 
 //! useful common utilities.
 
+// the parser combinator macros are only used by the (std-only) scanner.
+#![cfg_attr(not(feature = "std"), allow(unused_macros))]
+
 pub mod iter;
+pub mod small_string;
+pub mod normalization;
 
 #[macro_use]
 pub mod control;
@@ -36,4 +41,5 @@ pub use control::Either;
 pub use control::Maybe;
 pub use control::Result3;
 pub use misc::*;
+#[cfg(test)]
 pub use logging::*;
@@ -18,6 +18,7 @@
 
 //! character related utilities.
 
+use alloc::{string::String, vec::Vec};
 use unic_ucd_category::GeneralCategory;
 
 /// ASCII character categories.
@@ -52,10 +53,12 @@ pub enum Unicode {
     Numeric,
     /// Unicode decimal digits: `Nd`.
     Digit,
-    /// Unicode lowercase letters: `Lowercase`.
+    /// Unicode lowercase letters: `Ll`.
     Lower,
-    /// Unicode uppercase letters: `Uppercase`.
+    /// Unicode uppercase letters: `Lu`.
     Upper,
+    /// Unicode titlecase letters: `Lt`.
+    Titlecase,
     /// Unicode whitespaces: `White_Space`.
     White,
     /// Unicode symbol: `Sm`, `Sc`, `Sk`, `So`.
@@ -93,8 +96,9 @@ impl CharPredicate for Unicode {
             Unicode::AlphaNum => x.is_alphanumeric(),
             Unicode::Numeric => x.is_numeric(),
             Unicode::Digit => GeneralCategory::of(x) == GeneralCategory::DecimalNumber,
-            Unicode::Lower => x.is_lowercase(),
-            Unicode::Upper => x.is_uppercase(),
+            Unicode::Lower => GeneralCategory::of(x) == GeneralCategory::LowercaseLetter,
+            Unicode::Upper => GeneralCategory::of(x) == GeneralCategory::UppercaseLetter,
+            Unicode::Titlecase => GeneralCategory::of(x) == GeneralCategory::TitlecaseLetter,
             Unicode::White => x.is_whitespace(),
             Unicode::Symbol => GeneralCategory::of(x).is_symbol(),
             Unicode::Punct => GeneralCategory::of(x).is_punctuation(),
@@ -115,7 +119,7 @@ impl CharPredicate for char {
 /// assert_eq!(('a' .. 'z').check('a'), true);
 /// assert_eq!(('a' .. 'z').check('z'), false);
 /// ```
-pub type CharRange = std::ops::Range<char>;
+pub type CharRange = core::ops::Range<char>;
 
 /// A character range (closed), used as a candidate for [`CharPredicate`].
 ///
@@ -125,7 +129,7 @@ pub type CharRange = std::ops::Range<char>;
 /// assert_eq!(('a' ..= 'z').check('z'), true);
 /// assert_eq!(('a' ..= 'z').check('3'), false);
 /// ```
-pub type CharRangeInclusive = std::ops::RangeInclusive<char>;
+pub type CharRangeInclusive = core::ops::RangeInclusive<char>;
 
 impl CharPredicate for CharRange {
     fn check(&self, x: char) -> bool {
@@ -145,7 +149,7 @@ impl CharPredicate for str {
     }
 }
 
-impl<'a, P: CharPredicate + ?Sized> CharPredicate for &'a P {
+impl<P: CharPredicate + ?Sized> CharPredicate for &P {
     fn check(&self, x: char) -> bool {
         (*self).check(x)
     }
@@ -259,11 +263,14 @@ pub trait Stream {
 }
 
 macro_rules! alt {
-    ($lexer: expr) => { trace!(scanner, "alt: failed"); };
-    ($lexer: expr, $f: expr $(, $($rest: tt)+)?) => {
+    // `pure`: the alternatives report no diagnostics, see `Scanner::anchored_pure`.
+    (pure $lexer: expr $(, $($rest: tt)+)?) => { alt!(@anchored_pure $lexer $(, $($rest)+)?) };
+    ($lexer: expr $(, $($rest: tt)+)?) => { alt!(@anchored $lexer $(, $($rest)+)?) };
+    (@$anchor: ident $lexer: expr) => { trace!(scanner, "alt: failed"); };
+    (@$anchor: ident $lexer: expr, $f: expr $(, $($rest: tt)+)?) => {
         trace!(scanner, "alt: try parsing {}", stringify!($f));
         {
-            let res = $lexer.anchored($f);
+            let res = $lexer.$anchor($f);
             let just = $crate::utils::Maybe::is_just(&res);
             if let Ok(val) = $crate::utils::Either::into_result(res) {
                 if just {
@@ -272,11 +279,11 @@ macro_rules! alt {
                     trace!(scanner, "fail fast: {}", stringify!($f));
                 }
                 return $crate::utils::Either::right(
-                    std::convert::From::from(val));
+                    core::convert::From::from(val));
             }
         }
         trace!(scanner, "failed: {}", stringify!($f));
-        alt!($lexer $(, $($rest)+)?);
+        alt!(@$anchor $lexer $(, $($rest)+)?);
     }
 }
 
@@ -378,8 +385,8 @@ macro_rules! check {
     };
     (some, $lexer: expr, drop $x: ident, $predicate: expr) => {
         trace!(scanner, "analyse: checking +{0} as {0}, *{0} ...", stringify!($predicate));
-        check!(once, $lexer: expr, drop $x, $predicate);
-        check!(many, $lexer: expr, drop $x, $predicate);
+        check!(once, $lexer, drop $x, $predicate);
+        check!(many, $lexer, drop $x, $predicate);
     };
     (collect($e: expr, $cons: expr) many, $lexer: expr, $x: ident, $predicate: expr) => {
         let $x = $lexer.span(|$x| $predicate.check($x), $e, $cons);
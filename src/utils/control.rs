@@ -79,7 +79,7 @@ impl<T, E, M> Maybe for Result3<T, E, M> {
     }
 }
 
-/// The [`std::ops::Try`] trait is not yet stable. We roll up our own for now.
+/// The [`core::ops::Try`] trait is not yet stable. We roll up our own for now.
 /// It is named after `Either`, `Left`, and `Right` from Haskell.
 pub trait Either {
     /// The type to propagate in a `Left`.
@@ -151,7 +151,7 @@ impl<T, E, M> Either for Result3<T, E, M> {
         }
     }
 
-    fn into_result(self) -> std::result::Result<Result3<T, E, Void>, M> {
+    fn into_result(self) -> core::result::Result<Result3<T, E, Void>, M> {
         match self {
             Self::Success(x) => Ok(Result3::Success(x)),
             Self::FailFast(e) => Ok(Result3::FailFast(e)),
@@ -18,12 +18,26 @@
 
 //! Iterator utilities.
 
-use std::collections::VecDeque;
+use alloc::collections::VecDeque;
 
 /// Make a stream from an iterator.
 pub struct IterStream<I: Iterator> {
     raw_iter: I,
     buffer: VecDeque<I::Item>,
+    limit: Option<usize>,
+}
+
+/// A peek would buffer more items than allowed by [`IterStream::with_capacity_limit`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CapacityExceeded {
+    /// the capacity limit of the stream.
+    pub limit: usize,
+}
+
+impl core::fmt::Display for CapacityExceeded {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "peek buffer exceeds its capacity limit ({})", self.limit)
+    }
 }
 
 impl<I: Iterator> From<I> for IterStream<I> {
@@ -31,42 +45,137 @@ impl<I: Iterator> From<I> for IterStream<I> {
         IterStream {
             raw_iter,
             buffer: VecDeque::new(),
+            limit: None,
         }
     }
 }
 
 impl<I: Iterator> Iterator for IterStream<I> {
     type Item = I::Item;
+    #[inline]
     fn next(&mut self) -> Option<I::Item> {
         self.buffer.pop_front().or_else(|| self.raw_iter.next())
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.buffer.len();
+        let (lower, upper) = self.raw_iter.size_hint();
+        (lower.saturating_add(n), upper.and_then(|m| m.checked_add(n)))
+    }
+
+    fn nth(&mut self, n: usize) -> Option<I::Item> {
+        let buffered = self.buffer.len();
+        if n < buffered {
+            self.buffer.drain(..n);
+            return self.buffer.pop_front();
+        }
+        self.buffer.clear();
+        self.raw_iter.nth(n - buffered)
+    }
+
+    // drain the buffer, and then hand the rest to the underlying iterator as a whole.
+    fn fold<B, F: FnMut(B, I::Item) -> B>(self, init: B, mut f: F) -> B {
+        let init = self.buffer.into_iter().fold(init, &mut f);
+        self.raw_iter.fold(init, f)
+    }
 }
 
 impl<I: Iterator> IterStream<I> {
+    /// Make a stream which never buffers more than `limit` items for peeking.
+    /// See [`IterStream::try_peek`] for what happens when a peek goes beyond the limit.
+    pub fn with_capacity_limit(raw_iter: I, limit: usize) -> Self {
+        IterStream {
+            raw_iter,
+            buffer: VecDeque::with_capacity(limit),
+            limit: Some(limit),
+        }
+    }
+
     /// Put one item back to the stream.
+    ///
+    /// Items put back are not subject to the capacity limit.
+    #[inline]
     pub fn put_back(&mut self, x: I::Item) {
         self.buffer.push_front(x)
     }
 
-    fn prepare(&mut self, n: usize) -> Option<()> {
+    #[inline]
+    fn prepare(&mut self, n: usize) -> Result<Option<()>, CapacityExceeded> {
+        match self.limit {
+            Some(limit) if n >= limit && self.buffer.len() <= n =>
+                return Err(CapacityExceeded { limit }),
+            _ => (),
+        }
         while self.buffer.len() <= n {
-            self.buffer.push_back(self.raw_iter.next()?)
+            match self.raw_iter.next() {
+                Some(x) => self.buffer.push_back(x),
+                None => return Ok(None),
+            }
         }
-        Some(())
+        Ok(Some(()))
+    }
+
+    /// Peek the nth element without consuming it.
+    /// # Errors
+    /// Fails with [`CapacityExceeded`] if this requires buffering more items than the
+    /// capacity limit of this stream; nothing is consumed from the underlying iterator then.
+    #[inline]
+    pub fn try_peek(&mut self, n: usize) -> Result<Option<&I::Item>, CapacityExceeded> {
+        Ok(self.prepare(n)?.map(move |_| &self.buffer[n]))
     }
 
     /// Peek the nth element without consuming it.
+    ///
+    /// Returns `None` if the stream ends, or if the capacity limit would be exceeded;
+    /// use [`IterStream::try_peek`] to tell the two apart.
+    #[inline]
     pub fn peek(&mut self, n: usize) -> Option<&I::Item> {
-        self.prepare(n)?;
-        Some(&self.buffer[n])
+        self.try_peek(n).ok().flatten()
+    }
+
+    /// Consume the first item already peeked, without touching the underlying iterator:
+    /// `None` if nothing is peeked.
+    #[inline]
+    pub fn next_peeked(&mut self) -> Option<I::Item> {
+        self.buffer.pop_front()
+    }
+
+    /// The number of items already peeked but not consumed yet.
+    #[inline]
+    pub fn buffered(&self) -> usize { self.buffer.len() }
+
+    /// Consume `n` items at once, or all the remaining items if there are fewer.
+    pub fn advance(&mut self, n: usize) {
+        let buffered = n.min(self.buffer.len());
+        self.buffer.drain(..buffered);
+        for _ in buffered..n {
+            if self.raw_iter.next().is_none() { break; }
+        }
     }
 
+    /// Hand back the items already peeked but not consumed yet, without touching the
+    /// underlying iterator.
+    pub fn drain_buffered(&mut self) -> impl Iterator<Item=I::Item> + '_ {
+        self.buffer.drain(..)
+    }
+
+    /// Get a reference to the underlying iterator.
+    pub fn get(&self) -> &I { &self.raw_iter }
+
+    /// Get a mutable reference to the underlying iterator.
+    ///
+    /// Items already peeked but not consumed yet are not affected.
+    pub fn get_mut(&mut self) -> &mut I { &mut self.raw_iter }
+
     /// Unwraps the [`IterStream`] and get back the underlying iterator.
-    /// # Panics
-    /// Panics if there are items already peeked but not consumed yet.
-    pub fn unwrap(self) -> I {
-        assert!(self.buffer.is_empty());
-        self.raw_iter
+    /// # Errors
+    /// Gives back the stream itself if there are items already peeked but not consumed yet.
+    pub fn unwrap(self) -> Result<I, Self> {
+        if self.buffer.is_empty() {
+            Ok(self.raw_iter)
+        } else {
+            Err(self)
+        }
     }
 
     /// Unwraps the [`IterStream`] and get back the underlying iterator
@@ -76,7 +185,7 @@ impl<I: Iterator> IterStream<I> {
     }
 
     /// Begin the multi-peek mode.
-    pub fn multi_peek(&mut self) -> IterStreamMultiPeek<I> {
+    pub fn multi_peek(&mut self) -> IterStreamMultiPeek<'_, I> {
         IterStreamMultiPeek {
             iter_stream: self,
             current_position: 0,
@@ -105,3 +214,53 @@ impl<'a, I: Iterator> IterStreamMultiPeek<'a, I> where I::Item: Copy {
         self.peek_ref().copied()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{IterStream, CapacityExceeded};
+
+    #[test]
+    fn test_capacity_limit() {
+        let mut s = IterStream::with_capacity_limit(0..10, 2);
+        assert_eq!(s.peek(1), Some(&1));
+        assert_eq!(s.try_peek(2), Err(CapacityExceeded { limit: 2 }));
+        assert_eq!(s.peek(2), None);
+        assert_eq!(s.next(), Some(0));
+        assert_eq!(s.peek(1), Some(&2));
+        assert_eq!(s.multi_peek().peek(), Some(1));
+        s.advance(3);
+        assert_eq!(s.next(), Some(4));
+        assert_eq!(s.try_peek(0), Ok(Some(&5)));
+    }
+
+    #[test]
+    fn test_drain_and_unwrap() {
+        let mut s = IterStream::from(0..5);
+        assert_eq!(s.peek(2), Some(&2));
+        let s = match s.unwrap() {
+            Ok(_) => panic!("items are still buffered"),
+            Err(mut s) => {
+                assert_eq!(s.drain_buffered().collect::<Vec<_>>(), vec![0, 1, 2]);
+                s
+            }
+        };
+        let mut rest = s.unwrap().ok().expect("buffer already drained");
+        assert_eq!(rest.next(), Some(3));
+        let mut s = IterStream::from(0..3);
+        s.advance(10);
+        assert_eq!(s.next(), None);
+    }
+
+    #[test]
+    fn test_fold_and_nth() {
+        let mut s = IterStream::from(0..10);
+        assert_eq!(s.peek(3), Some(&3));
+        assert_eq!(s.size_hint(), (10, Some(10)));
+        assert_eq!(s.nth(1), Some(1));
+        assert_eq!(s.next_peeked(), Some(2));
+        assert_eq!(s.nth(2), Some(5));
+        assert_eq!(s.next_peeked(), None);
+        assert_eq!(s.peek(1), Some(&7));
+        assert_eq!(s.fold(Vec::new(), |mut v, x| { v.push(x); v }), vec![6, 7, 8, 9]);
+    }
+}
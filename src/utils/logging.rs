@@ -24,6 +24,7 @@ mod log_init {
 
     static LOG_INIT: Once = Once::new();
 
+    /// Setup the logger for tests, only the first call takes effect.
     pub fn setup_logger() {
         LOG_INIT.call_once(|| env_logger::Builder::new()
             .format_level(true)
@@ -40,6 +41,7 @@ mod log_init {
 #[cfg(all(test, feature = "log"))]
 pub use log_init::setup_logger;
 
+/// Setup the logger for tests, no-op without the `log` feature.
 #[cfg(all(test, not(feature = "log")))]
 pub fn setup_logger() {}
 
@@ -28,7 +28,7 @@
 /// ```
 #[inline]
 pub const fn round_to(x: usize, n: usize) -> usize {
-    (x + n - 1) / n * n
+    x.div_ceil(n) * n
 }
 
 /// Lorem ipsum. For test only.
@@ -0,0 +1,192 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Unicode canonical normalization forms (NFD and NFC), see "Unicode Standard Annex #15".
+
+mod tables;
+
+use core::cmp::Ordering;
+use alloc::string::String;
+use alloc::vec::Vec;
+use tables::{DECOMPOSITIONS, COMBINING_CLASSES, COMPOSITIONS};
+
+// Hangul syllables, see "The Unicode Standard, 3.12 Conjoining Jamo Behavior".
+const S_BASE: u32 = 0xAC00;
+const L_BASE: u32 = 0x1100;
+const V_BASE: u32 = 0x1161;
+const T_BASE: u32 = 0x11A7;
+const L_COUNT: u32 = 19;
+const V_COUNT: u32 = 21;
+const T_COUNT: u32 = 28;
+const N_COUNT: u32 = V_COUNT * T_COUNT;
+const S_COUNT: u32 = L_COUNT * N_COUNT;
+
+/// The canonical combining class of a character, 0 for starters.
+pub fn combining_class(c: char) -> u8 {
+    let c = c as u32;
+    match COMBINING_CLASSES.binary_search_by(|&(first, last, _)| {
+        if last < c { Ordering::Less } else if first > c { Ordering::Greater } else { Ordering::Equal }
+    }) {
+        Ok(k) => COMBINING_CLASSES[k].2,
+        Err(_) => 0,
+    }
+}
+
+fn decompose(c: char, res: &mut Vec<char>) {
+    let s = (c as u32).wrapping_sub(S_BASE);
+    if s < S_COUNT {
+        let jamos = [L_BASE + s / N_COUNT, V_BASE + s % N_COUNT / T_COUNT, T_BASE + s % T_COUNT];
+        let n = if s.is_multiple_of(T_COUNT) { 2 } else { 3 };
+        res.extend(jamos[..n].iter().copied().filter_map(char::from_u32));
+        return;
+    }
+    match DECOMPOSITIONS.binary_search_by_key(&(c as u32), |&(c, _, _)| c) {
+        Ok(k) => {
+            let (_, first, second) = DECOMPOSITIONS[k];
+            for x in [first, second].iter().copied().filter(|&x| x != 0).filter_map(char::from_u32) {
+                decompose(x, res)
+            }
+        }
+        Err(_) => res.push(c),
+    }
+}
+
+fn compose(first: char, second: char) -> Option<char> {
+    let (a, b) = (first as u32, second as u32);
+    // L + V, and LV + T.
+    if (L_BASE..L_BASE + L_COUNT).contains(&a) && (V_BASE..V_BASE + V_COUNT).contains(&b) {
+        return char::from_u32(S_BASE + ((a - L_BASE) * V_COUNT + (b - V_BASE)) * T_COUNT);
+    }
+    let s = a.wrapping_sub(S_BASE);
+    if s < S_COUNT && s.is_multiple_of(T_COUNT) && (T_BASE + 1..T_BASE + T_COUNT).contains(&b) {
+        return char::from_u32(a + (b - T_BASE));
+    }
+    COMPOSITIONS.binary_search_by_key(&(a, b), |&(x, y, _)| (x, y)).ok()
+        .and_then(|k| char::from_u32(COMPOSITIONS[k].2))
+}
+
+fn nfd_chars(s: &str) -> Vec<char> {
+    let mut res = Vec::with_capacity(s.len());
+    for c in s.chars() { decompose(c, &mut res) }
+    // the canonical ordering: stable sort of each run of non-starters.
+    let mut k = 0;
+    while k < res.len() {
+        if combining_class(res[k]) == 0 {
+            k += 1;
+            continue;
+        }
+        let n = res[k..].iter().take_while(|&&c| combining_class(c) != 0).count();
+        res[k..k + n].sort_by_key(|&c| combining_class(c));
+        k += n;
+    }
+    res
+}
+
+/// The Normalization Form D (canonical decomposition) of a string.
+pub fn nfd(s: &str) -> String {
+    nfd_chars(s).into_iter().collect()
+}
+
+/// The Normalization Form C (canonical decomposition, then canonical composition) of a string.
+pub fn nfc(s: &str) -> String {
+    let mut chars = nfd_chars(s);
+    // the last starter, and the class of the last character kept after it.
+    let mut starter: Option<usize> = None;
+    let mut last_class = 0;
+    let mut n = 0;
+    for k in 0..chars.len() {
+        let c = chars[k];
+        let class = combining_class(c);
+        if let Some(i) = starter {
+            // blocked if a character of the same or higher class comes in between.
+            let blocked = n > i + 1 && (last_class == 0 || last_class >= class);
+            if !blocked {
+                if let Some(composite) = compose(chars[i], c) {
+                    chars[i] = composite;
+                    continue;
+                }
+            }
+        }
+        if class == 0 { starter = Some(n) }
+        last_class = class;
+        chars[n] = c;
+        n += 1;
+    }
+    chars.truncate(n);
+    chars.into_iter().collect()
+}
+
+/// Check whether a string is in Normalization Form C.
+pub fn is_nfc(s: &str) -> bool {
+    s.is_ascii() || nfc(s) == s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{nfc, nfd, is_nfc};
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_normalization() {
+        // precomposed, decomposed, and the Angstrom sign (a singleton).
+        for s in &["\u{C5}", "A\u{30A}", "\u{212B}"] {
+            assert_eq!(nfc(s), "\u{C5}");
+            assert_eq!(nfd(s), "A\u{30A}");
+        }
+        // canonical ordering: dot below (220) before dot above (230), then composed.
+        assert_eq!(nfd("q\u{307}\u{323}"), "q\u{323}\u{307}");
+        assert_eq!(nfc("s\u{307}\u{323}"), "\u{1E69}");
+        // blocked by a mark of the same class.
+        assert_eq!(nfc("a\u{301}\u{301}"), "\u{E1}\u{301}");
+        // Hangul: L V T.
+        assert_eq!(nfc("\u{1100}\u{1161}\u{11A8}"), "\u{AC01}");
+        assert_eq!(nfd("\u{AC01}"), "\u{1100}\u{1161}\u{11A8}");
+        assert!(is_nfc("caf\u{E9}"));
+        assert!(!is_nfc("cafe\u{301}"));
+    }
+
+    #[test]
+    fn test_conformance() {
+        // the invariants of the canonical forms in NormalizationTest.txt, see `generate.py`.
+        let mut n = 0;
+        for line in include_str!("normalization/NormalizationTest.txt").lines() {
+            if line.starts_with('#') || line.starts_with('@') { continue; }
+            let chars = |column: &str| -> String {
+                column.split(' ').map(|c| u32::from_str_radix(c, 16).ok().and_then(char::from_u32).unwrap())
+                    .collect()
+            };
+            let columns: Vec<String> = line.split(';').take(5).map(chars).collect();
+            let (c1, c2, c3, c4, c5) = match &columns[..] {
+                [c1, c2, c3, c4, c5] => (c1, c2, c3, c4, c5),
+                _ => panic!("malformed line: {}", line),
+            };
+            for c in [c1, c2, c3] {
+                assert_eq!(nfc(c), *c2, "NFC of {}", line);
+                assert_eq!(nfd(c), *c3, "NFD of {}", line);
+            }
+            for c in [c4, c5] {
+                assert_eq!(nfc(c), *c4, "NFC of {}", line);
+                assert_eq!(nfd(c), *c5, "NFD of {}", line);
+            }
+            assert!(is_nfc(c2) && (c1 == c2 || !is_nfc(c1)), "{}", line);
+            n += 1;
+        }
+        assert!(n > 2000, "only {} cases", n);
+    }
+}
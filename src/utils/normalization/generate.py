@@ -0,0 +1,193 @@
+#!/usr/bin/env python3
+#
+# mini-haskell: light-weight Haskell for fun
+# Copyright (C) 2021  Xie Ruifeng
+#
+# This program is free software: you can redistribute it and/or modify
+# it under the terms of the GNU Affero General Public License as
+# published by the Free Software Foundation, either version 3 of the
+# License, or (at your option) any later version.
+#
+# This program is distributed in the hope that it will be useful,
+# but WITHOUT ANY WARRANTY; without even the implied warranty of
+# MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+# GNU Affero General Public License for more details.
+#
+# You should have received a copy of the GNU Affero General Public License
+# along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+"""Generate tables.rs and NormalizationTest.txt next to this script, with the Unicode
+Character Database of Python's unicodedata, which shall be the one of the tables:
+
+    python3 src/utils/normalization/generate.py [path/to/NormalizationTest.txt]
+
+NormalizationTest.txt is a subset of the file of the same name in the UCD, for the tests of
+the normalization module. Given the full file, the subset is taken from it; otherwise, it is
+computed with unicodedata instead (which passes the full file itself), in the same format.
+"""
+
+import os
+import sys
+import unicodedata
+
+UCD_VERSION = "14.0.0"
+HERE = os.path.dirname(os.path.abspath(__file__))
+HANGUL = range(0xAC00, 0xAC00 + 11172)
+
+HEADER = """/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Tables for the canonical normalization forms, generated by `generate.py` from the Unicode
+//! Character Database {} with Python's `unicodedata`. Hangul syllables are handled
+//! algorithmically instead.
+""".format(UCD_VERSION)
+
+
+def canonical_decomposition(c):
+    """The canonical decomposition of `c`, one level, or None."""
+    d = unicodedata.decomposition(chr(c))
+    if not d or d.startswith("<"):
+        return None
+    return [int(x, 16) for x in d.split()]
+
+
+def decompositions():
+    res = []
+    for c in range(0x110000):
+        d = canonical_decomposition(c)
+        if d is not None and c not in HANGUL:
+            res.append((c, d[0], d[1] if len(d) > 1 else 0))
+    return res
+
+
+def combining_classes():
+    res = []
+    for c in range(0x110000):
+        k = unicodedata.combining(chr(c))
+        if k == 0:
+            continue
+        if res and res[-1][1] == c - 1 and res[-1][2] == k:
+            res[-1] = (res[-1][0], c, k)
+        else:
+            res.append((c, c, k))
+    return res
+
+
+def compositions():
+    # the pairs that compose back: not excluded, nor singletons, nor non-starters.
+    res = []
+    for c, first, second in decompositions():
+        if second != 0 and unicodedata.normalize("NFC", chr(first) + chr(second)) == chr(c):
+            res.append((first, second, c))
+    return sorted(res)
+
+
+def table(name, doc, ty, rows):
+    lines = ["/// {}".format(doc), "pub static {}: &[{}] = &[".format(name, ty)]
+    for k in range(0, len(rows), 4):
+        lines.append("    " + " ".join(
+            "({}),".format(", ".join(x if isinstance(x, str) else "0x{:X}".format(x) for x in row))
+            for row in rows[k:k + 4]))
+    lines.append("];")
+    return "\n".join(lines) + "\n"
+
+
+def tables():
+    classes = [(first, last, str(k)) for first, last, k in combining_classes()]
+    return "\n".join([
+        HEADER,
+        table("DECOMPOSITIONS",
+              "Canonical decompositions, one level: `(c, first, second)`, with `second = 0` for singletons.",
+              "(u32, u32, u32)", decompositions()),
+        table("COMBINING_CLASSES", "Canonical combining classes other than 0: `(first, last, class)`.",
+              "(u32, u32, u8)", classes),
+        table("COMPOSITIONS", "Primary composites, i.e. the pairs which NFC composes: `(first, second, c)`.",
+              "(u32, u32, u32)", compositions()),
+    ])
+
+
+def hex_seq(s):
+    return " ".join("{:04X}".format(ord(c)) for c in s)
+
+
+def test_line(source):
+    forms = [source] + [unicodedata.normalize(f, source) for f in ("NFC", "NFD", "NFKC", "NFKD")]
+    return "".join(hex_seq(s) + ";" for s in forms) + " # " + "; ".join(
+        unicodedata.name(c, "U+{:04X}".format(ord(c))) for c in source)
+
+
+def test_sources():
+    """The parts of the subset: the source strings of each."""
+    # specific cases: singletons, reordering, blocking, and Hangul.
+    part0 = ["\u1E0A", "\u1E0C", "\u1E0A\u0323", "\u1E0C\u0307", "D\u0307\u0323", "D\u0323\u0307",
+             "\u1E0A\u031B", "\u1E0C\u031B", "\u1E0A\u031B\u0323", "\u212B", "\u2126", "\u1E69",
+             "a\u0301\u0301", "a\u05AE\u0301\u0300b", "\u1100\u1161\u11A8", "\uAC00\u11A8",
+             "\u0958", "\u0344", "\u0F73"]
+    # every character with a canonical decomposition, and some Hangul syllables.
+    part1 = [chr(c) for c, _, _ in decompositions()] + [chr(c) for c in HANGUL[::97]]
+    # canonical ordering: each class, before and after marks of other classes.
+    part2 = []
+    for first, _, _ in combining_classes():
+        part2.append("a\u0315\u0300\u05AE" + chr(first) + "b")
+        part2.append("a" + chr(first) + "\u0315\u0300\u05AEb")
+    return [part0, part1, part2]
+
+
+def subset_of(path):
+    """The lines of the full NormalizationTest.txt with the sources of the subset."""
+    wanted = {hex_seq(s) for part in test_sources() for s in part}
+    parts, current = [], None
+    with open(path, encoding="utf-8") as f:
+        for line in f:
+            line = line.rstrip("\n")
+            if line.startswith("@Part"):
+                current = []
+                parts.append(current)
+            elif current is not None and line and not line.startswith("#"):
+                if line.split(";")[0] in wanted:
+                    current.append(line)
+    return parts
+
+
+def normalization_test(full):
+    parts = subset_of(full) if full else [[test_line(s) for s in part] for part in test_sources()]
+    source = "the full NormalizationTest.txt" if full else "Python's unicodedata"
+    lines = [
+        "# A subset of NormalizationTest-{}.txt, in its format, generated by generate.py".format(UCD_VERSION),
+        "# from {}: see the Unicode Standard Annex #15.".format(source),
+        "#",
+        "# Columns: source; NFC; NFD; NFKC; NFKD.",
+    ]
+    for k, part in enumerate(parts):
+        lines.append("@Part{}".format(k))
+        lines.extend(part)
+    return "\n".join(lines) + "\n"
+
+
+def main():
+    if unicodedata.unidata_version != UCD_VERSION:
+        sys.exit("unicodedata has the UCD {}, not {}".format(unicodedata.unidata_version, UCD_VERSION))
+    full = sys.argv[1] if len(sys.argv) > 1 else None
+    with open(os.path.join(HERE, "tables.rs"), "w", encoding="utf-8") as f:
+        f.write(tables())
+    with open(os.path.join(HERE, "NormalizationTest.txt"), "w", encoding="utf-8") as f:
+        f.write(normalization_test(full))
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,204 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2020  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A small string, stored inline unless it is too long.
+
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::fmt::{Debug, Display, Formatter};
+use core::hash::{Hash, Hasher};
+use core::ops::Deref;
+use alloc::string::String;
+
+// with the length byte and the tag byte, 32 bytes: as many as a `String` (24 bytes on 64-bit)
+// takes with a tag padded to its alignment, so inlining makes `Repr` no larger.
+const INLINE_CAPACITY: usize = 30;
+
+/// A string stored inline (without allocation) when it is at most 30 bytes long.
+///
+/// Most identifiers in a Haskell program are short, so this saves an allocation per lexeme.
+#[derive(Clone)]
+pub struct SmallString(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    // invariant: `buf[..len]` is valid UTF-8.
+    Inline { len: u8, buf: [u8; INLINE_CAPACITY] },
+    Heap(String),
+}
+
+impl SmallString {
+    /// Create a new empty [`SmallString`].
+    pub const fn new() -> Self {
+        SmallString(Repr::Inline { len: 0, buf: [0; INLINE_CAPACITY] })
+    }
+
+    /// Whether this string is stored inline.
+    pub fn is_inline(&self) -> bool {
+        matches!(self.0, Repr::Inline { .. })
+    }
+
+    /// View this string as a `&str`.
+    pub fn as_str(&self) -> &str {
+        match &self.0 {
+            Repr::Inline { len, buf } => unsafe {
+                core::str::from_utf8_unchecked(&buf[..*len as usize])
+            },
+            Repr::Heap(s) => s,
+        }
+    }
+
+    /// Append a character, spilling to the heap if it no longer fits inline.
+    pub fn push(&mut self, c: char) {
+        let mut tmp = [0; 4];
+        self.push_str(c.encode_utf8(&mut tmp))
+    }
+
+    /// Append a string slice, spilling to the heap if it no longer fits inline.
+    pub fn push_str(&mut self, s: &str) {
+        match &mut self.0 {
+            Repr::Inline { len, buf } => {
+                let n = *len as usize;
+                if n + s.len() <= INLINE_CAPACITY {
+                    buf[n..n + s.len()].copy_from_slice(s.as_bytes());
+                    *len += s.len() as u8;
+                } else {
+                    let mut heap = String::with_capacity(n + s.len());
+                    heap.push_str(self.as_str());
+                    heap.push_str(s);
+                    self.0 = Repr::Heap(heap);
+                }
+            }
+            Repr::Heap(heap) => heap.push_str(s),
+        }
+    }
+
+    /// Shorten this string to `new_len` bytes; no-op if it is already no longer than that.
+    ///
+    /// Panics if `new_len` is not on a character boundary.
+    pub fn truncate(&mut self, new_len: usize) {
+        if new_len >= self.len() { return; }
+        assert!(self.is_char_boundary(new_len), "truncate: not a char boundary");
+        match &mut self.0 {
+            Repr::Inline { len, .. } => *len = new_len as u8,
+            Repr::Heap(heap) => heap.truncate(new_len),
+        }
+    }
+}
+
+impl Default for SmallString {
+    fn default() -> Self { Self::new() }
+}
+
+impl Deref for SmallString {
+    type Target = str;
+    fn deref(&self) -> &str { self.as_str() }
+}
+
+impl AsRef<str> for SmallString {
+    fn as_ref(&self) -> &str { self.as_str() }
+}
+
+impl Borrow<str> for SmallString {
+    fn borrow(&self) -> &str { self.as_str() }
+}
+
+impl From<&str> for SmallString {
+    fn from(s: &str) -> Self {
+        let mut res = Self::new();
+        res.push_str(s);
+        res
+    }
+}
+
+impl From<char> for SmallString {
+    fn from(c: char) -> Self {
+        let mut res = Self::new();
+        res.push(c);
+        res
+    }
+}
+
+impl From<String> for SmallString {
+    fn from(s: String) -> Self {
+        if s.len() <= INLINE_CAPACITY { Self::from(s.as_str()) } else { SmallString(Repr::Heap(s)) }
+    }
+}
+
+impl Extend<char> for SmallString {
+    fn extend<T: IntoIterator<Item = char>>(&mut self, iter: T) {
+        iter.into_iter().for_each(|c| self.push(c))
+    }
+}
+
+impl PartialEq for SmallString {
+    fn eq(&self, other: &Self) -> bool { self.as_str() == other.as_str() }
+}
+
+impl Eq for SmallString {}
+
+impl PartialEq<str> for SmallString {
+    fn eq(&self, other: &str) -> bool { self.as_str() == other }
+}
+
+impl PartialEq<&str> for SmallString {
+    fn eq(&self, other: &&str) -> bool { self.as_str() == *other }
+}
+
+impl PartialOrd for SmallString {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl Ord for SmallString {
+    fn cmp(&self, other: &Self) -> Ordering { self.as_str().cmp(other.as_str()) }
+}
+
+impl Hash for SmallString {
+    fn hash<H: Hasher>(&self, state: &mut H) { self.as_str().hash(state) }
+}
+
+impl Debug for SmallString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result { Debug::fmt(self.as_str(), f) }
+}
+
+impl Display for SmallString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result { Display::fmt(self.as_str(), f) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SmallString;
+
+    #[test]
+    fn test_small_string() {
+        let mut s = SmallString::new();
+        for c in "λx.xyz".chars() { s.push(c) }
+        assert!(s.is_inline());
+        assert_eq!(s, "λx.xyz");
+        s.push_str("_a_rather_long_suffix_here");
+        assert!(!s.is_inline());
+        assert_eq!(s.as_str(), "λx.xyz_a_rather_long_suffix_here");
+        s.truncate(2);
+        assert_eq!(s, "λ");
+        let mut t = SmallString::from("λx");
+        t.truncate(2);
+        assert_eq!(format!("{:?} {}", t, t), r#""λ" λ"#);
+        assert_eq!(SmallString::from(String::from("abc")), "abc");
+        assert_eq!(SmallString::from('a').to_string() + "bc", "abc");
+    }
+}
@@ -0,0 +1,115 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Allocation counts when lexing an identifier-heavy module, or a huge float literal.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use mini_haskell::scanner::layout::RawLexemeIterator;
+
+struct Counting;
+
+// per thread: the tests run in parallel.
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    // the size of the last allocation, and the number of allocations of a watched size.
+    static LAST_SIZE: Cell<usize> = const { Cell::new(0) };
+    static WATCHED: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+fn allocations() -> usize { ALLOCATIONS.with(Cell::get) }
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        let _ = LAST_SIZE.try_with(|n| n.set(layout.size()));
+        let _ = WATCHED.try_with(|w| {
+            let (size, n) = w.get();
+            if layout.size() == size { w.set((size, n + 1)) }
+        });
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) { System.dealloc(ptr, layout) }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+#[test]
+fn test_identifier_allocations() {
+    const N: usize = 10_000;
+    let src: String = (0..N).map(|k| format!("x{} = Data.Map.lookup{} k\n", k, k)).collect();
+    let before = allocations();
+    let count = RawLexemeIterator::new(src.as_bytes()).count();
+    let allocations = allocations() - before;
+    assert_eq!(count, 4 * N);
+    // each line has 5 names: `x{k}`, `Data`, `Map`, `lookup{k}`, and `k`.
+    let names = 5 * N;
+    if cfg!(feature = "small_string") {
+        // only the module name vectors should allocate.
+        assert!(allocations < names / 4, "too many allocations: {}", allocations);
+    } else {
+        // a few per name: each is a `String`, grown as it is read.
+        assert!(allocations < 3 * names, "too many allocations: {}", allocations);
+    }
+}
+
+#[test]
+fn test_lexeme_type_allocations() {
+    use mini_haskell::scanner::layout::RawLexemeTypeIterator;
+    const N: usize = 10_000;
+    let line = |k: usize| format!(
+        "instance{} = Data.Map.lookup{} k `seq` [0x{:x}, 1.5e-3] ++ \"line \\{}\\n\" : '\\'' -- {}\n",
+        k, k, k, k, k);
+    let src: String = (0..N).map(line).collect();
+    let before = allocations();
+    let mut it = RawLexemeTypeIterator::new(src.as_bytes());
+    let count = it.by_ref().count();
+    let allocations = allocations() - before;
+    let (err, scanner) = it.into_scanner();
+    assert!(err.is_none() && scanner.diagnostics().iter().count() == 0);
+    assert_eq!(count, 16 * N);
+    // none per lexeme: a few per input segment (its buffer, shared, in a node), and a few more
+    // for the scanner itself.
+    let segments = scanner.input_stats().segments();
+    assert!(allocations <= 4 * segments + 16, "too many allocations: {}", allocations);
+}
+
+#[test]
+fn test_huge_exponent_clones() {
+    use mini_haskell::error::{DiagnosticMessage, Error};
+    use mini_haskell::scanner::layout::FatLexemeIterator;
+    use num_bigint::BigInt;
+    let digits = "1234567890".repeat(1000);
+    let exponent: BigInt = digits.parse().unwrap();
+    // a clone of the exponent allocates exactly its digits, which growing it digit by digit
+    // (doubling its capacity) does not: count the allocations of that size.
+    drop(exponent.clone());
+    WATCHED.with(|w| w.set((LAST_SIZE.with(Cell::get), 0)));
+    let src = format!("x = 1e{}", digits);
+    let mut it = FatLexemeIterator::new(src.as_bytes());
+    assert_eq!(it.by_ref().count(), 3);
+    let (err, scanner) = it.into_scanner();
+    let clones = WATCHED.with(|w| w.replace((0, 0)).1);
+    assert!(err.is_none());
+    let diagnostics: Vec<_> = scanner.diagnostics().iter().collect();
+    assert!(matches!(diagnostics[..], [d] if matches!(d.message(),
+        DiagnosticMessage::Error(Error::FloatOutOfBound(x)) if *x == exponent)));
+    // moved from the digits into the diagnostic: never cloned.
+    assert_eq!(clones, 0, "the exponent was cloned {} times", clones);
+}
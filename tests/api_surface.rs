@@ -0,0 +1,287 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The public API surface considered stable, spelled out: a signature change, a new field,
+//! or a new variant fails to build (or run) here, and this file is then to be updated on
+//! purpose.
+
+// the types are spelled out in full on purpose.
+#![allow(clippy::type_complexity)]
+
+use std::mem::size_of;
+use std::path::PathBuf;
+use mini_haskell::*;
+use mini_haskell::error::{Error, Warning, Note, Construct, Label, Checkpoint, SharedDiagnostics};
+use mini_haskell::input::{Encoding, Utf8Error};
+use mini_haskell::printer::{Token, TriviaToken, ReconstructError};
+use mini_haskell::scanner::{Utf8Policy, BidiPolicy, Progress};
+use mini_haskell::scanner::layout::{Annotated, AnnotatedLexemeIterator, LayoutError, LayoutState, BlockId};
+use mini_haskell::driver::{CheckOptions, CheckReport};
+use mini_haskell::batch::{LexOptions, FileLexResult};
+use mini_haskell::lsp::TokenType;
+
+type Src = &'static [u8];
+
+#[test]
+fn functions() {
+    let _: fn(Src) -> Scanner<Src> = Scanner::new;
+    let _: fn(Src, Location) -> Scanner<Src> = Scanner::resume_at;
+    let _: fn(Scanner<Src>, ScannerConfig) -> Scanner<Src> = Scanner::with_config;
+    let _: fn(Scanner<Src>, GhcExtensions) -> Scanner<Src> = Scanner::with_extensions;
+    let _: fn(Scanner<Src>, ColumnMode) -> Scanner<Src> = Scanner::with_column_mode;
+    let _: fn(Scanner<Src>, Encoding) -> Scanner<Src> = Scanner::with_encoding;
+    let _: fn(&mut Scanner<Src>) -> scanner::Result<()> = Scanner::skip_trivia;
+    let _: fn(&mut Scanner<Src>) -> scanner::Result<Lexeme> = Scanner::next_lexeme;
+    let _: fn(&Scanner<Src>) -> Location = Scanner::location;
+    let _: fn(&Scanner<Src>) -> &DiagnosticsEngine = Scanner::diagnostics;
+    let _: fn(&Scanner<Src>) -> Option<&Utf8Error> = Scanner::utf8_error;
+    let _: fn(&Scanner<Src>) -> Progress = Scanner::progress;
+    let _: fn(Src) -> RawLexemeIterator<Src> = RawLexemeIterator::new;
+    let _: fn(Src) -> FatLexemeIterator<Src> = FatLexemeIterator::new;
+    let _: fn(Src) -> EnrichedLexemeIterator<Src> = EnrichedLexemeIterator::new;
+    let _: fn(Src) -> AugmentedLexemeIterator<Src> = AugmentedLexemeIterator::new;
+    let _: fn(Src) -> AnnotatedLexemeIterator<Src> = AnnotatedLexemeIterator::new;
+    let _: fn(RawLexemeIterator<Src>) -> (Option<LexError>, Scanner<Src>) =
+        RawLexemeIterator::into_scanner;
+    let _: fn(AugmentedLexemeIterator<Src>) -> (Option<LexError>, Scanner<Src>) =
+        AugmentedLexemeIterator::into_scanner;
+    let _: fn(&mut AugmentedLexemeIterator<Src>) -> Option<LayoutState> = AugmentedLexemeIterator::state;
+    let _: fn(Src, &str) -> Option<Range> = scanner::find_top_level;
+    let _: fn(Option<LexemeType>, &str) -> Result<Lexeme, LexError> = scanner::single::parse_single;
+    let _: fn(&str) -> Result<Lexeme, LexError> = scanner::single::parse_identifier;
+    let _: fn(&str) -> Result<Lexeme, LexError> = scanner::single::parse_operator;
+    let _: fn(&str) -> Result<Lexeme, LexError> = scanner::single::parse_numeric;
+    let _: fn(&str) -> Result<Lexeme, LexError> = scanner::single::parse_string_literal;
+    let _: fn(&str) -> Vec<scanner::lints::LintToken> = scanner::lints::tokens;
+    let _: fn(&[scanner::lints::LintToken]) -> Vec<Diagnostic> = scanner::lints::run_all;
+    let _: fn(&[Token]) -> Result<String, ReconstructError> = printer::reconstruct;
+    let _: fn(&str) -> Result<Vec<TriviaToken>, LexError> = printer::tokenize;
+    let _: fn(&str, Vec<Range>) -> Vec<Token> = printer::tokens_at;
+    let _: fn(&str) -> lexer::Lexer<'_> = lexer::lex;
+    let _: fn(Src) -> std::io::Result<Result<(), Utf8Error>> = input::validate_utf8;
+    let _: fn(Src, input::Encoding) -> std::io::Result<String> = input::decode;
+    let _: fn(Src, CheckOptions) -> CheckReport = driver::check;
+    let _: fn(&str, scanner::layout::FragmentKind)
+        -> Result<(Vec<AugmentedLexeme>, Vec<Diagnostic>), LexError> = scanner::layout::lex_fragment;
+    let _: fn(&[PathBuf], &LexOptions) -> Vec<FileLexResult> = batch::lex_files;
+    let _: fn(&mut SourceManager, &[PathBuf], &LexOptions) -> Vec<FileLexResult> = batch::lex_files_in;
+    let _: fn(Scanner<Src>, FileId) -> Scanner<Src> = Scanner::with_file;
+    let _: fn(&Scanner<Src>) -> FileId = Scanner::file;
+    let _: fn(&mut SourceManager, PathBuf) -> FileId = SourceManager::add;
+    let _: fn(&SourceManager, FileId) -> Option<&std::path::Path> = SourceManager::path;
+    let _: fn(&Diagnostic) -> FileId = Diagnostic::file;
+    let _: fn(&str, &[(Lexeme, Range)]) -> Vec<[u32; 5]> = lsp::semantic_tokens;
+    let _: fn(DiagnosticsEngine) -> SharedDiagnostics = SharedDiagnostics::new;
+    let _: fn(&SharedDiagnostics, &mut Scanner<Src>, fn(&mut Scanner<Src>)) = SharedDiagnostics::lend;
+    let _: fn(&DiagnosticsEngine) -> Option<&Diagnostic> = DiagnosticsEngine::peek;
+    let _: fn(&mut DiagnosticsEngine, Diagnostic) = DiagnosticsEngine::push;
+    let _: fn(&DiagnosticsEngine) -> Checkpoint = DiagnosticsEngine::checkpoint;
+    let _: fn(&mut DiagnosticsEngine, Checkpoint) = DiagnosticsEngine::revert;
+    let _: fn(&Diagnostic) -> Range = Diagnostic::range;
+    let _: fn(&Diagnostic) -> &DiagnosticMessage = Diagnostic::message;
+    let _: fn(&Diagnostic) -> &[Label] = Diagnostic::labels;
+    let _: fn(&Diagnostic) -> Severity = Diagnostic::severity;
+    let _: fn(&Diagnostic) -> Severity = Diagnostic::effective_severity;
+    let _: fn(&Diagnostic, &str) -> String = Diagnostic::render;
+    let _: fn(&Diagnostic, &str, &SourceManager, &term::Style) -> String = Diagnostic::render_in;
+    let _: fn(&QName) -> ModuleId = QName::as_module_path;
+}
+
+fn item<T, I: Iterator<Item=T>>() {}
+
+#[test]
+fn iterator_items() {
+    item::<Lexeme, RawLexemeIterator<Src>>();
+    item::<(Lexeme, Range), FatLexemeIterator<Src>>();
+    item::<Annotated, AnnotatedLexemeIterator<Src>>();
+    item::<EnrichedLexeme, EnrichedLexemeIterator<Src>>();
+    type Layout = scanner::layout::Layout<std::vec::IntoIter<EnrichedLexeme>>;
+    item::<Result<AugmentedLexeme, LayoutError>, Layout>();
+    item::<AugmentedLexeme, AugmentedLexemeIterator<Src>>();
+    item::<Diagnostic, <DiagnosticsEngine as IntoIterator>::IntoIter>();
+}
+
+#[test]
+fn plain_structs() {
+    // all fields public, none added silently.
+    let begin = Location { line: 1, column: 5, char_column: 5, offset: 4 };
+    assert_eq!(begin, Location::at(1, 5, 4));
+    let range = Range { begin, end: Location { line: 2, column: 1, char_column: 1, offset: 10 } };
+    assert_eq!(range, Range::new(range.begin, range.end));
+    let config = ScannerConfig {
+        max_float_exponent: 4096,
+        buf_size: 4096,
+        io_retries: 5,
+        max_errors: 1000,
+        utf8_policy: Utf8Policy::Recover,
+        bidi_policy: BidiPolicy::Warn,
+    };
+    assert_eq!(config, ScannerConfig::default());
+    let extensions = GhcExtensions {
+        magic_hash: false,
+        explicit_forall: false,
+        arrows: false,
+        lambda_case: false,
+        numeric_underscores: false,
+    };
+    assert_eq!(extensions, GhcExtensions::default());
+    let FileId(anonymous) = FileId::ANONYMOUS;
+    assert_eq!(FileId(anonymous), FileId::default());
+    let LexError { expected, unexpected, location, invalid_utf8 } =
+        RawLexemeIterator::new(&b"'"[..]).into_scanner().0.unwrap_or_else(|| LexError {
+            expected: Vec::new(), unexpected: None, location: Location::new(), invalid_utf8: None,
+        });
+    let _: (Vec<LexemeType>, Option<char>, Location, Option<Utf8Error>) =
+        (expected, unexpected, location, invalid_utf8);
+    let Utf8Error { offset, bytes } = Utf8Error { offset: 0, bytes: Vec::new() };
+    let _: (usize, Vec<u8>) = (offset, bytes);
+    let Token { text, range } = Token { text: String::new(), range };
+    let _: (String, Range) = (text, range);
+    let QName { module: ModuleId(module), name } = QName::new(Name::from("x"));
+    let _: (Vec<Name>, Name) = (module, name);
+    let _: BlockId = BlockId(0);
+}
+
+// exhaustive matches: a new variant must be listed here.
+#[allow(dead_code)]
+fn variants() {
+    fn lexeme(t: &Lexeme) -> LexemeType {
+        use Lexeme::*;
+        match t {
+            Whitespace => LexemeType::Whitespace,
+            Identifier(_) => LexemeType::Identifier,
+            Operator(_) => LexemeType::Operator,
+            QIdentifier(_) => LexemeType::QIdentifier,
+            QOperator(_) => LexemeType::QOperator,
+            QualifiedPrefix(_) => LexemeType::QualifiedPrefix,
+            Integer(_) => LexemeType::Integer,
+            Float(_) => LexemeType::Float,
+            CharLiteral(_) => LexemeType::CharLiteral,
+            StringLiteral(_) => LexemeType::StringLiteral,
+            ReservedId(_) => LexemeType::ReservedId,
+            ReservedOp(_) => LexemeType::ReservedOp,
+            Comma => LexemeType::Comma,
+            Semicolon => LexemeType::Semicolon,
+            Backtick => LexemeType::Backtick,
+            OpenCurlyBracket => LexemeType::OpenCurlyBracket,
+            CloseCurlyBracket => LexemeType::CloseCurlyBracket,
+            OpenParenthesis => LexemeType::OpenParenthesis,
+            CloseParenthesis => LexemeType::CloseParenthesis,
+            OpenSquareBracket => LexemeType::OpenSquareBracket,
+            CloseSquareBracket => LexemeType::CloseSquareBracket,
+            MagicHash(..) => LexemeType::MagicHash,
+            OpenUnboxedParenthesis => LexemeType::OpenUnboxedParenthesis,
+            CloseUnboxedParenthesis => LexemeType::CloseUnboxedParenthesis,
+        }
+    }
+    fn error(e: &Error) {
+        use Error::*;
+        match e {
+            InvalidUTF8(_) | InputFailure(_) | InvalidChar(_) | InvalidToken(_) |
+            IncompleteLexeme(_) | FloatOutOfBound(_) | CharOutOfBound(_) |
+            InvalidCharInString(_) | TabInIndentation(_) | TokenTooLong(..) |
+            ConfusableWhitespace(_) | Layout(_) | ControlChar(..) |
+            NonProgressingRule | MisplacedDigitSeparator | BidiControl(_) | UnknownEscape(_) |
+            MissingEscapeDigits(_) => (),
+        }
+    }
+    fn warning(w: &Warning) {
+        match w {
+            Warning::TabInIndentation(_) | Warning::AdjacentStringLiterals |
+            Warning::IdentifierNormalization(_) | Warning::BidiControl(_) => (),
+        }
+    }
+    fn note(n: Note) {
+        match n { Note::Latin1Fallback | Note::TooManyErrors(_) | Note::BirdTracks => () }
+    }
+    fn message(m: &DiagnosticMessage) {
+        use DiagnosticMessage::*;
+        match m { Error(_) | Warning(_) | Note(_) => () }
+    }
+    fn severity(s: Severity) {
+        match s { Severity::Note | Severity::Warning | Severity::Error | Severity::Fatal => () }
+    }
+    fn construct(c: Construct) {
+        match c {
+            Construct::Lexeme(_) | Construct::BlockComment | Construct::StringGap | Construct::Escape => (),
+        }
+    }
+    fn layout_error(e: LayoutError) {
+        match e {
+            LayoutError::UnmatchedCloseBracket(_) | LayoutError::UnclosedOpenBracket(_) |
+            LayoutError::LeavesEnclosingContext(_) => (),
+        }
+    }
+    fn reconstruct_error(e: ReconstructError) {
+        use ReconstructError::*;
+        match e {
+            Overlapping { index: _, previous_end: _ } | OutOfOrder { index: _, previous_begin: _ } |
+            RangeMismatch { index: _, actual_end: _ } => (),
+        }
+    }
+    fn enriched(t: &EnrichedLexeme) {
+        use EnrichedLexeme::*;
+        match t { CurlyN(_) | AngleN(_) | Normal(..) | Eof(_) => () }
+    }
+    fn augmented(t: &AugmentedLexeme) {
+        use AugmentedLexeme::*;
+        match t {
+            Real(..) | PhantomOpenCurlyBracket(_) | PhantomCloseCurlyBracket(_) |
+            PhantomSemicolon(_) | Eof(_) => (),
+        }
+    }
+    fn annotated(t: &Annotated) {
+        match t { Annotated::Token(..) | Annotated::Diag(_) => () }
+    }
+    fn trivia(t: &TriviaToken) {
+        match t { TriviaToken::Trivia(_) | TriviaToken::Token(_) => () }
+    }
+    fn options(e: Encoding, p: Utf8Policy, c: ColumnMode) {
+        match e { Encoding::Utf8 | Encoding::Latin1 => () }
+        match p { Utf8Policy::Recover | Utf8Policy::Fail => () }
+        match c { ColumnMode::Chars | ColumnMode::DisplayWidth => () }
+    }
+    fn token_type(t: TokenType) {
+        use TokenType::*;
+        match t { Keyword | Variable | Type | Operator | Number | String | Comment | Namespace => () }
+    }
+}
+
+#[test]
+fn keywords() {
+    // the Haskell 2010 ones: those of the extensions are matched exhaustively in `lexeme.rs`.
+    assert_eq!(RId::ALL.len(), 23);
+    assert_eq!(ROp::ALL.len(), 11);
+    assert_eq!(ROp::ARROW_TAILS.len(), 4);
+    assert_eq!(lsp::TOKEN_TYPES.len(), 8);
+}
+
+// sizes of the types passed around by value, on 64-bit targets: growing one is a conscious
+// decision, as it costs every token in every stream.
+#[cfg(target_pointer_width = "64")]
+#[test]
+fn sizes() {
+    assert_eq!(size_of::<Location>(), 32);
+    assert_eq!(size_of::<Range>(), 64);
+    assert_eq!(size_of::<Lexeme>(), 64);
+    assert_eq!(size_of::<(Lexeme, Range)>(), 128);
+    assert_eq!(size_of::<EnrichedLexeme>(), 128);
+    assert_eq!(size_of::<AugmentedLexeme>(), 128);
+    // `String`, or a small string with the `small_string` feature.
+    assert_eq!(size_of::<Name>(), if cfg!(feature = "small_string") { 32 } else { 24 });
+}
@@ -0,0 +1,97 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Lexer conformance suite: every `tests/conformance/*.hs` is lexed with the enriched
+//! iterator and compared against the golden `.tokens` file next to it.
+//!
+//! Run with `UPDATE_GOLDEN=1` to regenerate the golden files.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use mini_haskell::{LexError, Scanner};
+use mini_haskell::scanner::layout::{
+    EnrichedLexemeIterator, RawLexemeIterator, RawLexemeTypeIterator, render_enriched,
+};
+
+fn lex(path: &Path) -> String {
+    let file = File::open(path).unwrap();
+    let mut it = EnrichedLexemeIterator::new(file);
+    let mut res = render_enriched(it.by_ref());
+    let (err, scanner) = it.into_scanner();
+    for d in scanner.diagnostics().iter() {
+        res += &format!("-- {}\n", d);
+    }
+    if let Some(err) = err {
+        res += &format!("-- lexical error at {}\n", err);
+    }
+    res
+}
+
+fn inputs() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance");
+    let mut inputs: Vec<PathBuf> = std::fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "hs"))
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty(), "no conformance tests found in {}", dir.display());
+    inputs
+}
+
+#[test]
+fn conformance() {
+    let inputs = inputs();
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some_and(|v| v == "1");
+    let mut failures = Vec::new();
+    for input in &inputs {
+        let actual = lex(input);
+        let golden = input.with_extension("tokens");
+        if update {
+            std::fs::write(&golden, &actual).unwrap();
+            continue;
+        }
+        match std::fs::read_to_string(&golden) {
+            Ok(expected) if expected == actual => (),
+            Ok(expected) => failures.push(format!(
+                "{}: mismatch\n--- expected\n{}--- actual\n{}",
+                input.display(), expected, actual)),
+            Err(err) => failures.push(format!("{}: cannot read {}: {}",
+                                              input.display(), golden.display(), err)),
+        }
+    }
+    assert!(failures.is_empty(), "{} of {} conformance tests failed \
+        (run with UPDATE_GOLDEN=1 to regenerate):\n{}",
+            failures.len(), inputs.len(), failures.join("\n"));
+}
+
+#[test]
+fn lexeme_types() {
+    // the types only, and the same diagnostics, without the payloads.
+    fn diagnostics((err, scanner): (Option<LexError>, Scanner<File>)) -> Vec<String> {
+        let err = err.map(|err| format!("lexical error at {}", err));
+        scanner.diagnostics().iter().map(|d| d.to_string()).chain(err).collect()
+    }
+    for input in &inputs() {
+        let mut full = RawLexemeIterator::new(File::open(input).unwrap());
+        let mut types = RawLexemeTypeIterator::new(File::open(input).unwrap());
+        let expected: Vec<_> = full.by_ref().map(|t| t.get_type()).collect();
+        assert_eq!(types.by_ref().collect::<Vec<_>>(), expected, "on {}", input.display());
+        assert_eq!(diagnostics(types.into_scanner()), diagnostics(full.into_scanner()),
+                   "on {}", input.display());
+    }
+}
@@ -0,0 +1 @@
+c = ['a', '\n', '\'', '\\', '\65', '\x41', '\DEL', '"']
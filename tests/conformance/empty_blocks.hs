@@ -0,0 +1,3 @@
+f = do
+g = let in 1
+module' = x where
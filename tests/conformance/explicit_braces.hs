@@ -0,0 +1,6 @@
+module M where {
+f = do { x; y }
+; g = do
+    a
+    b
+}
@@ -0,0 +1,5 @@
+main = do
+  putStrLn "a"
+  x <- getLine
+  do print x
+     print x
@@ -0,0 +1,4 @@
+f = let x = 1
+        y = 2
+    in x + y
+g = let z = 3 in z
@@ -0,0 +1,3 @@
+f x = case x of
+  0 -> "zero"
+  _ -> case x of { 1 -> "one"; _ -> "many" }
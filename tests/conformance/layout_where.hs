@@ -0,0 +1,5 @@
+module Main where
+
+f x = g x
+  where g y = y
+        h = 1
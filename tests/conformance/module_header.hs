@@ -0,0 +1,4 @@
+module Data.Thing (Thing(..), make, (<+>)) where
+
+import qualified Data.Map as M
+import Prelude hiding (lookup)
@@ -0,0 +1,4 @@
+{- outer {- inner -} still outer -}
+x = {- inline -} 1
+{-- dashes --}
+{- {- {- deep -} -} -} y
@@ -0,0 +1,7 @@
+a = 0
+b = 007
+c = 0x1F 0X1f
+d = 0o17 0O17
+e = 1.5
+f = 1e10 1E+10 1.5e-3
+g = 1.e5
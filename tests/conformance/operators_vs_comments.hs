@@ -0,0 +1,6 @@
+x --> y
+a |-- b
+-- a line comment
+--- also a comment
+---| still a comment
+z = x -- trailing comment
@@ -0,0 +1,5 @@
+x = M.y
+y = A.B.C.d
+z = Data.Map.Map
+w = (M.+) (A.B.<$>)
+v = M.. 
@@ -0,0 +1,4 @@
+f :: a -> b => c
+f = \x -> x @ y ~ z
+[1 .. 2] | x <- xs
+a : b
@@ -0,0 +1 @@
+f (a, b) [c] = a `op` b; g {} = ()
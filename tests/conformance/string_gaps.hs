@@ -0,0 +1,5 @@
+s = "hello, \
+    \world"
+t = "tab\tnew\nline\
+\"
+u = "\SOH\&H\1234\x41\o101"
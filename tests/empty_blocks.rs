@@ -0,0 +1,83 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The layout algorithm on blocks left empty at the end of input, see "Haskell 2010 Report:
+//! 10.3 Layout", Notes 2 and 6: every case must give exactly the augmented stream, with no
+//! diagnostics and no lexical error.
+
+use mini_haskell::lexeme::Lexeme::{OpenCurlyBracket, CloseCurlyBracket};
+use mini_haskell::scanner::layout::{AugmentedLexemeIterator, AugmentedLexeme::*};
+
+fn test(input: &str, output: &str, eof: &str) {
+    let mut it = AugmentedLexemeIterator::new(input.as_bytes());
+    let mut res: Vec<_> = it.by_ref().map(|t| match t {
+        Real(t, _) if matches!(t, OpenCurlyBracket | CloseCurlyBracket) => format!("'{}'", t),
+        Real(t, _) => t.to_string(),
+        PhantomOpenCurlyBracket(_) => "{".to_string(),
+        PhantomSemicolon(_) => ";".to_string(),
+        PhantomCloseCurlyBracket(_) => "}".to_string(),
+        Eof(location) => format!("<eof> {}", location),
+    }).collect();
+    assert_eq!(res.pop(), Some(format!("<eof> {}", eof)), "{:?}", input);
+    assert_eq!(res.join(" "), output, "{:?}", input);
+    let (err, scanner) = it.into_scanner();
+    assert_eq!(err, None, "{:?}", input);
+    assert!(scanner.diagnostics().is_empty(), "{:?}", input);
+}
+
+#[test]
+fn trailing_where() {
+    // `{0}` after the `where`: an empty block, then `<0>` closes the top level.
+    test("f = x where", "{ f = x where { } }", "1:12");
+    test("f = x where\n", "{ f = x where { } }", "2:1");
+    // `{1}` is not indented more than the top level: empty, then `<1>` separates.
+    test("f = g where\nh = 1", "{ f = g where { } ; h = fromIntegral 1 }", "2:6");
+}
+
+#[test]
+fn module_without_body() {
+    // `{0}` opens the module body with an empty context stack: still an empty block.
+    test("module M where", "module M where { }", "1:15");
+    test("module M where\n\n", "module M where { }", "3:1");
+    test("module M (f) where -- nothing else", "module M ( f ) where { }", "1:35");
+}
+
+#[test]
+fn trailing_do() {
+    test("main = do", "{ main = do { } }", "1:10");
+    test("main = do\n  -- nothing", "{ main = do { } }", "2:13");
+}
+
+#[test]
+fn empty_alternatives() {
+    // the `{` after `of` is explicit: no `{n}` is inserted.
+    test("f x = case x of {}", "{ f x = case x of '{' '}' }", "1:19");
+    test("f x = case x of\n  {}\ng = 1", "{ f x = case x of '{' '}' ; g = fromIntegral 1 }", "3:6");
+    // ... but an implicit block is still empty when nothing follows.
+    test("f x = case x of", "{ f x = case x of { } }", "1:16");
+}
+
+#[test]
+fn no_lexemes() {
+    // the `{n}` before the first lexeme is never inserted, so only the end of input remains.
+    test("", "", "1:1");
+    test("\n\n", "", "3:1");
+    test("-- only a comment", "", "1:18");
+    test("-- only a comment\n", "", "2:1");
+    test("{- only a block comment -}", "", "1:27");
+}
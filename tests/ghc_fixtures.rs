@@ -0,0 +1,147 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Differential test against GHC's lexer: every `tests/ghc_fixtures/*.hs` is lexed into fat
+//! lexemes, normalized to `kind,line,col,text` rows through the translation table below, and
+//! compared with the GHC token dump (`.csv`) next to it. See `tests/ghc_fixtures/README.md` for
+//! how the dumps are made.
+//!
+//! The known divergences are listed by position in `tests/ghc_fixtures/allowlist.txt`, each
+//! with the reason; a divergence not listed fails the test, and so does an entry no longer
+//! diverging. The dumps come from GHC only, never from this crate: there is deliberately no way
+//! to write them from here.
+//!
+//! No dump is checked in yet, so the test is ignored: it is to be enabled along with the first
+//! dumps from `scripts/DumpTokens.hs`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use mini_haskell::lexeme::Lexeme::{self, *};
+use mini_haskell::scanner::basic::Large;
+use mini_haskell::scanner::layout::FatLexemeIterator;
+use mini_haskell::utils::char::CharPredicate;
+
+// (line, column) => (kind, text)
+type Rows = BTreeMap<(usize, usize), (String, String)>;
+
+// the translation table: the normalized kinds of the README.
+fn kind(lexeme: &Lexeme) -> &'static str {
+    let large = |s: &str| s.chars().next().is_some_and(|c| Large.check(c));
+    match lexeme {
+        Identifier(s) if large(s) => "conid",
+        Identifier(_) => "varid",
+        QIdentifier(q) if large(&q.name) => "qconid",
+        QIdentifier(_) => "qvarid",
+        Operator(s) if s.starts_with(':') => "consym",
+        Operator(_) => "varsym",
+        QOperator(q) if q.name.starts_with(':') => "qconsym",
+        QOperator(_) => "qvarsym",
+        // no such token in GHC: see the allowlist.
+        QualifiedPrefix(_) => "qualprefix",
+        Integer(_) => "integer",
+        Float(_) => "rational",
+        CharLiteral(_) => "char",
+        StringLiteral(_) => "string",
+        ReservedId(_) => "keyword",
+        ReservedOp(_) => "reservedop",
+        Comma | Semicolon | Backtick | OpenCurlyBracket | CloseCurlyBracket |
+        OpenParenthesis | CloseParenthesis | OpenSquareBracket | CloseSquareBracket => "special",
+        Whitespace | MagicHash(..) | OpenUnboxedParenthesis | CloseUnboxedParenthesis => "other",
+    }
+}
+
+// the text of a token as in the dumps: line breaks written as `\n`.
+fn text(source: &[char], begin: usize, end: usize) -> String {
+    source[begin..end].iter().collect::<String>().replace("\r\n", "\\n").replace('\n', "\\n")
+}
+
+fn lex(path: &Path) -> Rows {
+    let source = std::fs::read_to_string(path).unwrap();
+    let chars: Vec<char> = source.chars().collect();
+    let mut it = FatLexemeIterator::new(source.as_bytes());
+    let rows = it.by_ref().map(|(lexeme, range)| {
+        let position = (range.begin.line, range.begin.char_column);
+        (position, (kind(&lexeme).to_string(), text(&chars, range.begin.offset, range.end.offset)))
+    }).collect();
+    let (error, _) = it.into_scanner();
+    assert!(error.is_none(), "{}: lexical error at {}", path.display(), error.unwrap());
+    rows
+}
+
+fn read_dump(path: &Path) -> Rows {
+    let dump = std::fs::read_to_string(path)
+        .unwrap_or_else(|err| panic!("cannot read {} (written by DumpTokens.hs): {}", path.display(), err));
+    let mut lines = dump.lines();
+    assert_eq!(lines.next(), Some("kind,line,col,text"), "{}: bad header", path.display());
+    lines.map(|row| {
+        // the text comes last, commas and all.
+        let fields: Vec<_> = row.splitn(4, ',').collect();
+        let number = |k: usize| fields[k].parse::<usize>()
+            .unwrap_or_else(|_| panic!("{}: bad row {:?}", path.display(), row));
+        ((number(1), number(2)), (fields[0].to_string(), fields[3].to_string()))
+    }).collect()
+}
+
+// `file:line:col` entries, one per line, after `#` comments giving the reasons.
+fn read_allowlist(path: &Path) -> BTreeSet<(String, usize, usize)> {
+    std::fs::read_to_string(path).unwrap().lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|entry| {
+            let fields: Vec<_> = entry.split(':').collect();
+            let number = |k: usize| fields.get(k).and_then(|x| x.parse().ok())
+                .unwrap_or_else(|| panic!("bad allowlist entry {:?}", entry));
+            (fields[0].to_string(), number(1), number(2))
+        })
+        .collect()
+}
+
+fn show(row: Option<&(String, String)>) -> String {
+    row.map_or("nothing".to_string(), |(kind, text)| format!("{} {}", kind, text))
+}
+
+#[test]
+#[ignore = "needs the GHC token dumps of tests/ghc_fixtures/scripts/DumpTokens.hs"]
+fn ghc_fixtures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/ghc_fixtures");
+    let mut inputs: Vec<PathBuf> = std::fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "hs"))
+        .collect();
+    inputs.sort();
+    assert!(inputs.len() >= 5, "too few GHC fixtures in {}", dir.display());
+    let mut allowed = read_allowlist(&dir.join("allowlist.txt"));
+    let mut failures = Vec::new();
+    for input in &inputs {
+        let name = input.file_name().unwrap().to_string_lossy().into_owned();
+        let ours = lex(input);
+        let ghc = read_dump(&input.with_extension("csv"));
+        let positions: BTreeSet<_> = ours.keys().chain(ghc.keys()).collect();
+        for &(line, col) in positions {
+            let (a, b) = (ours.get(&(line, col)), ghc.get(&(line, col)));
+            if a == b { continue; }
+            if !allowed.remove(&(name.clone(), line, col)) {
+                failures.push(format!("{}:{}:{}: {}, but GHC has {}", name, line, col, show(a), show(b)));
+            }
+        }
+    }
+    failures.extend(allowed.iter().map(|(name, line, col)|
+        format!("{}:{}:{}: allowed, but no longer diverging", name, line, col)));
+    assert!(failures.is_empty(), "{} divergences from GHC (fix the scanner, or record them \
+        in the allowlist with the reason):\n{}", failures.len(), failures.join("\n"));
+}
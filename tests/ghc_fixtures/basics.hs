@@ -0,0 +1,24 @@
+module Data.Queue
+  ( Queue
+  , empty
+  , push
+  , pop
+  ) where
+
+import qualified Data.List as L
+import Prelude hiding (reverse)
+
+-- | A banker's queue.
+data Queue a = Queue [a] [a]
+  deriving (Eq, Show)
+
+empty :: Queue a
+empty = Queue [] []
+
+push :: a -> Queue a -> Queue a
+push x (Queue f r) = Queue f (x : r)
+
+pop :: Queue a -> Maybe (a, Queue a)
+pop (Queue [] []) = Nothing
+pop (Queue [] r) = pop (Queue (L.reverse r) [])
+pop (Queue (x:f) r) = Just (x, Queue f r)
@@ -0,0 +1,11 @@
+{-# LANGUAGE TypeApplications, BangPatterns, ScopedTypeVariables #-}
+module Divergences where
+
+import qualified Data.Map as M
+
+size = M.(size)
+readInt = read @Int
+strictSum !acc [] = acc
+lazyPat ~(a, b) = a
+poly :: forall a. a -> a
+poly x = x
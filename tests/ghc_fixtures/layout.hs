@@ -0,0 +1,16 @@
+module Layout where
+
+main :: IO ()
+main = do
+  let x = 1
+      y = 2
+  if x < y
+    then putStrLn "less"
+    else putStrLn "more"
+  case compare x y of
+    LT -> pure ()
+    _ -> do { print x ; print y }
+  where
+    helper n
+      | n > 0 = n
+      | otherwise = negate n
@@ -0,0 +1,8 @@
+module Literals where
+
+ints = [0, 42, 0x2A, 0X2a, 0o52, 0O52, 007]
+floats = [1.5, 2.0e10, 3e-2, 6.02E23, 1.0e+3]
+chars = ['a', '\n', '\'', '\\', '\x41', '\o101', '\65', '\SOH', '\^A', '"']
+strings = ["", "hello", "tab\there", "quote\"d", "\SO\&H", "gap\
+    \ped", "\1234\&5"]
+unicode = ["λ → ∀", 'λ', "\x2200"]
@@ -0,0 +1,14 @@
+module Operators where
+
+infixr 5 +++
+(+++) :: [a] -> [a] -> [a]
+xs +++ ys = foldr (:) ys xs
+
+sections = (map (+ 1), (`div` 2), (subtract 1), (.), (:[]))
+arrows = \x -> x >>= return . id
+compose = Prelude.map Prelude.. Prelude.filter
+dashes = (-->) 1 2 --> 3 -- a comment
+    where (-->) = (+)
+cons = x :| xs
+ranges = [1 .. 10] ++ [x | x <- [1..], even x]
+lenses = a ^. b & c .~ d
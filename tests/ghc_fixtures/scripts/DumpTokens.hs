@@ -0,0 +1,149 @@
+{- cabal:
+build-depends: base >=4.18 && <5, filepath, ghc-lib-parser ==9.8.*
+-}
+{-
+ - mini-haskell: light-weight Haskell for fun
+ - Copyright (C) 2021  Xie Ruifeng
+ -
+ - This program is free software: you can redistribute it and/or modify
+ - it under the terms of the GNU Affero General Public License as
+ - published by the Free Software Foundation, either version 3 of the
+ - License, or (at your option) any later version.
+ -
+ - This program is distributed in the hope that it will be useful,
+ - but WITHOUT ANY WARRANTY; without even the implied warranty of
+ - MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ - GNU Affero General Public License for more details.
+ -
+ - You should have received a copy of the GNU Affero General Public License
+ - along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ -}
+
+-- | Dump GHC's token stream of each fixture given, to the @.csv@ next to it, normalized as
+-- in @README.md@:
+--
+-- > cabal run tests/ghc_fixtures/scripts/DumpTokens.hs -- tests/ghc_fixtures/*.hs
+--
+-- The tokens are classified by the names of their constructors, so that the arities (which
+-- change between GHC versions) do not matter; the text comes from the source, by the spans.
+module Main (main) where
+
+import Data.Char (isSpace)
+import Data.List (isSuffixOf, nub, stripPrefix)
+import Data.Maybe (mapMaybe)
+import System.Environment (getArgs)
+import System.Exit (exitFailure)
+import System.FilePath (replaceExtension)
+import System.IO (hPutStrLn, stderr)
+
+import GHC.Data.EnumSet (EnumSet)
+import qualified GHC.Data.EnumSet as EnumSet
+import GHC.Data.FastString (mkFastString)
+import GHC.Data.StringBuffer (stringToStringBuffer)
+import GHC.Driver.Session (Language (Haskell2010), impliedXFlags, languageExtensions)
+import GHC.LanguageExtensions.Type (Extension)
+import GHC.Parser.Lexer (ParseResult (..), Token (..), lexTokenStream, mkParserOpts)
+import GHC.Types.SrcLoc
+import GHC.Utils.Error (emptyDiagOpts)
+
+main :: IO ()
+main = getArgs >>= mapM_ dump
+
+dump :: FilePath -> IO ()
+dump path = do
+  source <- readFile path
+  let opts = mkParserOpts (extensions source) emptyDiagOpts [] False False False False
+      start = mkRealSrcLoc (mkFastString path) 1 1
+  case lexTokenStream opts (stringToStringBuffer source) start of
+    POk _ tokens -> writeFile (replaceExtension path "csv") . unlines $
+      "kind,line,col,text" : mapMaybe (row (lines' source)) tokens
+    PFailed _ -> hPutStrLn stderr (path ++ ": lexical error in GHC") >> exitFailure
+
+-- | Haskell 2010, and the extensions of the LANGUAGE pragmas, with those they imply.
+extensions :: String -> EnumSet Extension
+extensions source = EnumSet.fromList (imply (nub (base ++ named)))
+  where
+    base = languageExtensions (Just Haskell2010)
+    named = [x | name <- pragmas source, x <- [minBound .. maxBound], show x == name]
+    imply xs = let xs' = nub (xs ++ [y | (x, True, y) <- impliedXFlags, x `elem` xs])
+               in if length xs' == length xs then xs else imply xs'
+
+-- | The names in the @{-# LANGUAGE ... #-}@ pragmas, one pragma per line.
+pragmas :: String -> [String]
+pragmas source = concatMap names (lines source)
+  where
+    names l = case stripPrefix "{-# LANGUAGE" l of
+      Just rest | "#-}" `isSuffixOf` rest -> words (map comma (take (length rest - 3) rest))
+      _ -> []
+    comma c = if c == ',' then ' ' else c
+
+-- | The source lines, with their line breaks.
+lines' :: String -> [String]
+lines' "" = []
+lines' s = let (l, rest) = break (== '\n') s in case rest of
+  '\n' : rest' -> (l ++ "\n") : lines' rest'
+  _ -> [l]
+
+-- | A row for a token, or nothing for the dropped ones (layout, comments, pragmas).
+row :: [String] -> Located Token -> Maybe String
+row source (L (RealSrcSpan sp _) token) = do
+  let name = takeWhile (not . isSpace) (show token)
+      (line, col) = (srcSpanStartLine sp, srcSpanStartCol sp)
+      (line', col') = (srcSpanEndLine sp, srcSpanEndCol sp)
+      text = slice source (line, col) (line', col')
+  k <- kind name text ((line, col) == (line', col'))
+  pure (k ++ "," ++ show line ++ "," ++ show (codePoint (source !! (line - 1)) col) ++ "," ++ escape text)
+row _ _ = Nothing
+
+-- | The normalized kind of a token, by the name of its constructor; `Nothing` for dropped
+-- ones: `empty` tells the zero-width tokens of the layout.
+kind :: String -> String -> Bool -> Maybe String
+kind name text empty
+  | name `elem` ["ITvocurly", "ITvccurly", "ITeof"] = Nothing
+  | name == "ITsemi" && empty = Nothing
+  | "Comment" `isSuffixOf` name || "_prag" `isSuffixOf` name = Nothing
+  | name `elem` ["ITvarid", "ITas", "IThiding", "ITqualified", "ITsafe", "ITunsafe", "ITexport",
+                  "ITfamily", "ITrole", "ITpattern", "ITstock", "ITanyclass", "ITvia"] = Just "varid"
+  | name `elem` ["ITconid", "ITqvarid", "ITqconid", "ITvarsym", "ITconsym", "ITqvarsym", "ITqconsym"] =
+      -- the loose infix `~` and `@` are varsyms in GHC 9: reserved operators in the Report.
+      Just (if name == "ITvarsym" && text `elem` ["~", "@"] then "reservedop" else drop 2 name)
+  | name == "ITinteger" = Just "integer"
+  | name == "ITrational" = Just "rational"
+  | name == "ITchar" = Just "char"
+  | name == "ITstring" = Just "string"
+  | name `elem` map ("IT" ++) keywords = Just "keyword"
+  | name `elem` map ("IT" ++) ["dotdot", "colon", "dcolon", "equal", "lam", "vbar", "larrow",
+                                "rarrow", "at", "darrow"] = Just "reservedop"
+  | name `elem` map ("IT" ++) ["oparen", "cparen", "obrack", "cbrack", "ocurly", "ccurly",
+                                "comma", "semi", "backquote"] = Just "special"
+  | name == "ITtypeApp" = Just "typeapp"
+  | name == "ITbang" = Just "bang"
+  | name == "ITtilde" = Just "tilde"
+  | otherwise = Just "other"
+  where
+    keywords = ["case", "class", "data", "default", "deriving", "do", "else", "if", "import",
+                "in", "infix", "infixl", "infixr", "instance", "let", "module", "newtype", "of",
+                "then", "type", "where", "underscore", "forall"]
+
+-- | The source text between two GHC positions, whose columns count tab stops of 8.
+slice :: [String] -> (Int, Int) -> (Int, Int) -> String
+slice source (l, c) (l', c')
+  | l == l' = take (index l c' - index l c) (drop (index l c) (line l))
+  | otherwise = drop (index l c) (line l) ++ concatMap line [l + 1 .. l' - 1] ++ take (index l' c') (line l')
+  where
+    line k = source !! (k - 1)
+    -- from 0, into the line.
+    index k col = codePoint (line k) col - 1
+
+-- | The code point column (from 1) of a GHC column on a line.
+codePoint :: String -> Int -> Int
+codePoint l col = go 1 1 l
+  where
+    go k c _ | c >= col = k
+    go k c ('\t' : rest) = go (k + 1) (((c - 1) `div` 8 + 1) * 8 + 1) rest
+    go k c (_ : rest) = go (k + 1) (c + 1) rest
+    go k _ [] = k
+
+-- | Line breaks as `\n`, as in the dumps.
+escape :: String -> String
+escape = concatMap (\c -> if c == '\n' then "\\n" else [c]) . filter (/= '\r')
@@ -0,0 +1,67 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The token types must keep building without the default `std` feature, and so must a
+//! `no_std` crate lexing a `&str` with them.
+
+use std::path::Path;
+use std::process::Command;
+
+fn build(dir: &Path, args: &[&str], target: &Path) -> bool {
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    // a separate target directory, so as not to wait for the lock held by this test run.
+    Command::new(cargo)
+        .args(args)
+        .env("CARGO_TARGET_DIR", target)
+        .current_dir(dir)
+        .status()
+        .expect("failed to run cargo")
+        .success()
+}
+
+#[test]
+fn no_std_build() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let target = root.join("target/no-std");
+    assert!(build(root, &["build", "--lib", "--no-default-features"], &target),
+            "`cargo build --no-default-features` failed");
+    // a `no_std` library using `lexer::lex`, with the same versions of the dependencies.
+    let consumer = target.join("consumer");
+    std::fs::create_dir_all(consumer.join("src")).unwrap();
+    std::fs::write(consumer.join("Cargo.toml"), format!(r#"
+        [package]
+        name = "no-std-consumer"
+        version = "0.0.0"
+        edition = "2018"
+
+        [dependencies]
+        mini-haskell = {{ path = {:?}, default-features = false }}
+
+        [workspace]
+    "#, root)).unwrap();
+    std::fs::write(consumer.join("src/lib.rs"), r#"
+        #![no_std]
+        pub fn count_identifiers(source: &str) -> usize {
+            mini_haskell::lexer::lex(source)
+                .filter(|x| matches!(x, Ok((mini_haskell::Lexeme::Identifier(_), _))))
+                .count()
+        }
+    "#).unwrap();
+    std::fs::copy(root.join("Cargo.lock"), consumer.join("Cargo.lock")).unwrap();
+    assert!(build(&consumer, &["build"], &target), "a `no_std` crate using `lexer::lex` failed to build");
+}
@@ -0,0 +1,46 @@
+/*
+ * mini-haskell: light-weight Haskell for fun
+ * Copyright (C) 2021  Xie Ruifeng
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The SARIF output against a golden log: `tests/sarif/main.hs` is checked as by the `compile`
+//! subcommand, and the log compared with `tests/sarif/main.sarif`.
+//!
+//! Run with `UPDATE_GOLDEN=1` to regenerate the golden log.
+
+#![cfg(feature = "serde")]
+
+use std::path::Path;
+use mini_haskell::driver::{check, CheckOptions};
+use mini_haskell::sarif::SarifLog;
+
+#[test]
+fn sarif_golden() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/sarif");
+    let source = std::fs::read_to_string(dir.join("main.hs")).unwrap();
+    let report = check(source.as_bytes(), CheckOptions::default());
+    let mut log = SarifLog::new();
+    log.add_file(Path::new("tests/sarif/main.hs"), &source, &report.diagnostics, report.lex_error.as_ref());
+    let actual = serde_json::to_string_pretty(&log).unwrap() + "\n";
+    let golden = dir.join("main.sarif");
+    if std::env::var_os("UPDATE_GOLDEN").is_some_and(|v| v == "1") {
+        std::fs::write(&golden, &actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&golden).unwrap();
+    assert!(expected == actual, "mismatch (run with UPDATE_GOLDEN=1 to regenerate)\n\
+        --- expected\n{}--- actual\n{}", expected, actual);
+}
@@ -0,0 +1,5 @@
+module Main where
+
+main = do
+	print x
+  where x = '\1114112'